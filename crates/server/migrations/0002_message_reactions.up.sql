@@ -0,0 +1,8 @@
+-- Per-user emoji reactions on messages.
+CREATE TABLE message_reactions (
+    message_id   bigint NOT NULL REFERENCES messages(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    user_id      int NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    emoji        VARCHAR(32) NOT NULL,
+    created_at   TIMESTAMPTZ NOT NULL,
+    CONSTRAINT message_reaction_pkey PRIMARY KEY (message_id, user_id, emoji)
+);
@@ -0,0 +1,2 @@
+-- Text of the replied message as it was at send time, survives later edits and deletion.
+ALTER TABLE messages ADD COLUMN reply_snapshot TEXT;
@@ -0,0 +1,2 @@
+DELETE FROM sessions WHERE logged_out_at IS NOT NULL;
+ALTER TABLE sessions DROP COLUMN IF EXISTS logged_out_at;
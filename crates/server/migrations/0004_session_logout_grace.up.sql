@@ -0,0 +1,2 @@
+-- Sessions logged out within grace window can be revived, purged afterwards.
+ALTER TABLE sessions ADD COLUMN logged_out_at TIMESTAMPTZ;
@@ -0,0 +1 @@
+ALTER TABLE messages DROP CONSTRAINT IF EXISTS message_has_content;
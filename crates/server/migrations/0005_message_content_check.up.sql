@@ -0,0 +1,3 @@
+-- Every message carries text, an attached resource or both.
+ALTER TABLE messages
+    ADD CONSTRAINT message_has_content CHECK (text IS NOT NULL OR resource_id IS NOT NULL);
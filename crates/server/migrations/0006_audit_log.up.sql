@@ -0,0 +1,13 @@
+-- Security relevant actions, kept for incident investigation by admins.
+CREATE TYPE audit_action AS ENUM ('user_invited', 'password_changed', 'alias_changed');
+
+CREATE TABLE audit_log (
+    id              bigint PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    actor_user_id   int REFERENCES users(id) ON UPDATE CASCADE ON DELETE SET NULL,
+    action          audit_action NOT NULL,
+    target_user_id  int REFERENCES users(id) ON UPDATE CASCADE ON DELETE SET NULL,
+    created_at      TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_audit_log_actor_id_desc
+    ON audit_log(actor_user_id, id DESC);
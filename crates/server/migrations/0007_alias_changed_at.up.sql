@@ -0,0 +1,2 @@
+-- Last alias change, used to rate limit alias changes. NULL when alias was never changed.
+ALTER TABLE users ADD COLUMN alias_changed_at TIMESTAMPTZ;
@@ -0,0 +1,9 @@
+-- Prior versions of edited messages, one row per edit holding the text it replaced.
+CREATE TABLE message_edits (
+    id           bigint PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    message_id   bigint NOT NULL REFERENCES messages(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    old_text     VARCHAR(4096),
+    edited_at    TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX idx_message_edits_message_id_id ON message_edits(message_id, id);
@@ -0,0 +1,8 @@
+-- Users hidden by each other, managed by the blocking user only.
+CREATE TABLE user_blocks (
+    user_id          int NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    blocked_user_id  int NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    created_at       TIMESTAMPTZ NOT NULL,
+    CONSTRAINT user_blocks_pkey PRIMARY KEY (user_id, blocked_user_id),
+    CONSTRAINT user_blocks_not_self CHECK (user_id <> blocked_user_id)
+);
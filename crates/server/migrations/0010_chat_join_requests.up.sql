@@ -0,0 +1,7 @@
+-- Outstanding requests to join group chats and channels, removed once approved or declined.
+CREATE TABLE chat_join_requests (
+    chat_id     bigint NOT NULL REFERENCES chats(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    user_id     int NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    created_at  TIMESTAMPTZ NOT NULL,
+    CONSTRAINT chat_join_requests_pkey PRIMARY KEY (chat_id, user_id)
+);
@@ -0,0 +1,12 @@
+-- Messages waiting to be sent at `send_at`, moved into `messages` by a background task.
+CREATE TABLE scheduled_messages (
+    id          bigint PRIMARY KEY GENERATED ALWAYS AS IDENTITY,
+    chat_id     bigint NOT NULL REFERENCES chats(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    user_id     int NOT NULL REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE,
+    text        VARCHAR(4096) NOT NULL,
+    send_at     TIMESTAMPTZ NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL
+);
+
+-- Supports picking due messages.
+CREATE INDEX idx_scheduled_messages_send_at ON scheduled_messages(send_at);
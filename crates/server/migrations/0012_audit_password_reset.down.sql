@@ -0,0 +1,7 @@
+-- Enum values can't be dropped, recreate the type without it.
+DELETE FROM audit_log WHERE action = 'password_reset';
+ALTER TYPE audit_action RENAME TO audit_action_old;
+CREATE TYPE audit_action AS ENUM ('user_invited', 'password_changed', 'alias_changed');
+ALTER TABLE audit_log
+    ALTER COLUMN action TYPE audit_action USING action::text::audit_action;
+DROP TYPE audit_action_old;
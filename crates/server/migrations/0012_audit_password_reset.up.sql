@@ -0,0 +1,2 @@
+-- Passwords reset by an admin for users who lost theirs.
+ALTER TYPE audit_action ADD VALUE IF NOT EXISTS 'password_reset';
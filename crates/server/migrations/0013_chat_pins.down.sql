@@ -0,0 +1,2 @@
+ALTER TABLE chats_members
+    DROP COLUMN IF EXISTS pinned_at;
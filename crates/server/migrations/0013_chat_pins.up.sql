@@ -0,0 +1,3 @@
+-- Set while the member keeps the chat pinned to the top of their chat list.
+ALTER TABLE chats_members
+    ADD COLUMN pinned_at TIMESTAMPTZ;
@@ -0,0 +1,2 @@
+-- Admins looking up how many messages a user posted, e.g. when chasing spam.
+ALTER TYPE audit_action ADD VALUE IF NOT EXISTS 'message_rate_viewed';
@@ -0,0 +1,21 @@
+use axum::async_trait;
+
+/// Human verification run on login before credentials are checked, deters scripted guessing on
+/// public deployments. Plug a provider (e.g. hCaptcha, Turnstile) in by replacing
+/// `AppState::captcha`, default [`NoCaptcha`] keeps it off.
+#[async_trait]
+pub trait CaptchaVerifier: Send + Sync {
+    /// `token` is the client response of the captcha widget, `None` when the client sent none.
+    /// Implementation decides whether provider outage fails open or closed.
+    async fn verify(&self, token: Option<&str>) -> bool;
+}
+
+/// Accepts every request, verification is disabled.
+pub struct NoCaptcha;
+
+#[async_trait]
+impl CaptchaVerifier for NoCaptcha {
+    async fn verify(&self, _token: Option<&str>) -> bool {
+        true
+    }
+}
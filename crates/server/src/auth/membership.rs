@@ -0,0 +1,66 @@
+use axum::extract::{FromRequestParts, Path};
+use axum::http::request::Parts;
+use axum::response::{IntoResponse, Response};
+use axum::{async_trait, RequestPartsExt};
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::auth::token::Claims;
+use crate::error::{RequestError, ValidationError};
+use crate::models::chat::{ChatId, ChatRole};
+use crate::models::session::SessionId;
+use crate::models::user::UserId;
+use crate::server::state::AppState;
+
+/// Caller's verified membership in the chat from `chat_id` path param, for chat scoped routes.
+///
+/// Non-members are rejected the same way as by other chat queries, see `ServerConfig::hide_existence`.
+#[derive(Debug)]
+pub struct ChatMember {
+    pub chat_id: ChatId,
+    pub user_id: UserId,
+    pub role: ChatRole,
+    /// Session the membership was checked with, long-lived connections recheck it.
+    pub session_id: SessionId,
+}
+
+/// Other path params of the route (e.g. `message_id`) are ignored.
+#[derive(Deserialize)]
+struct ChatPath {
+    chat_id: ChatId,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ChatMember
+where
+    S: AsRef<AppState> + Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let claims = Claims::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let Path(ChatPath { chat_id }) = parts.extract::<Path<ChatPath>>().await.map_err(|e| {
+            debug!("bad chat id in path: {e}");
+            RequestError::from(ValidationError::InvalidInput {
+                value: parts.uri.path().to_string(),
+                reason: "path should contain a valid chat id".to_string(),
+            })
+            .into_response()
+        })?;
+        let role = state
+            .as_ref()
+            .db_connection
+            .get_my_role(claims.user_id, chat_id)
+            .await
+            .map_err(IntoResponse::into_response)?
+            .role;
+        Ok(ChatMember {
+            chat_id,
+            user_id: claims.user_id,
+            role,
+            session_id: claims.session_id,
+        })
+    }
+}
@@ -1,2 +1,4 @@
+pub mod captcha;
+pub mod membership;
 pub mod token;
 pub mod utils;
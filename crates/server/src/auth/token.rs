@@ -1,8 +1,10 @@
 use axum::extract::FromRequestParts;
+use axum::http::header::AUTHORIZATION;
 use axum::http::request::Parts;
+use axum::http::HeaderValue;
 use axum::{async_trait, RequestPartsExt};
 use axum_extra::headers::authorization::Bearer;
-use axum_extra::headers::Authorization;
+use axum_extra::headers::{Authorization, Cookie};
 use axum_extra::TypedHeader;
 use base64::prelude::BASE64_STANDARD as BASE64;
 use base64::Engine;
@@ -10,10 +12,14 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use tracing::debug;
 
-use crate::auth::utils::{pack_session_id_and_token, unpack_session_id_and_token};
+use crate::auth::utils::{
+    pack_session_id_and_token, unpack_session_id_and_token, TokenKind, TokenUnpackError,
+    ACCESS_TOKEN_TTL,
+};
+use crate::config::ServerConfig;
 use crate::error::SessionError;
 use crate::models::session::SessionId;
-use crate::models::user::UserId;
+use crate::models::user::{UserId, WhoAmIResponse};
 use crate::server::state::AppState;
 
 pub type SessionToken = Vec<u8>;
@@ -33,21 +39,45 @@ where
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         let state = state.as_ref();
-        let TypedHeader(Authorization(bearer)) = parts
-            .extract::<TypedHeader<Authorization<Bearer>>>()
-            .await
-            .map_err(|e| {
-                debug!("malformed auth header token: {e}");
-                SessionError::BadToken
-            })?;
-        let access_token = BASE64.decode(bearer.token()).map_err(|_| {
-            debug!("malformed auth header token: bearer is not base64");
-            SessionError::BadToken
-        })?;
-        let (sid, access_token) = unpack_session_id_and_token(&access_token).ok_or_else(|| {
-            debug!("malformed auth header token: unable to unpack");
+        let encoded_token = match &state.config.server.access_token_cookie {
+            // Header still takes precedence, cookie is only a fallback for browser clients
+            Some(cookie_name) if !parts.headers.contains_key(AUTHORIZATION) => {
+                let TypedHeader(cookie) =
+                    parts.extract::<TypedHeader<Cookie>>().await.map_err(|e| {
+                        debug!("missing auth header and cookie: {e}");
+                        SessionError::BadToken
+                    })?;
+                cookie
+                    .get(cookie_name)
+                    .ok_or_else(|| {
+                        debug!("missing auth header and `{cookie_name}` cookie");
+                        SessionError::BadToken
+                    })?
+                    .to_string()
+            }
+            _ => {
+                let TypedHeader(Authorization(bearer)) = parts
+                    .extract::<TypedHeader<Authorization<Bearer>>>()
+                    .await
+                    .map_err(|e| {
+                        debug!("malformed auth header token: {e}");
+                        SessionError::BadToken
+                    })?;
+                bearer.token().to_string()
+            }
+        };
+        let access_token = BASE64.decode(encoded_token).map_err(|_| {
+            debug!("malformed auth token: not base64");
             SessionError::BadToken
         })?;
+        let (sid, access_token) = unpack_session_id_and_token(&access_token, TokenKind::Access)
+            .map_err(|e| match e {
+                TokenUnpackError::Malformed => {
+                    debug!("malformed auth token: unable to unpack");
+                    SessionError::BadToken
+                }
+                TokenUnpackError::WrongKind { .. } => SessionError::WrongTokenKind,
+            })?;
         let user_id = state
             .db_connection
             .resolve_session(sid, access_token)
@@ -75,8 +105,10 @@ impl TokenExchangePayload {
         access_token: B2,
         access_token_expires_at: DateTime<Utc>,
     ) -> Self {
-        let refresh_token = pack_session_id_and_token(session_id, refresh_token.as_ref());
-        let access_token = pack_session_id_and_token(session_id, access_token.as_ref());
+        let refresh_token =
+            pack_session_id_and_token(TokenKind::Refresh, session_id, refresh_token.as_ref());
+        let access_token =
+            pack_session_id_and_token(TokenKind::Access, session_id, access_token.as_ref());
         Self {
             refresh_token: BASE64.encode(refresh_token),
             refresh_token_expires_at: refresh_token_expires_at.to_rfc3339(),
@@ -86,11 +118,50 @@ impl TokenExchangePayload {
     }
 }
 
+/// `Set-Cookie` value handing the access token to browser clients, `None` if cookies are disabled.
+pub fn access_token_cookie(config: &ServerConfig, access_token: &str) -> Option<HeaderValue> {
+    build_access_token_cookie(config, access_token, ACCESS_TOKEN_TTL.num_seconds())
+}
+
+/// `Set-Cookie` value making browsers drop the access token cookie.
+pub fn expired_access_token_cookie(config: &ServerConfig) -> Option<HeaderValue> {
+    build_access_token_cookie(config, "", 0)
+}
+
+fn build_access_token_cookie(
+    config: &ServerConfig,
+    value: &str,
+    max_age_secs: i64,
+) -> Option<HeaderValue> {
+    let name = config.access_token_cookie.as_deref()?;
+    let secure = if config.access_token_cookie_secure {
+        "; Secure"
+    } else {
+        ""
+    };
+    let cookie = format!(
+        "{name}={value}; Max-Age={max_age_secs}; Path=/; HttpOnly; SameSite=Strict{secure}"
+    );
+    HeaderValue::from_str(&cookie).ok()
+}
+
+/// Login result, token fields are kept at top level to stay compatible with [`TokenExchangePayload`].
+#[derive(Debug, Serialize)]
+pub struct LoginResponse {
+    #[serde(flatten)]
+    pub tokens: TokenExchangePayload,
+    pub profile: WhoAmIResponse,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct AuthPayload {
     pub alias: String,
     pub password: String,
-    pub session_id: Option<String>, // TODO: use
+    /// Session of the same device from an earlier login, its tokens are rotated instead of
+    /// starting another session.
+    pub session_id: Option<SessionId>,
+    /// Checked by `AppState::captcha` before credentials, ignored while verification is off.
+    pub captcha_token: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -1,22 +1,28 @@
 use argon2::password_hash::rand_core::OsRng as PasswordOsRng;
 use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
-use argon2::Argon2;
+use argon2::{Algorithm, Argon2, Version};
 use chrono::{DateTime, Utc};
 use rand::rngs::OsRng;
 use rand::RngCore;
 use sha2::{Digest, Sha256};
+use strum_macros::Display;
 use subtle::ConstantTimeEq;
 
+use crate::config::AuthConfig;
 use crate::models::session::SessionId;
 
-pub fn hash_password(password: &str) -> String {
+pub fn hash_password(password: &str, config: &AuthConfig) -> String {
+    let params = config
+        .argon2_params()
+        .expect("argon2 parameters should be validated on config load");
     let salt = SaltString::generate(&mut PasswordOsRng);
-    Argon2::default()
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
         .hash_password(password.as_bytes(), &salt)
-        .expect("argon2 default configuration should always hash valid input")
+        .expect("argon2 configuration should always hash valid input")
         .to_string()
 }
 
+/// Cost parameters are taken from the stored hash, not from current config.
 pub fn verify_password(password: &str, hash: &str) -> bool {
     let Ok(parsed) = PasswordHash::new(hash) else {
         return false;
@@ -33,8 +39,10 @@ fn secure_random_bytes<const S: usize>() -> [u8; S] {
     buf
 }
 
+pub const SESSION_TOKEN_LEN: usize = 32;
+
 #[inline]
-pub fn generate_session_token() -> [u8; 32] {
+pub fn generate_session_token() -> [u8; SESSION_TOKEN_LEN] {
     secure_random_bytes()
 }
 
@@ -66,17 +74,142 @@ pub fn current_time() -> DateTime<Utc> {
     Utc::now()
 }
 
-pub fn pack_session_id_and_token(session_id: SessionId, token: &[u8]) -> Vec<u8> {
-    let sid_len = size_of::<SessionId>();
-    let mut out = vec![0; sid_len + token.len()];
-    out[..sid_len].copy_from_slice(session_id.as_bytes());
-    out[sid_len..].copy_from_slice(token);
+/// Leading byte of packed tokens, tells access and refresh tokens apart before any DB lookup.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display)]
+#[strum(serialize_all = "snake_case")]
+#[repr(u8)]
+pub enum TokenKind {
+    Access = 1,
+    Refresh = 2,
+}
+
+impl TokenKind {
+    fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(Self::Access),
+            2 => Some(Self::Refresh),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenUnpackError {
+    Malformed,
+    WrongKind { expected: TokenKind },
+}
+
+pub fn pack_session_id_and_token(kind: TokenKind, session_id: SessionId, token: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(1 + size_of::<SessionId>() + token.len());
+    out.push(kind as u8);
+    out.extend_from_slice(session_id.as_bytes());
+    out.extend_from_slice(token);
     out
 }
 
-pub fn unpack_session_id_and_token(packed: &[u8]) -> Option<(SessionId, &[u8])> {
+/// Tokens issued before kind tags were introduced are exactly session id and token long, they are
+/// accepted as any kind until they expire.
+pub fn unpack_session_id_and_token(
+    packed: &[u8],
+    expected: TokenKind,
+) -> Result<(SessionId, &[u8]), TokenUnpackError> {
     let sid_len = size_of::<SessionId>();
-    let session_id = SessionId::from_slice(packed.get(..sid_len)?).ok()?;
-    let token = packed.get(sid_len..)?;
-    Some((session_id, token))
+    let untagged = if packed.len() == sid_len + SESSION_TOKEN_LEN {
+        packed
+    } else {
+        let (&tag, rest) = packed.split_first().ok_or(TokenUnpackError::Malformed)?;
+        match TokenKind::from_tag(tag) {
+            Some(kind) if kind == expected => rest,
+            Some(_) => return Err(TokenUnpackError::WrongKind { expected }),
+            None => return Err(TokenUnpackError::Malformed),
+        }
+    };
+    let session_id = untagged
+        .get(..sid_len)
+        .and_then(|sid| SessionId::from_slice(sid).ok())
+        .ok_or(TokenUnpackError::Malformed)?;
+    Ok((session_id, &untagged[sid_len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hashes_verify_across_argon2_param_changes() {
+        let cheap = AuthConfig {
+            argon2_memory_kib: 1024,
+            argon2_iterations: 1,
+            argon2_parallelism: 1,
+            ..AuthConfig::default()
+        };
+        let expensive = AuthConfig {
+            argon2_memory_kib: 8192,
+            argon2_iterations: 3,
+            argon2_parallelism: 2,
+            ..AuthConfig::default()
+        };
+        let old_hash = hash_password("walrus_password", &cheap);
+        assert!(old_hash.contains("m=1024,t=1,p=1"), "{old_hash}");
+        assert!(verify_password("walrus_password", &old_hash));
+
+        let new_hash = hash_password("walrus_password", &expensive);
+        assert!(new_hash.contains("m=8192,t=3,p=2"), "{new_hash}");
+        assert!(verify_password("walrus_password", &new_hash));
+        assert!(verify_password("walrus_password", &old_hash));
+        assert!(!verify_password("other_password", &old_hash));
+    }
+
+    #[test]
+    fn packed_tokens_round_trip_for_their_kind() {
+        let session_id = SessionId::from_u128(7);
+        let token = generate_session_token();
+        for kind in [TokenKind::Access, TokenKind::Refresh] {
+            let packed = pack_session_id_and_token(kind, session_id, &token);
+            let (unpacked_id, unpacked_token) = unpack_session_id_and_token(&packed, kind).unwrap();
+            assert_eq!(unpacked_id, session_id);
+            assert_eq!(unpacked_token, token);
+        }
+    }
+
+    #[test]
+    fn cross_submitted_token_kinds_are_rejected() {
+        let session_id = SessionId::from_u128(7);
+        let token = generate_session_token();
+        let access = pack_session_id_and_token(TokenKind::Access, session_id, &token);
+        let refresh = pack_session_id_and_token(TokenKind::Refresh, session_id, &token);
+        assert_eq!(
+            unpack_session_id_and_token(&access, TokenKind::Refresh),
+            Err(TokenUnpackError::WrongKind {
+                expected: TokenKind::Refresh
+            })
+        );
+        assert_eq!(
+            unpack_session_id_and_token(&refresh, TokenKind::Access),
+            Err(TokenUnpackError::WrongKind {
+                expected: TokenKind::Access
+            })
+        );
+    }
+
+    #[test]
+    fn untagged_tokens_are_still_accepted() {
+        let session_id = SessionId::from_u128(7);
+        let token = generate_session_token();
+        let mut legacy = session_id.as_bytes().to_vec();
+        legacy.extend_from_slice(&token);
+        for kind in [TokenKind::Access, TokenKind::Refresh] {
+            let (unpacked_id, unpacked_token) = unpack_session_id_and_token(&legacy, kind).unwrap();
+            assert_eq!(unpacked_id, session_id);
+            assert_eq!(unpacked_token, token);
+        }
+        assert_eq!(
+            unpack_session_id_and_token(&[9; 49], TokenKind::Access),
+            Err(TokenUnpackError::Malformed)
+        );
+        assert_eq!(
+            unpack_session_id_and_token(&[], TokenKind::Access),
+            Err(TokenUnpackError::Malformed)
+        );
+    }
 }
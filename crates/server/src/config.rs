@@ -1,59 +1,498 @@
-use anyhow::{anyhow, Context};
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use argon2::Params;
 
 use crate::database::connection::DbConfig;
+use crate::models::message::MESSAGE_TEXT_MAX_LENGTH;
+use crate::models::user::UserRole;
 
 const ENV_DB_USERNAME: &str = "WALRUS_DB_USERNAME";
 const ENV_DB_PASSWORD: &str = "WALRUS_DB_PASSWORD";
 const ENV_DB_NAME: &str = "WALRUS_DB_NAME";
 const ENV_DB_ADDRESS: &str = "WALRUS_DB_ADDRESS";
 const ENV_DB_MAX_CONNECTIONS: &str = "WALRUS_DB_MAX_CONNECTIONS";
+const ENV_DB_TEST_BEFORE_ACQUIRE: &str = "WALRUS_DB_TEST_BEFORE_ACQUIRE";
+const ENV_HIDE_EXISTENCE: &str = "WALRUS_HIDE_EXISTENCE";
+const ENV_MAX_OWNED_CHATS: &str = "WALRUS_MAX_OWNED_CHATS";
+const ENV_LOGOUT_GRACE_SECS: &str = "WALRUS_LOGOUT_GRACE_SECS";
+const ENV_ALIAS_CHANGE_COOLDOWN_SECS: &str = "WALRUS_ALIAS_CHANGE_COOLDOWN_SECS";
+const ENV_IDLE_TIMEOUT_SECS: &str = "WALRUS_IDLE_TIMEOUT_SECS";
+const ENV_MAX_MESSAGE_LENGTH_WITH_SELF: &str = "WALRUS_MAX_MESSAGE_LENGTH_WITH_SELF";
+const ENV_MAX_MESSAGE_LENGTH_PRIVATE: &str = "WALRUS_MAX_MESSAGE_LENGTH_PRIVATE";
+const ENV_MAX_MESSAGE_LENGTH_GROUP: &str = "WALRUS_MAX_MESSAGE_LENGTH_GROUP";
+const ENV_MAX_MESSAGE_LENGTH_CHANNEL: &str = "WALRUS_MAX_MESSAGE_LENGTH_CHANNEL";
+pub const ENV_DB_AUTO_MIGRATE: &str = "WALRUS_DB_AUTO_MIGRATE";
+const ENV_HTTP_REQUEST_TIMEOUT_SECS: &str = "WALRUS_HTTP_REQUEST_TIMEOUT_SECS";
+const ENV_HTTP_HEADER_READ_TIMEOUT_SECS: &str = "WALRUS_HTTP_HEADER_READ_TIMEOUT_SECS";
+const ENV_HTTP_COMPRESSION: &str = "WALRUS_HTTP_COMPRESSION";
+const ENV_ACCESS_TOKEN_COOKIE: &str = "WALRUS_ACCESS_TOKEN_COOKIE";
+const ENV_ACCESS_TOKEN_COOKIE_SECURE: &str = "WALRUS_ACCESS_TOKEN_COOKIE_SECURE";
+const ENV_CHAT_EVENTS_CAPACITY: &str = "WALRUS_CHAT_EVENTS_CAPACITY";
+const ENV_TRUST_REAL_IP_HEADER: &str = "WALRUS_TRUST_REAL_IP_HEADER";
+const ENV_ARGON2_MEMORY_KIB: &str = "WALRUS_ARGON2_MEMORY_KIB";
+const ENV_ARGON2_ITERATIONS: &str = "WALRUS_ARGON2_ITERATIONS";
+const ENV_ARGON2_PARALLELISM: &str = "WALRUS_ARGON2_PARALLELISM";
+const ENV_DEFAULT_INVITED_ROLE: &str = "WALRUS_DEFAULT_INVITED_ROLE";
+const ENV_FEATURE_WEBSOCKETS: &str = "WALRUS_FEATURE_WEBSOCKETS";
+const ENV_FEATURE_CHANNELS: &str = "WALRUS_FEATURE_CHANNELS";
 pub const ENV_ORIGIN_PASSWORD: &str = "WALRUS_ORIGIN_PASSWORD";
 
 #[derive(Clone, Debug)]
 pub struct ServerConfig {
-    pub address: String,
+    /// Every address gets its own listener serving the same app, e.g. IPv4 and IPv6 for dual stack.
+    pub addresses: Vec<String>,
+    /// Upper bound for handling single request, exceeding it results in 408.
+    pub request_timeout: Duration,
+    /// Upper bound for receiving HTTP/1 request headers, protects against slow clients holding
+    /// connections.
+    pub header_read_timeout: Duration,
+    /// Compress responses for clients sending `Accept-Encoding`.
+    pub compression: bool,
+    /// Name of the cookie carrying the access token for browser clients, disabled when `None`.
+    pub access_token_cookie: Option<String>,
+    /// Only worth disabling for plain HTTP development setups.
+    pub access_token_cookie_secure: bool,
+    /// Real-time events buffered per chat, WebSocket subscribers lagging further behind are told
+    /// to resync.
+    pub chat_events_capacity: usize,
+    /// Take client address from `X-Real-IP` set by the reverse proxy instead of the connection,
+    /// must stay off when clients can reach the server directly and forge the header.
+    pub trust_real_ip_header: bool,
+    /// Report missing chat membership as not found, so outsiders can't probe which chats exist.
+    pub hide_existence: bool,
+}
+
+impl ServerConfig {
+    const REQUEST_TIMEOUT_FALLBACK: Duration = Duration::from_secs(30);
+    const HEADER_READ_TIMEOUT_FALLBACK: Duration = Duration::from_secs(10);
+    const COMPRESSION_FALLBACK: bool = true;
+    const ACCESS_TOKEN_COOKIE_SECURE_FALLBACK: bool = true;
+    const CHAT_EVENTS_CAPACITY_FALLBACK: usize = 256;
+    const TRUST_REAL_IP_HEADER_FALLBACK: bool = false;
+    pub(crate) const HIDE_EXISTENCE_FALLBACK: bool = true;
+}
+
+/// Password hashing cost, applies only to newly stored hashes. Existing ones carry their own
+/// parameters in the PHC string and keep verifying after these change.
+#[derive(Clone, Debug)]
+pub struct AuthConfig {
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    /// Role of invited users when the invite doesn't name one, never `Admin`.
+    pub default_invited_role: UserRole,
+    /// Bootstrap secret the origin user is created with on the first startup, should be unset once
+    /// the origin password is changed.
+    pub origin_password: Option<String>,
+}
+
+impl AuthConfig {
+    const ARGON2_MEMORY_KIB_FALLBACK: u32 = Params::DEFAULT_M_COST;
+    const ARGON2_ITERATIONS_FALLBACK: u32 = Params::DEFAULT_T_COST;
+    const ARGON2_PARALLELISM_FALLBACK: u32 = Params::DEFAULT_P_COST;
+    const DEFAULT_INVITED_ROLE_FALLBACK: UserRole = UserRole::Regular;
+    /// No built-in seed password, a fresh database can't be bootstrapped without one.
+    const ORIGIN_PASSWORD_FALLBACK: Option<String> = None;
+
+    pub fn argon2_params(&self) -> Result<Params, argon2::Error> {
+        Params::new(
+            self.argon2_memory_kib,
+            self.argon2_iterations,
+            self.argon2_parallelism,
+            None,
+        )
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            argon2_memory_kib: Self::ARGON2_MEMORY_KIB_FALLBACK,
+            argon2_iterations: Self::ARGON2_ITERATIONS_FALLBACK,
+            argon2_parallelism: Self::ARGON2_PARALLELISM_FALLBACK,
+            default_invited_role: Self::DEFAULT_INVITED_ROLE_FALLBACK,
+            origin_password: Self::ORIGIN_PASSWORD_FALLBACK,
+        }
+    }
+}
+
+/// Optional parts of the API, routes of disabled features are not registered and respond with 404.
+#[derive(Clone, Debug)]
+pub struct FeaturesConfig {
+    /// Real-time chat events over WebSocket.
+    pub websockets: bool,
+    /// Creation of new channels, existing ones keep working.
+    pub channels: bool,
+}
+
+impl FeaturesConfig {
+    const WEBSOCKETS_FALLBACK: bool = true;
+    const CHANNELS_FALLBACK: bool = true;
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        Self {
+            websockets: Self::WEBSOCKETS_FALLBACK,
+            channels: Self::CHANNELS_FALLBACK,
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub struct AppConfig {
     pub server: ServerConfig,
     pub database: DbConfig,
+    pub auth: AuthConfig,
+    pub features: FeaturesConfig,
 }
 
 impl AppConfig {
-    pub fn from_env_with_address(server_address: String) -> Result<Self, anyhow::Error> {
-        if server_address.trim().is_empty() {
-            return Err(anyhow!("server address cannot be empty"));
-        }
-        let max_connections = match optional_env(ENV_DB_MAX_CONNECTIONS) {
-            Some(raw) => Some(
-                raw.parse::<u32>()
-                    .with_context(|| format!("invalid `{ENV_DB_MAX_CONNECTIONS}` value `{raw}`"))?,
-            ),
-            None => None,
+    pub fn from_env_with_addresses(server_addresses: Vec<String>) -> Result<Self, anyhow::Error> {
+        Self::from_lookup(server_addresses, |name| std::env::var(name).ok())
+    }
+
+    /// Builds config from an arbitrary variable source, reporting every missing or invalid field
+    /// at once, each named by its config path (e.g. `database.password`) and backing env var.
+    pub fn from_lookup<F>(server_addresses: Vec<String>, lookup: F) -> Result<Self, anyhow::Error>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut loader = ConfigLoader {
+            lookup,
+            problems: Vec::new(),
+        };
+        if server_addresses.is_empty() {
+            loader
+                .problems
+                .push("server.addresses requires at least one address".to_string());
+        }
+        if server_addresses
+            .iter()
+            .any(|address| address.trim().is_empty())
+        {
+            loader
+                .problems
+                .push("server.addresses cannot contain empty address".to_string());
+        }
+        let request_timeout = loader
+            .parsed::<u64>("server.request_timeout", ENV_HTTP_REQUEST_TIMEOUT_SECS)
+            .map(Duration::from_secs)
+            .unwrap_or(ServerConfig::REQUEST_TIMEOUT_FALLBACK);
+        let header_read_timeout = loader
+            .parsed::<u64>(
+                "server.header_read_timeout",
+                ENV_HTTP_HEADER_READ_TIMEOUT_SECS,
+            )
+            .map(Duration::from_secs)
+            .unwrap_or(ServerConfig::HEADER_READ_TIMEOUT_FALLBACK);
+        let compression = loader
+            .parsed::<bool>("server.compression", ENV_HTTP_COMPRESSION)
+            .unwrap_or(ServerConfig::COMPRESSION_FALLBACK);
+        let access_token_cookie = loader.optional(ENV_ACCESS_TOKEN_COOKIE);
+        let access_token_cookie_secure = loader
+            .parsed::<bool>(
+                "server.access_token_cookie_secure",
+                ENV_ACCESS_TOKEN_COOKIE_SECURE,
+            )
+            .unwrap_or(ServerConfig::ACCESS_TOKEN_COOKIE_SECURE_FALLBACK);
+        let chat_events_capacity = loader
+            .parsed::<usize>("server.chat_events_capacity", ENV_CHAT_EVENTS_CAPACITY)
+            .unwrap_or(ServerConfig::CHAT_EVENTS_CAPACITY_FALLBACK);
+        if chat_events_capacity == 0 {
+            loader
+                .problems
+                .push("server.chat_events_capacity should be at least 1".to_string());
+        }
+        let trust_real_ip_header = loader
+            .parsed::<bool>("server.trust_real_ip_header", ENV_TRUST_REAL_IP_HEADER)
+            .unwrap_or(ServerConfig::TRUST_REAL_IP_HEADER_FALLBACK);
+        let hide_existence = loader
+            .parsed::<bool>("server.hide_existence", ENV_HIDE_EXISTENCE)
+            .unwrap_or(ServerConfig::HIDE_EXISTENCE_FALLBACK);
+        let username = loader.required("database.username", ENV_DB_USERNAME);
+        let password = loader.required("database.password", ENV_DB_PASSWORD);
+        let dbname = loader.required("database.dbname", ENV_DB_NAME);
+        let address = loader.optional(ENV_DB_ADDRESS);
+        let max_connections =
+            loader.parsed::<u32>("database.max_connections", ENV_DB_MAX_CONNECTIONS);
+        let test_before_acquire =
+            loader.parsed::<bool>("database.test_before_acquire", ENV_DB_TEST_BEFORE_ACQUIRE);
+        let max_owned_chats =
+            loader.parsed::<usize>("database.max_owned_chats", ENV_MAX_OWNED_CHATS);
+        let logout_grace_secs =
+            loader.parsed::<u64>("database.logout_grace_secs", ENV_LOGOUT_GRACE_SECS);
+        let alias_change_cooldown_secs = loader.parsed::<u64>(
+            "database.alias_change_cooldown_secs",
+            ENV_ALIAS_CHANGE_COOLDOWN_SECS,
+        );
+        let idle_timeout_secs =
+            loader.parsed::<u64>("database.idle_timeout_secs", ENV_IDLE_TIMEOUT_SECS);
+        let mut max_message_length = |field: &str, env: &str| {
+            let value = loader.parsed::<usize>(field, env);
+            if value.is_some_and(|limit| !(1..=MESSAGE_TEXT_MAX_LENGTH).contains(&limit)) {
+                loader.problems.push(format!(
+                    "{field} should be between 1 and {MESSAGE_TEXT_MAX_LENGTH}"
+                ));
+            }
+            value
+        };
+        let max_message_length_with_self = max_message_length(
+            "database.max_message_length_with_self",
+            ENV_MAX_MESSAGE_LENGTH_WITH_SELF,
+        );
+        let max_message_length_private = max_message_length(
+            "database.max_message_length_private",
+            ENV_MAX_MESSAGE_LENGTH_PRIVATE,
+        );
+        let max_message_length_group = max_message_length(
+            "database.max_message_length_group",
+            ENV_MAX_MESSAGE_LENGTH_GROUP,
+        );
+        let max_message_length_channel = max_message_length(
+            "database.max_message_length_channel",
+            ENV_MAX_MESSAGE_LENGTH_CHANNEL,
+        );
+        let auto_migrate = loader.parsed::<bool>("database.auto_migrate", ENV_DB_AUTO_MIGRATE);
+        let auth = AuthConfig {
+            argon2_memory_kib: loader
+                .parsed::<u32>("auth.argon2_memory_kib", ENV_ARGON2_MEMORY_KIB)
+                .unwrap_or(AuthConfig::ARGON2_MEMORY_KIB_FALLBACK),
+            argon2_iterations: loader
+                .parsed::<u32>("auth.argon2_iterations", ENV_ARGON2_ITERATIONS)
+                .unwrap_or(AuthConfig::ARGON2_ITERATIONS_FALLBACK),
+            argon2_parallelism: loader
+                .parsed::<u32>("auth.argon2_parallelism", ENV_ARGON2_PARALLELISM)
+                .unwrap_or(AuthConfig::ARGON2_PARALLELISM_FALLBACK),
+            default_invited_role: loader
+                .parsed::<UserRole>("auth.default_invited_role", ENV_DEFAULT_INVITED_ROLE)
+                .unwrap_or(AuthConfig::DEFAULT_INVITED_ROLE_FALLBACK),
+            origin_password: loader
+                .optional(ENV_ORIGIN_PASSWORD)
+                .or(AuthConfig::ORIGIN_PASSWORD_FALLBACK),
+        };
+        let features = FeaturesConfig {
+            websockets: loader
+                .parsed::<bool>("features.websockets", ENV_FEATURE_WEBSOCKETS)
+                .unwrap_or(FeaturesConfig::WEBSOCKETS_FALLBACK),
+            channels: loader
+                .parsed::<bool>("features.channels", ENV_FEATURE_CHANNELS)
+                .unwrap_or(FeaturesConfig::CHANNELS_FALLBACK),
         };
+        if let Err(e) = auth.argon2_params() {
+            loader
+                .problems
+                .push(format!("auth.argon2 parameters are out of range: {e}"));
+        }
+        if auth.default_invited_role == UserRole::Admin {
+            loader
+                .problems
+                .push("auth.default_invited_role cannot be admin".to_string());
+        }
+        if !loader.problems.is_empty() {
+            return Err(anyhow!(
+                "invalid configuration:\n  - {}",
+                loader.problems.join("\n  - ")
+            ));
+        }
         Ok(Self {
             server: ServerConfig {
-                address: server_address,
+                addresses: server_addresses,
+                request_timeout,
+                header_read_timeout,
+                compression,
+                access_token_cookie,
+                access_token_cookie_secure,
+                chat_events_capacity,
+                trust_real_ip_header,
+                hide_existence,
             },
             database: DbConfig {
-                username: required_env(ENV_DB_USERNAME)?,
-                password: required_env(ENV_DB_PASSWORD)?,
-                dbname: required_env(ENV_DB_NAME)?,
-                address: optional_env(ENV_DB_ADDRESS),
+                username: username.unwrap_or_default(),
+                password: password.unwrap_or_default(),
+                dbname: dbname.unwrap_or_default(),
+                address,
                 max_connections,
+                test_before_acquire,
+                max_owned_chats,
+                logout_grace_secs,
+                alias_change_cooldown_secs,
+                idle_timeout_secs,
+                max_message_length_with_self,
+                max_message_length_private,
+                max_message_length_group,
+                max_message_length_channel,
+                auto_migrate,
             },
+            auth,
+            features,
         })
     }
 }
 
-pub fn required_env(name: &str) -> Result<String, anyhow::Error> {
-    std::env::var(name).with_context(|| format!("missing required env var `{name}`"))
+/// Accumulates problems instead of failing on the first one, so a fresh setup can be fixed in one go.
+struct ConfigLoader<F> {
+    lookup: F,
+    problems: Vec<String>,
 }
 
-pub fn optional_env(name: &str) -> Option<String> {
-    std::env::var(name)
-        .ok()
-        .map(|value| value.trim().to_string())
-        .filter(|value| !value.is_empty())
+impl<F: Fn(&str) -> Option<String>> ConfigLoader<F> {
+    fn optional(&self, name: &str) -> Option<String> {
+        (self.lookup)(name)
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    }
+
+    fn required(&mut self, path: &str, name: &str) -> Option<String> {
+        let value = self.optional(name);
+        if value.is_none() {
+            self.problems
+                .push(format!("{path} is required (set `{name}`)"));
+        }
+        value
+    }
+
+    fn parsed<T>(&mut self, path: &str, name: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        let raw = self.optional(name)?;
+        match raw.parse::<T>() {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.problems
+                    .push(format!("{path} has invalid value `{raw}` in `{name}`: {e}"));
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn load(vars: &[(&str, &str)]) -> Result<AppConfig, anyhow::Error> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        AppConfig::from_lookup(vec!["127.0.0.1:3000".to_string()], |name| {
+            vars.get(name).cloned()
+        })
+    }
+
+    #[test]
+    fn missing_password_names_the_field() {
+        let err = load(&[(ENV_DB_USERNAME, "walrus"), (ENV_DB_NAME, "walrus")])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("database.password is required"), "{err}");
+        assert!(err.contains(ENV_DB_PASSWORD), "{err}");
+        assert!(!err.contains("database.username"), "{err}");
+    }
+
+    #[test]
+    fn reports_all_problems_at_once() {
+        let err = load(&[
+            (ENV_DB_USERNAME, "walrus"),
+            (ENV_HTTP_COMPRESSION, "sometimes"),
+        ])
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("database.password is required"), "{err}");
+        assert!(err.contains("database.dbname is required"), "{err}");
+        assert!(
+            err.contains("server.compression has invalid value `sometimes`"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn complete_config_uses_fallbacks() {
+        let config = load(&[
+            (ENV_DB_USERNAME, "walrus"),
+            (ENV_DB_PASSWORD, "secret"),
+            (ENV_DB_NAME, "walrus"),
+        ])
+        .unwrap();
+        assert_eq!(config.database.password, "secret");
+        assert_eq!(
+            config.server.request_timeout,
+            ServerConfig::REQUEST_TIMEOUT_FALLBACK
+        );
+        assert!(config.database.max_connections.is_none());
+        assert!(config.server.hide_existence);
+        assert!(config.auth.origin_password.is_none());
+        assert!(config.auth.argon2_params().is_ok());
+        assert_eq!(config.auth.default_invited_role, UserRole::Regular);
+        assert!(config.features.websockets);
+        assert!(config.features.channels);
+    }
+
+    #[test]
+    fn features_can_be_disabled() {
+        let config = load(&[
+            (ENV_DB_USERNAME, "walrus"),
+            (ENV_DB_PASSWORD, "secret"),
+            (ENV_DB_NAME, "walrus"),
+            (ENV_FEATURE_WEBSOCKETS, "false"),
+        ])
+        .unwrap();
+        assert!(!config.features.websockets);
+        assert!(config.features.channels);
+    }
+
+    #[test]
+    fn out_of_range_argon2_params_are_reported() {
+        let err = load(&[
+            (ENV_DB_USERNAME, "walrus"),
+            (ENV_DB_PASSWORD, "secret"),
+            (ENV_DB_NAME, "walrus"),
+            (ENV_ARGON2_ITERATIONS, "0"),
+        ])
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("auth.argon2 parameters are out of range"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn admin_as_default_invited_role_is_reported() {
+        let err = load(&[
+            (ENV_DB_USERNAME, "walrus"),
+            (ENV_DB_PASSWORD, "secret"),
+            (ENV_DB_NAME, "walrus"),
+            (ENV_DEFAULT_INVITED_ROLE, "admin"),
+        ])
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("auth.default_invited_role cannot be admin"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn message_length_above_column_size_is_reported() {
+        let err = load(&[
+            (ENV_DB_USERNAME, "walrus"),
+            (ENV_DB_PASSWORD, "secret"),
+            (ENV_DB_NAME, "walrus"),
+            (ENV_MAX_MESSAGE_LENGTH_CHANNEL, "10000"),
+        ])
+        .unwrap_err()
+        .to_string();
+        assert!(
+            err.contains("database.max_message_length_channel should be between 1 and 4096"),
+            "{err}"
+        );
+    }
 }
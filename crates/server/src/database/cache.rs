@@ -0,0 +1,154 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use tokio::sync::OnceCell;
+
+use crate::models::chat::ChatId;
+use crate::models::user::UserId;
+
+const PRIVATE_CHAT_CACHE_TTL: Duration = Duration::from_secs(60);
+/// Expired entries are only swept once the map grows past this size.
+const PRIVATE_CHAT_CACHE_SWEEP_THRESHOLD: usize = 10_000;
+
+/// Private chat ids by user pair, concurrent lookups of the same pair share a single query.
+///
+/// Only found chats are cached, pair never moves to another chat once created. The server never
+/// deletes private chats, one removed directly in the database is still served until its entry
+/// expires. Misses always hit the database, a chat created right after a miss is visible on the
+/// next lookup.
+pub struct PrivateChatCache {
+    ttl: Duration,
+    entries: DashMap<(UserId, UserId), CacheEntry>,
+}
+
+struct CacheEntry {
+    created_at: Instant,
+    chat_id: Arc<OnceCell<ChatId>>,
+}
+
+impl CacheEntry {
+    fn new(chat_id: Option<ChatId>) -> Self {
+        Self {
+            created_at: Instant::now(),
+            chat_id: Arc::new(OnceCell::new_with(chat_id)),
+        }
+    }
+
+    fn is_expired(&self, ttl: Duration) -> bool {
+        self.created_at.elapsed() > ttl
+    }
+}
+
+impl PrivateChatCache {
+    pub fn new() -> Self {
+        Self::with_ttl(PRIVATE_CHAT_CACHE_TTL)
+    }
+
+    fn with_ttl(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: DashMap::new(),
+        }
+    }
+
+    pub async fn get_or_lookup<F, Fut, E>(
+        &self,
+        user_id_a: UserId,
+        user_id_b: UserId,
+        lookup: F,
+    ) -> Result<Option<ChatId>, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<ChatId>, E>>,
+    {
+        let cell = self.cell(pair_key(user_id_a, user_id_b));
+        // Miss is reported as an error, so it leaves the cell empty for the next lookup
+        let result = cell
+            .get_or_try_init(|| async {
+                match lookup().await {
+                    Ok(Some(chat_id)) => Ok(chat_id),
+                    Ok(None) => Err(None),
+                    Err(e) => Err(Some(e)),
+                }
+            })
+            .await;
+        match result {
+            Ok(chat_id) => Ok(Some(*chat_id)),
+            Err(None) => Ok(None),
+            Err(Some(e)) => Err(e),
+        }
+    }
+
+    /// Called after the chat creation is committed.
+    pub fn insert(&self, user_id_a: UserId, user_id_b: UserId, chat_id: ChatId) {
+        self.entries.insert(
+            pair_key(user_id_a, user_id_b),
+            CacheEntry::new(Some(chat_id)),
+        );
+    }
+
+    pub fn clear(&self) {
+        self.entries.clear();
+    }
+
+    fn cell(&self, key: (UserId, UserId)) -> Arc<OnceCell<ChatId>> {
+        if self.entries.len() > PRIVATE_CHAT_CACHE_SWEEP_THRESHOLD {
+            self.entries.retain(|_, entry| !entry.is_expired(self.ttl));
+        }
+        let mut entry = self
+            .entries
+            .entry(key)
+            .or_insert_with(|| CacheEntry::new(None));
+        if entry.is_expired(self.ttl) {
+            *entry = CacheEntry::new(None);
+        }
+        entry.chat_id.clone()
+    }
+}
+
+fn pair_key(user_id_a: UserId, user_id_b: UserId) -> (UserId, UserId) {
+    if user_id_a < user_id_b {
+        (user_id_a, user_id_b)
+    } else {
+        (user_id_b, user_id_a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::PrivateChatCache;
+
+    #[tokio::test]
+    async fn concurrent_lookups_share_single_query() {
+        let cache = PrivateChatCache::new();
+        let queries = AtomicUsize::new(0);
+        let lookup = || async {
+            queries.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok::<_, ()>(Some(42))
+        };
+        let (a, b) = tokio::join!(
+            cache.get_or_lookup(1, 2, lookup),
+            cache.get_or_lookup(2, 1, lookup)
+        );
+        assert_eq!((a, b), (Ok(Some(42)), Ok(Some(42))));
+        assert_eq!(queries.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn misses_and_expired_entries_are_looked_up_again() {
+        let cache = PrivateChatCache::with_ttl(Duration::ZERO);
+        let missing = cache.get_or_lookup(1, 2, || async { Ok::<_, ()>(None) });
+        assert_eq!(missing.await, Ok(None));
+        let found = cache.get_or_lookup(1, 2, || async { Ok::<_, ()>(Some(7)) });
+        assert_eq!(found.await, Ok(Some(7)));
+        tokio::time::sleep(Duration::from_millis(1)).await;
+        let refreshed = cache.get_or_lookup(1, 2, || async { Ok::<_, ()>(Some(8)) });
+        assert_eq!(refreshed.await, Ok(Some(8)));
+    }
+}
@@ -1,34 +1,67 @@
 use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
 
 use chrono::{DateTime, Utc};
 use ipnetwork::IpNetwork;
-use sqlx::{Error as SqlxError, PgExecutor, Postgres, Row, Transaction};
-use tracing::{debug, info, instrument};
+use sqlx::{Connection, Error as SqlxError, PgExecutor, Postgres, Row, Transaction};
+use tracing::{debug, error, info, instrument};
 
-use crate::auth::token::TokenExchangePayload;
+use crate::auth::token::{LoginResponse, TokenExchangePayload};
 use crate::auth::utils::{
     current_time, generate_session_token, hash_password, hash_session_token,
     new_access_token_expiration, new_refresh_token_expiration, verify_password,
     verify_session_token,
 };
+use crate::config::AuthConfig;
 use crate::database::connection::DbConnection;
 use crate::database::queries::{
-    get_refresh_token, get_user_credentials_by_alias, get_user_credentials_by_user_id,
-    get_user_id_by_alias, get_user_role, is_user_in_chat, list_user_ids,
+    chat_exists, count_chat_messages, count_other_pinned_chats, count_owned_chats, get_chat_kind,
+    get_chat_role, get_logged_out_session, get_message, get_message_chat_id, get_private_chat_id,
+    get_user_credentials_by_alias, get_user_credentials_by_user_id, get_user_id_by_alias,
+    get_user_profile, get_user_role, get_whoami_by_user_id, is_blocked_between,
+    is_private_chat_blocked, is_session_of_user, is_user_in_chat, list_chat_member_ids_among,
+    list_existing_aliases, list_user_ids, lock_refresh_token,
 };
+use crate::database::utils::map_not_found_as_none;
 use crate::error::{RequestError, ValidationError};
-use crate::models::chat::{ChatId, ChatKind, ChatRole};
-use crate::models::message::MessageId;
+use crate::models::audit::AuditAction;
+use crate::models::chat::{
+    accepts_join_requests, can_manage_join_requests, can_manage_member, can_post_messages,
+    has_managed_membership, validate_chat_description, validate_chat_display_name, ChatId,
+    ChatKind, ChatRole, GROUP_INITIAL_MEMBERS_LIMIT,
+};
+use crate::models::message::{
+    validate_message_length, validate_message_text, validate_reaction, DeliveredMessage,
+    ImportedMessage, MessageId, MessageResponse, ScheduledMessageId, ScheduledMessageResponse,
+    SendPrivateMessageResponse,
+};
 use crate::models::resource::ResourceId;
 use crate::models::session::SessionId;
 use crate::models::user::{
-    validate_user_alias, validate_user_display_name, validate_user_password, UserId, UserRole,
+    can_manage_user, validate_user_alias, validate_user_display_name, validate_user_password,
+    InviteUserRequest, UserId, UserRole,
 };
 
 /// Number of sessions single account can have, older sessions will be silently removed when new are added,
 /// old sessions are determined by `access_token_expires_at`
 pub const MAX_SESSIONS_PER_USER: i32 = 100;
 
+/// Session `last_seen_at` is bumped at most this often, so idle timeout is accurate within it
+/// while requests don't write to the sessions table every time.
+pub const LAST_SEEN_UPDATE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Scheduled messages sent by single delivery round, the rest waits for the next one.
+pub const SCHEDULED_MESSAGES_DELIVERY_BATCH: i64 = 100;
+
+/// Number of users that can be invited with a single bulk request
+pub const MAX_BULK_INVITE_USERS: usize = 50;
+
+/// Number of messages that can be imported with a single request
+pub const MAX_IMPORTED_MESSAGES: usize = 1000;
+
+/// Number of chats single user can keep pinned to the top of their chat list
+pub const MAX_PINNED_CHATS: usize = 5;
+
 impl DbConnection {
     #[instrument(skip(self, initial_password))]
     pub async fn invite_user(
@@ -36,48 +69,87 @@ impl DbConnection {
         caller: UserId,
         alias: &str,
         initial_password: &str,
+        role: Option<UserRole>,
     ) -> Result<UserId, RequestError> {
         let mut transaction = self.pool().begin().await?;
-        let current_role = get_user_role(transaction.as_mut(), caller).await?.role;
-        let required_role = UserRole::Admin;
-        if current_role != required_role {
-            return Err(ValidationError::InsufficientPermissions {
-                current: current_role,
-                required: required_role,
-            }
-            .into());
-        }
-
+        ensure_admin(transaction.as_mut(), caller).await?;
         validate_user_alias(alias)?;
         validate_user_password(initial_password)?;
-        let existing_user_ids = list_user_ids(transaction.as_mut()).await?;
-        let password_hash = hash_password(initial_password);
-        let user_id = match create_user(
-            transaction.as_mut(),
-            alias,
+        let user_id = invite_user(
+            &mut transaction,
+            caller,
             alias,
-            &password_hash,
-            UserRole::Regular,
-            Some(caller),
+            initial_password,
+            role.unwrap_or(self.auth().default_invited_role),
+            self.auth(),
         )
-        .await
-        {
-            Ok(user_id) => user_id,
-            Err(error) => {
-                if let SqlxError::Database(db_error) = &error {
-                    if db_error.is_unique_violation() {
-                        return Err(ValidationError::AlreadyExists.into());
-                    }
-                }
-                return Err(error.into());
+        .await?;
+        transaction.commit().await?;
+        Ok(user_id)
+    }
+
+    /// Invite all given users at once, either every user is created or none of them.
+    #[instrument(skip_all, fields(caller, count = users.len()))]
+    pub async fn invite_users_bulk(
+        &self,
+        caller: UserId,
+        users: Vec<InviteUserRequest>,
+    ) -> Result<Vec<UserId>, RequestError> {
+        if users.is_empty() {
+            return Err(ValidationError::InvalidInput {
+                value: "users".to_string(),
+                reason: "at least one user should be provided".to_string(),
             }
-        };
-        let _ = create_with_self_chat(&mut transaction, user_id).await?;
-        for peer_user_id in existing_user_ids {
-            let _ = create_private_chat(&mut transaction, user_id, peer_user_id).await?;
+            .into());
+        }
+        if users.len() > MAX_BULK_INVITE_USERS {
+            return Err(ValidationError::LimitExceeded {
+                subject: "bulk invite".to_string(),
+                unit: "user".to_string(),
+                attempted: users.len(),
+                limit: MAX_BULK_INVITE_USERS,
+            }
+            .into());
+        }
+        let mut transaction = self.pool().begin().await?;
+        ensure_admin(transaction.as_mut(), caller).await?;
+        let mut aliases = Vec::with_capacity(users.len());
+        let mut colliding = Vec::new();
+        for user in &users {
+            validate_user_alias(&user.alias)?;
+            validate_user_password(&user.password)?;
+            if aliases.contains(&user.alias) && !colliding.contains(&user.alias) {
+                colliding.push(user.alias.clone());
+            }
+            aliases.push(user.alias.clone());
+        }
+        for alias in list_existing_aliases(transaction.as_mut(), &aliases).await? {
+            if !colliding.contains(&alias) {
+                colliding.push(alias);
+            }
+        }
+        if !colliding.is_empty() {
+            return Err(ValidationError::InvalidInput {
+                value: colliding.join(", "),
+                reason: "aliases are already taken or repeated".to_string(),
+            }
+            .into());
+        }
+        let mut user_ids = Vec::with_capacity(users.len());
+        for user in &users {
+            let user_id = invite_user(
+                &mut transaction,
+                caller,
+                &user.alias,
+                &user.password,
+                user.role.unwrap_or(self.auth().default_invited_role),
+                self.auth(),
+            )
+            .await?;
+            user_ids.push(user_id);
         }
         transaction.commit().await?;
-        Ok(user_id)
+        Ok(user_ids)
     }
 
     #[instrument(skip(self))]
@@ -96,6 +168,10 @@ impl DbConnection {
             }
             .into());
         }
+        if is_blocked_between(self.pool(), caller, recipient_id).await? {
+            debug!("attempt to create private chat with blocked user");
+            return Err(ValidationError::Forbidden.into());
+        }
         let mut transaction = self.pool().begin().await?;
         let chat_id = match create_private_chat(&mut transaction, caller, recipient_id).await {
             Ok(chat_id) => chat_id,
@@ -111,6 +187,7 @@ impl DbConnection {
             }
         };
         transaction.commit().await?;
+        self.private_chats().insert(caller, recipient_id, chat_id);
         Ok(chat_id)
     }
 
@@ -119,21 +196,14 @@ impl DbConnection {
         &self,
         caller: UserId,
         display_name: &str,
+        description: Option<&str>,
     ) -> Result<ChatId, RequestError> {
         // TODO: this helper is test-seeding oriented for now; add proper validation and role model before public API use
-        let mut transaction = self.pool().begin().await?;
-        let chat_id = create_chat(
-            transaction.as_mut(),
-            Some(display_name),
-            None,
-            ChatKind::Group,
-        )
-        .await?;
-        add_member_to_chat(transaction.as_mut(), caller, chat_id, ChatRole::Owner).await?;
-        transaction.commit().await?;
-        Ok(chat_id)
+        self.create_owned_chat(caller, display_name, description, ChatKind::Group)
+            .await
     }
 
+    /// All or nothing, a user already in the chat fails the whole batch with `AlreadyExists`.
     #[instrument(skip(self, members))]
     pub async fn add_members_to_group_chat(
         &self,
@@ -143,22 +213,295 @@ impl DbConnection {
     ) -> Result<(), RequestError> {
         // TODO: this helper is test-seeding oriented for now; enforce owner/admin checks and membership policy before public API use
         if !is_user_in_chat(self.pool(), chat_id, caller).await? {
-            return Err(ValidationError::NotFound.into());
+            return Err(self.chat_access_error(chat_id).await);
         }
         let mut transaction = self.pool().begin().await?;
         for member in members {
             if *member == caller {
                 continue;
             }
-            add_member_to_chat(transaction.as_mut(), *member, chat_id, ChatRole::Member).await?;
+            match add_member_to_chat(transaction.as_mut(), *member, chat_id, ChatRole::Member).await
+            {
+                Ok(()) => {}
+                Err(SqlxError::Database(db_error))
+                    if db_error.is_unique_violation()
+                        && db_error.constraint() == Some("chat_user_pkey") =>
+                {
+                    return Err(ValidationError::AlreadyExists.into());
+                }
+                Err(error) => return Err(error.into()),
+            }
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Ask to join group chat or channel known by id, e.g. from a shared link. Repeated request
+    /// keeps the original one and its place in the queue.
+    #[instrument(skip(self))]
+    pub async fn request_to_join_chat(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+    ) -> Result<(), RequestError> {
+        let Some(kind) = self.chat_exists(chat_id).await? else {
+            return Err(ValidationError::NotFound.into());
+        };
+        if !accepts_join_requests(kind) {
+            return Err(ValidationError::InvalidInput {
+                value: chat_id.to_string(),
+                reason: "only group chats and channels accept join requests".to_string(),
+            }
+            .into());
+        }
+        if is_user_in_chat(self.pool(), chat_id, caller).await? {
+            return Err(ValidationError::AlreadyExists.into());
+        }
+        create_join_request(self.pool(), chat_id, caller).await?;
+        debug!("requested to join chat");
+        Ok(())
+    }
+
+    /// Add requesting user to the chat as a plain member, available to owners and moderators.
+    #[instrument(skip(self))]
+    pub async fn approve_join_request(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<(), RequestError> {
+        let mut transaction = self.pool().begin().await?;
+        self.ensure_join_request_manager(&mut transaction, caller, chat_id)
+            .await?;
+        if !delete_join_request(transaction.as_mut(), chat_id, user_id).await? {
+            return Err(ValidationError::NotFound.into());
+        }
+        add_member_to_chat(transaction.as_mut(), user_id, chat_id, ChatRole::Member).await?;
+        transaction.commit().await?;
+        debug!("approved join request");
+        Ok(())
+    }
+
+    /// Drop join request without adding the user, they are free to ask again.
+    #[instrument(skip(self))]
+    pub async fn decline_join_request(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<(), RequestError> {
+        let mut transaction = self.pool().begin().await?;
+        self.ensure_join_request_manager(&mut transaction, caller, chat_id)
+            .await?;
+        if !delete_join_request(transaction.as_mut(), chat_id, user_id).await? {
+            return Err(ValidationError::NotFound.into());
+        }
+        transaction.commit().await?;
+        debug!("declined join request");
+        Ok(())
+    }
+
+    async fn ensure_join_request_manager(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        caller: UserId,
+        chat_id: ChatId,
+    ) -> Result<(), RequestError> {
+        let Some(role) = get_chat_role(transaction.as_mut(), chat_id, caller).await? else {
+            return Err(self.chat_access_error(chat_id).await);
+        };
+        if !can_manage_join_requests(role) {
+            return Err(ValidationError::Forbidden.into());
+        }
+        Ok(())
+    }
+
+    /// Remove member from group chat or channel. Owners and moderators can remove members and
+    /// moderators, only owners can remove other owners.
+    #[instrument(skip(self))]
+    pub async fn remove_member(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<(), RequestError> {
+        let mut transaction = self.pool().begin().await?;
+        self.ensure_member_manager(&mut transaction, caller, chat_id, user_id)
+            .await?;
+        remove_member_from_chat(transaction.as_mut(), chat_id, user_id).await?;
+        transaction.commit().await?;
+        debug!("removed member from chat");
+        Ok(())
+    }
+
+    /// Change role of group chat or channel member, same rules as for removal apply to both the
+    /// current and the new role, so moderators can neither demote nor appoint owners.
+    #[instrument(skip(self))]
+    pub async fn update_member_role(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+        user_id: UserId,
+        role: ChatRole,
+    ) -> Result<(), RequestError> {
+        let mut transaction = self.pool().begin().await?;
+        let caller_role = self
+            .ensure_member_manager(&mut transaction, caller, chat_id, user_id)
+            .await?;
+        if !can_manage_member(caller_role, role) {
+            return Err(ValidationError::Forbidden.into());
         }
+        update_chat_member_role(transaction.as_mut(), chat_id, user_id, role).await?;
         transaction.commit().await?;
+        debug!("updated member role");
         Ok(())
     }
 
+    /// Returns caller's role once they are allowed to manage the target's membership.
+    async fn ensure_member_manager(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        caller: UserId,
+        chat_id: ChatId,
+        user_id: UserId,
+    ) -> Result<ChatRole, RequestError> {
+        let Some(caller_role) = get_chat_role(transaction.as_mut(), chat_id, caller).await? else {
+            return Err(self.chat_access_error(chat_id).await);
+        };
+        if !has_managed_membership(get_chat_kind(transaction.as_mut(), chat_id).await?) {
+            return Err(ValidationError::InvalidInput {
+                value: chat_id.to_string(),
+                reason: "only group chats and channels have manageable members".to_string(),
+            }
+            .into());
+        }
+        if caller == user_id {
+            return Err(ValidationError::InvalidInput {
+                value: user_id.to_string(),
+                reason: "cannot manage own membership".to_string(),
+            }
+            .into());
+        }
+        let Some(target_role) =
+            lock_chat_member_role(transaction.as_mut(), chat_id, user_id).await?
+        else {
+            return Err(ValidationError::NotFound.into());
+        };
+        if !can_manage_member(caller_role, target_role) {
+            return Err(ValidationError::Forbidden.into());
+        }
+        Ok(caller_role)
+    }
+
+    /// Create channel with caller as the only member and owner, audience is added later.
+    #[instrument(skip(self))]
+    pub async fn create_channel_chat(
+        &self,
+        caller: UserId,
+        display_name: &str,
+        description: Option<&str>,
+    ) -> Result<ChatId, RequestError> {
+        validate_chat_display_name(display_name)?;
+        if let Some(description) = description {
+            validate_chat_description(description)?;
+        }
+        self.create_owned_chat(caller, display_name, description, ChatKind::Channel)
+            .await
+    }
+
+    /// Create group chat with caller as owner and members given by alias, all or nothing.
+    #[instrument(skip(self, members))]
+    pub async fn create_group_chat_with_members(
+        &self,
+        caller: UserId,
+        display_name: &str,
+        description: Option<&str>,
+        members: &[String],
+    ) -> Result<ChatId, RequestError> {
+        validate_chat_display_name(display_name)?;
+        if let Some(description) = description {
+            validate_chat_description(description)?;
+        }
+        if members.len() > GROUP_INITIAL_MEMBERS_LIMIT {
+            return Err(ValidationError::LimitExceeded {
+                subject: "group members".to_string(),
+                unit: "member".to_string(),
+                attempted: members.len(),
+                limit: GROUP_INITIAL_MEMBERS_LIMIT,
+            }
+            .into());
+        }
+        let mut transaction = self.pool().begin().await?;
+        let mut member_ids = Vec::with_capacity(members.len());
+        for alias in members {
+            let user_id =
+                map_not_found_as_none(get_user_id_by_alias(transaction.as_mut(), alias).await)?
+                    .ok_or_else(|| ValidationError::UserNotFound {
+                        alias: alias.clone(),
+                    })?
+                    .user_id;
+            if user_id != caller && !member_ids.contains(&user_id) {
+                member_ids.push(user_id);
+            }
+        }
+        let chat_id = self
+            .insert_owned_chat(
+                &mut transaction,
+                caller,
+                display_name,
+                description,
+                ChatKind::Group,
+            )
+            .await?;
+        for member in member_ids {
+            add_member_to_chat(transaction.as_mut(), member, chat_id, ChatRole::Member).await?;
+        }
+        transaction.commit().await?;
+        Ok(chat_id)
+    }
+
+    /// Create chat with caller as owner, subject to per-user limit of owned chats.
     #[instrument(skip(self))]
-    pub async fn create_channel_chat(&self) -> Result<(), RequestError> {
-        todo!()
+    async fn create_owned_chat(
+        &self,
+        caller: UserId,
+        display_name: &str,
+        description: Option<&str>,
+        kind: ChatKind,
+    ) -> Result<ChatId, RequestError> {
+        let mut transaction = self.pool().begin().await?;
+        let chat_id = self
+            .insert_owned_chat(&mut transaction, caller, display_name, description, kind)
+            .await?;
+        transaction.commit().await?;
+        Ok(chat_id)
+    }
+
+    async fn insert_owned_chat(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+        caller: UserId,
+        display_name: &str,
+        description: Option<&str>,
+        kind: ChatKind,
+    ) -> Result<ChatId, RequestError> {
+        if !lock_user(transaction.as_mut(), caller).await? {
+            return Err(ValidationError::NotFound.into());
+        }
+        let owned = count_owned_chats(transaction.as_mut(), caller).await? as usize;
+        if owned >= self.max_owned_chats {
+            return Err(ValidationError::LimitExceeded {
+                subject: "owned chats".to_string(),
+                unit: "chat".to_string(),
+                attempted: owned + 1,
+                limit: self.max_owned_chats,
+            }
+            .into());
+        }
+        let chat_id =
+            create_chat(transaction.as_mut(), Some(display_name), description, kind).await?;
+        add_member_to_chat(transaction.as_mut(), caller, chat_id, ChatRole::Owner).await?;
+        Ok(chat_id)
     }
 
     #[instrument(skip(self, current_password, new_password))]
@@ -178,17 +521,82 @@ impl DbConnection {
         if !verify_password(current_password, &creds.password_hash) {
             return Err(RequestError::BadCredentials);
         }
-        let new_hash = hash_password(new_password);
+        let new_hash = hash_password(new_password, self.auth());
         update_user_password(transaction.as_mut(), caller, &new_hash).await?;
+        record_audit(
+            transaction.as_mut(),
+            caller,
+            AuditAction::PasswordChanged,
+            Some(caller),
+        )
+        .await?;
         remove_sessions_for_user_except(transaction.as_mut(), caller, current_session).await?;
         transaction.commit().await?;
         Ok(())
     }
 
+    /// Manual recovery for a user who lost their password, every session of the user is dropped so
+    /// whoever knew the old password is logged out too.
+    /// Only regular users can be reset, see [`can_manage_user`].
+    #[instrument(skip(self, new_password))]
+    pub async fn admin_reset_password(
+        &self,
+        caller: UserId,
+        target_alias: &str,
+        new_password: &str,
+    ) -> Result<(), RequestError> {
+        validate_user_password(new_password)?;
+        let mut transaction = self.pool().begin().await?;
+        ensure_admin(transaction.as_mut(), caller).await?;
+        let target =
+            map_not_found_as_none(get_user_id_by_alias(transaction.as_mut(), target_alias).await)?
+                .ok_or_else(|| ValidationError::UserNotFound {
+                    alias: target_alias.to_string(),
+                })?
+                .user_id;
+        let target_role = get_user_role(transaction.as_mut(), target).await?.role;
+        if !can_manage_user(UserRole::Admin, target_role) {
+            return Err(ValidationError::Forbidden.into());
+        }
+        let new_hash = hash_password(new_password, self.auth());
+        update_user_password(transaction.as_mut(), target, &new_hash).await?;
+        record_audit(
+            transaction.as_mut(),
+            caller,
+            AuditAction::PasswordReset,
+            Some(target),
+        )
+        .await?;
+        remove_sessions_for_user(transaction.as_mut(), target).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
     #[instrument(skip(self))]
     pub async fn change_alias(&self, caller: UserId, new_alias: &str) -> Result<(), RequestError> {
         validate_user_alias(new_alias)?;
-        let updated = match update_user_alias(self.pool(), caller, new_alias).await {
+        let mut transaction = self.pool().begin().await?;
+        if !self.alias_change_cooldown.is_zero() {
+            let Some(changed_at) = lock_user_alias_changed_at(transaction.as_mut(), caller).await?
+            else {
+                return Err(ValidationError::NotFound.into());
+            };
+            if let Some(changed_at) = changed_at {
+                let elapsed = (current_time() - changed_at).to_std().unwrap_or_default();
+                let remaining = self.alias_change_cooldown.saturating_sub(elapsed);
+                if !remaining.is_zero() {
+                    return Err(ValidationError::InvalidInput {
+                        value: new_alias.to_string(),
+                        reason: format!(
+                            "alias can be changed again in {} seconds",
+                            remaining.as_secs_f64().ceil()
+                        ),
+                    }
+                    .into());
+                }
+            }
+        }
+        let updated = match update_user_alias(transaction.as_mut(), caller, new_alias).await {
             Ok(updated) => updated,
             Err(error) => {
                 if let SqlxError::Database(db_error) = &error {
@@ -202,6 +610,14 @@ impl DbConnection {
         if !updated {
             return Err(ValidationError::NotFound.into());
         }
+        record_audit(
+            transaction.as_mut(),
+            caller,
+            AuditAction::AliasChanged,
+            Some(caller),
+        )
+        .await?;
+        transaction.commit().await?;
         Ok(())
     }
 
@@ -219,6 +635,34 @@ impl DbConnection {
         Ok(())
     }
 
+    /// Add user to caller's block list, blocking already blocked user is a no-op.
+    #[instrument(skip(self))]
+    pub async fn block_user(&self, caller: UserId, user_id: UserId) -> Result<(), RequestError> {
+        if user_id == caller {
+            return Err(ValidationError::InvalidInput {
+                value: user_id.to_string(),
+                reason: "cannot block yourself".to_string(),
+            }
+            .into());
+        }
+        if get_user_profile(self.pool(), user_id).await?.is_none() {
+            return Err(ValidationError::NotFound.into());
+        }
+        create_user_block(self.pool(), caller, user_id).await?;
+        debug!("blocked user");
+        Ok(())
+    }
+
+    /// Remove user from caller's block list, fails when they weren't blocked.
+    #[instrument(skip(self))]
+    pub async fn unblock_user(&self, caller: UserId, user_id: UserId) -> Result<(), RequestError> {
+        if !delete_user_block(self.pool(), caller, user_id).await? {
+            return Err(ValidationError::NotFound.into());
+        }
+        debug!("unblocked user");
+        Ok(())
+    }
+
     #[instrument(skip(self))]
     pub async fn send_message(
         &self,
@@ -226,26 +670,377 @@ impl DbConnection {
         chat_id: ChatId,
         text: &str,
     ) -> Result<MessageId, RequestError> {
-        let mut transaction = self.pool().begin().await?;
-        if !is_user_in_chat(transaction.as_mut(), chat_id, caller).await? {
-            debug!("attempt to send message but user is not in chat");
-            return Err(ValidationError::NotFound.into());
-        }
-        let message_id = create_message(
-            transaction.as_mut(),
-            chat_id,
-            caller,
-            Some(text),
-            None,
-            None,
-        )
-        .await?;
-        update_chat_last_message(transaction.as_mut(), chat_id, message_id).await?;
-        transaction.commit().await?;
-        debug!("sent message in chat");
-        Ok(message_id)
+        self.send_message_with_reply(caller, chat_id, text, None)
+            .await
     }
 
+    #[instrument(skip(self))]
+    pub async fn send_message_with_reply(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+        text: &str,
+        reply_to: Option<MessageId>,
+    ) -> Result<MessageId, RequestError> {
+        let message = self
+            .send_message_with_response(caller, chat_id, text, reply_to)
+            .await?;
+        Ok(message.id)
+    }
+
+    /// Send message and return it in the same shape as listings, so clients can render it right away.
+    #[instrument(skip(self))]
+    pub async fn send_message_with_response(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+        text: &str,
+        reply_to: Option<MessageId>,
+    ) -> Result<MessageResponse, RequestError> {
+        // Only text messages can be sent for now, so text is the required content
+        validate_message_text(text)?;
+        let mut transaction = self.pool().begin().await?;
+        let Some(role) = get_chat_role(transaction.as_mut(), chat_id, caller).await? else {
+            debug!("attempt to send message but user is not in chat");
+            return Err(self.chat_access_error(chat_id).await);
+        };
+        let kind = get_chat_kind(transaction.as_mut(), chat_id).await?;
+        if !can_post_messages(kind, role) {
+            debug!("attempt to send message without posting rights");
+            return Err(ValidationError::Forbidden.into());
+        }
+        if kind == ChatKind::Private
+            && is_private_chat_blocked(transaction.as_mut(), chat_id).await?
+        {
+            debug!("attempt to send message to private chat with blocked user");
+            return Err(ValidationError::Forbidden.into());
+        }
+        validate_message_length(text, self.message_length_limits.for_kind(kind))?;
+        let mut reply_snapshot = None;
+        if let Some(reply_to) = reply_to {
+            let Some(replied) = get_message(transaction.as_mut(), chat_id, reply_to).await? else {
+                return Err(ValidationError::InvalidInput {
+                    value: reply_to.to_string(),
+                    reason: "replied message doesn't exist in this chat".to_string(),
+                }
+                .into());
+            };
+            reply_snapshot = replied.text;
+        }
+        let message_id = create_message(
+            transaction.as_mut(),
+            chat_id,
+            caller,
+            Some(text),
+            reply_to,
+            reply_snapshot.as_deref(),
+            None,
+        )
+        .await?;
+        update_chat_last_message(transaction.as_mut(), chat_id, message_id).await?;
+        let message = get_message(transaction.as_mut(), chat_id, message_id)
+            .await?
+            .ok_or(SqlxError::RowNotFound)?;
+        transaction.commit().await?;
+        debug!("sent message in chat");
+        Ok(message)
+    }
+
+    /// Store message to be sent at `send_at`, checked like a regular send at scheduling time.
+    #[instrument(skip(self, text))]
+    pub async fn schedule_message(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+        text: &str,
+        send_at: DateTime<Utc>,
+    ) -> Result<ScheduledMessageResponse, RequestError> {
+        validate_message_text(text)?;
+        let Some(role) = get_chat_role(self.pool(), chat_id, caller).await? else {
+            return Err(self.chat_access_error(chat_id).await);
+        };
+        let kind = get_chat_kind(self.pool(), chat_id).await?;
+        if !can_post_messages(kind, role) {
+            return Err(ValidationError::Forbidden.into());
+        }
+        validate_message_length(text, self.message_length_limits.for_kind(kind))?;
+        let scheduled =
+            create_scheduled_message(self.pool(), chat_id, caller, text, send_at).await?;
+        debug!("scheduled message");
+        Ok(scheduled)
+    }
+
+    /// Drop own scheduled message before it is sent.
+    #[instrument(skip(self))]
+    pub async fn cancel_scheduled_message(
+        &self,
+        caller: UserId,
+        scheduled_message_id: ScheduledMessageId,
+    ) -> Result<(), RequestError> {
+        if !delete_scheduled_message(self.pool(), scheduled_message_id, Some(caller)).await? {
+            return Err(ValidationError::NotFound.into());
+        }
+        Ok(())
+    }
+
+    /// Send scheduled messages which are due, at most one batch per call. Picked rows are locked
+    /// with `SKIP LOCKED`, so concurrent rounds never send the same message twice. Messages whose
+    /// author can't post in the chat anymore are dropped, as are the ones failing to send, each
+    /// message is sent under its own savepoint so a failure doesn't hold back the rest.
+    #[instrument(skip(self))]
+    pub async fn deliver_due_scheduled_messages(
+        &self,
+    ) -> Result<Vec<DeliveredMessage>, RequestError> {
+        let mut transaction = self.pool().begin().await?;
+        let due =
+            lock_due_scheduled_messages(transaction.as_mut(), SCHEDULED_MESSAGES_DELIVERY_BATCH)
+                .await?;
+        let mut delivered = Vec::with_capacity(due.len());
+        for scheduled in due {
+            delete_scheduled_message(transaction.as_mut(), scheduled.id, None).await?;
+            let mut savepoint = transaction.as_mut().begin().await?;
+            match deliver_scheduled_message(&mut savepoint, &scheduled).await {
+                Ok(message) => {
+                    savepoint.commit().await?;
+                    delivered.extend(message);
+                }
+                Err(e) => {
+                    savepoint.rollback().await?;
+                    error!(
+                        scheduled_message_id = scheduled.id,
+                        "dropped scheduled message that failed to send: {e}"
+                    );
+                }
+            }
+        }
+        transaction.commit().await?;
+        Ok(delivered)
+    }
+
+    /// Admin-only migration of history from another messenger, all messages are inserted at once
+    /// ordered by their original time. Ids follow insertion, so only chats without messages accept
+    /// an import, otherwise imported ones would be listed after newer ones.
+    #[instrument(skip(self, messages))]
+    pub async fn import_messages(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+        mut messages: Vec<ImportedMessage>,
+    ) -> Result<Vec<MessageId>, RequestError> {
+        if messages.len() > MAX_IMPORTED_MESSAGES {
+            return Err(ValidationError::LimitExceeded {
+                subject: "message import".to_string(),
+                unit: "message".to_string(),
+                attempted: messages.len(),
+                limit: MAX_IMPORTED_MESSAGES,
+            }
+            .into());
+        }
+        let now = current_time();
+        for message in &messages {
+            validate_message_text(&message.text)?;
+            if message.created_at > now {
+                return Err(ValidationError::InvalidInput {
+                    value: message.created_at.to_rfc3339(),
+                    reason: "imported message can't be from the future".to_string(),
+                }
+                .into());
+            }
+        }
+        let mut transaction = self.pool().begin().await?;
+        ensure_admin(transaction.as_mut(), caller).await?;
+        if chat_exists(transaction.as_mut(), chat_id).await?.is_none() {
+            return Err(ValidationError::NotFound.into());
+        }
+        if count_chat_messages(transaction.as_mut(), chat_id).await? > 0 {
+            return Err(ValidationError::InvalidInput {
+                value: chat_id.to_string(),
+                reason: "messages can only be imported into a chat without messages".to_string(),
+            }
+            .into());
+        }
+        let mut authors: Vec<UserId> = messages.iter().map(|message| message.user_id).collect();
+        authors.sort_unstable();
+        authors.dedup();
+        let members = list_chat_member_ids_among(transaction.as_mut(), chat_id, &authors).await?;
+        if let Some(outsider) = authors.iter().find(|author| !members.contains(author)) {
+            return Err(ValidationError::InvalidInput {
+                value: outsider.to_string(),
+                reason: "message author is not a member of the chat".to_string(),
+            }
+            .into());
+        }
+        messages.sort_by_key(|message| message.created_at);
+        let message_ids = create_messages_batch(transaction.as_mut(), chat_id, &messages).await?;
+        restore_chat_last_message(transaction.as_mut(), chat_id).await?;
+        transaction.commit().await?;
+        info!("imported {} messages", message_ids.len());
+        Ok(message_ids)
+    }
+
+    /// Send message to the private chat with recipient, creating the chat on first contact.
+    /// Both happen in one transaction, so a failed send leaves no empty chat behind.
+    #[instrument(skip(self))]
+    pub async fn send_private_message(
+        &self,
+        caller: UserId,
+        recipient_alias: &str,
+        text: &str,
+    ) -> Result<SendPrivateMessageResponse, RequestError> {
+        validate_message_text(text)?;
+        validate_message_length(text, self.message_length_limits.private)?;
+        let recipient_id =
+            map_not_found_as_none(get_user_id_by_alias(self.pool(), recipient_alias).await)?
+                .ok_or_else(|| ValidationError::UserNotFound {
+                    alias: recipient_alias.to_string(),
+                })?
+                .user_id;
+        if recipient_id == caller {
+            return Err(ValidationError::InvalidInput {
+                value: recipient_alias.to_string(),
+                reason: "cannot send private message to yourself".to_string(),
+            }
+            .into());
+        }
+        if is_blocked_between(self.pool(), caller, recipient_id).await? {
+            debug!("attempt to send private message to blocked user");
+            return Err(ValidationError::Forbidden.into());
+        }
+        let mut transaction = self.pool().begin().await?;
+        let chat_id = match get_private_chat_id(transaction.as_mut(), caller, recipient_id).await? {
+            Some(chat_id) => chat_id,
+            None => match create_private_chat(&mut transaction, caller, recipient_id).await {
+                Ok(chat_id) => chat_id,
+                Err(SqlxError::Database(db_error))
+                    if db_error.is_unique_violation()
+                        && db_error.constraint() == Some("private_chat_pair_unique") =>
+                {
+                    // Concurrent first contact created the chat, retry finds it
+                    return Err(RequestError::Interrupted);
+                }
+                Err(error) => return Err(error.into()),
+            },
+        };
+        let message_id = create_message(
+            transaction.as_mut(),
+            chat_id,
+            caller,
+            Some(text),
+            None,
+            None,
+            None,
+        )
+        .await?;
+        update_chat_last_message(transaction.as_mut(), chat_id, message_id).await?;
+        let message = get_message(transaction.as_mut(), chat_id, message_id)
+            .await?
+            .ok_or(SqlxError::RowNotFound)?;
+        transaction.commit().await?;
+        self.private_chats().insert(caller, recipient_id, chat_id);
+        Ok(SendPrivateMessageResponse {
+            chat_id,
+            message_id,
+            message,
+        })
+    }
+
+    /// Swap attached resource of own message, e.g. when user re-uploads an image. Only while the
+    /// caller is still a member of the message's chat.
+    #[instrument(skip(self))]
+    pub async fn replace_message_resource(
+        &self,
+        caller: UserId,
+        message_id: MessageId,
+        new_resource_id: ResourceId,
+    ) -> Result<(), RequestError> {
+        let updated =
+            update_message_resource(self.pool(), caller, message_id, new_resource_id).await?;
+        if !updated {
+            return Err(ValidationError::NotFound.into());
+        }
+        Ok(())
+    }
+
+    /// Replace text of own message, returns it in the same shape as listings.
+    #[instrument(skip(self, text))]
+    pub async fn edit_message(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+        message_id: MessageId,
+        text: &str,
+    ) -> Result<MessageResponse, RequestError> {
+        validate_message_text(text)?;
+        let mut transaction = self.pool().begin().await?;
+        if !is_user_in_chat(transaction.as_mut(), chat_id, caller).await? {
+            return Err(self.chat_access_error(chat_id).await);
+        }
+        let kind = get_chat_kind(transaction.as_mut(), chat_id).await?;
+        validate_message_length(text, self.message_length_limits.for_kind(kind))?;
+        if !update_message_text(transaction.as_mut(), caller, chat_id, message_id, text).await? {
+            return Err(ValidationError::NotFound.into());
+        }
+        let message = get_message(transaction.as_mut(), chat_id, message_id)
+            .await?
+            .ok_or(SqlxError::RowNotFound)?;
+        transaction.commit().await?;
+        Ok(message)
+    }
+
+    /// Delete own message, replies to it keep their quoted snapshot.
+    #[instrument(skip(self))]
+    pub async fn delete_message(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> Result<(), RequestError> {
+        let mut transaction = self.pool().begin().await?;
+        if !is_user_in_chat(transaction.as_mut(), chat_id, caller).await? {
+            return Err(self.chat_access_error(chat_id).await);
+        }
+        if !delete_message(transaction.as_mut(), caller, chat_id, message_id).await? {
+            return Err(ValidationError::NotFound.into());
+        }
+        restore_chat_last_message(transaction.as_mut(), chat_id).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub async fn create_resource(
+        &self,
+        caller: UserId,
+        url: &str,
+    ) -> Result<ResourceId, RequestError> {
+        Ok(create_resource(self.pool(), caller, url).await?)
+    }
+
+    #[instrument(skip(self))]
+    pub async fn add_reaction(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+        message_id: MessageId,
+        emoji: &str,
+    ) -> Result<(), RequestError> {
+        validate_reaction(emoji)?;
+        let mut transaction = self.pool().begin().await?;
+        if !is_user_in_chat(transaction.as_mut(), chat_id, caller).await? {
+            return Err(self.chat_access_error(chat_id).await);
+        }
+        if get_message(transaction.as_mut(), chat_id, message_id)
+            .await?
+            .is_none()
+        {
+            return Err(ValidationError::NotFound.into());
+        }
+        create_reaction(transaction.as_mut(), message_id, caller, emoji).await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Cursor only moves forward, marking an older message is accepted and changes nothing.
     #[instrument(skip(self))]
     pub async fn mark_chat_read(
         &self,
@@ -253,6 +1048,14 @@ impl DbConnection {
         chat_id: ChatId,
         up_to_message_id: MessageId,
     ) -> Result<(), RequestError> {
+        if get_message_chat_id(self.pool(), up_to_message_id).await? != Some(chat_id) {
+            return Err(ValidationError::InvalidInput {
+                value: up_to_message_id.to_string(),
+                reason: "message doesn't belong to the chat".to_string(),
+            }
+            .into());
+        }
+        // Scoped to caller's membership, and rechecks the message as it may be deleted in between
         let updated =
             update_chat_read_cursor(self.pool(), caller, chat_id, up_to_message_id).await?;
         if !updated {
@@ -261,12 +1064,53 @@ impl DbConnection {
         Ok(())
     }
 
+    #[instrument(skip(self))]
+    pub async fn pin_chat(&self, caller: UserId, chat_id: ChatId) -> Result<(), RequestError> {
+        let mut transaction = self.pool().begin().await?;
+        if !lock_user(transaction.as_mut(), caller).await? {
+            return Err(ValidationError::NotFound.into());
+        }
+        let pinned =
+            count_other_pinned_chats(transaction.as_mut(), caller, chat_id).await? as usize;
+        if pinned >= MAX_PINNED_CHATS {
+            return Err(ValidationError::LimitExceeded {
+                subject: "pinned chats".to_string(),
+                unit: "chat".to_string(),
+                attempted: pinned + 1,
+                limit: MAX_PINNED_CHATS,
+            }
+            .into());
+        }
+        if !update_chat_pin(transaction.as_mut(), caller, chat_id, Some(current_time())).await? {
+            return Err(ValidationError::NotFound.into());
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    #[instrument(skip(self))]
+    pub async fn unpin_chat(&self, caller: UserId, chat_id: ChatId) -> Result<(), RequestError> {
+        if !update_chat_pin(self.pool(), caller, chat_id, None).await? {
+            return Err(ValidationError::NotFound.into());
+        }
+        Ok(())
+    }
+
+    #[instrument(skip(self, password))]
+    pub async fn login(&self, alias: &str, password: &str) -> Result<LoginResponse, RequestError> {
+        self.login_with_session(alias, password, None).await
+    }
+
+    /// Login reusing `session_id` of an earlier login when given, so frequent re-logins on the
+    /// same device don't pile up sessions. Session of another user or an unknown one is
+    /// `NotFound`, the client should drop it and log in without it.
     #[instrument(skip(self, password))]
-    pub async fn login(
+    pub async fn login_with_session(
         &self,
         alias: &str,
         password: &str,
-    ) -> Result<TokenExchangePayload, RequestError> {
+        session_id: Option<SessionId>,
+    ) -> Result<LoginResponse, RequestError> {
         let mut transaction = self.pool().begin().await?;
         let Some(creds) = get_user_credentials_by_alias(transaction.as_mut(), alias).await? else {
             return Err(RequestError::BadCredentials);
@@ -280,33 +1124,121 @@ impl DbConnection {
         let access_token_expires_at = new_access_token_expiration();
         let refresh_token_hash = hash_session_token(&refresh_token);
         let access_token_hash = hash_session_token(&access_token);
+        if let Some(session_id) = session_id {
+            if !is_session_of_user(transaction.as_mut(), session_id, creds.user_id).await? {
+                return Err(ValidationError::NotFound.into());
+            }
+            let Some(refresh_counter) =
+                lock_session_refresh_counter(transaction.as_mut(), session_id).await?
+            else {
+                return Err(ValidationError::NotFound.into());
+            };
+            if !update_session_tokens(
+                transaction.as_mut(),
+                session_id,
+                &refresh_token_hash,
+                &refresh_token_expires_at,
+                &access_token_hash,
+                &access_token_expires_at,
+                refresh_counter,
+            )
+            .await?
+            {
+                return Err(RequestError::Interrupted);
+            }
+            revive_session(transaction.as_mut(), session_id).await?;
+            touch_session(transaction.as_mut(), session_id).await?;
+            let profile = get_whoami_by_user_id(transaction.as_mut(), creds.user_id).await?;
+            transaction.commit().await?;
+            return Ok(LoginResponse {
+                tokens: TokenExchangePayload::new(
+                    session_id,
+                    refresh_token,
+                    refresh_token_expires_at,
+                    access_token,
+                    access_token_expires_at,
+                ),
+                profile,
+            });
+        }
         let session_id = create_session(
             transaction.as_mut(),
             creds.user_id,
-            &IpNetwork::from(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
-            Some("Google Pixel"),
-            Some("Android 6.0"),
-            Some("Walrus Messenger for Android 0.0.1"),
-            &refresh_token_hash,
-            &refresh_token_expires_at,
-            &access_token_hash,
-            &access_token_expires_at,
+            NewSession {
+                ip: &IpNetwork::from(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
+                device_name: Some("Google Pixel"),
+                os_version: Some("Android 6.0"),
+                app_version: Some("Walrus Messenger for Android 0.0.1"),
+                refresh_token_hash: &refresh_token_hash,
+                refresh_token_expires_at: &refresh_token_expires_at,
+                access_token_hash: &access_token_hash,
+                access_token_expires_at: &access_token_expires_at,
+            },
         )
         .await?;
-        trim_sessions_for_user(transaction.as_mut(), creds.user_id, MAX_SESSIONS_PER_USER).await?;
-        transaction.commit().await?;
-        Ok(TokenExchangePayload::new(
+        trim_sessions_for_user(
+            transaction.as_mut(),
+            creds.user_id,
             session_id,
-            refresh_token,
-            refresh_token_expires_at,
-            access_token,
-            access_token_expires_at,
-        ))
+            MAX_SESSIONS_PER_USER,
+        )
+        .await?;
+        let profile = get_whoami_by_user_id(transaction.as_mut(), creds.user_id).await?;
+        transaction.commit().await?;
+        Ok(LoginResponse {
+            tokens: TokenExchangePayload::new(
+                session_id,
+                refresh_token,
+                refresh_token_expires_at,
+                access_token,
+                access_token_expires_at,
+            ),
+            profile,
+        })
     }
 
     #[instrument(skip(self))]
     pub async fn logout(&self, session_id: SessionId) -> Result<(), RequestError> {
-        Ok(remove_session(self.pool(), session_id).await?)
+        if self.logout_grace().is_zero() {
+            return Ok(remove_session(self.pool(), session_id).await?);
+        }
+        Ok(mark_session_logged_out(self.pool(), session_id).await?)
+    }
+
+    /// Mark session as active right now, for long-lived clients keeping it from going idle.
+    #[instrument(skip(self))]
+    pub async fn touch_session(&self, session_id: SessionId) -> Result<(), RequestError> {
+        Ok(touch_session(self.pool(), session_id).await?)
+    }
+
+    /// Revive session logged out by mistake, possible only within logout grace window.
+    #[instrument(skip(self, refresh_token))]
+    pub async fn undo_logout(
+        &self,
+        session_id: SessionId,
+        refresh_token: &[u8],
+    ) -> Result<(), RequestError> {
+        let Some(session) = get_logged_out_session(self.pool(), session_id).await? else {
+            return Err(RequestError::BadCredentials);
+        };
+        if !verify_session_token(refresh_token, &session.refresh_token_hash) {
+            return Err(RequestError::BadCredentials);
+        }
+        let grace = chrono::Duration::from_std(self.logout_grace()).unwrap_or_default();
+        if session.logged_out_at + grace <= current_time() {
+            return Err(RequestError::Expired);
+        }
+        if !revive_session(self.pool(), session_id).await? {
+            return Err(RequestError::Interrupted);
+        }
+        Ok(())
+    }
+
+    /// Hard-delete sessions whose logout grace window has passed, returns number of removed ones.
+    #[instrument(skip(self))]
+    pub async fn purge_logged_out_sessions(&self) -> Result<u64, RequestError> {
+        let grace = chrono::Duration::from_std(self.logout_grace()).unwrap_or_default();
+        Ok(remove_logged_out_sessions(self.pool(), current_time() - grace).await?)
     }
 
     pub async fn refresh_session(
@@ -314,8 +1246,10 @@ impl DbConnection {
         session_id: SessionId,
         refresh_token: &[u8],
     ) -> Result<TokenExchangePayload, RequestError> {
+        // Row lock rather than REPEATABLE READ, which would fail the losing refresh with a
+        // serialization error instead of a plain credentials mismatch.
         let mut transaction = self.pool().begin().await?;
-        let Some(from_db) = get_refresh_token(transaction.as_mut(), session_id).await? else {
+        let Some(from_db) = lock_refresh_token(transaction.as_mut(), session_id).await? else {
             return Err(RequestError::BadCredentials);
         };
         if !verify_session_token(refresh_token, &from_db.refresh_token_hash) {
@@ -341,7 +1275,7 @@ impl DbConnection {
         )
         .await?;
         if !updated {
-            // if refresh_counter didn't match, concurrent update likely happened
+            // unreachable while the row is locked, kept as a guard against changes in locking
             return Err(RequestError::Interrupted);
         }
         transaction.commit().await?;
@@ -355,163 +1289,701 @@ impl DbConnection {
     }
 }
 
-#[instrument(skip(executor, password_hash))]
-pub(super) async fn create_user<'a, E: PgExecutor<'a>>(
+#[instrument(skip(executor))]
+pub(super) async fn ensure_admin<'a, E: PgExecutor<'a>>(
+    executor: E,
+    caller: UserId,
+) -> Result<(), RequestError> {
+    let current_role = get_user_role(executor, caller).await?.role;
+    let required_role = UserRole::Admin;
+    if current_role != required_role {
+        return Err(ValidationError::InsufficientPermissions {
+            current: current_role,
+            required: required_role,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// Create user along with chat with self and private chats with every existing user, input is
+/// expected to be validated by the caller.
+#[instrument(skip(transaction, initial_password, auth))]
+pub(super) async fn invite_user<'a>(
+    transaction: &mut Transaction<'a, Postgres>,
+    caller: UserId,
+    alias: &str,
+    initial_password: &str,
+    role: UserRole,
+    auth: &AuthConfig,
+) -> Result<UserId, RequestError> {
+    let existing_user_ids = list_user_ids(transaction.as_mut()).await?;
+    let password_hash = hash_password(initial_password, auth);
+    let user_id = match create_user(
+        transaction.as_mut(),
+        alias,
+        alias,
+        &password_hash,
+        role,
+        Some(caller),
+    )
+    .await
+    {
+        Ok(user_id) => user_id,
+        Err(error) => {
+            if let SqlxError::Database(db_error) = &error {
+                if db_error.is_unique_violation() {
+                    return Err(ValidationError::AlreadyExists.into());
+                }
+            }
+            return Err(error.into());
+        }
+    };
+    let _ = create_with_self_chat(transaction, user_id).await?;
+    for peer_user_id in existing_user_ids {
+        let _ = create_private_chat(transaction, user_id, peer_user_id).await?;
+    }
+    record_audit(
+        transaction.as_mut(),
+        caller,
+        AuditAction::UserInvited,
+        Some(user_id),
+    )
+    .await?;
+    Ok(user_id)
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn record_audit<'a, E: PgExecutor<'a>>(
+    executor: E,
+    actor: UserId,
+    action: AuditAction,
+    target: Option<UserId>,
+) -> Result<(), SqlxError> {
+    sqlx::query(
+        "
+        INSERT INTO audit_log (actor_user_id, action, target_user_id, created_at)
+        VALUES ($1, $2, $3, current_timestamp);
+    ",
+    )
+    .bind(actor)
+    .bind(action)
+    .bind(target)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[instrument(skip(executor, password_hash))]
+pub(super) async fn create_user<'a, E: PgExecutor<'a>>(
+    executor: E,
+    alias: &str,
+    display_name: &str,
+    password_hash: &str,
+    role: UserRole,
+    invited_by: Option<UserId>,
+) -> Result<UserId, SqlxError> {
+    let result = sqlx::query(
+        "
+        INSERT INTO users (alias, display_name, password_hash, role, invited_by, created_at)
+        VALUES ($1, $2, $3, $4, $5, current_timestamp) RETURNING id;
+    ",
+    )
+    .bind(alias)
+    .bind(display_name)
+    .bind(password_hash)
+    .bind(role)
+    .bind(invited_by)
+    .fetch_one(executor)
+    .await?
+    .try_get("id")?;
+    info!("created user with id: {}", result);
+    Ok(result)
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn create_chat<'a, E: PgExecutor<'a>>(
+    executor: E,
+    display_name: Option<&str>,
+    description: Option<&str>,
+    kind: ChatKind,
+) -> Result<ChatId, SqlxError> {
+    let result = sqlx::query(
+        "
+        INSERT INTO chats (display_name, description, kind, created_at)
+        VALUES ($1, $2, $3, current_timestamp) RETURNING id;
+    ",
+    )
+    .bind(display_name)
+    .bind(description)
+    .bind(kind)
+    .fetch_one(executor)
+    .await?
+    .try_get("id")?;
+    info!("created new chat with id: {}", result);
+    Ok(result)
+}
+
+#[instrument(skip(executor, password_hash))]
+pub(super) async fn update_user_password<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+    password_hash: &str,
+) -> Result<(), SqlxError> {
+    sqlx::query(
+        "
+        UPDATE users
+        SET password_hash = $1
+        WHERE id = $2;
+    ",
+    )
+    .bind(password_hash)
+    .bind(user_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// User row stays locked until the transaction ends, so concurrent per-user limit checks are
+/// serialized and each one counts what the previous one committed. `false` when user doesn't exist.
+#[instrument(skip(executor))]
+async fn lock_user<'a, E: PgExecutor<'a>>(executor: E, user_id: UserId) -> Result<bool, SqlxError> {
+    let locked = sqlx::query(
+        "
+        SELECT 1 FROM users WHERE id = $1 FOR UPDATE;
+    ",
+    )
+    .bind(user_id)
+    .fetch_optional(executor)
+    .await?;
+    Ok(locked.is_some())
+}
+
+/// Last alias change of the user, row stays locked until the transaction ends so concurrent
+/// changes can't both pass the cooldown check. Outer `None` when user doesn't exist.
+#[instrument(skip(executor))]
+async fn lock_user_alias_changed_at<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+) -> Result<Option<Option<DateTime<Utc>>>, SqlxError> {
+    sqlx::query_scalar(
+        "
+        SELECT alias_changed_at FROM users WHERE id = $1 FOR UPDATE;
+    ",
+    )
+    .bind(user_id)
+    .fetch_optional(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn update_user_alias<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+    new_alias: &str,
+) -> Result<bool, SqlxError> {
+    let result = sqlx::query(
+        "
+        UPDATE users
+        SET alias = $1, alias_changed_at = current_timestamp
+        WHERE id = $2;
+    ",
+    )
+    .bind(new_alias)
+    .bind(user_id)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() != 0)
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn update_user_display_name<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+    new_display_name: &str,
+) -> Result<bool, SqlxError> {
+    let result = sqlx::query(
+        "
+        UPDATE users
+        SET display_name = $1
+        WHERE id = $2;
+    ",
+    )
+    .bind(new_display_name)
+    .bind(user_id)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() != 0)
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn add_member_to_chat<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+    chat_id: ChatId,
+    role: ChatRole,
+) -> Result<(), SqlxError> {
+    sqlx::query(
+        "
+        INSERT INTO chats_members (user_id, chat_id, role)
+        VALUES ($1, $2, $3);
+    ",
+    )
+    .bind(user_id)
+    .bind(chat_id)
+    .bind(role)
+    .execute(executor)
+    .await?;
+    info!("added member to chat");
+    Ok(())
+}
+
+#[instrument(skip(executor))]
+async fn lock_chat_member_role<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    user_id: UserId,
+) -> Result<Option<ChatRole>, SqlxError> {
+    sqlx::query_scalar(
+        "
+        SELECT role FROM chats_members WHERE chat_id = $1 AND user_id = $2 FOR UPDATE;
+    ",
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .fetch_optional(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn remove_member_from_chat<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    user_id: UserId,
+) -> Result<(), SqlxError> {
+    sqlx::query(
+        "
+        DELETE FROM chats_members WHERE chat_id = $1 AND user_id = $2;
+    ",
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .execute(executor)
+    .await?;
+    info!("removed member from chat");
+    Ok(())
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn update_chat_member_role<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    user_id: UserId,
+    role: ChatRole,
+) -> Result<(), SqlxError> {
+    sqlx::query(
+        "
+        UPDATE chats_members SET role = $3 WHERE chat_id = $1 AND user_id = $2;
+    ",
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .bind(role)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn create_join_request<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    user_id: UserId,
+) -> Result<(), SqlxError> {
+    sqlx::query(
+        "
+        INSERT INTO chat_join_requests (chat_id, user_id, created_at)
+        VALUES ($1, $2, current_timestamp)
+        ON CONFLICT DO NOTHING;
+    ",
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn delete_join_request<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    user_id: UserId,
+) -> Result<bool, SqlxError> {
+    let result = sqlx::query(
+        "
+        DELETE FROM chat_join_requests WHERE chat_id = $1 AND user_id = $2;
+    ",
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() != 0)
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn create_message<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    user_id: UserId,
+    text: Option<&str>,
+    reply_to: Option<MessageId>,
+    reply_snapshot: Option<&str>,
+    resource_id: Option<ResourceId>,
+) -> Result<MessageId, SqlxError> {
+    let result = sqlx::query(
+        "
+        INSERT INTO messages (chat_id, user_id, text, reply_to, reply_snapshot, resource_id, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, current_timestamp) RETURNING id;
+    ",
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .bind(text)
+    .bind(reply_to)
+    .bind(reply_snapshot)
+    .bind(resource_id)
+    .fetch_one(executor)
+    .await?
+    .try_get("id")?;
+    debug!("created message with id: {}", result);
+    Ok(result)
+}
+
+/// Single multi-row insert, ids are assigned in the order of `messages`.
+#[instrument(skip(executor, messages))]
+pub(super) async fn create_messages_batch<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    messages: &[ImportedMessage],
+) -> Result<Vec<MessageId>, SqlxError> {
+    let user_ids: Vec<UserId> = messages.iter().map(|message| message.user_id).collect();
+    let texts: Vec<&str> = messages
+        .iter()
+        .map(|message| message.text.as_str())
+        .collect();
+    let created_at: Vec<DateTime<Utc>> =
+        messages.iter().map(|message| message.created_at).collect();
+    let mut ids: Vec<MessageId> = sqlx::query_scalar(
+        "
+        INSERT INTO messages (chat_id, user_id, text, created_at)
+        SELECT $1, imported.user_id, imported.text, imported.created_at
+        FROM UNNEST($2::int[], $3::text[], $4::timestamptz[])
+            WITH ORDINALITY AS imported(user_id, text, created_at, position)
+        ORDER BY imported.position
+        RETURNING id;
+    ",
+    )
+    .bind(chat_id)
+    .bind(user_ids)
+    .bind(texts)
+    .bind(created_at)
+    .fetch_all(executor)
+    .await?;
+    // RETURNING order is not guaranteed, ids themselves are
+    ids.sort_unstable();
+    Ok(ids)
+}
+
+#[instrument(skip(executor, text))]
+pub(super) async fn create_scheduled_message<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    user_id: UserId,
+    text: &str,
+    send_at: DateTime<Utc>,
+) -> Result<ScheduledMessageResponse, SqlxError> {
+    sqlx::query_as(
+        "
+        INSERT INTO scheduled_messages (chat_id, user_id, text, send_at, created_at)
+        VALUES ($1, $2, $3, $4, current_timestamp)
+        RETURNING id, chat_id, user_id, text, send_at, created_at;
+    ",
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .bind(text)
+    .bind(send_at)
+    .fetch_one(executor)
+    .await
+}
+
+/// Turns scheduled message into a regular one, `None` when its author can't post in the chat
+/// anymore.
+async fn deliver_scheduled_message(
+    transaction: &mut Transaction<'_, Postgres>,
+    scheduled: &ScheduledMessageResponse,
+) -> Result<Option<DeliveredMessage>, SqlxError> {
+    let (chat_id, author) = (scheduled.chat_id, scheduled.user_id);
+    let Some(role) = get_chat_role(transaction.as_mut(), chat_id, author).await? else {
+        debug!("dropped scheduled message of user who left the chat");
+        return Ok(None);
+    };
+    let kind = get_chat_kind(transaction.as_mut(), chat_id).await?;
+    if !can_post_messages(kind, role) {
+        debug!("dropped scheduled message of user without posting rights");
+        return Ok(None);
+    }
+    let message_id = create_message(
+        transaction.as_mut(),
+        chat_id,
+        author,
+        Some(&scheduled.text),
+        None,
+        None,
+        None,
+    )
+    .await?;
+    update_chat_last_message(transaction.as_mut(), chat_id, message_id).await?;
+    let message = get_message(transaction.as_mut(), chat_id, message_id)
+        .await?
+        .ok_or(SqlxError::RowNotFound)?;
+    Ok(Some(DeliveredMessage { chat_id, message }))
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn lock_due_scheduled_messages<'a, E: PgExecutor<'a>>(
+    executor: E,
+    limit: i64,
+) -> Result<Vec<ScheduledMessageResponse>, SqlxError> {
+    sqlx::query_as(
+        "
+        SELECT id, chat_id, user_id, text, send_at, created_at
+        FROM scheduled_messages
+        WHERE send_at <= current_timestamp
+        ORDER BY send_at, id
+        LIMIT $1
+        FOR UPDATE SKIP LOCKED;
+    ",
+    )
+    .bind(limit)
+    .fetch_all(executor)
+    .await
+}
+
+/// Removes scheduled message, only when authored by `user_id` if it is given.
+#[instrument(skip(executor))]
+pub(super) async fn delete_scheduled_message<'a, E: PgExecutor<'a>>(
     executor: E,
-    alias: &str,
-    display_name: &str,
-    password_hash: &str,
-    role: UserRole,
-    invited_by: Option<UserId>,
-) -> Result<UserId, SqlxError> {
+    scheduled_message_id: ScheduledMessageId,
+    user_id: Option<UserId>,
+) -> Result<bool, SqlxError> {
     let result = sqlx::query(
         "
-        INSERT INTO users (alias, display_name, password_hash, role, invited_by, created_at)
-        VALUES ($1, $2, $3, $4, $5, current_timestamp) RETURNING id;
+        DELETE FROM scheduled_messages WHERE id = $1 AND ($2::int IS NULL OR user_id = $2);
     ",
     )
-    .bind(alias)
-    .bind(display_name)
-    .bind(password_hash)
-    .bind(role)
-    .bind(invited_by)
-    .fetch_one(executor)
-    .await?
-    .try_get("id")?;
-    info!("created user with id: {}", result);
-    Ok(result)
+    .bind(scheduled_message_id)
+    .bind(user_id)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() != 0)
 }
 
 #[instrument(skip(executor))]
-pub(super) async fn create_chat<'a, E: PgExecutor<'a>>(
+pub(super) async fn update_message_resource<'a, E: PgExecutor<'a>>(
     executor: E,
-    display_name: Option<&str>,
-    description: Option<&str>,
-    kind: ChatKind,
-) -> Result<ChatId, SqlxError> {
+    user_id: UserId,
+    message_id: MessageId,
+    resource_id: ResourceId,
+) -> Result<bool, SqlxError> {
     let result = sqlx::query(
         "
-        INSERT INTO chats (display_name, description, kind, created_at)
-        VALUES ($1, $2, $3, current_timestamp) RETURNING id;
+        UPDATE messages
+        SET resource_id = $3, edited_at = current_timestamp
+        WHERE
+            id = $2
+            AND user_id = $1
+            AND EXISTS (
+                SELECT 1
+                FROM chats_members
+                WHERE chats_members.chat_id = messages.chat_id AND chats_members.user_id = $1
+            )
+            AND EXISTS (
+                SELECT 1
+                FROM resources
+                WHERE resources.id = $3 AND resources.uploaded_by_user_id = $1
+            );
     ",
     )
-    .bind(display_name)
-    .bind(description)
-    .bind(kind)
-    .fetch_one(executor)
-    .await?
-    .try_get("id")?;
-    info!("created new chat with id: {}", result);
-    Ok(result)
+    .bind(user_id)
+    .bind(message_id)
+    .bind(resource_id)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() != 0)
 }
 
-#[instrument(skip(executor, password_hash))]
-pub(super) async fn update_user_password<'a, E: PgExecutor<'a>>(
+#[instrument(skip(executor, text))]
+pub(super) async fn update_message_text<'a, E: PgExecutor<'a>>(
     executor: E,
     user_id: UserId,
-    password_hash: &str,
-) -> Result<(), SqlxError> {
-    sqlx::query(
+    chat_id: ChatId,
+    message_id: MessageId,
+    text: &str,
+) -> Result<bool, SqlxError> {
+    let result = sqlx::query(
         "
-        UPDATE users
-        SET password_hash = $1
-        WHERE id = $2;
+        WITH previous AS (
+            SELECT id, text FROM messages
+            WHERE id = $3 AND chat_id = $2 AND user_id = $1
+            FOR UPDATE
+        ), history AS (
+            INSERT INTO message_edits (message_id, old_text, edited_at)
+            SELECT id, text, current_timestamp FROM previous
+        )
+        UPDATE messages
+        SET text = $4, edited_at = current_timestamp
+        FROM previous
+        WHERE messages.id = previous.id;
     ",
     )
-    .bind(password_hash)
     .bind(user_id)
+    .bind(chat_id)
+    .bind(message_id)
+    .bind(text)
     .execute(executor)
     .await?;
-    Ok(())
+    Ok(result.rows_affected() != 0)
 }
 
 #[instrument(skip(executor))]
-pub(super) async fn update_user_alias<'a, E: PgExecutor<'a>>(
+pub(super) async fn delete_message<'a, E: PgExecutor<'a>>(
     executor: E,
     user_id: UserId,
-    new_alias: &str,
+    chat_id: ChatId,
+    message_id: MessageId,
 ) -> Result<bool, SqlxError> {
     let result = sqlx::query(
         "
-        UPDATE users
-        SET alias = $1
-        WHERE id = $2;
+        DELETE FROM messages
+        WHERE id = $3 AND chat_id = $2 AND user_id = $1;
     ",
     )
-    .bind(new_alias)
     .bind(user_id)
+    .bind(chat_id)
+    .bind(message_id)
     .execute(executor)
     .await?;
     Ok(result.rows_affected() != 0)
 }
 
+/// Point chat preview to the newest remaining message after its last message was deleted.
 #[instrument(skip(executor))]
-pub(super) async fn update_user_display_name<'a, E: PgExecutor<'a>>(
+pub(super) async fn restore_chat_last_message<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+) -> Result<(), SqlxError> {
+    sqlx::query(
+        "
+        UPDATE chats
+        SET
+            last_message_id = latest.id,
+            last_message_at = latest.created_at
+        FROM (
+            SELECT id, created_at
+            FROM messages
+            WHERE chat_id = $1
+            ORDER BY id DESC
+            LIMIT 1
+        ) AS latest
+        WHERE chats.id = $1 AND chats.last_message_id IS NULL;
+    ",
+    )
+    .bind(chat_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+#[instrument(skip(executor))]
+pub(super) async fn create_resource<'a, E: PgExecutor<'a>>(
+    executor: E,
+    uploaded_by: UserId,
+    url: &str,
+) -> Result<ResourceId, SqlxError> {
+    sqlx::query_scalar(
+        "
+        INSERT INTO resources (uploaded_by_user_id, url)
+        VALUES ($1, $2) RETURNING id;
+    ",
+    )
+    .bind(uploaded_by)
+    .bind(url)
+    .fetch_one(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn create_reaction<'a, E: PgExecutor<'a>>(
     executor: E,
+    message_id: MessageId,
     user_id: UserId,
-    new_display_name: &str,
-) -> Result<bool, SqlxError> {
-    let result = sqlx::query(
+    emoji: &str,
+) -> Result<(), SqlxError> {
+    sqlx::query(
         "
-        UPDATE users
-        SET display_name = $1
-        WHERE id = $2;
+        INSERT INTO message_reactions (message_id, user_id, emoji, created_at)
+        VALUES ($1, $2, $3, current_timestamp)
+        ON CONFLICT DO NOTHING;
     ",
     )
-    .bind(new_display_name)
+    .bind(message_id)
     .bind(user_id)
+    .bind(emoji)
     .execute(executor)
     .await?;
-    Ok(result.rows_affected() != 0)
+    Ok(())
 }
 
 #[instrument(skip(executor))]
-pub(super) async fn add_member_to_chat<'a, E: PgExecutor<'a>>(
+pub(super) async fn create_user_block<'a, E: PgExecutor<'a>>(
     executor: E,
     user_id: UserId,
-    chat_id: ChatId,
-    role: ChatRole,
+    blocked_user_id: UserId,
 ) -> Result<(), SqlxError> {
     sqlx::query(
         "
-        INSERT INTO chats_members (user_id, chat_id, role)
-        VALUES ($1, $2, $3);
+        INSERT INTO user_blocks (user_id, blocked_user_id, created_at)
+        VALUES ($1, $2, current_timestamp)
+        ON CONFLICT DO NOTHING;
     ",
     )
     .bind(user_id)
-    .bind(chat_id)
-    .bind(role)
+    .bind(blocked_user_id)
     .execute(executor)
     .await?;
-    info!("added member to chat");
     Ok(())
 }
 
 #[instrument(skip(executor))]
-pub(super) async fn create_message<'a, E: PgExecutor<'a>>(
+pub(super) async fn delete_user_block<'a, E: PgExecutor<'a>>(
     executor: E,
-    chat_id: ChatId,
     user_id: UserId,
-    text: Option<&str>,
-    reply_to: Option<MessageId>,
-    resource_id: Option<ResourceId>,
-) -> Result<MessageId, SqlxError> {
+    blocked_user_id: UserId,
+) -> Result<bool, SqlxError> {
     let result = sqlx::query(
         "
-        INSERT INTO messages (chat_id, user_id, text, reply_to, resource_id, created_at)
-        VALUES ($1, $2, $3, $4, $5, current_timestamp) RETURNING id;
+        DELETE FROM user_blocks WHERE user_id = $1 AND blocked_user_id = $2;
     ",
     )
-    .bind(chat_id)
     .bind(user_id)
-    .bind(text)
-    .bind(reply_to)
-    .bind(resource_id)
-    .fetch_one(executor)
-    .await?
-    .try_get("id")?;
-    debug!("created message with id: {}", result);
-    Ok(result)
+    .bind(blocked_user_id)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() != 0)
 }
 
 #[instrument(skip(executor))]
@@ -570,6 +2042,29 @@ pub(super) async fn update_chat_read_cursor<'a, E: PgExecutor<'a>>(
     Ok(result.rows_affected() != 0)
 }
 
+/// Pinning an already pinned chat keeps its original `pinned_at`, so its position doesn't change
+#[instrument(skip(executor))]
+pub(super) async fn update_chat_pin<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+    chat_id: ChatId,
+    pinned_at: Option<DateTime<Utc>>,
+) -> Result<bool, SqlxError> {
+    let result = sqlx::query(
+        "
+        UPDATE chats_members
+        SET pinned_at = CASE WHEN $3::timestamptz IS NULL THEN NULL ELSE COALESCE(pinned_at, $3) END
+        WHERE user_id = $1 AND chat_id = $2;
+    ",
+    )
+    .bind(user_id)
+    .bind(chat_id)
+    .bind(pinned_at)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() != 0)
+}
+
 #[instrument(skip(transaction))]
 pub(super) async fn create_with_self_chat<'a>(
     transaction: &mut Transaction<'a, Postgres>,
@@ -621,18 +2116,23 @@ pub(super) async fn create_private_chat_membership<'a, E: PgExecutor<'a>>(
     Ok(())
 }
 
+/// Device details and token hashes of a session being created.
+pub(super) struct NewSession<'a> {
+    pub ip: &'a IpNetwork,
+    pub device_name: Option<&'a str>,
+    pub os_version: Option<&'a str>,
+    pub app_version: Option<&'a str>,
+    pub refresh_token_hash: &'a [u8],
+    pub refresh_token_expires_at: &'a DateTime<Utc>,
+    pub access_token_hash: &'a [u8],
+    pub access_token_expires_at: &'a DateTime<Utc>,
+}
+
 #[instrument(skip_all, fields(user_id, ip))]
 pub(super) async fn create_session<'a, E: PgExecutor<'a>>(
     executor: E,
     user_id: UserId,
-    ip: &IpNetwork,
-    device_name: Option<&str>,
-    os_version: Option<&str>,
-    app_version: Option<&str>,
-    refresh_token_hash: &[u8],
-    refresh_token_expires_at: &DateTime<Utc>,
-    access_token_hash: &[u8],
-    access_token_expires_at: &DateTime<Utc>,
+    session: NewSession<'_>,
 ) -> Result<SessionId, SqlxError> {
     let result = sqlx::query(
         "
@@ -641,14 +2141,14 @@ pub(super) async fn create_session<'a, E: PgExecutor<'a>>(
     ",
     )
         .bind(user_id)
-        .bind(ip)
-        .bind(device_name)
-        .bind(os_version)
-        .bind(app_version)
-        .bind(refresh_token_hash)
-        .bind(refresh_token_expires_at)
-        .bind(access_token_hash)
-        .bind(access_token_expires_at)
+        .bind(session.ip)
+        .bind(session.device_name)
+        .bind(session.os_version)
+        .bind(session.app_version)
+        .bind(session.refresh_token_hash)
+        .bind(session.refresh_token_expires_at)
+        .bind(session.access_token_hash)
+        .bind(session.access_token_expires_at)
         .fetch_one(executor)
         .await?
         .try_get("id")?;
@@ -701,6 +2201,107 @@ pub(super) async fn remove_session<'a, E: PgExecutor<'a>>(
     Ok(())
 }
 
+#[instrument(skip(executor))]
+pub(super) async fn mark_session_logged_out<'a, E: PgExecutor<'a>>(
+    executor: E,
+    session_id: SessionId,
+) -> Result<(), SqlxError> {
+    sqlx::query(
+        "
+        UPDATE sessions SET logged_out_at = current_timestamp
+        WHERE id = $1 AND logged_out_at IS NULL;
+    ",
+    )
+    .bind(session_id)
+    .execute(executor)
+    .await?;
+    debug!("marked session as logged out");
+    Ok(())
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn touch_session<'a, E: PgExecutor<'a>>(
+    executor: E,
+    session_id: SessionId,
+) -> Result<(), SqlxError> {
+    sqlx::query(
+        "
+        UPDATE sessions SET last_seen_at = current_timestamp
+        WHERE id = $1;
+    ",
+    )
+    .bind(session_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
+/// Refresh counter of the session whether logged out or not, row stays locked until the
+/// transaction ends.
+#[instrument(skip(executor))]
+async fn lock_session_refresh_counter<'a, E: PgExecutor<'a>>(
+    executor: E,
+    session_id: SessionId,
+) -> Result<Option<i32>, SqlxError> {
+    sqlx::query_scalar(
+        "
+        SELECT refresh_counter FROM sessions WHERE id = $1 FOR UPDATE;
+    ",
+    )
+    .bind(session_id)
+    .fetch_optional(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn revive_session<'a, E: PgExecutor<'a>>(
+    executor: E,
+    session_id: SessionId,
+) -> Result<bool, SqlxError> {
+    let result = sqlx::query(
+        "
+        UPDATE sessions SET logged_out_at = NULL
+        WHERE id = $1 AND logged_out_at IS NOT NULL;
+    ",
+    )
+    .bind(session_id)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected() != 0)
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn remove_logged_out_sessions<'a, E: PgExecutor<'a>>(
+    executor: E,
+    logged_out_before: DateTime<Utc>,
+) -> Result<u64, SqlxError> {
+    let result = sqlx::query(
+        "
+        DELETE FROM sessions WHERE logged_out_at <= $1;
+    ",
+    )
+    .bind(logged_out_before)
+    .execute(executor)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn remove_sessions_for_user<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+) -> Result<(), SqlxError> {
+    sqlx::query(
+        "
+        DELETE FROM sessions WHERE user_id = $1;
+    ",
+    )
+    .bind(user_id)
+    .execute(executor)
+    .await?;
+    Ok(())
+}
+
 #[instrument(skip(executor))]
 pub(super) async fn remove_sessions_for_user_except<'a, E: PgExecutor<'a>>(
     executor: E,
@@ -719,22 +2320,31 @@ pub(super) async fn remove_sessions_for_user_except<'a, E: PgExecutor<'a>>(
     Ok(())
 }
 
+/// Keep at most `max_sessions` sessions of the user, `keep_session_id` (the one just created) is never
+/// trimmed, even if other sessions expire later or at the same time. Sessions logged out within grace
+/// window go before any live one.
 #[instrument(skip(executor))]
 pub(super) async fn trim_sessions_for_user<'a, E: PgExecutor<'a>>(
     executor: E,
     user_id: UserId,
+    keep_session_id: SessionId,
     max_sessions: i32,
 ) -> Result<(), SqlxError> {
     let result = sqlx::query(
         "
         DELETE FROM sessions WHERE id IN (
-            SELECT id FROM sessions WHERE user_id = $1 ORDER BY access_token_expires_at DESC OFFSET $2
+            SELECT id FROM sessions
+            WHERE user_id = $1 AND id <> $2
+            ORDER BY logged_out_at IS NULL DESC, access_token_expires_at DESC, first_seen_at DESC
+            OFFSET $3 - 1
         );
-    ")
-        .bind(user_id)
-        .bind(max_sessions)
-        .execute(executor)
-        .await?;
+    ",
+    )
+    .bind(user_id)
+    .bind(keep_session_id)
+    .bind(max_sessions)
+    .execute(executor)
+    .await?;
     debug!("trimmed {} sessions", result.rows_affected());
     Ok(())
 }
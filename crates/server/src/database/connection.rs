@@ -1,8 +1,17 @@
+use std::time::Duration;
+
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::{PgPool, PgPoolOptions};
 use sqlx::Error as SqlxError;
 use tracing::debug;
 
+use crate::config::{AuthConfig, ServerConfig};
+use crate::database::cache::PrivateChatCache;
+use crate::database::queries::chat_exists;
+use crate::error::{RequestError, ValidationError};
+use crate::models::chat::ChatId;
+use crate::models::message::{MessageLengthLimits, MESSAGE_TEXT_MAX_LENGTH};
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbConfig {
     pub username: String,
@@ -10,11 +19,37 @@ pub struct DbConfig {
     pub dbname: String,
     pub address: Option<String>,
     pub max_connections: Option<u32>,
+    /// Ping pooled connection before handing it out, so connections left stale by a Postgres
+    /// restart are replaced instead of failing the request.
+    pub test_before_acquire: Option<bool>,
+    /// Upper bound for group chats and channels owned by single user, basic abuse guard.
+    pub max_owned_chats: Option<usize>,
+    /// Logged out session stays revivable for this long, zero deletes it right away.
+    pub logout_grace_secs: Option<u64>,
+    /// Minimum time between alias changes of a single user, zero disables the limit.
+    pub alias_change_cooldown_secs: Option<u64>,
+    /// Session unused for this long is rejected even with unexpired access token, zero disables it.
+    pub idle_timeout_secs: Option<u64>,
+    /// Maximum message text length per chat kind, in characters, capped by the column size.
+    pub max_message_length_with_self: Option<usize>,
+    pub max_message_length_private: Option<usize>,
+    pub max_message_length_group: Option<usize>,
+    pub max_message_length_channel: Option<usize>,
+    /// Apply pending migrations on startup, otherwise startup fails until they are applied.
+    pub auto_migrate: Option<bool>,
 }
 
 impl DbConfig {
     const ADDRESS_FALLBACK: &'static str = "localhost";
     const MAX_CONN_FALLBACK: u32 = 5;
+    const TEST_BEFORE_ACQUIRE_FALLBACK: bool = true;
+    const MAX_OWNED_CHATS_FALLBACK: usize = 1000;
+    const LOGOUT_GRACE_SECS_FALLBACK: u64 = 0;
+    const ALIAS_CHANGE_COOLDOWN_SECS_FALLBACK: u64 = 0;
+    const IDLE_TIMEOUT_SECS_FALLBACK: u64 = 0;
+    const MAX_MESSAGE_LENGTH_FALLBACK: usize = MESSAGE_TEXT_MAX_LENGTH;
+    /// Convenient for development, release builds leave schema changes to the operator.
+    const AUTO_MIGRATE_FALLBACK: bool = cfg!(debug_assertions);
 
     #[cfg(test)]
     pub fn development(dbname: &str, username: &str, password: &str) -> Self {
@@ -24,6 +59,16 @@ impl DbConfig {
             password: password.to_string(),
             address: None,
             max_connections: None,
+            test_before_acquire: None,
+            max_owned_chats: None,
+            logout_grace_secs: None,
+            alias_change_cooldown_secs: None,
+            idle_timeout_secs: None,
+            max_message_length_with_self: None,
+            max_message_length_private: None,
+            max_message_length_group: None,
+            max_message_length_channel: None,
+            auto_migrate: None,
         }
     }
 
@@ -43,23 +88,123 @@ impl DbConfig {
     pub fn max_connections(&self) -> u32 {
         self.max_connections.unwrap_or(Self::MAX_CONN_FALLBACK)
     }
+
+    pub fn test_before_acquire(&self) -> bool {
+        self.test_before_acquire
+            .unwrap_or(Self::TEST_BEFORE_ACQUIRE_FALLBACK)
+    }
+
+    pub fn max_owned_chats(&self) -> usize {
+        self.max_owned_chats
+            .unwrap_or(Self::MAX_OWNED_CHATS_FALLBACK)
+    }
+
+    pub fn logout_grace(&self) -> Duration {
+        Duration::from_secs(
+            self.logout_grace_secs
+                .unwrap_or(Self::LOGOUT_GRACE_SECS_FALLBACK),
+        )
+    }
+
+    pub fn alias_change_cooldown(&self) -> Duration {
+        Duration::from_secs(
+            self.alias_change_cooldown_secs
+                .unwrap_or(Self::ALIAS_CHANGE_COOLDOWN_SECS_FALLBACK),
+        )
+    }
+
+    pub fn idle_timeout(&self) -> Duration {
+        Duration::from_secs(
+            self.idle_timeout_secs
+                .unwrap_or(Self::IDLE_TIMEOUT_SECS_FALLBACK),
+        )
+    }
+
+    pub fn message_length_limits(&self) -> MessageLengthLimits {
+        let limit = |value: Option<usize>| value.unwrap_or(Self::MAX_MESSAGE_LENGTH_FALLBACK);
+        MessageLengthLimits {
+            with_self: limit(self.max_message_length_with_self),
+            private: limit(self.max_message_length_private),
+            group: limit(self.max_message_length_group),
+            channel: limit(self.max_message_length_channel),
+        }
+    }
+
+    pub fn auto_migrate(&self) -> bool {
+        self.auto_migrate.unwrap_or(Self::AUTO_MIGRATE_FALLBACK)
+    }
 }
 
 pub struct DbConnection {
     pool: PgPool,
+    hide_existence: bool,
+    pub(super) max_owned_chats: usize,
+    logout_grace: Duration,
+    pub(super) alias_change_cooldown: Duration,
+    pub(super) idle_timeout: Duration,
+    pub(super) message_length_limits: MessageLengthLimits,
+    pub(super) auto_migrate: bool,
+    auth: AuthConfig,
+    private_chats: PrivateChatCache,
 }
 
 impl DbConnection {
-    pub async fn connect(config: &DbConfig) -> Result<Self, SqlxError> {
+    pub async fn connect(config: &DbConfig, auth: &AuthConfig) -> Result<Self, SqlxError> {
         debug!("Connecting to database at `{}`...", config.address());
         let pool = PgPoolOptions::new()
             .max_connections(config.max_connections())
+            .test_before_acquire(config.test_before_acquire())
             .connect(&config.get_url())
             .await?;
-        Ok(Self { pool })
+        Ok(Self {
+            pool,
+            hide_existence: ServerConfig::HIDE_EXISTENCE_FALLBACK,
+            max_owned_chats: config.max_owned_chats(),
+            logout_grace: config.logout_grace(),
+            alias_change_cooldown: config.alias_change_cooldown(),
+            idle_timeout: config.idle_timeout(),
+            message_length_limits: config.message_length_limits(),
+            auto_migrate: config.auto_migrate(),
+            auth: auth.clone(),
+            private_chats: PrivateChatCache::new(),
+        })
+    }
+
+    /// Existence hiding policy of chat access errors, see `ServerConfig::hide_existence`.
+    pub fn with_hide_existence(mut self, hide_existence: bool) -> Self {
+        self.hide_existence = hide_existence;
+        self
     }
 
     pub fn pool(&self) -> &PgPool {
         &self.pool
     }
+
+    pub fn logout_grace(&self) -> Duration {
+        self.logout_grace
+    }
+
+    pub fn message_length_limits(&self) -> MessageLengthLimits {
+        self.message_length_limits
+    }
+
+    pub fn auth(&self) -> &AuthConfig {
+        &self.auth
+    }
+
+    pub fn private_chats(&self) -> &PrivateChatCache {
+        &self.private_chats
+    }
+
+    /// Error for a caller that isn't a member of the chat, depends on existence hiding policy.
+    pub(super) async fn chat_access_error(&self, chat_id: ChatId) -> RequestError {
+        if self.hide_existence {
+            return ValidationError::NotFound.into();
+        }
+        match chat_exists(self.pool(), chat_id).await {
+            Ok(Some(_)) => ValidationError::Forbidden.into(),
+            Ok(None) => ValidationError::NotFound.into(),
+            Err(e) => e.into(),
+        }
+    }
 }
@@ -1,5 +1,10 @@
+pub mod cache;
 pub mod commands;
 pub mod connection;
+pub mod pagination;
 pub mod queries;
 pub mod schema;
 pub mod utils;
+
+#[cfg(test)]
+mod tests;
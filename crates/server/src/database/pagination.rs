@@ -0,0 +1,127 @@
+use sqlx::postgres::PgArguments;
+use sqlx::query::QueryAs;
+use sqlx::Postgres;
+
+use crate::error::{RequestError, ValidationError};
+use crate::models::listing::ListingMode;
+
+/// `LIMIT`/`OFFSET` of a listing page, offset is computed in `i64` so far pages can't overflow.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pagination {
+    pub limit: i32,
+    pub skip: i64,
+}
+
+impl Pagination {
+    /// 1-based page of `limit` elements.
+    pub fn page(limit: i32, page: i32) -> Self {
+        Self {
+            limit,
+            skip: i64::from(page - 1) * i64::from(limit),
+        }
+    }
+
+    /// First `limit` elements, e.g. right after a keyset cursor.
+    pub fn first(limit: i32) -> Self {
+        Self { limit, skip: 0 }
+    }
+
+    /// Page mode lists from the start, offset mode is a keyset cursor on the listing sort key, e.g.
+    /// the last id seen. Window mode is not supported, `listing` names the listing in the error.
+    pub fn from_listing(
+        mode: ListingMode,
+        listing: &str,
+    ) -> Result<(Option<i64>, Self), RequestError> {
+        match mode {
+            ListingMode::Page { limit, page } => Ok((None, Self::page(limit, page))),
+            ListingMode::Offset { offset, limit } => Ok((Some(offset), Self::first(limit))),
+            ListingMode::Window { .. } => Err(ValidationError::InvalidInput {
+                value: "before/after".to_string(),
+                reason: format!("window mode is not supported for {listing}"),
+            }
+            .into()),
+        }
+    }
+}
+
+/// Append `LIMIT`/`OFFSET` to `base_sql` as placeholders `next_param` and the one after it, they
+/// are filled by [`bind_pagination`] after every other parameter.
+pub fn paginated(base_sql: &str, next_param: usize) -> String {
+    format!(
+        "{}\n    LIMIT ${next_param} OFFSET ${};",
+        base_sql.trim_end(),
+        next_param + 1
+    )
+}
+
+pub fn bind_pagination<'q, O>(
+    query: QueryAs<'q, Postgres, O, PgArguments>,
+    pagination: Pagination,
+) -> QueryAs<'q, Postgres, O, PgArguments> {
+    query.bind(pagination.limit).bind(pagination.skip)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_mode_skips_previous_pages() {
+        let (cursor, pagination) =
+            Pagination::from_listing(ListingMode::Page { limit: 25, page: 3 }, "test").unwrap();
+        assert_eq!(cursor, None);
+        assert_eq!(
+            pagination,
+            Pagination {
+                limit: 25,
+                skip: 50
+            }
+        );
+    }
+
+    #[test]
+    fn far_pages_do_not_overflow() {
+        let pagination = Pagination::page(200, i32::MAX);
+        assert_eq!(pagination.skip, (i64::from(i32::MAX) - 1) * 200);
+    }
+
+    #[test]
+    fn offset_mode_is_keyset_from_cursor() {
+        let (cursor, pagination) = Pagination::from_listing(
+            ListingMode::Offset {
+                offset: 42,
+                limit: 10,
+            },
+            "test",
+        )
+        .unwrap();
+        assert_eq!(cursor, Some(42));
+        assert_eq!(pagination, Pagination::first(10));
+    }
+
+    #[test]
+    fn window_mode_is_rejected() {
+        let err = Pagination::from_listing(
+            ListingMode::Window {
+                before: Some(1),
+                after: None,
+                limit: 10,
+            },
+            "test listing",
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("test listing"), "{err}");
+    }
+
+    #[test]
+    fn clause_uses_next_placeholders() {
+        let sql = paginated(
+            "SELECT id FROM messages WHERE chat_id = $1 ORDER BY id\n    ",
+            2,
+        );
+        assert_eq!(
+            sql,
+            "SELECT id FROM messages WHERE chat_id = $1 ORDER BY id\n    LIMIT $2 OFFSET $3;"
+        );
+    }
+}
@@ -1,21 +1,72 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
 use sqlx::{Error as SqlxError, PgExecutor};
 use tracing::{error, instrument};
 
 use crate::auth::utils::current_time;
+use crate::database::commands::{
+    ensure_admin, record_audit, touch_session, LAST_SEEN_UPDATE_INTERVAL,
+};
 use crate::database::connection::DbConnection;
-use crate::database::utils::map_not_found_as_none;
+use crate::database::pagination::{bind_pagination, paginated, Pagination};
+use crate::database::utils::{map_not_found_as_none, retry_once_on_connection_loss};
 use crate::error::{RequestError, SessionError, ValidationError};
-use crate::models::chat::{ChatId, ChatResponse, IsUserInChatResponse, ListChatsResponse};
-use crate::models::message::{ListMessagesResponse, MessageId, MessageResponse};
-use crate::models::session::{RefreshTokenResponse, ResolveSessionResponse, SessionId};
+use crate::models::audit::{
+    validate_audit_query, AuditAction, AuditEntryResponse, ListAuditQuery, ListAuditResponse,
+};
+use crate::models::chat::{
+    can_manage_join_requests, can_see_members, ChatId, ChatKind, ChatMemberResponse, ChatResponse,
+    ChatRole, IsUserInChatResponse, JoinRequestResponse, LatestMessageIdsResponse,
+    ListChatStaffResponse, ListChatsResponse, ListChatsWithPendingRequestsResponse,
+    ListJoinRequestsResponse, ListMembersResponse, ListMembershipsResponse,
+    ListPrivateChatPreviewsResponse, MembershipResponse, MyRoleResponse,
+    PendingJoinRequestsResponse, PrivateChatPreviewResponse, PrivateChatResponse, SelfChatResponse,
+    TotalUnreadResponse, UnreadCountsResponse,
+};
+use crate::models::export::{
+    ExportedMessageResponse, ExportedSessionResponse, UserExportResponse,
+    EXPORT_MESSAGES_BATCH_SIZE,
+};
+use crate::models::listing::ListingMode;
+use crate::models::message::{
+    validate_reaction, ChannelFeedResponse, FeedMessageResponse, ListMessageEditsResponse,
+    ListMessagesResponse, ListReactorsResponse, MessageDetailsResponse, MessageEditResponse,
+    MessageId, MessageResponse, ReactionSummaryResponse, ReactorResponse, THREAD_MAX_DEPTH,
+    THREAD_MAX_MESSAGES,
+};
+use crate::models::session::{
+    LoggedOutSessionResponse, RefreshTokenResponse, ResolveSessionResponse, SessionId,
+};
+use crate::models::stats::{AdminStatsResponse, ChatMessageCountResponse, UserMessageRateResponse};
 use crate::models::user::{
-    GetUserCredentialsByAliasResponse, GetUserIdByAliasResponse, GetUserRoleResponse, UserId,
-    WhoAmIResponse,
+    validate_user_search_query, BlockedUserResponse, GetUserCredentialsByAliasResponse,
+    GetUserIdByAliasResponse, GetUserRoleResponse, InactiveUserResponse, InviteeResponse,
+    ListBlockedUsersResponse, ListInactiveUsersResponse, ListInviteesResponse, SearchUsersResponse,
+    UserId, UserProfileResponse, WhoAmIResponse,
+};
+use crate::server::constants::{
+    MAX_JOIN_REQUEST_LISTING_ELEMENTS, MAX_LATEST_MESSAGE_IDS_CHATS, MAX_UNREAD_COUNTS_CHATS,
 };
 
 impl DbConnection {
     pub async fn whoami(&self, user_id: UserId) -> Result<WhoAmIResponse, SqlxError> {
-        get_whoami_by_user_id(self.pool(), user_id).await
+        retry_once_on_connection_loss(|| get_whoami_by_user_id(self.pool(), user_id)).await
+    }
+
+    pub async fn get_user_by_id(
+        &self,
+        user_id: UserId,
+    ) -> Result<UserProfileResponse, RequestError> {
+        get_user_profile(self.pool(), user_id)
+            .await?
+            .ok_or(ValidationError::NotFound.into())
+    }
+
+    /// Kind of the chat, `None` when there is no chat with such id. Doesn't check membership, so
+    /// the result shouldn't reach callers outside of the chat.
+    pub async fn chat_exists(&self, chat_id: ChatId) -> Result<Option<ChatKind>, SqlxError> {
+        chat_exists(self.pool(), chat_id).await
     }
 
     pub async fn list_chats(
@@ -23,10 +74,400 @@ impl DbConnection {
         user_id: UserId,
         page_size: i32,
         page_num: i32,
+        kind: Option<ChatKind>,
+    ) -> Result<ListChatsResponse, SqlxError> {
+        retry_once_on_connection_loss(|| {
+            list_chats_for_user(
+                self.pool(),
+                user_id,
+                Pagination::page(page_size, page_num),
+                kind,
+                None,
+                false,
+                true,
+            )
+        })
+        .await
+    }
+
+    /// Chats with at least one message, most recently active first, for the "recents" view.
+    pub async fn list_recent_chats(
+        &self,
+        caller: UserId,
+        limit: i32,
     ) -> Result<ListChatsResponse, SqlxError> {
-        list_chats_for_user(self.pool(), user_id, page_size, page_num).await
+        retry_once_on_connection_loss(|| {
+            list_chats_for_user(
+                self.pool(),
+                caller,
+                Pagination::first(limit),
+                None,
+                None,
+                true,
+                false,
+            )
+        })
+        .await
+    }
+
+    /// Caller's private chats with the other participant and latest message, most recently active
+    /// first. Activity reorders chats, so only page mode is supported.
+    pub async fn list_private_chats_with_preview(
+        &self,
+        caller: UserId,
+        listing: ListingMode,
+    ) -> Result<ListPrivateChatPreviewsResponse, RequestError> {
+        let ListingMode::Page { limit, page } = listing else {
+            return Err(ValidationError::InvalidInput {
+                value: "offset/before/after".to_string(),
+                reason: "only page mode is supported for private chats listing".to_string(),
+            }
+            .into());
+        };
+        let chats = retry_once_on_connection_loss(|| {
+            list_private_chat_previews(self.pool(), caller, Pagination::page(limit, page))
+        })
+        .await?;
+        Ok(ListPrivateChatPreviewsResponse { chats })
+    }
+
+    /// Private chat between caller and the other user, the "open DM" path.
+    pub async fn get_private_chat(
+        &self,
+        caller: UserId,
+        other_user_id: UserId,
+    ) -> Result<PrivateChatResponse, RequestError> {
+        self.find_private_chat(caller, other_user_id)
+            .await?
+            .map(|chat_id| PrivateChatResponse { chat_id })
+            .ok_or(ValidationError::NotFound.into())
+    }
+
+    /// Caller's chat with self, so clients don't have to scan the chat list for it.
+    pub async fn get_self_chat(&self, caller: UserId) -> Result<SelfChatResponse, RequestError> {
+        get_self_chat_id(self.pool(), caller)
+            .await?
+            .map(|chat_id| SelfChatResponse { chat_id })
+            .ok_or(ValidationError::NotFound.into())
+    }
+
+    /// Id of the private chat between two users, `None` when they have none.
+    pub async fn find_private_chat(
+        &self,
+        user_id_a: UserId,
+        user_id_b: UserId,
+    ) -> Result<Option<ChatId>, SqlxError> {
+        self.private_chats()
+            .get_or_lookup(user_id_a, user_id_b, || {
+                get_private_chat_id(self.pool(), user_id_a, user_id_b)
+            })
+            .await
+    }
+
+    /// Caller's profile, chats, authored messages and sessions metadata, read from one snapshot.
+    /// Messages are fetched in batches so a single query doesn't hold the whole history.
+    pub async fn export_user_data(
+        &self,
+        caller: UserId,
+    ) -> Result<UserExportResponse, RequestError> {
+        let mut transaction = self.pool().begin().await?;
+        sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY;")
+            .execute(transaction.as_mut())
+            .await?;
+        let profile = get_user_profile(transaction.as_mut(), caller)
+            .await?
+            .ok_or(ValidationError::NotFound)?;
+        let memberships = list_memberships_for_user(transaction.as_mut(), caller)
+            .await?
+            .memberships;
+        let mut messages = Vec::new();
+        loop {
+            let after = messages
+                .last()
+                .map_or(0, |message: &ExportedMessageResponse| message.id);
+            let batch = list_user_messages_after(
+                transaction.as_mut(),
+                caller,
+                after,
+                EXPORT_MESSAGES_BATCH_SIZE,
+            )
+            .await?;
+            let is_last = (batch.len() as i64) < EXPORT_MESSAGES_BATCH_SIZE;
+            messages.extend(batch);
+            if is_last {
+                break;
+            }
+        }
+        let sessions = list_user_sessions_metadata(transaction.as_mut(), caller).await?;
+        transaction.commit().await?;
+        Ok(UserExportResponse {
+            exported_at: current_time(),
+            profile,
+            memberships,
+            messages,
+            sessions,
+        })
+    }
+
+    /// Users other than the caller whose alias or display name contains `query`, prefix matches
+    /// first. Used to pick a recipient when starting a new chat, so users blocked by or blocking
+    /// the caller are left out.
+    pub async fn search_users(
+        &self,
+        caller: UserId,
+        query: &str,
+        limit: i32,
+    ) -> Result<SearchUsersResponse, RequestError> {
+        validate_user_search_query(query)?;
+        let users = search_users(self.pool(), caller, query.trim(), limit).await?;
+        Ok(SearchUsersResponse { users })
+    }
+
+    /// Admin-only totals of users, chats by kind, messages and active sessions.
+    pub async fn admin_stats(&self, caller: UserId) -> Result<AdminStatsResponse, RequestError> {
+        ensure_admin(self.pool(), caller).await?;
+        Ok(get_admin_stats(self.pool()).await?)
+    }
+
+    /// Admin-only view of the audit log, newest first, narrowed by any combination of filters.
+    pub async fn admin_list_audit(
+        &self,
+        caller: UserId,
+        query: &ListAuditQuery,
+        limit: i32,
+    ) -> Result<ListAuditResponse, RequestError> {
+        validate_audit_query(query)?;
+        ensure_admin(self.pool(), caller).await?;
+        let entries = list_audit_entries(self.pool(), query, limit).await?;
+        Ok(ListAuditResponse { entries })
+    }
+
+    /// Admin-only view of users invited by `user_id`, with `recursive` the whole invite subtree,
+    /// to trace accounts back to whoever let them in.
+    pub async fn list_invitees(
+        &self,
+        caller: UserId,
+        user_id: UserId,
+        recursive: bool,
+    ) -> Result<ListInviteesResponse, RequestError> {
+        ensure_admin(self.pool(), caller).await?;
+        if get_user_profile(self.pool(), user_id).await?.is_none() {
+            return Err(ValidationError::NotFound.into());
+        }
+        let invitees = list_invitees(self.pool(), user_id, recursive).await?;
+        Ok(ListInviteesResponse { invitees })
+    }
+
+    /// Admin-only report of dormant accounts, users whose latest session activity is older than
+    /// `since` or who have no session at all. Longest inactive come first.
+    pub async fn list_inactive_users(
+        &self,
+        caller: UserId,
+        since: DateTime<Utc>,
+    ) -> Result<ListInactiveUsersResponse, RequestError> {
+        ensure_admin(self.pool(), caller).await?;
+        let users = list_users_inactive_since(self.pool(), since).await?;
+        Ok(ListInactiveUsersResponse { users })
+    }
+
+    /// Admin-only count of messages `target` sent since the given time, with `by_chat` also split
+    /// per chat. Every lookup is recorded in the audit log.
+    pub async fn user_message_rate(
+        &self,
+        caller: UserId,
+        target: UserId,
+        since: DateTime<Utc>,
+        by_chat: bool,
+    ) -> Result<UserMessageRateResponse, RequestError> {
+        let mut transaction = self.pool().begin().await?;
+        ensure_admin(transaction.as_mut(), caller).await?;
+        if get_user_profile(transaction.as_mut(), target)
+            .await?
+            .is_none()
+        {
+            return Err(ValidationError::NotFound.into());
+        }
+        let counts = count_user_messages_per_chat(transaction.as_mut(), target, since).await?;
+        record_audit(
+            transaction.as_mut(),
+            caller,
+            AuditAction::MessageRateViewed,
+            Some(target),
+        )
+        .await?;
+        transaction.commit().await?;
+        Ok(UserMessageRateResponse {
+            user_id: target,
+            since,
+            total: counts.iter().map(|chat| chat.count).sum(),
+            by_chat: by_chat.then_some(counts),
+        })
+    }
+
+    /// Chats both users are members of, e.g. "groups in common" on a profile page, ordered like
+    /// [`Self::list_chats`].
+    pub async fn shared_chats(
+        &self,
+        caller: UserId,
+        other_user_id: UserId,
+        page_size: i32,
+        page_num: i32,
+    ) -> Result<ListChatsResponse, RequestError> {
+        if caller == other_user_id {
+            return Err(ValidationError::InvalidInput {
+                value: other_user_id.to_string(),
+                reason: "can't list chats shared with self".to_string(),
+            }
+            .into());
+        }
+        let response = list_chats_for_user(
+            self.pool(),
+            caller,
+            Pagination::page(page_size, page_num),
+            None,
+            Some(other_user_id),
+            false,
+            true,
+        )
+        .await?;
+        Ok(response)
+    }
+
+    pub async fn list_memberships(
+        &self,
+        user_id: UserId,
+    ) -> Result<ListMembershipsResponse, SqlxError> {
+        retry_once_on_connection_loss(|| list_memberships_for_user(self.pool(), user_id)).await
+    }
+
+    pub async fn total_unread(&self, caller: UserId) -> Result<TotalUnreadResponse, SqlxError> {
+        let total_unread =
+            retry_once_on_connection_loss(|| count_total_unread(self.pool(), caller)).await?;
+        Ok(TotalUnreadResponse { total_unread })
+    }
+
+    /// Unread counters of several chats at once, e.g. on app start instead of a query per chat.
+    pub async fn unread_counts(
+        &self,
+        caller: UserId,
+        chat_ids: &[ChatId],
+    ) -> Result<UnreadCountsResponse, RequestError> {
+        if chat_ids.len() > MAX_UNREAD_COUNTS_CHATS {
+            return Err(ValidationError::LimitExceeded {
+                subject: "unread counts".to_string(),
+                unit: "chat".to_string(),
+                attempted: chat_ids.len(),
+                limit: MAX_UNREAD_COUNTS_CHATS,
+            }
+            .into());
+        }
+        let counts =
+            retry_once_on_connection_loss(|| count_unread_by_chat(self.pool(), caller, chat_ids))
+                .await?;
+        Ok(UnreadCountsResponse {
+            unread_counts: counts.into_iter().collect(),
+        })
+    }
+
+    /// Newest message id of several chats at once, so sync clients can compare them with local
+    /// cursors and only resync chats that moved.
+    pub async fn latest_message_ids(
+        &self,
+        caller: UserId,
+        chat_ids: &[ChatId],
+    ) -> Result<LatestMessageIdsResponse, RequestError> {
+        if chat_ids.len() > MAX_LATEST_MESSAGE_IDS_CHATS {
+            return Err(ValidationError::LimitExceeded {
+                subject: "latest message ids".to_string(),
+                unit: "chat".to_string(),
+                attempted: chat_ids.len(),
+                limit: MAX_LATEST_MESSAGE_IDS_CHATS,
+            }
+            .into());
+        }
+        let latest =
+            retry_once_on_connection_loss(|| get_latest_message_ids(self.pool(), caller, chat_ids))
+                .await?;
+        Ok(LatestMessageIdsResponse {
+            latest_message_ids: latest.into_iter().collect(),
+        })
+    }
+
+    pub async fn get_my_role(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+    ) -> Result<MyRoleResponse, RequestError> {
+        let Some(role) = get_chat_role(self.pool(), chat_id, caller).await? else {
+            return Err(self.chat_access_error(chat_id).await);
+        };
+        Ok(MyRoleResponse { role })
+    }
+
+    pub async fn list_members(
+        &self,
+        user_id: UserId,
+        chat_id: ChatId,
+    ) -> Result<ListMembersResponse, RequestError> {
+        let Some(role) = get_chat_role(self.pool(), chat_id, user_id).await? else {
+            return Err(self.chat_access_error(chat_id).await);
+        };
+        let kind = get_chat_kind(self.pool(), chat_id).await?;
+        if !can_see_members(kind, role) {
+            let members_count = count_chat_members(self.pool(), chat_id).await?;
+            return Ok(ListMembersResponse {
+                members_count,
+                members: None,
+            });
+        }
+        let members = list_chat_members(self.pool(), chat_id, false).await?;
+        Ok(ListMembersResponse {
+            members_count: members.len() as i64,
+            members: Some(members),
+        })
+    }
+
+    /// Owners and moderators of the chat, visible to every member, channel audience included.
+    pub async fn list_chat_staff(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+    ) -> Result<ListChatStaffResponse, RequestError> {
+        if !is_user_in_chat(self.pool(), chat_id, caller).await? {
+            return Err(self.chat_access_error(chat_id).await);
+        }
+        let staff = list_chat_members(self.pool(), chat_id, true).await?;
+        Ok(ListChatStaffResponse { staff })
+    }
+
+    /// Outstanding join requests of the chat, available to owners and moderators.
+    pub async fn list_join_requests(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+    ) -> Result<ListJoinRequestsResponse, RequestError> {
+        let Some(role) = get_chat_role(self.pool(), chat_id, caller).await? else {
+            return Err(self.chat_access_error(chat_id).await);
+        };
+        if !can_manage_join_requests(role) {
+            return Err(ValidationError::Forbidden.into());
+        }
+        let requests =
+            list_chat_join_requests(self.pool(), chat_id, MAX_JOIN_REQUEST_LISTING_ELEMENTS)
+                .await?;
+        Ok(ListJoinRequestsResponse { requests })
+    }
+
+    /// Chats owned or moderated by caller that have outstanding join requests, with their counts.
+    pub async fn list_chats_with_pending_requests(
+        &self,
+        caller: UserId,
+    ) -> Result<ListChatsWithPendingRequestsResponse, RequestError> {
+        let chats = list_pending_join_requests_by_chat(self.pool(), caller).await?;
+        Ok(ListChatsWithPendingRequestsResponse { chats })
     }
 
+    /// Page of messages ordered by `id` ascending, i.e. in insertion order regardless of `created_at`.
     pub async fn list_messages(
         &self,
         user_id: UserId,
@@ -35,9 +476,29 @@ impl DbConnection {
         page_num: i32,
     ) -> Result<ListMessagesResponse, RequestError> {
         if !is_user_in_chat(self.pool(), chat_id, user_id).await? {
-            return Err(ValidationError::NotFound.into());
+            return Err(self.chat_access_error(chat_id).await);
         }
-        Ok(list_messages_for_user(self.pool(), chat_id, page_size, page_num).await?)
+        Ok(
+            list_messages_for_user(self.pool(), chat_id, Pagination::page(page_size, page_num))
+                .await?,
+        )
+    }
+
+    /// Same as [`Self::list_messages`] plus `total_pages`, so clients can tell an empty page past
+    /// the end from an empty chat.
+    pub async fn list_messages_with_total(
+        &self,
+        user_id: UserId,
+        chat_id: ChatId,
+        page_size: i32,
+        page_num: i32,
+    ) -> Result<ListMessagesResponse, RequestError> {
+        let mut response = self
+            .list_messages(user_id, chat_id, page_size, page_num)
+            .await?;
+        let total = count_chat_messages(self.pool(), chat_id).await?;
+        response.total_pages = Some((total + i64::from(page_size) - 1) / i64::from(page_size));
+        Ok(response)
     }
 
     pub async fn list_messages_after(
@@ -47,10 +508,218 @@ impl DbConnection {
         after_message_id: MessageId,
         limit: i32,
     ) -> Result<ListMessagesResponse, RequestError> {
+        self.list_messages_since(user_id, chat_id, after_message_id, limit)
+            .await
+    }
+
+    /// Up to `limit` messages older than `before` and newer than `after` in ascending order, e.g. to
+    /// load around a permalinked message passed as both cursors. Limit is split evenly, a side with
+    /// fewer messages leaves the rest to the other one.
+    pub async fn list_messages_window(
+        &self,
+        user_id: UserId,
+        chat_id: ChatId,
+        before: Option<MessageId>,
+        after: Option<MessageId>,
+        limit: i32,
+    ) -> Result<ListMessagesResponse, RequestError> {
+        if !is_user_in_chat(self.pool(), chat_id, user_id).await? {
+            return Err(self.chat_access_error(chat_id).await);
+        }
+        let mut older = match before {
+            Some(before) => {
+                list_messages_for_user_before(self.pool(), chat_id, before, limit)
+                    .await?
+                    .messages
+            }
+            None => Vec::new(),
+        };
+        let mut newer = match after {
+            Some(after) => {
+                list_messages_for_user_after(self.pool(), chat_id, after, limit)
+                    .await?
+                    .messages
+            }
+            None => Vec::new(),
+        };
+        let limit = limit as usize;
+        let older_len = older
+            .len()
+            .min(limit.saturating_sub(newer.len()).max(limit / 2));
+        newer.truncate(limit - older_len);
+        // Older side comes newest first, keep the ones closest to the cursor
+        older.truncate(older_len);
+        older.reverse();
+        older.append(&mut newer);
+        Ok(ListMessagesResponse {
+            messages: older,
+            total_pages: None,
+        })
+    }
+
+    /// Delta sync for reconnecting clients, returns messages newer than `since_id` in ascending order.
+    pub async fn list_messages_since(
+        &self,
+        user_id: UserId,
+        chat_id: ChatId,
+        since_id: MessageId,
+        limit: i32,
+    ) -> Result<ListMessagesResponse, RequestError> {
+        if !is_user_in_chat(self.pool(), chat_id, user_id).await? {
+            return Err(self.chat_access_error(chat_id).await);
+        }
+        Ok(list_messages_for_user_after(self.pool(), chat_id, since_id, limit).await?)
+    }
+
+    /// Messages of others that mention caller as `@alias` or reply to caller's messages, ordered by
+    /// id. In offset mode `offset` is the last message id seen.
+    pub async fn list_messages_involving(
+        &self,
+        caller: UserId,
+        chat_id: ChatId,
+        listing: ListingMode,
+    ) -> Result<ListMessagesResponse, RequestError> {
+        if !is_user_in_chat(self.pool(), chat_id, caller).await? {
+            return Err(self.chat_access_error(chat_id).await);
+        }
+        let (after_message_id, pagination) = Pagination::from_listing(listing, "mentions listing")?;
+        let messages = list_messages_involving_user(
+            self.pool(),
+            chat_id,
+            caller,
+            after_message_id.unwrap_or(0),
+            pagination,
+        )
+        .await?;
+        Ok(ListMessagesResponse {
+            messages,
+            total_pages: None,
+        })
+    }
+
+    pub async fn get_message_details(
+        &self,
+        user_id: UserId,
+        chat_id: ChatId,
+        message_id: MessageId,
+    ) -> Result<MessageDetailsResponse, RequestError> {
         if !is_user_in_chat(self.pool(), chat_id, user_id).await? {
+            return Err(self.chat_access_error(chat_id).await);
+        }
+        let Some(message) = get_message(self.pool(), chat_id, message_id).await? else {
+            return Err(ValidationError::NotFound.into());
+        };
+        let sender_role = match message.user_id {
+            Some(sender_id) => get_chat_role(self.pool(), chat_id, sender_id).await?,
+            None => None,
+        };
+        let reply_to_message = match message.reply_to {
+            Some(reply_to) => get_message(self.pool(), chat_id, reply_to).await?,
+            None => None,
+        };
+        let reactions = list_message_reactions(self.pool(), message_id, user_id).await?;
+        Ok(MessageDetailsResponse {
+            message,
+            sender_role,
+            reply_to_message,
+            reactions,
+        })
+    }
+
+    /// Root message followed by every message whose reply chain leads to it, ordered by id.
+    pub async fn list_thread(
+        &self,
+        caller: UserId,
+        root_message_id: MessageId,
+    ) -> Result<ListMessagesResponse, RequestError> {
+        let Some(chat_id) = get_message_chat_id(self.pool(), root_message_id).await? else {
+            return Err(ValidationError::NotFound.into());
+        };
+        if !is_user_in_chat(self.pool(), chat_id, caller).await? {
+            return Err(self.chat_access_error(chat_id).await);
+        }
+        let messages = list_thread_messages(
+            self.pool(),
+            root_message_id,
+            THREAD_MAX_DEPTH,
+            THREAD_MAX_MESSAGES,
+        )
+        .await?;
+        Ok(ListMessagesResponse {
+            messages,
+            total_pages: None,
+        })
+    }
+
+    /// Prior texts of the message, oldest first, visible to every member of its chat.
+    pub async fn list_message_edits(
+        &self,
+        caller: UserId,
+        message_id: MessageId,
+    ) -> Result<ListMessageEditsResponse, RequestError> {
+        let Some(chat_id) = get_message_chat_id(self.pool(), message_id).await? else {
+            return Err(ValidationError::NotFound.into());
+        };
+        if !is_user_in_chat(self.pool(), chat_id, caller).await? {
+            return Err(self.chat_access_error(chat_id).await);
+        }
+        let edits = list_message_edits(self.pool(), message_id).await?;
+        Ok(ListMessageEditsResponse { edits })
+    }
+
+    /// Users who reacted to the message with `emoji`, ordered by user id. In offset mode `offset`
+    /// is the last user id seen, so pages stay stable while new reactions arrive.
+    pub async fn list_reactions(
+        &self,
+        caller: UserId,
+        message_id: MessageId,
+        emoji: &str,
+        listing: ListingMode,
+    ) -> Result<ListReactorsResponse, RequestError> {
+        validate_reaction(emoji)?;
+        let Some(chat_id) = get_message_chat_id(self.pool(), message_id).await? else {
             return Err(ValidationError::NotFound.into());
+        };
+        if !is_user_in_chat(self.pool(), chat_id, caller).await? {
+            return Err(self.chat_access_error(chat_id).await);
         }
-        Ok(list_messages_for_user_after(self.pool(), chat_id, after_message_id, limit).await?)
+        let (after_user_id, pagination) = Pagination::from_listing(listing, "reactions listing")?;
+        let reactors = list_message_reactors(
+            self.pool(),
+            message_id,
+            emoji,
+            after_user_id.unwrap_or(0),
+            pagination,
+        )
+        .await?;
+        Ok(ListReactorsResponse { reactors })
+    }
+
+    /// Newest messages across all channels caller is a member of, newest first. In offset mode
+    /// `offset` is the last message id seen and the feed continues with older messages.
+    pub async fn channel_feed(
+        &self,
+        caller: UserId,
+        listing: ListingMode,
+    ) -> Result<ChannelFeedResponse, RequestError> {
+        let (before_message_id, pagination) = Pagination::from_listing(listing, "channel feed")?;
+        let messages =
+            list_channel_feed_for_user(self.pool(), caller, before_message_id, pagination).await?;
+        Ok(ChannelFeedResponse { messages })
+    }
+
+    /// Users blocked by caller, ordered by user id. In offset mode `offset` is the last user id
+    /// seen, like in reactions listing.
+    pub async fn list_blocked_users(
+        &self,
+        caller: UserId,
+        listing: ListingMode,
+    ) -> Result<ListBlockedUsersResponse, RequestError> {
+        let (after_user_id, pagination) =
+            Pagination::from_listing(listing, "blocked users listing")?;
+        let blocked_users =
+            list_user_blocks(self.pool(), caller, after_user_id.unwrap_or(0), pagination).await?;
+        Ok(ListBlockedUsersResponse { blocked_users })
     }
 
     pub async fn resolve_session(
@@ -70,11 +739,48 @@ impl DbConnection {
         if !crate::auth::utils::verify_session_token(access_token, &token.access_token_hash) {
             return Err(SessionError::TokenNotFound);
         }
-        if token.access_token_expires_at <= current_time() {
-            return Err(SessionError::TokenExpired);
+        let idle_for = self.check_session_expiration(&token)?;
+        if idle_for >= LAST_SEEN_UPDATE_INTERVAL {
+            touch_session(self.pool(), session_id).await.map_err(|e| {
+                error!("{e}");
+                SessionError::Internal
+            })?;
         }
         Ok(token.user_id)
     }
+
+    /// Recheck of a session already resolved from its token, for connections outliving a single
+    /// request. Fails the same way as `resolve_session` once the session is logged out, revoked,
+    /// expired or idle for too long.
+    pub async fn ensure_session_active(&self, session_id: SessionId) -> Result<(), SessionError> {
+        let Some(token) = get_access_token(self.pool(), session_id)
+            .await
+            .map_err(|e| {
+                error!("{e}");
+                SessionError::Internal
+            })?
+        else {
+            return Err(SessionError::TokenNotFound);
+        };
+        self.check_session_expiration(&token)?;
+        Ok(())
+    }
+
+    /// Returns how long the session has been idle.
+    fn check_session_expiration(
+        &self,
+        token: &ResolveSessionResponse,
+    ) -> Result<Duration, SessionError> {
+        let now = current_time();
+        if token.access_token_expires_at <= now {
+            return Err(SessionError::TokenExpired);
+        }
+        let idle_for = (now - token.last_seen_at).to_std().unwrap_or_default();
+        if !self.idle_timeout.is_zero() && idle_for >= self.idle_timeout {
+            return Err(SessionError::TokenExpired);
+        }
+        Ok(idle_for)
+    }
 }
 
 #[instrument(skip(executor))]
@@ -111,91 +817,541 @@ pub(super) async fn get_whoami_by_user_id<'a, E: PgExecutor<'a>>(
 }
 
 #[instrument(skip(executor))]
-pub(super) async fn get_user_id_by_alias<'a, E: PgExecutor<'a>>(
+pub(super) async fn get_user_profile<'a, E: PgExecutor<'a>>(
     executor: E,
-    alias: &str,
-) -> Result<GetUserIdByAliasResponse, SqlxError> {
+    user_id: UserId,
+) -> Result<Option<UserProfileResponse>, SqlxError> {
     let result = sqlx::query_as(
         "
-    SELECT id AS user_id FROM users WHERE alias = $1;
+    SELECT id AS user_id, alias, display_name, role, bio
+    FROM users
+    WHERE id = $1;
     ",
     )
-    .bind(alias)
+    .bind(user_id)
     .fetch_one(executor)
-    .await?;
-    Ok(result)
+    .await;
+    map_not_found_as_none(result)
 }
 
 #[instrument(skip(executor))]
-pub(super) async fn list_user_ids<'a, E: PgExecutor<'a>>(
+pub(super) async fn search_users<'a, E: PgExecutor<'a>>(
     executor: E,
-) -> Result<Vec<UserId>, SqlxError> {
-    sqlx::query_scalar(
+    caller: UserId,
+    query: &str,
+    limit: i32,
+) -> Result<Vec<UserProfileResponse>, SqlxError> {
+    let escaped = query
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    sqlx::query_as(
         "
-    SELECT id FROM users ORDER BY id;
+    SELECT id AS user_id, alias, display_name, role, bio
+    FROM users
+    WHERE
+        id <> $1
+        AND (alias ILIKE '%' || $2 || '%' OR display_name ILIKE '%' || $2 || '%')
+        AND NOT EXISTS(
+            SELECT 1 FROM user_blocks
+            WHERE
+                (user_id = $1 AND blocked_user_id = users.id)
+                OR (user_id = users.id AND blocked_user_id = $1)
+        )
+    ORDER BY
+        (alias ILIKE $2 || '%' OR display_name ILIKE $2 || '%') DESC,
+        alias
+    LIMIT $3;
     ",
     )
+    .bind(caller)
+    .bind(escaped)
+    .bind(limit)
     .fetch_all(executor)
     .await
 }
 
+/// Invitees ordered by depth, then by creation. Invite tree is acyclic since a user can only be
+/// invited by an already existing one.
 #[instrument(skip(executor))]
-pub(super) async fn get_user_credentials_by_alias<'a, E: PgExecutor<'a>>(
+pub(super) async fn list_invitees<'a, E: PgExecutor<'a>>(
     executor: E,
-    alias: &str,
-) -> Result<Option<GetUserCredentialsByAliasResponse>, SqlxError> {
-    let result = sqlx::query_as(
+    user_id: UserId,
+    recursive: bool,
+) -> Result<Vec<InviteeResponse>, SqlxError> {
+    sqlx::query_as(
         "
-    SELECT id AS user_id, password_hash FROM users WHERE alias = $1;
+    WITH RECURSIVE invitees AS (
+        SELECT id, invited_by, 1 AS depth
+        FROM users
+        WHERE invited_by = $1
+        UNION ALL
+        SELECT users.id, users.invited_by, invitees.depth + 1
+        FROM users
+        JOIN invitees ON users.invited_by = invitees.id
+        WHERE $2
+    )
+    SELECT
+        users.id AS user_id,
+        users.alias,
+        users.display_name,
+        users.role,
+        invitees.invited_by,
+        invitees.depth,
+        users.created_at
+    FROM invitees
+    JOIN users ON users.id = invitees.id
+    ORDER BY invitees.depth, users.created_at, users.id;
     ",
     )
-    .bind(alias)
-    .fetch_one(executor)
-    .await;
-    map_not_found_as_none(result)
+    .bind(user_id)
+    .bind(recursive)
+    .fetch_all(executor)
+    .await
 }
 
 #[instrument(skip(executor))]
-pub(super) async fn get_user_credentials_by_user_id<'a, E: PgExecutor<'a>>(
+pub(super) async fn list_user_messages_after<'a, E: PgExecutor<'a>>(
     executor: E,
     user_id: UserId,
-) -> Result<Option<GetUserCredentialsByAliasResponse>, SqlxError> {
-    let result = sqlx::query_as(
+    after_id: MessageId,
+    limit: i64,
+) -> Result<Vec<ExportedMessageResponse>, SqlxError> {
+    sqlx::query_as(
         "
-    SELECT id AS user_id, password_hash FROM users WHERE id = $1;
+    SELECT id, chat_id, text, reply_to, created_at, edited_at
+    FROM messages
+    WHERE user_id = $1 AND id > $2
+    ORDER BY id
+    LIMIT $3;
     ",
     )
     .bind(user_id)
-    .fetch_one(executor)
-    .await;
-    map_not_found_as_none(result)
+    .bind(after_id)
+    .bind(limit)
+    .fetch_all(executor)
+    .await
 }
 
 #[instrument(skip(executor))]
-pub(super) async fn list_chats_for_user<'a, E: PgExecutor<'a>>(
+pub(super) async fn list_user_sessions_metadata<'a, E: PgExecutor<'a>>(
     executor: E,
     user_id: UserId,
-    page_size: i32,
-    page_num: i32,
-) -> Result<ListChatsResponse, SqlxError> {
-    let chats: Vec<ChatResponse> = sqlx::query_as(
+) -> Result<Vec<ExportedSessionResponse>, SqlxError> {
+    sqlx::query_as(
         "
-    SELECT
-        chats.id AS id,
-        COALESCE(chats.display_name, peer.display_name) AS display_name,
-        chats.kind AS kind,
+    SELECT host(ip) AS ip, first_seen_at, last_seen_at, device_name, os_version, app_version
+    FROM sessions
+    WHERE user_id = $1
+    ORDER BY first_seen_at;
+    ",
+    )
+    .bind(user_id)
+    .fetch_all(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn get_admin_stats<'a, E: PgExecutor<'a>>(
+    executor: E,
+) -> Result<AdminStatsResponse, SqlxError> {
+    sqlx::query_as(
+        "
+    SELECT
+        (SELECT COUNT(*) FROM users) AS users,
+        COUNT(*) FILTER (WHERE kind = 'with_self') AS with_self,
+        COUNT(*) FILTER (WHERE kind = 'private') AS private,
+        COUNT(*) FILTER (WHERE kind = 'group') AS \"group\",
+        COUNT(*) FILTER (WHERE kind = 'channel') AS channel,
+        (SELECT COUNT(*) FROM messages) AS messages,
+        (
+            SELECT COUNT(*) FROM sessions
+            WHERE logged_out_at IS NULL AND refresh_token_expires_at > current_timestamp
+        ) AS active_sessions
+    FROM chats;
+    ",
+    )
+    .fetch_one(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_users_inactive_since<'a, E: PgExecutor<'a>>(
+    executor: E,
+    since: DateTime<Utc>,
+) -> Result<Vec<InactiveUserResponse>, SqlxError> {
+    sqlx::query_as(
+        "
+    SELECT
+        users.id AS user_id,
+        users.alias,
+        users.display_name,
+        users.role,
+        users.created_at,
+        MAX(sessions.last_seen_at) AS last_seen_at
+    FROM
+        users
+        LEFT JOIN sessions ON sessions.user_id = users.id
+    GROUP BY users.id
+    HAVING MAX(sessions.last_seen_at) IS NULL OR MAX(sessions.last_seen_at) < $1
+    ORDER BY last_seen_at ASC NULLS FIRST, users.id;
+    ",
+    )
+    .bind(since)
+    .fetch_all(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn count_user_messages_per_chat<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+    since: DateTime<Utc>,
+) -> Result<Vec<ChatMessageCountResponse>, SqlxError> {
+    sqlx::query_as(
+        "
+    SELECT chat_id, COUNT(*) AS count
+    FROM messages
+    WHERE user_id = $1 AND created_at >= $2
+    GROUP BY chat_id
+    ORDER BY count DESC, chat_id;
+    ",
+    )
+    .bind(user_id)
+    .bind(since)
+    .fetch_all(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_audit_entries<'a, E: PgExecutor<'a>>(
+    executor: E,
+    query: &ListAuditQuery,
+    limit: i32,
+) -> Result<Vec<AuditEntryResponse>, SqlxError> {
+    sqlx::query_as(
+        "
+    SELECT id, actor_user_id, action, target_user_id, created_at
+    FROM audit_log
+    WHERE
+        ($1::int IS NULL OR actor_user_id = $1)
+        AND ($2::audit_action IS NULL OR action = $2)
+        AND ($3::timestamptz IS NULL OR created_at >= $3)
+        AND ($4::timestamptz IS NULL OR created_at < $4)
+        AND ($5::bigint IS NULL OR id < $5)
+    ORDER BY id DESC
+    LIMIT $6;
+    ",
+    )
+    .bind(query.actor_user_id)
+    .bind(query.action)
+    .bind(query.created_from)
+    .bind(query.created_to)
+    .bind(query.before)
+    .bind(limit)
+    .fetch_all(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn get_self_chat_id<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+) -> Result<Option<ChatId>, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT chats.id
+    FROM chats
+    JOIN chats_members ON chats_members.chat_id = chats.id
+    WHERE chats_members.user_id = $1 AND chats.kind = 'with_self'
+    ORDER BY chats.id
+    LIMIT 1;
+    ",
+    )
+    .bind(user_id)
+    .fetch_optional(executor)
+    .await
+}
+
+/// Whether either user blocked the other, blocks apply both ways.
+#[instrument(skip(executor))]
+pub(super) async fn is_blocked_between<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id_a: UserId,
+    user_id_b: UserId,
+) -> Result<bool, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT EXISTS(
+        SELECT 1 FROM user_blocks
+        WHERE
+            (user_id = $1 AND blocked_user_id = $2)
+            OR (user_id = $2 AND blocked_user_id = $1)
+    );
+    ",
+    )
+    .bind(user_id_a)
+    .bind(user_id_b)
+    .fetch_one(executor)
+    .await
+}
+
+/// Whether either side of the private chat blocked the other, false for other chat kinds.
+#[instrument(skip(executor))]
+pub(super) async fn is_private_chat_blocked<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+) -> Result<bool, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT EXISTS(
+        SELECT 1
+        FROM private_chats
+        JOIN user_blocks ON
+            (user_blocks.user_id = private_chats.user_id_low
+                AND user_blocks.blocked_user_id = private_chats.user_id_high)
+            OR (user_blocks.user_id = private_chats.user_id_high
+                AND user_blocks.blocked_user_id = private_chats.user_id_low)
+        WHERE private_chats.chat_id = $1
+    );
+    ",
+    )
+    .bind(chat_id)
+    .fetch_one(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn get_private_chat_id<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id_a: UserId,
+    user_id_b: UserId,
+) -> Result<Option<ChatId>, SqlxError> {
+    let (user_id_low, user_id_high) = if user_id_a < user_id_b {
+        (user_id_a, user_id_b)
+    } else {
+        (user_id_b, user_id_a)
+    };
+    let result = sqlx::query_scalar(
+        "
+    SELECT private_chats.chat_id
+    FROM private_chats
+    JOIN chats ON chats.id = private_chats.chat_id AND chats.kind = 'private'
+    WHERE private_chats.user_id_low = $1 AND private_chats.user_id_high = $2;
+    ",
+    )
+    .bind(user_id_low)
+    .bind(user_id_high)
+    .fetch_one(executor)
+    .await;
+    map_not_found_as_none(result)
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn get_user_id_by_alias<'a, E: PgExecutor<'a>>(
+    executor: E,
+    alias: &str,
+) -> Result<GetUserIdByAliasResponse, SqlxError> {
+    let result = sqlx::query_as(
+        "
+    SELECT id AS user_id FROM users WHERE alias = $1;
+    ",
+    )
+    .bind(alias)
+    .fetch_one(executor)
+    .await?;
+    Ok(result)
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_user_ids<'a, E: PgExecutor<'a>>(
+    executor: E,
+) -> Result<Vec<UserId>, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT id FROM users ORDER BY id;
+    ",
+    )
+    .fetch_all(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_existing_aliases<'a, E: PgExecutor<'a>>(
+    executor: E,
+    aliases: &[String],
+) -> Result<Vec<String>, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT alias FROM users WHERE alias = ANY($1) ORDER BY alias;
+    ",
+    )
+    .bind(aliases)
+    .fetch_all(executor)
+    .await
+}
+
+/// Subset of `user_ids` who are members of the chat.
+#[instrument(skip(executor))]
+pub(super) async fn list_chat_member_ids_among<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    user_ids: &[UserId],
+) -> Result<Vec<UserId>, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT user_id FROM chats_members WHERE chat_id = $1 AND user_id = ANY($2) ORDER BY user_id;
+    ",
+    )
+    .bind(chat_id)
+    .bind(user_ids)
+    .fetch_all(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn get_user_credentials_by_alias<'a, E: PgExecutor<'a>>(
+    executor: E,
+    alias: &str,
+) -> Result<Option<GetUserCredentialsByAliasResponse>, SqlxError> {
+    let result = sqlx::query_as(
+        "
+    SELECT id AS user_id, password_hash FROM users WHERE alias = $1;
+    ",
+    )
+    .bind(alias)
+    .fetch_one(executor)
+    .await;
+    map_not_found_as_none(result)
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn get_user_credentials_by_user_id<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+) -> Result<Option<GetUserCredentialsByAliasResponse>, SqlxError> {
+    let result = sqlx::query_as(
+        "
+    SELECT id AS user_id, password_hash FROM users WHERE id = $1;
+    ",
+    )
+    .bind(user_id)
+    .fetch_one(executor)
+    .await;
+    map_not_found_as_none(result)
+}
+
+/// Same unread rule as in [`list_chats_for_user`], summed over all caller's chats.
+#[instrument(skip(executor))]
+pub(super) async fn count_total_unread<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+) -> Result<i64, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT
+        COUNT(messages.id)
+    FROM
+        chats_members self_member
+        JOIN messages
+            ON messages.chat_id = self_member.chat_id
+            AND messages.id > COALESCE(self_member.last_read_message_id, 0)
+            AND (messages.user_id IS NULL OR messages.user_id <> self_member.user_id)
+    WHERE
+        self_member.user_id = $1;
+    ",
+    )
+    .bind(user_id)
+    .fetch_one(executor)
+    .await
+}
+
+/// Same unread rule as in [`list_chats_for_user`], per each of `chat_ids` the user is member of.
+#[instrument(skip(executor))]
+pub(super) async fn count_unread_by_chat<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+    chat_ids: &[ChatId],
+) -> Result<Vec<(ChatId, i64)>, SqlxError> {
+    sqlx::query_as(
+        "
+    SELECT
+        self_member.chat_id,
+        COUNT(messages.id)
+    FROM
+        chats_members self_member
+        LEFT JOIN messages
+            ON messages.chat_id = self_member.chat_id
+            AND messages.id > COALESCE(self_member.last_read_message_id, 0)
+            AND (messages.user_id IS NULL OR messages.user_id <> self_member.user_id)
+    WHERE
+        self_member.user_id = $1
+        AND self_member.chat_id = ANY($2)
+    GROUP BY self_member.chat_id;
+    ",
+    )
+    .bind(user_id)
+    .bind(chat_ids)
+    .fetch_all(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn get_latest_message_ids<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+    chat_ids: &[ChatId],
+) -> Result<Vec<(ChatId, Option<MessageId>)>, SqlxError> {
+    sqlx::query_as(
+        "
+    SELECT
+        self_member.chat_id,
+        MAX(messages.id)
+    FROM
+        chats_members self_member
+        LEFT JOIN messages ON messages.chat_id = self_member.chat_id
+    WHERE
+        self_member.user_id = $1
+        AND self_member.chat_id = ANY($2)
+    GROUP BY self_member.chat_id;
+    ",
+    )
+    .bind(user_id)
+    .bind(chat_ids)
+    .fetch_all(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_chats_for_user<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+    pagination: Pagination,
+    kind: Option<ChatKind>,
+    shared_with: Option<UserId>,
+    with_messages_only: bool,
+    pinned_first: bool,
+) -> Result<ListChatsResponse, SqlxError> {
+    let sql = paginated(
+        "
+    SELECT
+        chats.id AS id,
+        COALESCE(chats.display_name, peer.display_name) AS display_name,
+        chats.description AS description,
+        chats.kind AS kind,
         chats.last_message_id AS last_message_id,
         last_message.text AS last_message_text,
         chats.last_message_at AS last_message_at,
-        COALESCE(unread.unread_count, 0) AS unread_count
+        COALESCE(unread.unread_count, 0) AS unread_count,
+        self_member.pinned_at AS pinned_at
     FROM
         chats_members self_member
         JOIN chats ON self_member.chat_id = chats.id
-        LEFT JOIN chats_members peer_member
-            ON chats.kind = 'private'
-            AND peer_member.chat_id = chats.id
-            AND peer_member.user_id != self_member.user_id
-        LEFT JOIN users peer ON peer.id = peer_member.user_id
+        LEFT JOIN private_chats pair ON pair.chat_id = chats.id
+        LEFT JOIN users peer ON peer.id = CASE
+            WHEN pair.user_id_low = self_member.user_id THEN pair.user_id_high
+            ELSE pair.user_id_low
+        END
         LEFT JOIN messages last_message ON last_message.id = chats.last_message_id
         LEFT JOIN LATERAL (
             SELECT COUNT(*) AS unread_count
@@ -206,95 +1362,733 @@ pub(super) async fn list_chats_for_user<'a, E: PgExecutor<'a>>(
                 AND (messages.user_id IS NULL OR messages.user_id <> self_member.user_id)
         ) unread ON TRUE
     WHERE
-        self_member.user_id = $1
+        self_member.user_id = $1
+        AND ($2::chat_kind IS NULL OR chats.kind = $2)
+        AND (
+            $3::int IS NULL
+            OR (
+                chats.kind <> 'with_self'
+                AND EXISTS (
+                    SELECT 1 FROM chats_members other_member
+                    WHERE other_member.chat_id = chats.id AND other_member.user_id = $3
+                )
+            )
+        )
+        AND (NOT $4 OR chats.last_message_id IS NOT NULL)
+    ORDER BY
+        CASE WHEN $5 THEN self_member.pinned_at END DESC NULLS LAST,
+        chats.last_message_at DESC NULLS LAST,
+        chats.id DESC
+    ",
+        6,
+    );
+    let query = sqlx::query_as(&sql)
+        .bind(user_id)
+        .bind(kind)
+        .bind(shared_with)
+        .bind(with_messages_only)
+        .bind(pinned_first);
+    let chats: Vec<ChatResponse> = bind_pagination(query, pagination)
+        .fetch_all(executor)
+        .await?;
+    Ok(ListChatsResponse { chats })
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_memberships_for_user<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+) -> Result<ListMembershipsResponse, SqlxError> {
+    let memberships: Vec<MembershipResponse> = sqlx::query_as(
+        "
+    SELECT
+        chats.id AS chat_id,
+        COALESCE(chats.display_name, peer.display_name) AS display_name,
+        chats.kind AS kind,
+        self_member.role AS role
+    FROM
+        chats_members self_member
+        JOIN chats ON self_member.chat_id = chats.id
+        LEFT JOIN private_chats pair ON pair.chat_id = chats.id
+        LEFT JOIN users peer ON peer.id = CASE
+            WHEN pair.user_id_low = self_member.user_id THEN pair.user_id_high
+            ELSE pair.user_id_low
+        END
+    WHERE
+        self_member.user_id = $1
+    ORDER BY
+        chats.id;
+    ",
+    )
+    .bind(user_id)
+    .fetch_all(executor)
+    .await?;
+    Ok(ListMembershipsResponse { memberships })
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn chat_exists<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+) -> Result<Option<ChatKind>, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT kind FROM chats WHERE id = $1;
+    ",
+    )
+    .bind(chat_id)
+    .fetch_optional(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn is_user_in_chat<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    user_id: UserId,
+) -> Result<bool, SqlxError> {
+    let result: IsUserInChatResponse = sqlx::query_as(
+        "
+    SELECT EXISTS(SELECT 1 FROM chats_members WHERE chat_id = $1 AND user_id = $2) AS is_in_chat;
+    ",
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .fetch_one(executor)
+    .await?;
+    Ok(result.is_in_chat)
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_private_chat_previews<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+    pagination: Pagination,
+) -> Result<Vec<PrivateChatPreviewResponse>, SqlxError> {
+    let sql = paginated(
+        "
+    SELECT
+        chats.id AS chat_id,
+        peer.id AS peer_user_id,
+        peer.alias AS peer_alias,
+        peer.display_name AS peer_display_name,
+        chats.last_message_id AS last_message_id,
+        last_message.text AS last_message_text,
+        last_message.user_id AS last_message_user_id,
+        chats.last_message_at AS last_message_at
+    FROM
+        private_chats pair
+        JOIN chats ON chats.id = pair.chat_id
+        JOIN users peer ON peer.id = CASE
+            WHEN pair.user_id_low = $1 THEN pair.user_id_high
+            ELSE pair.user_id_low
+        END
+        LEFT JOIN messages last_message ON last_message.id = chats.last_message_id
+    WHERE
+        chats.kind = 'private'
+        AND (pair.user_id_low = $1 OR pair.user_id_high = $1)
+        AND EXISTS (
+            SELECT 1 FROM chats_members
+            WHERE chats_members.chat_id = chats.id AND chats_members.user_id = $1
+        )
+    ORDER BY
+        chats.last_message_at DESC NULLS LAST,
+        chats.id DESC
+    ",
+        2,
+    );
+    let query = sqlx::query_as(&sql).bind(user_id);
+    bind_pagination(query, pagination).fetch_all(executor).await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_messages_for_user<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    pagination: Pagination,
+) -> Result<ListMessagesResponse, SqlxError> {
+    let sql = paginated(
+        "
+    SELECT
+        messages.id AS id, messages.text AS text, messages.created_at AS created_at, messages.edited_at AS edited_at,
+        messages.user_id as user_id, users.display_name AS user_display_name,
+        messages.reply_to AS reply_to, messages.reply_snapshot AS reply_snapshot,
+        resources.url AS resource_url
+    FROM
+        messages
+        LEFT JOIN users ON messages.user_id = users.id
+        LEFT JOIN resources ON messages.resource_id = resources.id
+    WHERE
+        messages.chat_id = $1
+    ORDER BY
+        messages.id
+    ",
+        2,
+    );
+    let query = sqlx::query_as(&sql).bind(chat_id);
+    let messages: Vec<MessageResponse> = bind_pagination(query, pagination)
+        .fetch_all(executor)
+        .await?;
+    Ok(ListMessagesResponse {
+        messages,
+        total_pages: None,
+    })
+}
+
+/// Aliases consist of letters, digits and underscores only, so they are safe to embed in the
+/// pattern as is. A mention must not be glued to other word characters, e.g. `@bob` doesn't
+/// mention `bo`.
+#[instrument(skip(executor))]
+pub(super) async fn list_messages_involving_user<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    user_id: UserId,
+    after_message_id: MessageId,
+    pagination: Pagination,
+) -> Result<Vec<MessageResponse>, SqlxError> {
+    let sql = paginated(
+        "
+    SELECT
+        messages.id AS id, messages.text AS text, messages.created_at AS created_at, messages.edited_at AS edited_at,
+        messages.user_id as user_id, users.display_name AS user_display_name,
+        messages.reply_to AS reply_to, messages.reply_snapshot AS reply_snapshot,
+        resources.url AS resource_url
+    FROM
+        messages
+        CROSS JOIN (SELECT alias FROM users WHERE id = $2) AS mentioned
+        LEFT JOIN users ON messages.user_id = users.id
+        LEFT JOIN resources ON messages.resource_id = resources.id
+        LEFT JOIN messages replied ON replied.id = messages.reply_to
+    WHERE
+        messages.chat_id = $1
+        AND messages.id > $3
+        AND messages.user_id IS DISTINCT FROM $2
+        AND (
+            messages.text ~* ('(^|[^[:alnum:]_])@' || mentioned.alias || '($|[^[:alnum:]_])')
+            OR replied.user_id = $2
+        )
+    ORDER BY
+        messages.id
+    ",
+        4,
+    );
+    let query = sqlx::query_as(&sql)
+        .bind(chat_id)
+        .bind(user_id)
+        .bind(after_message_id);
+    bind_pagination(query, pagination).fetch_all(executor).await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_channel_feed_for_user<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+    before_message_id: Option<MessageId>,
+    pagination: Pagination,
+) -> Result<Vec<FeedMessageResponse>, SqlxError> {
+    let sql = paginated(
+        "
+    SELECT
+        chats.id AS chat_id, chats.display_name AS chat_display_name,
+        messages.id AS id, messages.text AS text, messages.created_at AS created_at, messages.edited_at AS edited_at,
+        messages.user_id as user_id, users.display_name AS user_display_name,
+        messages.reply_to AS reply_to, messages.reply_snapshot AS reply_snapshot,
+        resources.url AS resource_url
+    FROM
+        chats_members self_member
+        JOIN chats ON chats.id = self_member.chat_id
+        JOIN messages ON messages.chat_id = chats.id
+        LEFT JOIN users ON messages.user_id = users.id
+        LEFT JOIN resources ON messages.resource_id = resources.id
+    WHERE
+        self_member.user_id = $1
+        AND chats.kind = 'channel'
+        AND ($2::bigint IS NULL OR messages.id < $2)
+    ORDER BY
+        messages.id DESC
+    ",
+        3,
+    );
+    let query = sqlx::query_as(&sql).bind(user_id).bind(before_message_id);
+    bind_pagination(query, pagination).fetch_all(executor).await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_messages_for_user_after<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    after_message_id: MessageId,
+    limit: i32,
+) -> Result<ListMessagesResponse, SqlxError> {
+    let messages: Vec<MessageResponse> = sqlx::query_as(
+        "
+    SELECT
+        messages.id AS id, messages.text AS text, messages.created_at AS created_at, messages.edited_at AS edited_at,
+        messages.user_id as user_id, users.display_name AS user_display_name,
+        messages.reply_to AS reply_to, messages.reply_snapshot AS reply_snapshot,
+        resources.url AS resource_url
+    FROM
+        messages
+        LEFT JOIN users ON messages.user_id = users.id
+        LEFT JOIN resources ON messages.resource_id = resources.id
+    WHERE
+        messages.chat_id = $1 AND messages.id > $2
     ORDER BY
-        chats.last_message_at DESC NULLS LAST,
-        chats.id DESC
-    LIMIT $2 OFFSET ($3 - 1) * $2;
+        messages.id
+    LIMIT $3;
     ",
     )
-    .bind(user_id)
-    .bind(page_size)
-    .bind(page_num)
+    .bind(chat_id)
+    .bind(after_message_id)
+    .bind(limit)
     .fetch_all(executor)
     .await?;
-    Ok(ListChatsResponse { chats })
+    Ok(ListMessagesResponse {
+        messages,
+        total_pages: None,
+    })
 }
 
+/// Messages older than `before_message_id`, newest first.
 #[instrument(skip(executor))]
-pub(super) async fn is_user_in_chat<'a, E: PgExecutor<'a>>(
+pub(super) async fn list_messages_for_user_before<'a, E: PgExecutor<'a>>(
     executor: E,
     chat_id: ChatId,
-    user_id: UserId,
-) -> Result<bool, SqlxError> {
-    let result: IsUserInChatResponse = sqlx::query_as(
+    before_message_id: MessageId,
+    limit: i32,
+) -> Result<ListMessagesResponse, SqlxError> {
+    let messages: Vec<MessageResponse> = sqlx::query_as(
         "
-    SELECT EXISTS(SELECT 1 FROM chats_members WHERE chat_id = $1 AND user_id = $2) AS is_in_chat;
+    SELECT
+        messages.id AS id, messages.text AS text, messages.created_at AS created_at, messages.edited_at AS edited_at,
+        messages.user_id as user_id, users.display_name AS user_display_name,
+        messages.reply_to AS reply_to, messages.reply_snapshot AS reply_snapshot,
+        resources.url AS resource_url
+    FROM
+        messages
+        LEFT JOIN users ON messages.user_id = users.id
+        LEFT JOIN resources ON messages.resource_id = resources.id
+    WHERE
+        messages.chat_id = $1 AND messages.id < $2
+    ORDER BY
+        messages.id DESC
+    LIMIT $3;
     ",
     )
     .bind(chat_id)
-    .bind(user_id)
-    .fetch_one(executor)
+    .bind(before_message_id)
+    .bind(limit)
+    .fetch_all(executor)
     .await?;
-    Ok(result.is_in_chat)
+    Ok(ListMessagesResponse {
+        messages,
+        total_pages: None,
+    })
 }
 
 #[instrument(skip(executor))]
-pub(super) async fn list_messages_for_user<'a, E: PgExecutor<'a>>(
+pub(super) async fn get_message<'a, E: PgExecutor<'a>>(
     executor: E,
     chat_id: ChatId,
-    page_size: i32,
-    page_num: i32,
-) -> Result<ListMessagesResponse, SqlxError> {
-    let messages: Vec<MessageResponse> = sqlx::query_as(
+    message_id: MessageId,
+) -> Result<Option<MessageResponse>, SqlxError> {
+    let result = sqlx::query_as(
         "
     SELECT
         messages.id AS id, messages.text AS text, messages.created_at AS created_at, messages.edited_at AS edited_at,
-        messages.user_id as user_id, users.display_name AS user_display_name
+        messages.user_id as user_id, users.display_name AS user_display_name,
+        messages.reply_to AS reply_to, messages.reply_snapshot AS reply_snapshot,
+        resources.url AS resource_url
     FROM
-        messages LEFT JOIN users ON messages.user_id = users.id
+        messages
+        LEFT JOIN users ON messages.user_id = users.id
+        LEFT JOIN resources ON messages.resource_id = resources.id
     WHERE
-        messages.chat_id = $1
+        messages.chat_id = $1 AND messages.id = $2;
+    ",
+    )
+    .bind(chat_id)
+    .bind(message_id)
+    .fetch_one(executor)
+    .await;
+    map_not_found_as_none(result)
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn get_message_chat_id<'a, E: PgExecutor<'a>>(
+    executor: E,
+    message_id: MessageId,
+) -> Result<Option<ChatId>, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT chat_id FROM messages WHERE id = $1;
+    ",
+    )
+    .bind(message_id)
+    .fetch_optional(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_message_edits<'a, E: PgExecutor<'a>>(
+    executor: E,
+    message_id: MessageId,
+) -> Result<Vec<MessageEditResponse>, SqlxError> {
+    sqlx::query_as(
+        "
+    SELECT id, old_text, edited_at
+    FROM message_edits
+    WHERE message_id = $1
+    ORDER BY id;
+    ",
+    )
+    .bind(message_id)
+    .fetch_all(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_thread_messages<'a, E: PgExecutor<'a>>(
+    executor: E,
+    root_message_id: MessageId,
+    max_depth: i32,
+    max_messages: i64,
+) -> Result<Vec<MessageResponse>, SqlxError> {
+    sqlx::query_as(
+        "
+    WITH RECURSIVE thread AS (
+        SELECT id, 0 AS depth FROM messages WHERE id = $1
+        UNION ALL
+        SELECT messages.id, thread.depth + 1
+        FROM messages JOIN thread ON messages.reply_to = thread.id
+        WHERE thread.depth < $2
+    )
+    SELECT
+        messages.id AS id, messages.text AS text, messages.created_at AS created_at, messages.edited_at AS edited_at,
+        messages.user_id as user_id, users.display_name AS user_display_name,
+        messages.reply_to AS reply_to, messages.reply_snapshot AS reply_snapshot,
+        resources.url AS resource_url
+    FROM
+        thread
+        JOIN messages ON messages.id = thread.id
+        LEFT JOIN users ON messages.user_id = users.id
+        LEFT JOIN resources ON messages.resource_id = resources.id
     ORDER BY
         messages.id
-    LIMIT $2 OFFSET ($3 - 1) * $2;
+    LIMIT $3;
     ",
     )
-    .bind(chat_id)
-    .bind(page_size)
-    .bind(page_num)
+    .bind(root_message_id)
+    .bind(max_depth)
+    .bind(max_messages)
     .fetch_all(executor)
-    .await?;
-    Ok(ListMessagesResponse { messages })
+    .await
 }
 
 #[instrument(skip(executor))]
-pub(super) async fn list_messages_for_user_after<'a, E: PgExecutor<'a>>(
+pub(super) async fn get_chat_role<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    user_id: UserId,
+) -> Result<Option<ChatRole>, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT role FROM chats_members WHERE chat_id = $1 AND user_id = $2;
+    ",
+    )
+    .bind(chat_id)
+    .bind(user_id)
+    .fetch_optional(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_chat_join_requests<'a, E: PgExecutor<'a>>(
     executor: E,
     chat_id: ChatId,
-    after_message_id: MessageId,
     limit: i32,
-) -> Result<ListMessagesResponse, SqlxError> {
-    let messages: Vec<MessageResponse> = sqlx::query_as(
+) -> Result<Vec<JoinRequestResponse>, SqlxError> {
+    sqlx::query_as(
         "
     SELECT
-        messages.id AS id, messages.text AS text, messages.created_at AS created_at, messages.edited_at AS edited_at,
-        messages.user_id as user_id, users.display_name AS user_display_name
+        users.id AS user_id,
+        users.alias AS alias,
+        users.display_name AS display_name,
+        chat_join_requests.created_at AS requested_at
     FROM
-        messages LEFT JOIN users ON messages.user_id = users.id
+        chat_join_requests
+        JOIN users ON chat_join_requests.user_id = users.id
     WHERE
-        messages.chat_id = $1 AND messages.id > $2
+        chat_join_requests.chat_id = $1
     ORDER BY
-        messages.id
-    LIMIT $3;
+        chat_join_requests.created_at, chat_join_requests.user_id
+    LIMIT $2;
     ",
     )
     .bind(chat_id)
-    .bind(after_message_id)
     .bind(limit)
     .fetch_all(executor)
-    .await?;
-    Ok(ListMessagesResponse { messages })
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_pending_join_requests_by_chat<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+) -> Result<Vec<PendingJoinRequestsResponse>, SqlxError> {
+    sqlx::query_as(
+        "
+    SELECT
+        chats.id AS chat_id,
+        chats.display_name AS display_name,
+        chats.kind AS kind,
+        COUNT(*) AS pending_requests,
+        MIN(chat_join_requests.created_at) AS oldest_request_at
+    FROM
+        chats_members
+        JOIN chats ON chats.id = chats_members.chat_id
+        JOIN chat_join_requests ON chat_join_requests.chat_id = chats.id
+    WHERE
+        chats_members.user_id = $1
+        AND chats_members.role IN ('owner', 'moderator')
+    GROUP BY
+        chats.id
+    ORDER BY
+        oldest_request_at, chats.id;
+    ",
+    )
+    .bind(user_id)
+    .fetch_all(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn get_chat_kind<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+) -> Result<ChatKind, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT kind FROM chats WHERE id = $1;
+    ",
+    )
+    .bind(chat_id)
+    .fetch_one(executor)
+    .await
+}
+
+/// Duplicate display names are detected among all chat members, also when listing only staff.
+#[instrument(skip(executor))]
+pub(super) async fn list_chat_members<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+    staff_only: bool,
+) -> Result<Vec<ChatMemberResponse>, SqlxError> {
+    sqlx::query_as(
+        "
+    SELECT user_id, alias, display_name, role, duplicate_display_name
+    FROM (
+        SELECT
+            users.id AS user_id,
+            users.alias AS alias,
+            users.display_name AS display_name,
+            chats_members.role AS role,
+            COUNT(*) OVER (PARTITION BY lower(users.display_name)) > 1 AS duplicate_display_name
+        FROM
+            chats_members JOIN users ON chats_members.user_id = users.id
+        WHERE
+            chats_members.chat_id = $1
+    ) members
+    WHERE
+        NOT $2 OR role IN ('owner', 'moderator')
+    ORDER BY
+        role,
+        user_id;
+    ",
+    )
+    .bind(chat_id)
+    .bind(staff_only)
+    .fetch_all(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn count_owned_chats<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+) -> Result<i64, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT COUNT(*)
+    FROM chats_members JOIN chats ON chats_members.chat_id = chats.id
+    WHERE
+        chats_members.user_id = $1
+        AND chats_members.role = 'owner'
+        AND chats.kind IN ('group', 'channel');
+    ",
+    )
+    .bind(user_id)
+    .fetch_one(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn count_other_pinned_chats<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+    chat_id: ChatId,
+) -> Result<i64, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT COUNT(*)
+    FROM chats_members
+    WHERE user_id = $1 AND chat_id <> $2 AND pinned_at IS NOT NULL;
+    ",
+    )
+    .bind(user_id)
+    .bind(chat_id)
+    .fetch_one(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn count_chat_messages<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+) -> Result<i64, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT COUNT(*) FROM messages WHERE chat_id = $1;
+    ",
+    )
+    .bind(chat_id)
+    .fetch_one(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn count_chat_members<'a, E: PgExecutor<'a>>(
+    executor: E,
+    chat_id: ChatId,
+) -> Result<i64, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT COUNT(*) FROM chats_members WHERE chat_id = $1;
+    ",
+    )
+    .bind(chat_id)
+    .fetch_one(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_message_reactions<'a, E: PgExecutor<'a>>(
+    executor: E,
+    message_id: MessageId,
+    caller: UserId,
+) -> Result<Vec<ReactionSummaryResponse>, SqlxError> {
+    sqlx::query_as(
+        "
+    SELECT
+        emoji,
+        COUNT(*) AS count,
+        BOOL_OR(user_id = $2) AS reacted_by_caller
+    FROM
+        message_reactions
+    WHERE
+        message_id = $1
+    GROUP BY
+        emoji
+    ORDER BY
+        count DESC,
+        MIN(created_at);
+    ",
+    )
+    .bind(message_id)
+    .bind(caller)
+    .fetch_all(executor)
+    .await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_user_blocks<'a, E: PgExecutor<'a>>(
+    executor: E,
+    user_id: UserId,
+    after_user_id: i64,
+    pagination: Pagination,
+) -> Result<Vec<BlockedUserResponse>, SqlxError> {
+    let sql = paginated(
+        "
+    SELECT
+        users.id AS user_id,
+        users.alias AS alias,
+        users.display_name AS display_name,
+        user_blocks.created_at AS blocked_at
+    FROM
+        user_blocks
+        JOIN users ON user_blocks.blocked_user_id = users.id
+    WHERE
+        user_blocks.user_id = $1
+        AND user_blocks.blocked_user_id > $2
+    ORDER BY
+        user_blocks.blocked_user_id
+    ",
+        3,
+    );
+    let query = sqlx::query_as(&sql).bind(user_id).bind(after_user_id);
+    bind_pagination(query, pagination).fetch_all(executor).await
+}
+
+#[instrument(skip(executor))]
+pub(super) async fn list_message_reactors<'a, E: PgExecutor<'a>>(
+    executor: E,
+    message_id: MessageId,
+    emoji: &str,
+    after_user_id: i64,
+    pagination: Pagination,
+) -> Result<Vec<ReactorResponse>, SqlxError> {
+    let sql = paginated(
+        "
+    SELECT
+        users.id AS user_id,
+        users.display_name AS display_name,
+        message_reactions.created_at AS reacted_at
+    FROM
+        message_reactions
+        JOIN users ON message_reactions.user_id = users.id
+    WHERE
+        message_reactions.message_id = $1
+        AND message_reactions.emoji = $2
+        AND message_reactions.user_id > $3
+    ORDER BY
+        message_reactions.user_id
+    ",
+        4,
+    );
+    let query = sqlx::query_as(&sql)
+        .bind(message_id)
+        .bind(emoji)
+        .bind(after_user_id);
+    bind_pagination(query, pagination).fetch_all(executor).await
+}
+
+/// Ownership check for session scoped actions, sessions of other users and unknown ones are
+/// indistinguishable, callers report both as `NotFound`.
+#[instrument(skip(executor))]
+pub(super) async fn is_session_of_user<'a, E: PgExecutor<'a>>(
+    executor: E,
+    session_id: SessionId,
+    user_id: UserId,
+) -> Result<bool, SqlxError> {
+    sqlx::query_scalar(
+        "
+    SELECT EXISTS(SELECT 1 FROM sessions WHERE id = $1 AND user_id = $2);
+    ",
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .fetch_one(executor)
+    .await
 }
 
 #[instrument(skip(executor))]
@@ -304,7 +2098,9 @@ pub(super) async fn get_access_token<'a, E: PgExecutor<'a>>(
 ) -> Result<Option<ResolveSessionResponse>, SqlxError> {
     let result = sqlx::query_as(
         "
-    SELECT user_id, access_token_hash, access_token_expires_at FROM sessions WHERE id = $1;
+    SELECT user_id, access_token_hash, access_token_expires_at, last_seen_at
+    FROM sessions
+    WHERE id = $1 AND logged_out_at IS NULL;
     ",
     )
     .bind(session_id)
@@ -313,14 +2109,19 @@ pub(super) async fn get_access_token<'a, E: PgExecutor<'a>>(
     map_not_found_as_none(result)
 }
 
+/// Refresh token of an active session, row stays locked until the transaction ends so concurrent
+/// refreshes are serialized and only the first one sees the token it was given.
 #[instrument(skip(executor))]
-pub(super) async fn get_refresh_token<'a, E: PgExecutor<'a>>(
+pub(super) async fn lock_refresh_token<'a, E: PgExecutor<'a>>(
     executor: E,
     session_id: SessionId,
 ) -> Result<Option<RefreshTokenResponse>, SqlxError> {
     let result = sqlx::query_as(
         "
-    SELECT refresh_token_hash, refresh_token_expires_at, refresh_counter FROM sessions WHERE id = $1;
+    SELECT refresh_token_hash, refresh_token_expires_at, refresh_counter
+    FROM sessions
+    WHERE id = $1 AND logged_out_at IS NULL
+    FOR UPDATE;
     ",
     )
     .bind(session_id)
@@ -328,3 +2129,20 @@ pub(super) async fn get_refresh_token<'a, E: PgExecutor<'a>>(
     .await;
     map_not_found_as_none(result)
 }
+
+#[instrument(skip(executor))]
+pub(super) async fn get_logged_out_session<'a, E: PgExecutor<'a>>(
+    executor: E,
+    session_id: SessionId,
+) -> Result<Option<LoggedOutSessionResponse>, SqlxError> {
+    sqlx::query_as(
+        "
+    SELECT refresh_token_hash, logged_out_at
+    FROM sessions
+    WHERE id = $1 AND logged_out_at IS NOT NULL;
+    ",
+    )
+    .bind(session_id)
+    .fetch_optional(executor)
+    .await
+}
@@ -1,19 +1,24 @@
 use std::string::ToString;
 
 use sqlx::migrate::Migrator;
-use sqlx::{Error as SqlxError, Postgres, Transaction};
+#[cfg(any(test, debug_assertions))]
+use sqlx::{ConnectOptions, Connection};
+use sqlx::{Error as SqlxError, PgExecutor, Postgres, Transaction};
 use tracing::info;
 
-use crate::auth::utils::hash_password;
-use crate::config::{optional_env, ENV_ORIGIN_PASSWORD};
-use crate::database::commands::{create_user, create_with_self_chat};
+use crate::auth::utils::{hash_password, verify_password};
+use crate::config::{AuthConfig, ENV_DB_AUTO_MIGRATE, ENV_ORIGIN_PASSWORD};
+use crate::database::commands::{create_user, create_with_self_chat, ensure_admin};
 use crate::database::connection::DbConnection;
-use crate::models::user::{CreateUserRequest, UserId, UserRole};
+use crate::database::queries::get_user_credentials_by_user_id;
+use crate::database::utils::map_not_found_as_none;
+use crate::error::RequestError;
+use crate::models::user::{BootstrapStatusResponse, CreateUserRequest, UserId, UserRole};
 
 static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
 
-fn origin_user_from_env() -> Result<CreateUserRequest, SqlxError> {
-    let Some(password) = optional_env(ENV_ORIGIN_PASSWORD) else {
+fn origin_user_from_config(auth: &AuthConfig) -> Result<CreateUserRequest, SqlxError> {
+    let Some(password) = &auth.origin_password else {
         return Err(SqlxError::Protocol(format!(
             "missing required env var `{ENV_ORIGIN_PASSWORD}` for initial origin-user bootstrap"
         )));
@@ -22,7 +27,7 @@ fn origin_user_from_env() -> Result<CreateUserRequest, SqlxError> {
         alias: "origin".to_string(),
         display_name: "Origin User".to_string(),
         role: UserRole::Admin,
-        password_hash: hash_password(&password),
+        password_hash: hash_password(password, auth),
         invited_by: None,
     })
 }
@@ -35,19 +40,96 @@ impl DbConnection {
         Ok(())
     }
 
+    /// Startup schema setup, pending migrations are applied only when `DbConfig::auto_migrate`
+    /// allows it, otherwise they fail the startup.
+    pub async fn prepare_schema(&self) -> Result<(), SqlxError> {
+        if self.auto_migrate {
+            return self.init_schema().await;
+        }
+        self.ensure_migrations_applied().await?;
+        self.ensure_origin_user_exists().await?;
+        Ok(())
+    }
+
+    async fn ensure_migrations_applied(&self) -> Result<(), SqlxError> {
+        let tracked: bool =
+            sqlx::query_scalar("SELECT to_regclass('_sqlx_migrations') IS NOT NULL;")
+                .fetch_one(self.pool())
+                .await?;
+        let applied: Vec<i64> = if tracked {
+            sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success;")
+                .fetch_all(self.pool())
+                .await?
+        } else {
+            Vec::new()
+        };
+        let pending: Vec<i64> = MIGRATOR
+            .iter()
+            .filter(|migration| !migration.migration_type.is_down_migration())
+            .map(|migration| migration.version)
+            .filter(|version| !applied.contains(version))
+            .collect();
+        if !pending.is_empty() {
+            return Err(SqlxError::Protocol(format!(
+                "database schema has pending migrations {pending:?}, apply them or set `{ENV_DB_AUTO_MIGRATE}=true`"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Revert every migration, leaving database as it was before the first startup.
     #[cfg(test)]
     pub async fn drop_schema(&self) -> Result<(), SqlxError> {
-        // Revert all applied reversible migrations (versions > -1 includes 0-prefixed migration).
         MIGRATOR.undo(self.pool(), -1).await?;
+        self.private_chats().clear();
         Ok(())
     }
 
+    /// Drop and recreate schema with fresh origin user in a single transaction.
+    /// Destroys all data, so it's only compiled into debug builds.
+    /// Pooled connections which already resolved custom types keep stale type ids, reconnect after reset.
+    #[cfg(any(test, debug_assertions))]
+    pub async fn reset_schema(&self) -> Result<(), SqlxError> {
+        // Dedicated connection, pooled ones may have custom type ids cached from the old schema.
+        let mut connection = self.pool().connect_options().connect().await?;
+        let mut transaction = connection.begin().await?;
+        // Revert all applied reversible migrations (versions > -1 includes 0-prefixed migration).
+        MIGRATOR.undo(&mut *transaction, -1).await?;
+        MIGRATOR.run(&mut *transaction).await?;
+        create_origin_user(&mut transaction, self.auth()).await?;
+        transaction.commit().await?;
+        connection.close().await?;
+        self.private_chats().clear();
+        info!("database schema was reset");
+        Ok(())
+    }
+
+    /// Whether origin user still logs in with the `WALRUS_ORIGIN_PASSWORD` bootstrap secret, i.e. the
+    /// seed password was never rotated. False when the secret is no longer configured.
+    pub async fn origin_password_is_default(&self) -> Result<bool, SqlxError> {
+        let Some(bootstrap_password) = &self.auth().origin_password else {
+            return Ok(false);
+        };
+        let origin_user_id = get_origin_user_id(self.pool()).await?;
+        let Some(creds) = get_user_credentials_by_user_id(self.pool(), origin_user_id).await?
+        else {
+            return Err(SqlxError::RowNotFound);
+        };
+        Ok(verify_password(bootstrap_password, &creds.password_hash))
+    }
+
+    pub async fn bootstrap_status(
+        &self,
+        caller: UserId,
+    ) -> Result<BootstrapStatusResponse, RequestError> {
+        ensure_admin(self.pool(), caller).await?;
+        Ok(BootstrapStatusResponse {
+            origin_password_is_default: self.origin_password_is_default().await?,
+        })
+    }
+
     async fn ensure_origin_user_exists(&self) -> Result<(), SqlxError> {
-        let origin_user_id = sqlx::query_scalar::<_, UserId>(
-            "SELECT origin_user_id FROM system_state WHERE singleton = TRUE;",
-        )
-        .fetch_optional(self.pool())
-        .await?;
+        let origin_user_id = map_not_found_as_none(get_origin_user_id(self.pool()).await)?;
         if let Some(origin_user_id) = origin_user_id {
             let origin_exists: bool =
                 sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM users WHERE id = $1);")
@@ -63,16 +145,23 @@ impl DbConnection {
         }
 
         let mut transaction = self.pool().begin().await?;
-        create_origin_user(&mut transaction).await?;
+        create_origin_user(&mut transaction, self.auth()).await?;
         transaction.commit().await?;
         Ok(())
     }
 }
 
+async fn get_origin_user_id<'a, E: PgExecutor<'a>>(executor: E) -> Result<UserId, SqlxError> {
+    sqlx::query_scalar("SELECT origin_user_id FROM system_state WHERE singleton = TRUE;")
+        .fetch_one(executor)
+        .await
+}
+
 pub async fn create_origin_user(
     transaction: &mut Transaction<'_, Postgres>,
+    auth: &AuthConfig,
 ) -> Result<(), SqlxError> {
-    let user = origin_user_from_env()?;
+    let user = origin_user_from_config(auth)?;
     let origin_user_id = create_user(
         transaction.as_mut(),
         &user.alias,
@@ -0,0 +1,226 @@
+//! Tests of database helpers running inside a rollback transaction, they live here to reach
+//! helpers that aren't visible outside of the `database` module.
+
+use std::net::{IpAddr, Ipv4Addr};
+
+use ipnetwork::IpNetwork;
+
+use crate::auth::utils::{
+    generate_session_token, hash_session_token, new_access_token_expiration,
+    new_refresh_token_expiration,
+};
+use crate::config::AuthConfig;
+use crate::database::commands::{
+    create_session, create_user, ensure_admin, invite_user, record_audit, update_user_alias,
+    update_user_display_name, NewSession,
+};
+use crate::database::queries::{get_whoami_by_user_id, is_session_of_user, list_audit_entries};
+use crate::error::{RequestError, ValidationError};
+use crate::models::audit::{AuditAction, ListAuditQuery};
+use crate::models::session::SessionId;
+use crate::models::user::UserRole;
+use crate::tests::db::{begin_rollback_tx, connect_db};
+
+#[tokio::test]
+async fn ensure_admin_accepts_only_admins() {
+    let mut tx = begin_rollback_tx().await;
+
+    let origin_user_id = 1;
+    let inviter = invite_user(
+        &mut tx,
+        origin_user_id,
+        "rollback_not_admin",
+        "passforadmin",
+        UserRole::Regular,
+        &AuthConfig::default(),
+    )
+    .await
+    .unwrap();
+
+    let err = ensure_admin(tx.as_mut(), inviter).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InsufficientPermissions { .. })
+    ));
+    ensure_admin(tx.as_mut(), origin_user_id).await.unwrap();
+}
+
+#[tokio::test]
+async fn whoami_reflects_alias_and_display_name_updates() {
+    let mut tx = begin_rollback_tx().await;
+
+    let initial_alias = "rollback_whoami";
+    let user_id = invite_user(
+        &mut tx,
+        1,
+        initial_alias,
+        "existing_password_a",
+        UserRole::Regular,
+        &AuthConfig::default(),
+    )
+    .await
+    .unwrap();
+
+    let initial_whoami = get_whoami_by_user_id(tx.as_mut(), user_id).await.unwrap();
+    assert_eq!(initial_whoami.user_id, user_id);
+    assert_eq!(initial_whoami.alias, initial_alias);
+    assert_eq!(initial_whoami.display_name, initial_alias);
+    assert_eq!(initial_whoami.role, UserRole::Regular);
+
+    assert!(
+        update_user_alias(tx.as_mut(), user_id, "rollback_whoami_renamed")
+            .await
+            .unwrap()
+    );
+    assert!(
+        update_user_display_name(tx.as_mut(), user_id, "Renamed Display")
+            .await
+            .unwrap()
+    );
+
+    let updated_whoami = get_whoami_by_user_id(tx.as_mut(), user_id).await.unwrap();
+    assert_eq!(updated_whoami.user_id, user_id);
+    assert_eq!(updated_whoami.alias, "rollback_whoami_renamed");
+    assert_eq!(updated_whoami.display_name, "Renamed Display");
+    assert_eq!(updated_whoami.role, UserRole::Regular);
+}
+
+#[tokio::test]
+async fn rollback_tx_leaves_no_trace() {
+    {
+        let mut tx = begin_rollback_tx().await;
+        invite_user(
+            &mut tx,
+            1,
+            "rollback_ghost",
+            "passforghost",
+            UserRole::Regular,
+            &AuthConfig::default(),
+        )
+        .await
+        .unwrap();
+    }
+
+    let mut tx = begin_rollback_tx().await;
+    let count: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE alias = 'rollback_ghost'")
+            .fetch_one(tx.as_mut())
+            .await
+            .unwrap();
+    assert_eq!(count, 0);
+}
+
+#[tokio::test]
+async fn get_user_by_id_returns_profile() {
+    let mut tx = begin_rollback_tx().await;
+    let origin_user_id = 1;
+    let uncommitted_user = invite_user(
+        &mut tx,
+        origin_user_id,
+        "rollback_profile",
+        "passforprofile",
+        UserRole::Regular,
+        &AuthConfig::default(),
+    )
+    .await
+    .unwrap();
+
+    // Separate connection, the rollback transaction keeps the shared schema from being reset.
+    let db = connect_db().await;
+    let origin = db.get_user_by_id(origin_user_id).await.unwrap();
+    assert_eq!(origin.user_id, origin_user_id);
+    assert_eq!(origin.role, UserRole::Admin);
+    assert!(origin.bio.is_none());
+
+    let err = db.get_user_by_id(uncommitted_user).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
+}
+
+#[tokio::test]
+async fn audit_entries_outlive_their_actor() {
+    let mut tx = begin_rollback_tx().await;
+
+    let origin_user_id = 1;
+    // Bare user without chats, so nothing else holds on to it
+    let actor = create_user(
+        tx.as_mut(),
+        "rollback_audit_actor",
+        "Audit Actor",
+        "not a real hash",
+        UserRole::Admin,
+        Some(origin_user_id),
+    )
+    .await
+    .unwrap();
+    record_audit(
+        tx.as_mut(),
+        actor,
+        AuditAction::AliasChanged,
+        Some(origin_user_id),
+    )
+    .await
+    .unwrap();
+
+    sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(actor)
+        .execute(tx.as_mut())
+        .await
+        .unwrap();
+
+    let query = ListAuditQuery {
+        action: Some(AuditAction::AliasChanged),
+        ..Default::default()
+    };
+    let entries = list_audit_entries(tx.as_mut(), &query, 1).await.unwrap();
+    assert_eq!(entries[0].actor_user_id, None);
+    assert_eq!(entries[0].target_user_id, Some(origin_user_id));
+}
+
+#[tokio::test]
+async fn session_ownership_is_checked_against_user() {
+    let mut tx = begin_rollback_tx().await;
+
+    let origin_user_id = 1;
+    let other_user = invite_user(
+        &mut tx,
+        origin_user_id,
+        "rollback_session_other",
+        "passforsessionother",
+        UserRole::Regular,
+        &AuthConfig::default(),
+    )
+    .await
+    .unwrap();
+    let token_hash = hash_session_token(&generate_session_token());
+    let session_id = create_session(
+        tx.as_mut(),
+        origin_user_id,
+        NewSession {
+            ip: &IpNetwork::from(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            device_name: None,
+            os_version: None,
+            app_version: None,
+            refresh_token_hash: &token_hash,
+            refresh_token_expires_at: &new_refresh_token_expiration(),
+            access_token_hash: &token_hash,
+            access_token_expires_at: &new_access_token_expiration(),
+        },
+    )
+    .await
+    .unwrap();
+
+    assert!(is_session_of_user(tx.as_mut(), session_id, origin_user_id)
+        .await
+        .unwrap());
+    assert!(!is_session_of_user(tx.as_mut(), session_id, other_user)
+        .await
+        .unwrap());
+    assert!(
+        !is_session_of_user(tx.as_mut(), SessionId::from_u128(0), origin_user_id)
+            .await
+            .unwrap()
+    );
+}
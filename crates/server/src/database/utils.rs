@@ -1,3 +1,7 @@
+use std::future::Future;
+
+use tracing::warn;
+
 pub fn map_not_found_as_none<T>(result: Result<T, sqlx::Error>) -> Result<Option<T>, sqlx::Error> {
     match result {
         Ok(ok) => Ok(Some(ok)),
@@ -10,3 +14,29 @@ pub fn map_not_found_as_none<T>(result: Result<T, sqlx::Error>) -> Result<Option
         }
     }
 }
+
+/// Connection was dropped on the server side (restart, admin termination), a fresh one is likely to work.
+pub fn is_connection_lost(error: &sqlx::Error) -> bool {
+    match error {
+        sqlx::Error::Io(_) => true,
+        sqlx::Error::Database(db_error) => db_error
+            .code()
+            .is_some_and(|code| code.starts_with("08") || code == "57P01"),
+        _ => false,
+    }
+}
+
+/// Run idempotent read again if it failed on a dead pooled connection, pool replaces it meanwhile.
+pub async fn retry_once_on_connection_loss<T, F, Fut>(mut operation: F) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    match operation().await {
+        Err(e) if is_connection_lost(&e) => {
+            warn!("retrying read after lost database connection: {e}");
+            operation().await
+        }
+        result => result,
+    }
+}
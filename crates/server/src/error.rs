@@ -5,14 +5,20 @@ use serde::{Deserialize, Serialize};
 use thiserror::Error;
 use tracing::error;
 
+use crate::auth::utils::TokenKind;
 use crate::models::user::UserRole;
 
 #[derive(Debug, Error)]
 pub enum RequestError {
     #[error("bad auth or refresh credentials")]
     BadCredentials,
+    /// Token of the other kind was submitted, e.g. access token to refresh endpoint.
+    #[error("wrong token kind, expected {expected} token")]
+    WrongTokenKind { expected: TokenKind },
     #[error("rate limit exceeded for {0}")]
     RateLimited(&'static str),
+    #[error("captcha verification failed")]
+    CaptchaRejected,
     #[error("interrupted operation")]
     Interrupted,
     #[error("operation is not valid anymore, likely requires session refresh or re-login")]
@@ -45,11 +51,20 @@ pub enum ValidationError {
     AlreadyExists,
     #[error("requested object doesn't exist or the caller doesn't have access")]
     NotFound,
+    /// User referenced by alias in request body doesn't exist, names it so the client can point at it.
+    #[error("user with alias `{alias}` doesn't exist")]
+    UserNotFound { alias: String },
+    /// Produced for outsiders only when existence hiding is disabled, see `ServerConfig::hide_existence`,
+    /// members get it for actions their chat role doesn't allow.
+    #[error("caller doesn't have access to requested object")]
+    Forbidden,
 }
 
-impl IntoResponse for RequestError {
-    fn into_response(self) -> Response {
-        let (status, error) = match self {
+impl RequestError {
+    /// Status and message exposed to clients, internal errors are logged and replaced by a generic
+    /// message.
+    pub fn into_status_and_message(self) -> (StatusCode, String) {
+        match self {
             Self::Sqlx(e) => match e {
                 sqlx::Error::RowNotFound => (StatusCode::NOT_FOUND, "not found".into()),
                 e => {
@@ -61,14 +76,26 @@ impl IntoResponse for RequestError {
                 }
             },
             Self::Validation(e) => match e {
-                ValidationError::NotFound => (StatusCode::NOT_FOUND, e.to_string()),
+                ValidationError::NotFound | ValidationError::UserNotFound { .. } => {
+                    (StatusCode::NOT_FOUND, e.to_string())
+                }
+                ValidationError::Forbidden => (StatusCode::FORBIDDEN, e.to_string()),
+                ValidationError::AlreadyExists => (StatusCode::CONFLICT, e.to_string()),
                 _ => (StatusCode::BAD_REQUEST, e.to_string()),
             },
             e @ Self::BadCredentials => (StatusCode::UNAUTHORIZED, e.to_string()),
+            e @ Self::WrongTokenKind { .. } => (StatusCode::BAD_REQUEST, e.to_string()),
             e @ Self::RateLimited(_) => (StatusCode::TOO_MANY_REQUESTS, e.to_string()),
+            e @ Self::CaptchaRejected => (StatusCode::FORBIDDEN, e.to_string()),
             e @ Self::Interrupted => (StatusCode::CONFLICT, e.to_string()),
             e @ Self::Expired => (StatusCode::UNAUTHORIZED, e.to_string()),
-        };
+        }
+    }
+}
+
+impl IntoResponse for RequestError {
+    fn into_response(self) -> Response {
+        let (status, error) = self.into_status_and_message();
         (status, Json(ErrorResponse { error })).into_response()
     }
 }
@@ -76,16 +103,26 @@ impl IntoResponse for RequestError {
 #[derive(Clone, Debug)]
 pub enum SessionError {
     BadToken,
+    /// Refresh token was used to authorize a request.
+    WrongTokenKind,
     TokenNotFound,
     TokenExpired,
     Internal,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-struct ErrorResponse {
+pub struct ErrorResponse {
     error: String,
 }
 
+impl ErrorResponse {
+    pub fn new(error: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+        }
+    }
+}
+
 impl IntoResponse for SessionError {
     fn into_response(self) -> Response {
         let (status, error) = match self {
@@ -93,6 +130,10 @@ impl IntoResponse for SessionError {
                 StatusCode::BAD_REQUEST,
                 "Missing or bad token in request".to_string(),
             ),
+            Self::WrongTokenKind => (
+                StatusCode::BAD_REQUEST,
+                "Refresh token cannot authorize requests, use access token".to_string(),
+            ),
             Self::TokenNotFound => (
                 StatusCode::UNAUTHORIZED,
                 "Token cannot be found".to_string(),
@@ -109,10 +150,20 @@ impl IntoResponse for SessionError {
 
 #[cfg(test)]
 mod tests {
+    use axum::body::to_bytes;
     use axum::http::StatusCode;
-    use axum::response::IntoResponse;
+    use axum::response::{IntoResponse, Response};
 
-    use super::{RequestError, ValidationError};
+    use super::{ErrorResponse, RequestError, SessionError, ValidationError};
+    use crate::auth::utils::TokenKind;
+    use crate::models::user::UserRole;
+
+    async fn status_and_error(response: Response) -> (StatusCode, String) {
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let payload: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        (status, payload.error)
+    }
 
     #[test]
     fn validation_not_found_maps_to_404() {
@@ -121,8 +172,103 @@ mod tests {
     }
 
     #[test]
-    fn other_validation_errors_stay_400() {
+    fn validation_already_exists_maps_to_409() {
         let response = RequestError::Validation(ValidationError::AlreadyExists).into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn validation_forbidden_maps_to_403() {
+        let response = RequestError::Validation(ValidationError::Forbidden).into_response();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn other_validation_errors_stay_400() {
+        let response = RequestError::Validation(ValidationError::InvalidInput {
+            value: "value".to_string(),
+            reason: "reason".to_string(),
+        })
+        .into_response();
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
+
+    #[tokio::test]
+    async fn request_errors_map_to_status_and_json_body() {
+        let cases = [
+            (RequestError::BadCredentials, StatusCode::UNAUTHORIZED),
+            (
+                RequestError::RateLimited("login"),
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (RequestError::CaptchaRejected, StatusCode::FORBIDDEN),
+            (RequestError::Interrupted, StatusCode::CONFLICT),
+            (RequestError::Expired, StatusCode::UNAUTHORIZED),
+            (
+                RequestError::WrongTokenKind {
+                    expected: TokenKind::Refresh,
+                },
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                RequestError::Validation(ValidationError::LimitExceeded {
+                    subject: "users".to_string(),
+                    unit: "user".to_string(),
+                    attempted: 2,
+                    limit: 1,
+                }),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                RequestError::Validation(ValidationError::InsufficientPermissions {
+                    required: UserRole::Admin,
+                    current: UserRole::Regular,
+                }),
+                StatusCode::BAD_REQUEST,
+            ),
+            (
+                RequestError::Validation(ValidationError::NotFound),
+                StatusCode::NOT_FOUND,
+            ),
+        ];
+        for (error, expected_status) in cases {
+            // Validation errors expose only the inner reason, without the wrapper prefix
+            let expected_error = match &error {
+                RequestError::Validation(e) => e.to_string(),
+                e => e.to_string(),
+            };
+            let (status, body_error) = status_and_error(error.into_response()).await;
+            assert_eq!(status, expected_status, "{expected_error}");
+            assert_eq!(body_error, expected_error);
+        }
+    }
+
+    #[tokio::test]
+    async fn sqlx_errors_hide_details() {
+        let (status, error) =
+            status_and_error(RequestError::Sqlx(sqlx::Error::RowNotFound).into_response()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(error, "not found");
+
+        let (status, error) =
+            status_and_error(RequestError::Sqlx(sqlx::Error::PoolTimedOut).into_response()).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error, "Something went wrong");
+    }
+
+    #[tokio::test]
+    async fn session_errors_map_to_status_and_json_body() {
+        let cases = [
+            (SessionError::BadToken, StatusCode::BAD_REQUEST),
+            (SessionError::WrongTokenKind, StatusCode::BAD_REQUEST),
+            (SessionError::TokenNotFound, StatusCode::UNAUTHORIZED),
+            (SessionError::TokenExpired, StatusCode::UNAUTHORIZED),
+            (SessionError::Internal, StatusCode::INTERNAL_SERVER_ERROR),
+        ];
+        for (error, expected_status) in cases {
+            let (status, body_error) = status_and_error(error.into_response()).await;
+            assert_eq!(status, expected_status);
+            assert!(!body_error.is_empty());
+        }
+    }
 }
@@ -1,6 +1,8 @@
 use clap::Parser;
 
 use crate::config::AppConfig;
+#[cfg(debug_assertions)]
+use crate::database::connection::DbConnection;
 
 pub(crate) mod auth;
 pub(crate) mod config;
@@ -15,8 +17,19 @@ mod tests;
 #[derive(Debug, Parser)]
 #[command(name = "walrus-server")]
 struct CliArgs {
-    #[arg(short, long, value_name = "HOST:PORT")]
-    address: String,
+    /// Can be repeated or comma-separated to listen on several addresses, e.g. IPv4 and IPv6
+    #[arg(
+        short,
+        long = "address",
+        value_name = "HOST:PORT",
+        value_delimiter = ',',
+        required = true
+    )]
+    addresses: Vec<String>,
+    /// Drop all data and recreate schema before start, available only in debug builds
+    #[cfg(debug_assertions)]
+    #[arg(long)]
+    reset_schema: bool,
 }
 
 #[tokio::main]
@@ -24,7 +37,14 @@ async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
 
     let args = CliArgs::parse();
-    let config = AppConfig::from_env_with_address(args.address)?;
+    let config = AppConfig::from_env_with_addresses(args.addresses)?;
+    #[cfg(debug_assertions)]
+    if args.reset_schema {
+        DbConnection::connect(&config.database, &config.auth)
+            .await?
+            .reset_schema()
+            .await?;
+    }
     server::run_all(&config).await?;
 
     Ok(())
@@ -0,0 +1,69 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::ValidationError;
+use crate::models::user::UserId;
+
+pub type AuditEntryId = i64;
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "audit_action")]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    UserInvited,
+    PasswordChanged,
+    AliasChanged,
+    PasswordReset,
+    /// Admin looked up how many messages the target user sent.
+    MessageRateViewed,
+}
+
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct AuditEntryResponse {
+    pub id: AuditEntryId,
+    /// User who performed the action, `None` once that user is deleted.
+    pub actor_user_id: Option<UserId>,
+    pub action: AuditAction,
+    /// User the action was applied to, `None` once that user is deleted.
+    pub target_user_id: Option<UserId>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ListAuditResponse {
+    pub entries: Vec<AuditEntryResponse>,
+}
+
+/// Every filter is optional, entries come newest first and `before` takes the last seen id.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ListAuditQuery {
+    pub actor_user_id: Option<UserId>,
+    pub action: Option<AuditAction>,
+    /// Inclusive lower bound of `created_at`.
+    pub created_from: Option<DateTime<Utc>>,
+    /// Exclusive upper bound of `created_at`.
+    pub created_to: Option<DateTime<Utc>>,
+    pub before: Option<AuditEntryId>,
+    pub limit: Option<i32>,
+}
+
+pub fn validate_audit_query(query: &ListAuditQuery) -> Result<(), ValidationError> {
+    if let (Some(from), Some(to)) = (query.created_from, query.created_to) {
+        if from >= to {
+            return Err(ValidationError::InvalidInput {
+                value: format!("{from}..{to}"),
+                reason: "created_from should be earlier than created_to".to_string(),
+            });
+        }
+    }
+    if let Some(before) = query.before {
+        if before < 1 {
+            return Err(ValidationError::InvalidInput {
+                value: before.to_string(),
+                reason: "before should be a positive audit entry id".to_string(),
+            });
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,26 @@
+use serde::Serialize;
+
+use crate::models::message::MessageLengthLimits;
+
+/// Server side constraints clients can discover at runtime instead of hardcoding them.
+#[derive(Clone, Debug, Serialize)]
+pub struct CapabilitiesResponse {
+    /// Hard cap for `limit` of any listing, specific listings may allow less.
+    pub max_listing_elements: i32,
+    pub message_length_limits: MessageLengthLimits,
+    /// Upper bound for API request bodies, there is no separate file upload limit yet.
+    pub max_request_body_bytes: usize,
+    /// Oldest sessions are dropped once a new login exceeds it.
+    pub max_sessions_per_user: i32,
+    pub features: FeaturesResponse,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FeaturesResponse {
+    /// Real-time chat events over `/chats/{chat_id}/events`.
+    pub websockets: bool,
+    /// Creation of new channels via `/chats/channel`.
+    pub channels: bool,
+    pub server_sent_events: bool,
+    pub uploads: bool,
+}
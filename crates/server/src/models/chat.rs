@@ -1,11 +1,21 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::error::ValidationError;
 use crate::models::message::MessageId;
+use crate::models::user::UserId;
 
 pub type ChatId = i64;
+/// Matches `chats.display_name` column size.
+const CHAT_DISPLAY_NAME_LENGTH_LIMIT: usize = 50;
+/// Matches `chats.description` column size.
+const CHAT_DESCRIPTION_LENGTH_LIMIT: usize = 255;
+/// Members added along with group creation, more can be invited later.
+pub const GROUP_INITIAL_MEMBERS_LIMIT: usize = 100;
 
-#[derive(Clone, Debug, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "chat_kind")]
 #[sqlx(rename_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
@@ -16,9 +26,10 @@ pub enum ChatKind {
     Channel,
 }
 
-#[derive(Clone, Debug, Copy, PartialEq, Eq, sqlx::Type)]
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(type_name = "chat_role")]
 #[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 pub enum ChatRole {
     Owner,
     Moderator,
@@ -29,11 +40,23 @@ pub enum ChatRole {
 pub struct ChatResponse {
     pub id: ChatId,
     pub display_name: Option<String>,
+    pub description: Option<String>,
     pub kind: ChatKind,
     pub last_message_id: Option<MessageId>,
     pub last_message_text: Option<String>,
     pub last_message_at: Option<DateTime<Utc>>,
     pub unread_count: i64,
+    pub pinned_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ListChatsRequest {
+    pub kind: Option<ChatKind>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RecentChatsQuery {
+    pub limit: Option<i32>,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -41,6 +64,157 @@ pub struct ListChatsResponse {
     pub chats: Vec<ChatResponse>,
 }
 
+/// Inbox row of a private chat, resolved to the other participant with the latest message.
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct PrivateChatPreviewResponse {
+    pub chat_id: ChatId,
+    pub peer_user_id: UserId,
+    pub peer_alias: String,
+    pub peer_display_name: String,
+    pub last_message_id: Option<MessageId>,
+    pub last_message_text: Option<String>,
+    pub last_message_user_id: Option<UserId>,
+    pub last_message_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ListPrivateChatPreviewsResponse {
+    pub chats: Vec<PrivateChatPreviewResponse>,
+}
+
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct MembershipResponse {
+    pub chat_id: ChatId,
+    pub display_name: Option<String>,
+    pub kind: ChatKind,
+    pub role: ChatRole,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ListMembershipsResponse {
+    pub memberships: Vec<MembershipResponse>,
+}
+
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct ChatMemberResponse {
+    pub user_id: UserId,
+    pub alias: String,
+    pub display_name: String,
+    pub role: ChatRole,
+    /// Another member of the chat has the same display name ignoring case, client should show
+    /// `alias` next to it to tell them apart.
+    pub duplicate_display_name: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ListMembersResponse {
+    pub members_count: i64,
+    /// `None` when the caller isn't allowed to see other members, see [`can_see_members`].
+    pub members: Option<Vec<ChatMemberResponse>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ListChatStaffResponse {
+    /// Owners first, then moderators.
+    pub staff: Vec<ChatMemberResponse>,
+}
+
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct JoinRequestResponse {
+    pub user_id: UserId,
+    pub alias: String,
+    pub display_name: String,
+    pub requested_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ListJoinRequestsResponse {
+    /// Oldest first.
+    pub requests: Vec<JoinRequestResponse>,
+}
+
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct PendingJoinRequestsResponse {
+    pub chat_id: ChatId,
+    pub display_name: Option<String>,
+    pub kind: ChatKind,
+    pub pending_requests: i64,
+    pub oldest_request_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ListChatsWithPendingRequestsResponse {
+    /// Chats waiting longest come first.
+    pub chats: Vec<PendingJoinRequestsResponse>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MyRoleResponse {
+    pub role: ChatRole,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CreateGroupChatRequest {
+    pub display_name: String,
+    pub description: Option<String>,
+    /// Aliases of users to add besides the caller, who becomes the owner.
+    pub members: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CreateChannelChatRequest {
+    pub display_name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CreateChatResponse {
+    pub chat_id: ChatId,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PrivateChatResponse {
+    pub chat_id: ChatId,
+}
+
+/// Personal notes chat, created for every user at invite time.
+#[derive(Clone, Debug, Serialize)]
+pub struct SelfChatResponse {
+    pub chat_id: ChatId,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TotalUnreadResponse {
+    pub total_unread: i64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UnreadCountsRequest {
+    pub chat_ids: Vec<ChatId>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct UnreadCountsResponse {
+    /// Only chats the caller is a member of, others are left out.
+    pub unread_counts: HashMap<ChatId, i64>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct LatestMessageIdsRequest {
+    pub chat_ids: Vec<ChatId>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LatestMessageIdsResponse {
+    /// Only chats the caller is a member of, `None` for chats without messages.
+    pub latest_message_ids: HashMap<ChatId, Option<MessageId>>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UpdateMemberRoleRequest {
+    pub role: ChatRole,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct MarkChatReadRequest {
     pub up_to_message_id: MessageId,
@@ -50,3 +224,93 @@ pub struct MarkChatReadRequest {
 pub struct IsUserInChatResponse {
     pub is_in_chat: bool,
 }
+
+/// Channel audience is hidden from plain members, only owners and moderators can see who is in it.
+pub fn can_see_members(kind: ChatKind, role: ChatRole) -> bool {
+    kind != ChatKind::Channel || role != ChatRole::Member
+}
+
+/// Only group chats and channels can be joined on request, other kinds have fixed membership.
+pub fn accepts_join_requests(kind: ChatKind) -> bool {
+    matches!(kind, ChatKind::Group | ChatKind::Channel)
+}
+
+/// Join requests are handled by owners and moderators.
+pub fn can_manage_join_requests(role: ChatRole) -> bool {
+    role != ChatRole::Member
+}
+
+/// Only group chats and channels have members that can be removed or promoted.
+pub fn has_managed_membership(kind: ChatKind) -> bool {
+    matches!(kind, ChatKind::Group | ChatKind::Channel)
+}
+
+/// Owners and moderators manage members, but only owners can act on other owners.
+pub fn can_manage_member(actor: ChatRole, target: ChatRole) -> bool {
+    match actor {
+        ChatRole::Owner => true,
+        ChatRole::Moderator => target != ChatRole::Owner,
+        ChatRole::Member => false,
+    }
+}
+
+/// Channels are broadcast-only, plain members read what owners and moderators post.
+pub fn can_post_messages(kind: ChatKind, role: ChatRole) -> bool {
+    kind != ChatKind::Channel || role != ChatRole::Member
+}
+
+pub fn validate_chat_display_name(display_name: &str) -> Result<(), ValidationError> {
+    if display_name.trim().len() != display_name.len() {
+        return Err(ValidationError::InvalidInput {
+            value: display_name.to_string(),
+            reason: "chat display name cannot be surrounded with whitespace characters".to_string(),
+        });
+    }
+    if display_name.is_empty() {
+        return Err(ValidationError::InvalidInput {
+            value: display_name.to_string(),
+            reason: "chat display name cannot be empty".to_string(),
+        });
+    }
+    if display_name.chars().count() > CHAT_DISPLAY_NAME_LENGTH_LIMIT {
+        return Err(ValidationError::InvalidInput {
+            value: display_name.to_string(),
+            reason: format!(
+                "chat display name cannot be longer than {} chars",
+                CHAT_DISPLAY_NAME_LENGTH_LIMIT
+            ),
+        });
+    }
+    Ok(())
+}
+
+pub fn validate_chat_description(description: &str) -> Result<(), ValidationError> {
+    if description.chars().count() > CHAT_DESCRIPTION_LENGTH_LIMIT {
+        return Err(ValidationError::InvalidInput {
+            value: description.to_string(),
+            reason: format!(
+                "chat description cannot be longer than {} chars",
+                CHAT_DESCRIPTION_LENGTH_LIMIT
+            ),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chat_role_uses_snake_case_on_the_wire() {
+        for (role, wire) in [
+            (ChatRole::Owner, "\"owner\""),
+            (ChatRole::Moderator, "\"moderator\""),
+            (ChatRole::Member, "\"member\""),
+        ] {
+            assert_eq!(serde_json::to_string(&role).unwrap(), wire);
+            assert_eq!(serde_json::from_str::<ChatRole>(wire).unwrap(), role);
+        }
+        serde_json::from_str::<ChatRole>("\"Owner\"").unwrap_err();
+    }
+}
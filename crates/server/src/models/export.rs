@@ -0,0 +1,41 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::models::chat::{ChatId, MembershipResponse};
+use crate::models::message::MessageId;
+use crate::models::user::UserProfileResponse;
+
+/// Messages read from the database per query while collecting an export.
+pub const EXPORT_MESSAGES_BATCH_SIZE: i64 = 1000;
+
+/// Message authored by the exporting user.
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct ExportedMessageResponse {
+    pub id: MessageId,
+    pub chat_id: ChatId,
+    pub text: Option<String>,
+    pub reply_to: Option<MessageId>,
+    pub created_at: DateTime<Utc>,
+    pub edited_at: Option<DateTime<Utc>>,
+}
+
+/// Session metadata without token material.
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct ExportedSessionResponse {
+    pub ip: String,
+    pub first_seen_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+    pub device_name: Option<String>,
+    pub os_version: Option<String>,
+    pub app_version: Option<String>,
+}
+
+/// Everything stored about the user, for data portability requests.
+#[derive(Clone, Debug, Serialize)]
+pub struct UserExportResponse {
+    pub exported_at: DateTime<Utc>,
+    pub profile: UserProfileResponse,
+    pub memberships: Vec<MembershipResponse>,
+    pub messages: Vec<ExportedMessageResponse>,
+    pub sessions: Vec<ExportedSessionResponse>,
+}
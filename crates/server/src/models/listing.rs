@@ -11,15 +11,33 @@ pub struct ListingQuery {
     pub limit: Option<i32>,
     pub page: Option<i32>,
     pub offset: Option<MessageId>,
+    pub before: Option<MessageId>,
+    pub after: Option<MessageId>,
 }
 
 #[derive(Debug)]
 pub enum ListingMode {
-    Page { limit: i32, page: i32 },
-    Offset { offset: MessageId, limit: i32 },
+    Page {
+        limit: i32,
+        page: i32,
+    },
+    Offset {
+        offset: MessageId,
+        limit: i32,
+    },
+    /// Elements older than `before` and newer than `after`, limit is split between directions.
+    /// At least one cursor is set, `after` is never lower than `before`.
+    Window {
+        before: Option<MessageId>,
+        after: Option<MessageId>,
+        limit: i32,
+    },
 }
 
-pub fn validate_limit(limit: i32) -> Result<(), RequestError> {
+/// Validate requested page size against resource specific `max_limit`,
+/// which itself is capped by [`MAX_LISTING_ELEMENTS`].
+pub fn validate_limit(limit: i32, max_limit: i32) -> Result<(), RequestError> {
+    let max_limit = max_limit.min(MAX_LISTING_ELEMENTS);
     if limit < 1 {
         return Err(ValidationError::InvalidInput {
             value: limit.to_string(),
@@ -27,12 +45,12 @@ pub fn validate_limit(limit: i32) -> Result<(), RequestError> {
         }
         .into());
     }
-    if limit > MAX_LISTING_ELEMENTS {
+    if limit > max_limit {
         return Err(ValidationError::LimitExceeded {
             subject: "listing limit".to_string(),
             unit: "element".to_string(),
             attempted: limit as usize,
-            limit: MAX_LISTING_ELEMENTS as usize,
+            limit: max_limit as usize,
         }
         .into());
     }
@@ -62,9 +80,12 @@ pub fn validate_message_offset(offset: MessageId) -> Result<(), RequestError> {
 }
 
 impl ListingMode {
-    pub fn from_query(query: ListingQuery) -> Result<Self, RequestError> {
-        let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
-        validate_limit(limit)?;
+    pub fn from_query(query: ListingQuery, max_limit: i32) -> Result<Self, RequestError> {
+        let limit = query.limit.unwrap_or(DEFAULT_LIMIT.min(max_limit));
+        validate_limit(limit, max_limit)?;
+        if query.before.is_some() || query.after.is_some() {
+            return Self::window_from_query(query, limit);
+        }
         if let Some(offset) = query.offset {
             if query.page.is_some() {
                 return Err(ValidationError::InvalidInput {
@@ -81,19 +102,73 @@ impl ListingMode {
             Ok(Self::Page { limit, page })
         }
     }
+
+    /// `(limit, page)` of page mode, for listings without a keyset cursor. Other modes are rejected,
+    /// `listing` names the listing in the error.
+    pub fn into_page(self, listing: &str) -> Result<(i32, i32), RequestError> {
+        let (value, mode) = match self {
+            Self::Page { limit, page } => return Ok((limit, page)),
+            Self::Offset { .. } => ("offset", "offset"),
+            Self::Window { .. } => ("before/after", "window"),
+        };
+        Err(ValidationError::InvalidInput {
+            value: value.to_string(),
+            reason: format!("{mode} mode is not supported for {listing}"),
+        }
+        .into())
+    }
+
+    fn window_from_query(query: ListingQuery, limit: i32) -> Result<Self, RequestError> {
+        for (name, is_set) in [
+            ("page", query.page.is_some()),
+            ("offset", query.offset.is_some()),
+        ] {
+            if is_set {
+                return Err(ValidationError::InvalidInput {
+                    value: name.to_string(),
+                    reason: format!("{name} cannot be used with before/after cursors"),
+                }
+                .into());
+            }
+        }
+        for cursor in [query.before, query.after].into_iter().flatten() {
+            validate_message_offset(cursor)?;
+        }
+        if let (Some(before), Some(after)) = (query.before, query.after) {
+            if after < before {
+                return Err(ValidationError::InvalidInput {
+                    value: after.to_string(),
+                    reason: "after cannot be lower than before, directions would overlap"
+                        .to_string(),
+                }
+                .into());
+            }
+        }
+        Ok(Self::Window {
+            before: query.before,
+            after: query.after,
+            limit,
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::server::constants::{MAX_CHAT_LISTING_ELEMENTS, MAX_MESSAGE_LISTING_ELEMENTS};
 
     #[test]
     fn from_query_uses_defaults_for_page_mode() {
-        let mode = ListingMode::from_query(ListingQuery {
-            limit: None,
-            page: None,
-            offset: None,
-        })
+        let mode = ListingMode::from_query(
+            ListingQuery {
+                limit: None,
+                page: None,
+                offset: None,
+                before: None,
+                after: None,
+            },
+            MAX_LISTING_ELEMENTS,
+        )
         .unwrap();
 
         match mode {
@@ -101,17 +176,22 @@ mod tests {
                 assert_eq!(limit, DEFAULT_LIMIT);
                 assert_eq!(page, DEFAULT_PAGE);
             }
-            ListingMode::Offset { .. } => panic!("expected page mode"),
+            _ => panic!("expected page mode"),
         }
     }
 
     #[test]
     fn from_query_parses_offset_mode() {
-        let mode = ListingMode::from_query(ListingQuery {
-            limit: Some(25),
-            page: None,
-            offset: Some(42),
-        })
+        let mode = ListingMode::from_query(
+            ListingQuery {
+                limit: Some(25),
+                page: None,
+                offset: Some(42),
+                before: None,
+                after: None,
+            },
+            MAX_LISTING_ELEMENTS,
+        )
         .unwrap();
 
         match mode {
@@ -119,17 +199,22 @@ mod tests {
                 assert_eq!(offset, 42);
                 assert_eq!(limit, 25);
             }
-            ListingMode::Page { .. } => panic!("expected offset mode"),
+            _ => panic!("expected offset mode"),
         }
     }
 
     #[test]
     fn from_query_rejects_offset_with_page() {
-        let err = ListingMode::from_query(ListingQuery {
-            limit: Some(25),
-            page: Some(2),
-            offset: Some(42),
-        })
+        let err = ListingMode::from_query(
+            ListingQuery {
+                limit: Some(25),
+                page: Some(2),
+                offset: Some(42),
+                before: None,
+                after: None,
+            },
+            MAX_LISTING_ELEMENTS,
+        )
         .expect_err("expected invalid input error");
 
         assert!(matches!(
@@ -140,11 +225,16 @@ mod tests {
 
     #[test]
     fn from_query_rejects_invalid_limit() {
-        let err = ListingMode::from_query(ListingQuery {
-            limit: Some(0),
-            page: Some(1),
-            offset: None,
-        })
+        let err = ListingMode::from_query(
+            ListingQuery {
+                limit: Some(0),
+                page: Some(1),
+                offset: None,
+                before: None,
+                after: None,
+            },
+            MAX_LISTING_ELEMENTS,
+        )
         .expect_err("expected invalid input error");
 
         assert!(matches!(
@@ -155,11 +245,16 @@ mod tests {
 
     #[test]
     fn from_query_rejects_page_below_one() {
-        let err = ListingMode::from_query(ListingQuery {
-            limit: Some(5),
-            page: Some(0),
-            offset: None,
-        })
+        let err = ListingMode::from_query(
+            ListingQuery {
+                limit: Some(5),
+                page: Some(0),
+                offset: None,
+                before: None,
+                after: None,
+            },
+            MAX_LISTING_ELEMENTS,
+        )
         .expect_err("expected invalid input error");
 
         assert!(matches!(
@@ -170,11 +265,16 @@ mod tests {
 
     #[test]
     fn from_query_rejects_negative_offset() {
-        let err = ListingMode::from_query(ListingQuery {
-            limit: Some(10),
-            page: None,
-            offset: Some(-1),
-        })
+        let err = ListingMode::from_query(
+            ListingQuery {
+                limit: Some(10),
+                page: None,
+                offset: Some(-1),
+                before: None,
+                after: None,
+            },
+            MAX_LISTING_ELEMENTS,
+        )
         .expect_err("expected invalid input error");
 
         assert!(matches!(
@@ -182,4 +282,132 @@ mod tests {
             RequestError::Validation(ValidationError::InvalidInput { value, .. }) if value == "-1"
         ));
     }
+
+    fn window_query(before: Option<MessageId>, after: Option<MessageId>) -> ListingQuery {
+        ListingQuery {
+            limit: Some(10),
+            page: None,
+            offset: None,
+            before,
+            after,
+        }
+    }
+
+    #[test]
+    fn into_page_accepts_only_page_mode() {
+        let page = ListingMode::Page { limit: 5, page: 2 };
+        assert_eq!(page.into_page("test listing").unwrap(), (5, 2));
+        let err = ListingMode::Offset {
+            offset: 42,
+            limit: 5,
+        }
+        .into_page("test listing")
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            RequestError::Validation(ValidationError::InvalidInput { value, .. }) if value == "offset"
+        ));
+    }
+
+    #[test]
+    fn from_query_parses_window_mode() {
+        let mode =
+            ListingMode::from_query(window_query(Some(7), Some(7)), MAX_LISTING_ELEMENTS).unwrap();
+        assert!(matches!(
+            mode,
+            ListingMode::Window {
+                before: Some(7),
+                after: Some(7),
+                limit: 10
+            }
+        ));
+        let mode =
+            ListingMode::from_query(window_query(None, Some(3)), MAX_LISTING_ELEMENTS).unwrap();
+        assert!(matches!(
+            mode,
+            ListingMode::Window {
+                before: None,
+                after: Some(3),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn from_query_rejects_invalid_window() {
+        let overlapping =
+            ListingMode::from_query(window_query(Some(8), Some(5)), MAX_LISTING_ELEMENTS);
+        assert!(matches!(
+            overlapping,
+            Err(RequestError::Validation(ValidationError::InvalidInput { value, .. })) if value == "5"
+        ));
+        let mut with_page = window_query(Some(8), None);
+        with_page.page = Some(1);
+        assert!(matches!(
+            ListingMode::from_query(with_page, MAX_LISTING_ELEMENTS),
+            Err(RequestError::Validation(ValidationError::InvalidInput { value, .. })) if value == "page"
+        ));
+        let negative = ListingMode::from_query(window_query(Some(-1), None), MAX_LISTING_ELEMENTS);
+        assert!(matches!(
+            negative,
+            Err(RequestError::Validation(ValidationError::InvalidInput { value, .. })) if value == "-1"
+        ));
+    }
+
+    fn query_with_limit(limit: i32) -> ListingQuery {
+        ListingQuery {
+            limit: Some(limit),
+            page: None,
+            offset: None,
+            before: None,
+            after: None,
+        }
+    }
+
+    fn assert_limit_exceeded(result: Result<ListingMode, RequestError>, expected_limit: i32) {
+        assert!(matches!(
+            result,
+            Err(RequestError::Validation(ValidationError::LimitExceeded { limit, .. }))
+                if limit == expected_limit as usize
+        ));
+    }
+
+    #[test]
+    fn from_query_enforces_chat_listing_max() {
+        let max = MAX_CHAT_LISTING_ELEMENTS;
+        assert!(ListingMode::from_query(query_with_limit(max), max).is_ok());
+        assert_limit_exceeded(ListingMode::from_query(query_with_limit(max + 1), max), max);
+    }
+
+    #[test]
+    fn from_query_enforces_message_listing_max() {
+        let max = MAX_MESSAGE_LISTING_ELEMENTS;
+        assert!(ListingMode::from_query(query_with_limit(max), max).is_ok());
+        assert_limit_exceeded(ListingMode::from_query(query_with_limit(max + 1), max), max);
+    }
+
+    #[test]
+    fn from_query_caps_resource_max_at_hard_limit() {
+        let max = MAX_LISTING_ELEMENTS + 100;
+        assert_limit_exceeded(
+            ListingMode::from_query(query_with_limit(MAX_LISTING_ELEMENTS + 1), max),
+            MAX_LISTING_ELEMENTS,
+        );
+    }
+
+    #[test]
+    fn from_query_default_limit_respects_resource_max() {
+        let mode = ListingMode::from_query(
+            ListingQuery {
+                limit: None,
+                page: None,
+                offset: None,
+                before: None,
+                after: None,
+            },
+            10,
+        )
+        .unwrap();
+        assert!(matches!(mode, ListingMode::Page { limit: 10, .. }));
+    }
 }
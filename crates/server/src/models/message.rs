@@ -2,35 +2,235 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
 use crate::error::ValidationError;
+use crate::models::chat::{ChatId, ChatKind, ChatRole};
+use crate::models::resource::ResourceId;
 use crate::models::user::UserId;
 
 pub type MessageId = i64;
+pub type ScheduledMessageId = i64;
+/// Matches `messages.text` column size, in characters.
 pub const MESSAGE_TEXT_MAX_LENGTH: usize = 4096;
+/// Matches `message_reactions.emoji` column size, in characters.
+pub const REACTION_MAX_LENGTH: usize = 32;
+/// Upper bound for ids clients assign to pending messages, in characters.
+pub const CLIENT_TEMP_ID_MAX_LENGTH: usize = 64;
+/// How deep reply chains are followed when collecting a thread.
+pub const THREAD_MAX_DEPTH: i32 = 32;
+/// Upper bound for messages in single thread response, root included.
+pub const THREAD_MAX_MESSAGES: i64 = 500;
+
+/// Maximum message text length per chat kind, in characters. None exceeds the column size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct MessageLengthLimits {
+    pub with_self: usize,
+    pub private: usize,
+    pub group: usize,
+    pub channel: usize,
+}
+
+impl MessageLengthLimits {
+    pub fn for_kind(&self, kind: ChatKind) -> usize {
+        match kind {
+            ChatKind::WithSelf => self.with_self,
+            ChatKind::Private => self.private,
+            ChatKind::Group => self.group,
+            ChatKind::Channel => self.channel,
+        }
+    }
+}
+
+impl Default for MessageLengthLimits {
+    fn default() -> Self {
+        Self {
+            with_self: MESSAGE_TEXT_MAX_LENGTH,
+            private: MESSAGE_TEXT_MAX_LENGTH,
+            group: MESSAGE_TEXT_MAX_LENGTH,
+            channel: MESSAGE_TEXT_MAX_LENGTH,
+        }
+    }
+}
 
 #[derive(Clone, Debug, Serialize, sqlx::FromRow)]
 pub struct MessageResponse {
+    /// Canonical sort key, follows insertion order. `created_at` is informational and may disagree
+    /// with it, e.g. after a server clock adjustment.
     pub id: MessageId,
     pub text: Option<String>,
     pub created_at: DateTime<Utc>,
     pub edited_at: Option<DateTime<Utc>>,
     pub user_id: Option<UserId>,
     pub user_display_name: Option<String>,
-    // pub resource_url: Option<ResourceId>,
+    pub reply_to: Option<MessageId>,
+    /// Text of the replied message captured at send time, unaffected by its later edits.
+    pub reply_snapshot: Option<String>,
+    pub resource_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct ReactionSummaryResponse {
+    pub emoji: String,
+    pub count: i64,
+    pub reacted_by_caller: bool,
+}
+
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct ReactorResponse {
+    pub user_id: UserId,
+    pub display_name: String,
+    pub reacted_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ListReactorsResponse {
+    pub reactors: Vec<ReactorResponse>,
+}
+
+/// Single message with everything needed to render it standalone, e.g. when following a deep link.
+#[derive(Clone, Debug, Serialize)]
+pub struct MessageDetailsResponse {
+    #[serde(flatten)]
+    pub message: MessageResponse,
+    pub sender_role: Option<ChatRole>,
+    pub reply_to_message: Option<MessageResponse>,
+    pub reactions: Vec<ReactionSummaryResponse>,
 }
 
 #[derive(Clone, Debug, Serialize)]
 pub struct ListMessagesResponse {
     pub messages: Vec<MessageResponse>,
+    /// Number of pages of the requested size, only counted in page mode when asked for. A page
+    /// beyond it is valid and simply empty.
+    pub total_pages: Option<i64>,
+}
+
+/// Message in the feed merged across caller's channels, tagged with the channel it was posted in.
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct FeedMessageResponse {
+    pub chat_id: ChatId,
+    pub chat_display_name: Option<String>,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub message: MessageResponse,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ChannelFeedResponse {
+    pub messages: Vec<FeedMessageResponse>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ListMessagesQuery {
+    pub with_total: Option<bool>,
+}
+
+pub type MessageEditId = i64;
+
+/// Message text as it was before a single edit.
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct MessageEditResponse {
+    pub id: MessageEditId,
+    pub old_text: Option<String>,
+    /// When this text was replaced by the next version.
+    pub edited_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ListMessageEditsResponse {
+    pub edits: Vec<MessageEditResponse>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ListMessagesSinceQuery {
+    pub limit: Option<i32>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ChatEventsQuery {
+    /// Last message id seen before reconnecting, newer messages are replayed before live events.
+    pub last_event_id: Option<MessageId>,
 }
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct SendMessageRequest {
     pub text: String,
+    pub reply_to: Option<MessageId>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ScheduleMessageRequest {
+    pub text: String,
+    /// Time in the past is accepted, the message is sent with the next delivery round.
+    pub send_at: DateTime<Utc>,
+}
+
+/// Historical message migrated from another messenger, keeps its original author and time.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ImportedMessage {
+    pub user_id: UserId,
+    pub text: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ImportMessagesRequest {
+    pub messages: Vec<ImportedMessage>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ImportMessagesResponse {
+    /// Ids of imported messages, oldest first.
+    pub message_ids: Vec<MessageId>,
+}
+
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct ScheduledMessageResponse {
+    pub id: ScheduledMessageId,
+    pub chat_id: ChatId,
+    pub user_id: UserId,
+    pub text: String,
+    pub send_at: DateTime<Utc>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Scheduled message turned into a regular one, to be announced to chat subscribers.
+#[derive(Clone, Debug)]
+pub struct DeliveredMessage {
+    pub chat_id: ChatId,
+    pub message: MessageResponse,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SendPrivateMessageRequest {
+    pub recipient_alias: String,
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SendPrivateMessageResponse {
+    pub chat_id: ChatId,
+    pub message_id: MessageId,
+    pub message: MessageResponse,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct EditMessageRequest {
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ReplaceMessageResourceRequest {
+    pub resource_id: ResourceId,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct AddReactionRequest {
+    pub emoji: String,
 }
 
 #[derive(Clone, Debug, Serialize)]
 pub struct SendMessageResponse {
     pub message_id: MessageId,
+    pub message: MessageResponse,
 }
 
 pub fn validate_message_text(text: &str) -> Result<(), ValidationError> {
@@ -40,13 +240,76 @@ pub fn validate_message_text(text: &str) -> Result<(), ValidationError> {
             reason: "text should not be empty".to_string(),
         });
     }
-    if text.len() > MESSAGE_TEXT_MAX_LENGTH {
+    validate_message_length(text, MESSAGE_TEXT_MAX_LENGTH)
+}
+
+/// Kind-specific limit check, applies on top of [`validate_message_text`].
+pub fn validate_message_length(text: &str, limit: usize) -> Result<(), ValidationError> {
+    let length = text.chars().count();
+    if length > limit {
         return Err(ValidationError::LimitExceeded {
             subject: "message text length".to_string(),
             unit: "character".to_string(),
-            attempted: text.len(),
-            limit: MESSAGE_TEXT_MAX_LENGTH,
+            attempted: length,
+            limit,
+        });
+    }
+    Ok(())
+}
+
+pub fn validate_client_temp_id(client_temp_id: &str) -> Result<(), ValidationError> {
+    if client_temp_id.is_empty() {
+        return Err(ValidationError::InvalidInput {
+            value: client_temp_id.to_string(),
+            reason: "client temp id should not be empty".to_string(),
+        });
+    }
+    let length = client_temp_id.chars().count();
+    if length > CLIENT_TEMP_ID_MAX_LENGTH {
+        return Err(ValidationError::LimitExceeded {
+            subject: "client temp id length".to_string(),
+            unit: "character".to_string(),
+            attempted: length,
+            limit: CLIENT_TEMP_ID_MAX_LENGTH,
         });
     }
     Ok(())
 }
+
+pub fn validate_reaction(emoji: &str) -> Result<(), ValidationError> {
+    if emoji.is_empty() || emoji.chars().any(char::is_whitespace) {
+        return Err(ValidationError::InvalidInput {
+            value: emoji.to_string(),
+            reason: "reaction should be non-empty and contain no whitespace".to_string(),
+        });
+    }
+    let length = emoji.chars().count();
+    if length > REACTION_MAX_LENGTH {
+        return Err(ValidationError::LimitExceeded {
+            subject: "reaction length".to_string(),
+            unit: "character".to_string(),
+            attempted: length,
+            limit: REACTION_MAX_LENGTH,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_limits_count_characters_not_bytes() {
+        validate_message_text(&"ж".repeat(MESSAGE_TEXT_MAX_LENGTH)).unwrap();
+        let err = validate_message_text(&"ж".repeat(MESSAGE_TEXT_MAX_LENGTH + 1)).unwrap_err();
+        assert!(matches!(
+            err,
+            ValidationError::LimitExceeded { attempted, .. } if attempted == MESSAGE_TEXT_MAX_LENGTH + 1
+        ));
+
+        // Four bytes per emoji in UTF-8
+        validate_reaction(&"👍".repeat(REACTION_MAX_LENGTH)).unwrap();
+        validate_reaction(&"👍".repeat(REACTION_MAX_LENGTH + 1)).unwrap_err();
+    }
+}
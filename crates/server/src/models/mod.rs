@@ -1,6 +1,10 @@
+pub mod audit;
+pub mod capabilities;
 pub mod chat;
+pub mod export;
 pub mod listing;
 pub mod message;
 pub mod resource;
 pub mod session;
+pub mod stats;
 pub mod user;
@@ -10,6 +10,13 @@ pub struct ResolveSessionResponse {
     pub user_id: UserId,
     pub access_token_hash: SessionToken,
     pub access_token_expires_at: DateTime<Utc>,
+    pub last_seen_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct LoggedOutSessionResponse {
+    pub refresh_token_hash: SessionToken,
+    pub logged_out_at: DateTime<Utc>,
 }
 
 #[derive(Clone, Debug, sqlx::FromRow)]
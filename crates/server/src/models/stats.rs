@@ -0,0 +1,47 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::models::chat::ChatId;
+use crate::models::user::UserId;
+
+/// Overview for the admin dashboard, every count is exact and read from one snapshot.
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct AdminStatsResponse {
+    pub users: i64,
+    #[sqlx(flatten)]
+    pub chats: ChatCountsResponse,
+    pub messages: i64,
+    /// Sessions neither logged out nor past refresh token expiry.
+    pub active_sessions: i64,
+}
+
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct ChatCountsResponse {
+    pub with_self: i64,
+    pub private: i64,
+    pub group: i64,
+    pub channel: i64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct UserMessageRateQuery {
+    /// Inclusive lower bound of message `created_at`.
+    pub since: DateTime<Utc>,
+    pub by_chat: Option<bool>,
+}
+
+/// Messages a user sent in a time window, for spotting spammers.
+#[derive(Clone, Debug, Serialize)]
+pub struct UserMessageRateResponse {
+    pub user_id: UserId,
+    pub since: DateTime<Utc>,
+    pub total: i64,
+    /// Only chats with messages in the window, busiest first. Present only when asked for.
+    pub by_chat: Option<Vec<ChatMessageCountResponse>>,
+}
+
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct ChatMessageCountResponse {
+    pub chat_id: ChatId,
+    pub count: i64,
+}
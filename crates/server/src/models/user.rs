@@ -1,13 +1,19 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use strum_macros::Display;
+use strum_macros::{Display, EnumString};
 
 use crate::error::ValidationError;
 
 pub type UserId = i32;
+/// Matches `users.display_name` column size, in characters like every length limit here.
 const USER_DISPLAY_NAME_LENGTH_LIMIT: usize = 30;
-const USER_ALIAS_LENGTH_LIMIT: usize = 30;
+/// Matches `users.alias` column size.
+pub const USER_ALIAS_LENGTH_LIMIT: usize = 30;
+const USER_ALIAS_MIN_LENGTH: usize = 1;
 const USER_PASSWORD_MIN_LENGTH: usize = 8;
 const USER_PASSWORD_MAX_LENGTH: usize = 80;
+/// Longer query can't match neither alias nor display name.
+const USER_SEARCH_QUERY_LENGTH_LIMIT: usize = 30;
 
 #[derive(Clone, Debug, Serialize, sqlx::FromRow)]
 pub struct WhoAmIResponse {
@@ -17,12 +23,101 @@ pub struct WhoAmIResponse {
     pub role: UserRole,
 }
 
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct UserProfileResponse {
+    pub user_id: UserId,
+    pub alias: String,
+    pub display_name: String,
+    pub role: UserRole,
+    pub bio: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SearchUsersQuery {
+    pub query: String,
+    pub limit: Option<i32>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SearchUsersResponse {
+    pub users: Vec<UserProfileResponse>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ListInviteesQuery {
+    /// Include users invited by invitees too, i.e. the whole invite subtree.
+    pub recursive: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct InviteeResponse {
+    pub user_id: UserId,
+    pub alias: String,
+    pub display_name: String,
+    pub role: UserRole,
+    pub invited_by: UserId,
+    /// Distance from the queried user in the invite tree, 1 for direct invitees.
+    pub depth: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ListInviteesResponse {
+    pub invitees: Vec<InviteeResponse>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ListInactiveUsersQuery {
+    /// Users last seen before it are reported.
+    pub since: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct InactiveUserResponse {
+    pub user_id: UserId,
+    pub alias: String,
+    pub display_name: String,
+    pub role: UserRole,
+    pub created_at: DateTime<Utc>,
+    /// Latest activity across all sessions, `None` when the user has no session at all.
+    pub last_seen_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ListInactiveUsersResponse {
+    pub users: Vec<InactiveUserResponse>,
+}
+
+#[derive(Clone, Debug, Serialize, sqlx::FromRow)]
+pub struct BlockedUserResponse {
+    pub user_id: UserId,
+    pub alias: String,
+    pub display_name: String,
+    pub blocked_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ListBlockedUsersResponse {
+    pub blocked_users: Vec<BlockedUserResponse>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BootstrapStatusResponse {
+    pub origin_password_is_default: bool,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct ChangePasswordRequest {
     pub current_password: String,
     pub new_password: String,
 }
 
+#[derive(Clone, Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub alias: String,
+    pub new_password: String,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 pub struct ChangeAliasRequest {
     pub new_alias: String,
@@ -37,6 +132,11 @@ pub struct ChangeDisplayNameRequest {
 pub struct InviteUserRequest {
     pub alias: String,
     pub password: String,
+    /// Falls back to the configured default invited role.
+    #[serde(default)]
+    pub role: Option<UserRole>,
+    /// Checked by `AppState::captcha` before a single invite, bulk invites ignore it.
+    pub captcha_token: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -44,15 +144,33 @@ pub struct InviteUserResponse {
     pub user_id: UserId,
 }
 
-#[derive(Clone, Debug, Copy, PartialEq, Eq, Display, Serialize, sqlx::Type)]
+#[derive(Clone, Debug, Deserialize)]
+pub struct InviteUsersBulkRequest {
+    pub users: Vec<InviteUserRequest>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct InviteUsersBulkResponse {
+    pub user_ids: Vec<UserId>,
+}
+
+#[derive(
+    Clone, Debug, Copy, PartialEq, Eq, Display, EnumString, Serialize, Deserialize, sqlx::Type,
+)]
 #[sqlx(type_name = "user_role")]
 #[sqlx(rename_all = "snake_case")]
 #[serde(rename_all = "snake_case")]
+#[strum(ascii_case_insensitive)]
 pub enum UserRole {
     Admin,
     Regular,
 }
 
+/// Admins act on regular users only, other admins and origin are out of each other's reach.
+pub fn can_manage_user(actor: UserRole, target: UserRole) -> bool {
+    actor == UserRole::Admin && target == UserRole::Regular
+}
+
 #[derive(Clone, Debug)]
 pub struct CreateUserRequest {
     pub alias: String,
@@ -78,16 +196,6 @@ pub struct GetUserCredentialsByAliasResponse {
     pub password_hash: String,
 }
 
-// TODO: remove
-// #[derive(Clone, Debug, sqlx::FromRow)]
-// pub struct User {
-//     pub user_id: UserId,
-//     pub display_name: String,
-//     pub role: UserRole,
-//     pub created_at: DateTime<Utc>,
-//     pub invited_by: UserId,
-// }
-
 // TODO: add regexes
 pub fn validate_user_alias(alias: &str) -> Result<(), ValidationError> {
     for ch in alias.chars() {
@@ -98,13 +206,13 @@ pub fn validate_user_alias(alias: &str) -> Result<(), ValidationError> {
             });
         }
     }
-    if alias.is_empty() {
+    if alias.chars().count() < USER_ALIAS_MIN_LENGTH {
         return Err(ValidationError::InvalidInput {
             value: alias.to_string(),
             reason: "user alias cannot be empty".to_string(),
         });
     }
-    if alias.len() > USER_ALIAS_LENGTH_LIMIT {
+    if alias.chars().count() > USER_ALIAS_LENGTH_LIMIT {
         return Err(ValidationError::InvalidInput {
             value: alias.to_string(),
             reason: format!(
@@ -129,7 +237,7 @@ pub fn validate_user_display_name(display_name: &str) -> Result<(), ValidationEr
             reason: "user display name cannot be empty".to_string(),
         });
     }
-    if display_name.len() > USER_DISPLAY_NAME_LENGTH_LIMIT {
+    if display_name.chars().count() > USER_DISPLAY_NAME_LENGTH_LIMIT {
         return Err(ValidationError::InvalidInput {
             value: display_name.to_string(),
             reason: format!(
@@ -141,8 +249,28 @@ pub fn validate_user_display_name(display_name: &str) -> Result<(), ValidationEr
     Ok(())
 }
 
+pub fn validate_user_search_query(query: &str) -> Result<(), ValidationError> {
+    if query.trim().is_empty() {
+        return Err(ValidationError::InvalidInput {
+            value: query.to_string(),
+            reason: "search query cannot be empty".to_string(),
+        });
+    }
+    if query.chars().count() > USER_SEARCH_QUERY_LENGTH_LIMIT {
+        return Err(ValidationError::InvalidInput {
+            value: query.to_string(),
+            reason: format!(
+                "search query cannot be longer than {} chars",
+                USER_SEARCH_QUERY_LENGTH_LIMIT
+            ),
+        });
+    }
+    Ok(())
+}
+
 pub fn validate_user_password(password: &str) -> Result<(), ValidationError> {
-    if password.len() < USER_PASSWORD_MIN_LENGTH || password.len() > USER_PASSWORD_MAX_LENGTH {
+    let length = password.chars().count();
+    if !(USER_PASSWORD_MIN_LENGTH..=USER_PASSWORD_MAX_LENGTH).contains(&length) {
         return Err(ValidationError::InvalidInput {
             value: "<password>".to_string(),
             reason: format!(
@@ -153,3 +281,44 @@ pub fn validate_user_password(password: &str) -> Result<(), ValidationError> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn length_limits_count_characters_not_bytes() {
+        // Two bytes per character in UTF-8
+        let at_limit = "ж".repeat(USER_DISPLAY_NAME_LENGTH_LIMIT);
+        let over_limit = "ж".repeat(USER_DISPLAY_NAME_LENGTH_LIMIT + 1);
+        validate_user_display_name(&at_limit).unwrap();
+        validate_user_display_name(&over_limit).unwrap_err();
+        validate_user_alias(&at_limit).unwrap();
+        validate_user_alias(&over_limit).unwrap_err();
+
+        validate_user_password(&"ж".repeat(USER_PASSWORD_MAX_LENGTH)).unwrap();
+        validate_user_password(&"ж".repeat(USER_PASSWORD_MAX_LENGTH + 1)).unwrap_err();
+        validate_user_password(&"ж".repeat(USER_PASSWORD_MIN_LENGTH - 1)).unwrap_err();
+    }
+
+    #[test]
+    fn alias_length_boundaries() {
+        validate_user_alias(&"a".repeat(USER_ALIAS_MIN_LENGTH)).unwrap();
+        validate_user_alias(&"a".repeat(USER_ALIAS_MIN_LENGTH - 1)).unwrap_err();
+        validate_user_alias(&"a".repeat(USER_ALIAS_LENGTH_LIMIT)).unwrap();
+        let err = validate_user_alias(&"a".repeat(USER_ALIAS_LENGTH_LIMIT + 1)).unwrap_err();
+        assert!(err.to_string().contains("longer than 30 chars"), "{err}");
+    }
+
+    #[test]
+    fn user_role_uses_snake_case_on_the_wire() {
+        for (role, wire) in [
+            (UserRole::Admin, "\"admin\""),
+            (UserRole::Regular, "\"regular\""),
+        ] {
+            assert_eq!(serde_json::to_string(&role).unwrap(), wire);
+            assert_eq!(serde_json::from_str::<UserRole>(wire).unwrap(), role);
+        }
+        serde_json::from_str::<UserRole>("\"Admin\"").unwrap_err();
+    }
+}
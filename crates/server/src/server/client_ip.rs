@@ -0,0 +1,41 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+
+use axum::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+
+use crate::server::state::AppState;
+
+const REAL_IP_HEADER: &str = "x-real-ip";
+
+/// Address of the client, from `X-Real-IP` when `ServerConfig::trust_real_ip_header` is enabled
+/// and from the connection otherwise. `None` when neither is known, e.g. for in-process requests.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ClientIp
+where
+    S: AsRef<AppState> + Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if state.as_ref().config.server.trust_real_ip_header {
+            let forwarded = parts
+                .headers
+                .get(REAL_IP_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse::<IpAddr>().ok());
+            if forwarded.is_some() {
+                return Ok(ClientIp(forwarded));
+            }
+        }
+        let connected = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(address)| address.ip());
+        Ok(ClientIp(connected))
+    }
+}
@@ -1,6 +1,33 @@
 /// Hard upper bound for any listing `LIMIT`/page size to protect DB and memory usage.
 pub const MAX_LISTING_ELEMENTS: i32 = 200;
 
+/// Page size limit for chats listing, each chat row carries preview and unread counter lookups.
+pub const MAX_CHAT_LISTING_ELEMENTS: i32 = 100;
+
+/// Page size limit for messages listing.
+pub const MAX_MESSAGE_LISTING_ELEMENTS: i32 = 200;
+
+/// Page size limit for users who reacted to a message with the same emoji.
+pub const MAX_REACTOR_LISTING_ELEMENTS: i32 = 100;
+
+/// Oldest join requests returned for a single chat, the pending requests dashboard has full counts.
+pub const MAX_JOIN_REQUEST_LISTING_ELEMENTS: i32 = 100;
+
+/// Page size limit for the caller's blocked users.
+pub const MAX_BLOCKED_USER_LISTING_ELEMENTS: i32 = 100;
+
+/// Result limit for user search, it only needs to fill a picker while the user types.
+pub const MAX_USER_SEARCH_ELEMENTS: i32 = 50;
+
+/// Page size limit for audit log listing.
+pub const MAX_AUDIT_LISTING_ELEMENTS: i32 = 100;
+
+/// Number of chats unread counts can be fetched for with a single request.
+pub const MAX_UNREAD_COUNTS_CHATS: usize = 100;
+
+/// Number of chats latest message ids can be fetched for with a single request.
+pub const MAX_LATEST_MESSAGE_IDS_CHATS: usize = 100;
+
 /// Maximum accepted HTTP request body size for API handlers.
 /// Covers JSON auth payloads and message sends while rejecting oversized bodies early.
 pub const MAX_REQUEST_BODY_BYTES: usize = 64 * 1024;
@@ -0,0 +1,193 @@
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use crate::models::chat::ChatId;
+use crate::models::message::{MessageId, MessageResponse};
+use crate::models::user::UserId;
+
+/// Real-time update pushed to clients subscribed to a chat, applied in place by them.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChatEvent {
+    NewMessage {
+        #[serde(flatten)]
+        message: MessageResponse,
+        /// Id the sender gave the pending message when sending over the socket, see
+        /// [`ChatEvent::for_subscriber`].
+        #[serde(skip_serializing_if = "Option::is_none")]
+        client_temp_id: Option<String>,
+    },
+    EditedMessage(MessageResponse),
+    DeletedMessage {
+        message_id: MessageId,
+    },
+    /// Member was removed from the chat, their own subscriptions are closed right after it.
+    MemberRemoved {
+        user_id: UserId,
+    },
+    /// Subscriber fell behind and missed events, client should refetch via `list_messages_since`.
+    Resync {
+        chat_id: ChatId,
+    },
+    /// Frame sent over the socket was rejected, delivered only to that socket and never published.
+    Rejected {
+        client_temp_id: Option<String>,
+        error: String,
+    },
+}
+
+impl ChatEvent {
+    pub fn new_message(message: MessageResponse) -> Self {
+        Self::NewMessage {
+            message,
+            client_temp_id: None,
+        }
+    }
+
+    /// Event as delivered to the given subscriber, temp ids are only meaningful to the sender.
+    pub fn for_subscriber(mut self, user_id: UserId) -> Self {
+        if let Self::NewMessage {
+            message,
+            client_temp_id,
+        } = &mut self
+        {
+            if message.user_id != Some(user_id) {
+                *client_temp_id = None;
+            }
+        }
+        self
+    }
+}
+
+/// Frame sent by a client over the chat events socket.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientFrame {
+    /// Same as sending over HTTP, the `new_message` event delivered back to the sender carries
+    /// `client_temp_id` so the pending message can be reconciled with the stored one.
+    SendMessage {
+        text: String,
+        reply_to: Option<MessageId>,
+        client_temp_id: Option<String>,
+    },
+}
+
+/// Per-chat broadcast channels, created on first subscription and dropped with the last one.
+pub struct ChatEvents {
+    channels: DashMap<ChatId, broadcast::Sender<ChatEvent>>,
+    /// Events buffered per chat, subscribers lagging further behind get a resync hint.
+    capacity: usize,
+}
+
+impl ChatEvents {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            channels: DashMap::new(),
+            capacity,
+        }
+    }
+
+    pub fn subscribe(&self, chat_id: ChatId) -> broadcast::Receiver<ChatEvent> {
+        self.channels
+            .entry(chat_id)
+            .or_insert_with(|| broadcast::channel(self.capacity).0)
+            .subscribe()
+    }
+
+    /// No-op when nobody listens to the chat.
+    pub fn publish(&self, chat_id: ChatId, event: ChatEvent) {
+        if let Some(sender) = self.channels.get(&chat_id) {
+            let _ = sender.send(event);
+        }
+    }
+
+    #[cfg(test)]
+    pub fn subscriber_count(&self, chat_id: ChatId) -> usize {
+        self.channels
+            .get(&chat_id)
+            .map_or(0, |sender| sender.receiver_count())
+    }
+
+    /// Drop chat channel once its last receiver is gone, called by subscribers on disconnect.
+    pub fn release(&self, chat_id: ChatId) {
+        self.channels
+            .remove_if(&chat_id, |_, sender| sender.receiver_count() == 0);
+    }
+}
+
+/// Next event for a chat subscriber, `None` once the chat channel is closed.
+///
+/// Lagging receiver skips to the oldest buffered event, the gap is reported as `Resync` so the
+/// client refetches instead of silently losing messages.
+pub async fn recv_or_resync(
+    events: &mut broadcast::Receiver<ChatEvent>,
+    chat_id: ChatId,
+) -> Option<ChatEvent> {
+    match events.recv().await {
+        Ok(event) => Some(event),
+        Err(RecvError::Lagged(skipped)) => {
+            warn!("chat events subscriber lagged behind, skipped {skipped} events");
+            Some(ChatEvent::Resync { chat_id })
+        }
+        Err(RecvError::Closed) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temp_id_is_kept_only_for_sender() {
+        let message = MessageResponse {
+            id: 10,
+            text: Some("hello".to_string()),
+            created_at: chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            edited_at: None,
+            user_id: Some(1),
+            user_display_name: Some("sender".to_string()),
+            reply_to: None,
+            reply_snapshot: None,
+            resource_url: None,
+        };
+        let event = ChatEvent::NewMessage {
+            message,
+            client_temp_id: Some("tmp-1".to_string()),
+        };
+
+        let for_sender = serde_json::to_value(event.clone().for_subscriber(1)).unwrap();
+        assert_eq!(for_sender["type"], "new_message");
+        assert_eq!(for_sender["id"], 10);
+        assert_eq!(for_sender["client_temp_id"], "tmp-1");
+        let for_other = serde_json::to_value(event.for_subscriber(2)).unwrap();
+        assert_eq!(for_other["id"], 10);
+        assert!(for_other.get("client_temp_id").is_none(), "{for_other}");
+    }
+
+    #[tokio::test]
+    async fn lagging_subscriber_gets_resync_hint() {
+        let chat_id = 7;
+        let chat_events = ChatEvents::new(2);
+        let mut events = chat_events.subscribe(chat_id);
+        for message_id in 1..=5 {
+            chat_events.publish(chat_id, ChatEvent::DeletedMessage { message_id });
+        }
+
+        assert!(matches!(
+            recv_or_resync(&mut events, chat_id).await,
+            Some(ChatEvent::Resync { chat_id: 7 })
+        ));
+        // Receiver continues from the oldest event still buffered
+        assert!(matches!(
+            recv_or_resync(&mut events, chat_id).await,
+            Some(ChatEvent::DeletedMessage { message_id: 4 })
+        ));
+        assert!(matches!(
+            recv_or_resync(&mut events, chat_id).await,
+            Some(ChatEvent::DeletedMessage { message_id: 5 })
+        ));
+    }
+}
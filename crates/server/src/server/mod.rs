@@ -1,16 +1,95 @@
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::config::AppConfig;
+use tracing::{debug, error, warn};
+
+use crate::config::{AppConfig, ENV_ORIGIN_PASSWORD};
+use crate::database::commands::SCHEDULED_MESSAGES_DELIVERY_BATCH;
+use crate::server::events::ChatEvent;
 use crate::server::state::AppState;
 
+pub mod client_ip;
 pub mod constants;
+pub mod events;
 pub mod rate_limit;
 pub mod router;
 pub mod state;
 
 pub async fn run_all(config: &AppConfig) -> anyhow::Result<()> {
     let app_state = Arc::new(AppState::try_init(config).await?);
-    app_state.db_connection.init_schema().await?;
+    app_state.db_connection.prepare_schema().await?;
+    warn_if_origin_password_is_default(&app_state).await;
+    if !app_state.db_connection.logout_grace().is_zero() {
+        tokio::spawn(purge_logged_out_sessions(app_state.clone()));
+    }
+    tokio::spawn(deliver_scheduled_messages(app_state.clone()));
     router::serve(app_state).await?;
     Ok(())
 }
+
+async fn warn_if_origin_password_is_default(app_state: &AppState) {
+    match app_state.db_connection.origin_password_is_default().await {
+        Ok(true) => warn!(
+            "origin user still uses the {ENV_ORIGIN_PASSWORD} bootstrap password, change it and unset the variable"
+        ),
+        Ok(false) => {}
+        Err(e) => error!("failed to check origin user password: {e}"),
+    }
+}
+
+const LOGGED_OUT_SESSIONS_PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Background cleanup of sessions which weren't revived within logout grace window.
+async fn purge_logged_out_sessions(app_state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(LOGGED_OUT_SESSIONS_PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        match app_state.db_connection.purge_logged_out_sessions().await {
+            Ok(removed) => debug!("purged {removed} logged out sessions"),
+            Err(e) => error!("failed to purge logged out sessions: {e}"),
+        }
+    }
+}
+
+const SCHEDULED_MESSAGES_DELIVERY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Background delivery of scheduled messages, so they are sent within one interval of `send_at`.
+async fn deliver_scheduled_messages(app_state: Arc<AppState>) {
+    let mut interval = tokio::time::interval(SCHEDULED_MESSAGES_DELIVERY_INTERVAL);
+    loop {
+        interval.tick().await;
+        let delivered = deliver_scheduled_messages_round(&app_state).await;
+        if delivered > 0 {
+            debug!("delivered {delivered} scheduled messages");
+        }
+    }
+}
+
+/// Deliver every due scheduled message batch by batch and announce them like regular sends,
+/// returns number of delivered messages.
+pub(crate) async fn deliver_scheduled_messages_round(app_state: &AppState) -> usize {
+    let mut total = 0;
+    loop {
+        let delivered = match app_state
+            .db_connection
+            .deliver_due_scheduled_messages()
+            .await
+        {
+            Ok(delivered) => delivered,
+            Err(e) => {
+                error!("failed to deliver scheduled messages: {e}");
+                return total;
+            }
+        };
+        let count = delivered.len();
+        for message in delivered {
+            app_state
+                .chat_events
+                .publish(message.chat_id, ChatEvent::new_message(message.message));
+        }
+        total += count;
+        if (count as i64) < SCHEDULED_MESSAGES_DELIVERY_BATCH {
+            return total;
+        }
+    }
+}
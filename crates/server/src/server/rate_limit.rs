@@ -1,8 +1,10 @@
 use std::fmt::Debug;
+use std::net::IpAddr;
 use std::num::NonZeroU32;
+use std::time::Duration;
 
 use dashmap::DashSet;
-use governor::clock::DefaultClock;
+use governor::clock::{Clock, DefaultClock};
 use governor::state::keyed::DashMapStateStore;
 use governor::{Quota, RateLimiter as GovernorRateLimiter};
 use tracing::warn;
@@ -13,11 +15,16 @@ use crate::models::user::UserId;
 
 type KeyedRateLimiter<K> = GovernorRateLimiter<K, DashMapStateStore<K>, DefaultClock>;
 
+/// Upper bound of the backoff for a throttled alias, keeps the account usable under attack.
+const LOGIN_ALIAS_MAX_DELAY: Duration = Duration::from_secs(3);
+
 pub struct RateLimiter {
     login_by_alias: KeyedRateLimiter<String>,
+    login_by_ip: KeyedRateLimiter<IpAddr>,
     refresh_by_session: KeyedRateLimiter<SessionId>,
     change_password_by_user: KeyedRateLimiter<UserId>,
     login_limited_keys: DashSet<String>,
+    login_limited_ips: DashSet<IpAddr>,
     refresh_limited_keys: DashSet<SessionId>,
     change_password_limited_keys: DashSet<UserId>,
 }
@@ -26,33 +33,50 @@ impl RateLimiter {
     pub fn new() -> Self {
         Self::new_with_quotas(
             quota_per_minute(6),
+            quota_per_minute(20),
             quota_per_minute(30),
             quota_per_minute(5),
         )
     }
 
     fn new_with_quotas(
-        login_quota: Quota,
+        login_alias_quota: Quota,
+        login_ip_quota: Quota,
         refresh_quota: Quota,
         change_password_quota: Quota,
     ) -> Self {
         Self {
-            login_by_alias: KeyedRateLimiter::keyed(login_quota),
+            login_by_alias: KeyedRateLimiter::keyed(login_alias_quota),
+            login_by_ip: KeyedRateLimiter::keyed(login_ip_quota),
             refresh_by_session: KeyedRateLimiter::keyed(refresh_quota),
             change_password_by_user: KeyedRateLimiter::keyed(change_password_quota),
             login_limited_keys: DashSet::new(),
+            login_limited_ips: DashSet::new(),
             refresh_limited_keys: DashSet::new(),
             change_password_limited_keys: DashSet::new(),
         }
     }
 
-    pub fn check_login_alias(&self, alias: &str) -> Result<(), RequestError> {
-        check_key_with_log_once(
-            &self.login_by_alias,
-            &self.login_limited_keys,
-            alias.to_string(),
-            "auth/login",
-        )
+    /// Client address is blocked hard once over its quota, while alias over its quota only gets
+    /// a delay to wait before the attempt. Otherwise spamming wrong passwords for someone else's
+    /// alias would lock them out of their account.
+    pub fn check_login(&self, alias: &str, ip: Option<IpAddr>) -> Result<Duration, RequestError> {
+        if let Some(ip) = ip {
+            check_key_with_log_once(&self.login_by_ip, &self.login_limited_ips, ip, "auth/login")?;
+        }
+        match self.login_by_alias.check_key(&alias.to_string()) {
+            Ok(()) => {
+                self.login_limited_keys.remove(alias);
+                Ok(Duration::ZERO)
+            }
+            Err(not_until) => {
+                if self.login_limited_keys.insert(alias.to_string()) {
+                    warn!(subject = "auth/login", key = ?alias, "login attempts are throttled");
+                }
+                let wait = not_until.wait_time_from(self.login_by_alias.clock().now());
+                Ok(wait.min(LOGIN_ALIAS_MAX_DELAY))
+            }
+        }
     }
 
     pub fn check_refresh_session(&self, session_id: SessionId) -> Result<(), RequestError> {
@@ -101,39 +125,103 @@ fn quota_per_minute(max_requests: u32) -> Quota {
 
 #[cfg(test)]
 mod tests {
+    use std::net::Ipv4Addr;
     use std::num::NonZeroU32;
 
     use super::*;
 
+    fn per_second(max_requests: u32) -> Quota {
+        Quota::per_second(NonZeroU32::new(max_requests).unwrap())
+    }
+
+    fn ip(last: u8) -> Option<IpAddr> {
+        Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, last)))
+    }
+
     #[test]
     fn blocks_when_limit_is_reached() {
         let limiter = RateLimiter::new_with_quotas(
-            Quota::per_second(NonZeroU32::new(2).unwrap()),
-            Quota::per_second(NonZeroU32::new(2).unwrap()),
-            Quota::per_second(NonZeroU32::new(2).unwrap()),
+            per_second(2),
+            per_second(2),
+            per_second(2),
+            per_second(2),
         );
 
-        assert!(limiter.check_login_alias("alice").is_ok());
-        assert!(limiter.check_login_alias("alice").is_ok());
+        assert!(limiter
+            .check_refresh_session(SessionId::from_u128(1))
+            .is_ok());
+        assert!(limiter
+            .check_refresh_session(SessionId::from_u128(1))
+            .is_ok());
         assert!(matches!(
-            limiter.check_login_alias("alice"),
-            Err(RequestError::RateLimited("auth/login"))
+            limiter.check_refresh_session(SessionId::from_u128(1)),
+            Err(RequestError::RateLimited("auth/refresh"))
         ));
     }
 
     #[test]
     fn keeps_limits_independent_per_key() {
         let limiter = RateLimiter::new_with_quotas(
-            Quota::per_second(NonZeroU32::new(1).unwrap()),
-            Quota::per_second(NonZeroU32::new(1).unwrap()),
-            Quota::per_second(NonZeroU32::new(1).unwrap()),
+            per_second(1),
+            per_second(1),
+            per_second(1),
+            per_second(1),
+        );
+
+        assert!(limiter
+            .check_refresh_session(SessionId::from_u128(1))
+            .is_ok());
+        assert!(limiter
+            .check_refresh_session(SessionId::from_u128(2))
+            .is_ok());
+        assert!(matches!(
+            limiter.check_refresh_session(SessionId::from_u128(1)),
+            Err(RequestError::RateLimited("auth/refresh"))
+        ));
+    }
+
+    #[test]
+    fn login_blocks_client_address_hard() {
+        let limiter = RateLimiter::new_with_quotas(
+            per_second(10),
+            per_second(2),
+            per_second(1),
+            per_second(1),
         );
 
-        assert!(limiter.check_login_alias("alice").is_ok());
-        assert!(limiter.check_login_alias("bob").is_ok());
+        assert!(limiter.check_login("alice", ip(1)).is_ok());
+        assert!(limiter.check_login("bob", ip(1)).is_ok());
         assert!(matches!(
-            limiter.check_login_alias("alice"),
+            limiter.check_login("carol", ip(1)),
             Err(RequestError::RateLimited("auth/login"))
         ));
+        assert!(limiter.check_login("carol", ip(2)).is_ok());
+    }
+
+    #[test]
+    fn login_attempts_for_alias_are_slowed_down_not_locked() {
+        let limiter = RateLimiter::new_with_quotas(
+            per_second(2),
+            per_second(1000),
+            per_second(1),
+            per_second(1),
+        );
+
+        for _ in 0..2 {
+            assert_eq!(
+                limiter.check_login("victim", ip(1)).unwrap(),
+                Duration::ZERO
+            );
+        }
+        // Wrong password spam keeps going, but never turns into a rejection
+        for _ in 0..50 {
+            let delay = limiter.check_login("victim", ip(1)).unwrap();
+            assert!(delay > Duration::ZERO);
+            assert!(delay <= LOGIN_ALIAS_MAX_DELAY);
+        }
+        // Owner logging in from elsewhere only waits for the backoff
+        let delay = limiter.check_login("victim", ip(2)).unwrap();
+        assert!(delay <= LOGIN_ALIAS_MAX_DELAY);
+        assert_eq!(limiter.check_login("other", ip(1)).unwrap(), Duration::ZERO);
     }
 }
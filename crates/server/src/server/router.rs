@@ -1,93 +1,444 @@
 use std::sync::Arc;
+use std::time::Duration;
 
-use axum::extract::{DefaultBodyLimit, Path, Query, State};
-use axum::http::StatusCode;
-use axum::routing::{get, post};
-use axum::{Json, Router};
+use anyhow::Context;
+use axum::error_handling::HandleErrorLayer;
+use axum::extract::ws::{Message as WsMessage, WebSocket, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, DefaultBodyLimit, Path, Query, Request, State};
+use axum::http::header::SET_COOKIE;
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::Response;
+use axum::routing::{delete, get, post, put};
+use axum::{BoxError, Json, Router};
 use base64::prelude::BASE64_STANDARD as BASE64;
 use base64::Engine;
-use tracing::info;
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+use tower::timeout::error::Elapsed;
+use tower::{ServiceBuilder, ServiceExt};
+use tower_http::compression::CompressionLayer;
+use tracing::{debug, error, info, warn};
 
-use crate::auth::token::{AuthPayload, Claims, RefreshPayload, TokenExchangePayload};
-use crate::auth::utils::unpack_session_id_and_token;
-use crate::error::{RequestError, ValidationError};
-use crate::models::chat::{ChatId, ListChatsResponse, MarkChatReadRequest};
-use crate::models::listing::{ListingMode, ListingQuery};
+use crate::auth::membership::ChatMember;
+use crate::auth::token::{
+    access_token_cookie, expired_access_token_cookie, AuthPayload, Claims, LoginResponse,
+    RefreshPayload, TokenExchangePayload,
+};
+use crate::auth::utils::{unpack_session_id_and_token, TokenKind, TokenUnpackError};
+use crate::config::ServerConfig;
+use crate::database::commands::MAX_SESSIONS_PER_USER;
+use crate::error::{ErrorResponse, RequestError};
+use crate::models::audit::{ListAuditQuery, ListAuditResponse};
+use crate::models::capabilities::{CapabilitiesResponse, FeaturesResponse};
+use crate::models::chat::{
+    ChatId, CreateChannelChatRequest, CreateChatResponse, CreateGroupChatRequest,
+    LatestMessageIdsRequest, LatestMessageIdsResponse, ListChatStaffResponse, ListChatsRequest,
+    ListChatsResponse, ListChatsWithPendingRequestsResponse, ListJoinRequestsResponse,
+    ListMembersResponse, ListMembershipsResponse, ListPrivateChatPreviewsResponse,
+    MarkChatReadRequest, MyRoleResponse, PrivateChatResponse, RecentChatsQuery, SelfChatResponse,
+    TotalUnreadResponse, UnreadCountsRequest, UnreadCountsResponse, UpdateMemberRoleRequest,
+};
+use crate::models::export::UserExportResponse;
+use crate::models::listing::{
+    validate_limit, validate_message_offset, ListingMode, ListingQuery, DEFAULT_LIMIT,
+};
 use crate::models::message::{
-    validate_message_text, ListMessagesResponse, SendMessageRequest, SendMessageResponse,
+    validate_client_temp_id, AddReactionRequest, ChannelFeedResponse, ChatEventsQuery,
+    EditMessageRequest, ImportMessagesRequest, ImportMessagesResponse, ListMessageEditsResponse,
+    ListMessagesQuery, ListMessagesResponse, ListMessagesSinceQuery, ListReactorsResponse,
+    MessageDetailsResponse, MessageId, MessageLengthLimits, MessageResponse,
+    ReplaceMessageResourceRequest, ScheduleMessageRequest, ScheduledMessageId,
+    ScheduledMessageResponse, SendMessageRequest, SendMessageResponse, SendPrivateMessageRequest,
+    SendPrivateMessageResponse,
 };
+use crate::models::session::SessionId;
+use crate::models::stats::{AdminStatsResponse, UserMessageRateQuery, UserMessageRateResponse};
 use crate::models::user::{
-    ChangeAliasRequest, ChangeDisplayNameRequest, ChangePasswordRequest, InviteUserRequest,
-    InviteUserResponse, WhoAmIResponse,
+    BootstrapStatusResponse, ChangeAliasRequest, ChangeDisplayNameRequest, ChangePasswordRequest,
+    InviteUserRequest, InviteUserResponse, InviteUsersBulkRequest, InviteUsersBulkResponse,
+    ListBlockedUsersResponse, ListInactiveUsersQuery, ListInactiveUsersResponse, ListInviteesQuery,
+    ListInviteesResponse, ResetPasswordRequest, SearchUsersQuery, SearchUsersResponse, UserId,
+    UserProfileResponse, WhoAmIResponse,
+};
+use crate::server::client_ip::ClientIp;
+use crate::server::constants::{
+    MAX_AUDIT_LISTING_ELEMENTS, MAX_BLOCKED_USER_LISTING_ELEMENTS, MAX_CHAT_LISTING_ELEMENTS,
+    MAX_LISTING_ELEMENTS, MAX_MESSAGE_LISTING_ELEMENTS, MAX_REACTOR_LISTING_ELEMENTS,
+    MAX_REQUEST_BODY_BYTES, MAX_USER_SEARCH_ELEMENTS,
 };
-use crate::server::constants::MAX_REQUEST_BODY_BYTES;
+use crate::server::events::{recv_or_resync, ChatEvent, ClientFrame};
 use crate::server::state::AppState;
 
 pub async fn serve(state: Arc<AppState>) -> anyhow::Result<()> {
-    let addr = state.config.server.address.clone();
-    let app = Router::new()
+    let server_config = state.config.server.clone();
+    let app = routes(state);
+    let app = with_request_timeout(app, server_config.request_timeout);
+    let app = if server_config.compression {
+        with_compression(app)
+    } else {
+        app
+    };
+
+    let mut listeners = Vec::with_capacity(server_config.addresses.len());
+    for address in &server_config.addresses {
+        let listener = TcpListener::bind(address)
+            .await
+            .with_context(|| format!("failed to bind `{address}`"))?;
+        info!("starting server on: {}", listener.local_addr()?);
+        listeners.push(listener);
+    }
+    serve_listeners(listeners, app, &server_config).await
+}
+
+/// All API routes bound to the state, without connection level layers like timeouts.
+pub fn routes(state: Arc<AppState>) -> Router {
+    let features = &state.config.features;
+    let mut router = Router::new()
         .route("/health", get(health))
+        .route("/capabilities", get(capabilities))
         .route("/auth/whoami", get(whoami))
+        .route("/me/export", get(export_user_data))
         .route("/auth/login", post(login))
         .route("/auth/refresh", post(refresh))
         .route("/auth/change-password", post(change_password))
         .route("/auth/change-alias", post(change_alias))
         .route("/auth/change-display-name", post(change_display_name))
         .route("/auth/logout", post(logout))
+        .route("/auth/undo-logout", post(undo_logout))
+        .route("/sessions/ping", post(ping_session))
         .route("/users/invite", post(invite_user))
+        .route("/users/search", get(search_users))
+        .route("/users/:user_id", get(get_user))
+        .route("/users/:user_id/shared-chats", get(shared_chats))
+        .route("/users/:user_id/private-chat", get(get_private_chat))
+        .route("/blocks", get(list_blocked_users))
+        .route("/blocks/:user_id", put(block_user).delete(unblock_user))
+        .route("/admin/invite-bulk", post(invite_users_bulk))
+        .route("/admin/bootstrap-status", get(bootstrap_status))
+        .route("/admin/audit", get(admin_list_audit))
+        .route("/admin/stats", get(admin_stats))
+        .route("/admin/inactive-users", get(admin_list_inactive_users))
+        .route("/admin/users/:user_id/invitees", get(admin_list_invitees))
+        .route(
+            "/admin/users/:user_id/message-rate",
+            get(admin_user_message_rate),
+        )
+        .route("/admin/reset-password", post(admin_reset_password))
+        .route(
+            "/admin/chats/:chat_id/import-messages",
+            post(admin_import_messages),
+        )
         .route("/chats", get(list_chats))
+        .route("/chats/group", post(create_group_chat))
+        .route("/chats/private", get(list_private_chats))
+        .route("/chats/private/messages", post(send_private_message))
+        .route("/chats/recent", get(list_recent_chats))
+        .route("/chats/self", get(get_self_chat))
+        .route(
+            "/chats/join-requests",
+            get(list_chats_with_pending_requests),
+        )
+        .route("/chats/memberships", get(list_memberships))
+        .route("/chats/unread", get(total_unread))
+        .route("/chats/unread-counts", post(unread_counts))
+        .route("/chats/latest-message-ids", post(latest_message_ids))
         .route("/chats/:chat_id/read", post(mark_chat_read))
+        .route("/chats/:chat_id/pin", put(pin_chat).delete(unpin_chat))
+        .route("/chats/:chat_id/members", get(list_members))
+        .route("/chats/:chat_id/members/:user_id", delete(remove_member))
+        .route(
+            "/chats/:chat_id/members/:user_id/role",
+            put(update_member_role),
+        )
+        .route("/chats/:chat_id/staff", get(list_chat_staff))
+        .route("/chats/:chat_id/mentions", get(list_mentions))
+        .route(
+            "/chats/:chat_id/join-requests",
+            get(list_join_requests).post(request_to_join_chat),
+        )
+        .route(
+            "/chats/:chat_id/join-requests/:user_id",
+            delete(decline_join_request),
+        )
+        .route(
+            "/chats/:chat_id/join-requests/:user_id/approve",
+            post(approve_join_request),
+        )
+        .route("/chats/:chat_id/my-role", get(get_my_role))
         .route(
             "/chats/:chat_id/messages",
             get(list_messages).post(send_message),
         )
+        .route(
+            "/chats/:chat_id/messages/since/:since_id",
+            get(list_messages_since),
+        )
+        .route(
+            "/chats/:chat_id/messages/:message_id",
+            get(get_message).patch(edit_message).delete(delete_message),
+        )
+        .route(
+            "/chats/:chat_id/messages/:message_id/reactions",
+            post(add_reaction),
+        )
+        .route("/chats/:chat_id/scheduled-messages", post(schedule_message))
+        .route(
+            "/scheduled-messages/:scheduled_message_id",
+            delete(cancel_scheduled_message),
+        )
+        .route("/messages/limits", get(get_message_limits))
+        .route("/messages/:message_id/thread", get(list_thread))
+        .route("/messages/:message_id/edits", get(list_message_edits))
+        .route(
+            "/messages/:message_id/reactions/:emoji",
+            get(list_reactions),
+        )
+        .route(
+            "/messages/:message_id/resource",
+            put(replace_message_resource),
+        )
+        .route("/chats/channel/feed", get(channel_feed));
+    if features.channels {
+        router = router.route("/chats/channel", post(create_channel_chat));
+    }
+    if features.websockets {
+        router = router.route("/chats/:chat_id/events", get(chat_events));
+    }
+    router
         .layer(DefaultBodyLimit::max(MAX_REQUEST_BODY_BYTES))
-        .with_state(state);
+        .with_state(state)
+}
+
+/// Serve the same app on every listener, stops as soon as any of them fails or once all of them
+/// have shut down.
+async fn serve_listeners(
+    listeners: Vec<TcpListener>,
+    app: Router,
+    config: &ServerConfig,
+) -> anyhow::Result<()> {
+    let mut tasks = JoinSet::new();
+    for listener in listeners {
+        let app = app.clone();
+        let config = config.clone();
+        tasks.spawn(async move { serve_connections(listener, app, &config).await });
+    }
+    while let Some(result) = tasks.join_next().await {
+        result??;
+    }
+    Ok(())
+}
+
+fn with_request_timeout(app: Router, timeout: Duration) -> Router {
+    app.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(handle_timeout_error))
+            .timeout(timeout),
+    )
+}
 
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    info!("starting server on: {}", listener.local_addr()?);
-    axum::serve(listener, app).await?;
+/// Default predicate skips tiny bodies and already compressed content like images.
+fn with_compression(app: Router) -> Router {
+    app.layer(CompressionLayer::new())
+}
+
+async fn handle_timeout_error(error: BoxError) -> (StatusCode, Json<ErrorResponse>) {
+    if error.is::<Elapsed>() {
+        (
+            StatusCode::REQUEST_TIMEOUT,
+            Json(ErrorResponse::new("request timed out")),
+        )
+    } else {
+        error!("unhandled middleware error: {error}");
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse::new("service is unavailable")),
+        )
+    }
+}
+
+/// Accept loop serving HTTP/1 and HTTP/2 with the header read timeout `axum::serve` doesn't
+/// expose. On shutdown signal stops accepting and waits for open connections to finish.
+async fn serve_connections(
+    listener: TcpListener,
+    app: Router,
+    config: &ServerConfig,
+) -> anyhow::Result<()> {
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(config.header_read_timeout);
+    let graceful = GracefulShutdown::new();
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("failed to accept connection: {e}");
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+        let connection_app = app
+            .clone()
+            .map_request(move |mut request: Request<Incoming>| {
+                request.extensions_mut().insert(ConnectInfo(remote_addr));
+                request
+            });
+        let service = TowerToHyperService::new(connection_app);
+        let connection = graceful.watch(
+            builder
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .into_owned(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                debug!("connection with {remote_addr} closed with error: {e}");
+            }
+        });
+    }
+    info!("shutting down, waiting for open connections");
+    graceful.shutdown().await;
     Ok(())
 }
 
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("failed to listen for ctrl-c: {e}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("failed to listen for SIGTERM: {e}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
 pub async fn health() -> StatusCode {
     StatusCode::OK
 }
 
+pub async fn capabilities(State(state): State<Arc<AppState>>) -> Json<CapabilitiesResponse> {
+    Json(CapabilitiesResponse {
+        max_listing_elements: MAX_LISTING_ELEMENTS,
+        message_length_limits: state.db_connection.message_length_limits(),
+        max_request_body_bytes: MAX_REQUEST_BODY_BYTES,
+        max_sessions_per_user: MAX_SESSIONS_PER_USER,
+        features: FeaturesResponse {
+            websockets: state.config.features.websockets,
+            channels: state.config.features.channels,
+            server_sent_events: false,
+            uploads: false,
+        },
+    })
+}
+
 pub async fn login(
     State(state): State<Arc<AppState>>,
+    ClientIp(client_ip): ClientIp,
     Json(payload): Json<AuthPayload>,
-) -> Result<Json<TokenExchangePayload>, RequestError> {
-    state.rate_limiter.check_login_alias(&payload.alias)?;
+) -> Result<(HeaderMap, Json<LoginResponse>), RequestError> {
+    let delay = state.rate_limiter.check_login(&payload.alias, client_ip)?;
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    if !state.captcha.verify(payload.captcha_token.as_deref()).await {
+        return Err(RequestError::CaptchaRejected);
+    }
     let payload = state
         .db_connection
-        .login(&payload.alias, &payload.password)
+        .login_with_session(&payload.alias, &payload.password, payload.session_id)
         .await?;
-    Ok(Json(payload))
+    let cookie = access_token_cookie(&state.config.server, &payload.tokens.access_token);
+    Ok((set_cookie_headers(cookie), Json(payload)))
 }
 
 pub async fn refresh(
     State(state): State<Arc<AppState>>,
     Json(payload): Json<RefreshPayload>,
-) -> Result<Json<TokenExchangePayload>, RequestError> {
+) -> Result<(HeaderMap, Json<TokenExchangePayload>), RequestError> {
     let packed_bytes = BASE64
         .decode(&payload.refresh_token)
         .map_err(|_| RequestError::BadCredentials)?;
-    let (session_id, refresh_token) =
-        unpack_session_id_and_token(&packed_bytes).ok_or(RequestError::BadCredentials)?;
+    let (session_id, refresh_token) = unpack_refresh_token(&packed_bytes)?;
     state.rate_limiter.check_refresh_session(session_id)?;
     let payload = state
         .db_connection
         .refresh_session(session_id, refresh_token)
         .await?;
-    Ok(Json(payload))
+    let cookie = access_token_cookie(&state.config.server, &payload.access_token);
+    Ok((set_cookie_headers(cookie), Json(payload)))
 }
 
 pub async fn logout(
     State(state): State<Arc<AppState>>,
     claims: Claims,
-) -> Result<StatusCode, RequestError> {
+) -> Result<(StatusCode, HeaderMap), RequestError> {
     state.db_connection.logout(claims.session_id).await?;
+    let cookie = expired_access_token_cookie(&state.config.server);
+    Ok((StatusCode::NO_CONTENT, set_cookie_headers(cookie)))
+}
+
+fn unpack_refresh_token(packed: &[u8]) -> Result<(SessionId, &[u8]), RequestError> {
+    unpack_session_id_and_token(packed, TokenKind::Refresh).map_err(|e| match e {
+        TokenUnpackError::Malformed => RequestError::BadCredentials,
+        TokenUnpackError::WrongKind { expected } => RequestError::WrongTokenKind { expected },
+    })
+}
+
+/// Empty when access token cookies are disabled, so bearer-only clients see no difference.
+fn set_cookie_headers(cookie: Option<HeaderValue>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(cookie) = cookie {
+        headers.insert(SET_COOKIE, cookie);
+    }
+    headers
+}
+
+pub async fn ping_session(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+) -> Result<StatusCode, RequestError> {
+    state.db_connection.touch_session(claims.session_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn undo_logout(
+    State(state): State<Arc<AppState>>,
+    Json(payload): Json<RefreshPayload>,
+) -> Result<StatusCode, RequestError> {
+    let packed_bytes = BASE64
+        .decode(&payload.refresh_token)
+        .map_err(|_| RequestError::BadCredentials)?;
+    let (session_id, refresh_token) = unpack_refresh_token(&packed_bytes)?;
+    state.rate_limiter.check_refresh_session(session_id)?;
+    state
+        .db_connection
+        .undo_logout(session_id, refresh_token)
+        .await?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -143,89 +494,1112 @@ pub async fn whoami(
     Ok(Json(response))
 }
 
+pub async fn export_user_data(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+) -> Result<Json<UserExportResponse>, RequestError> {
+    let response = state.db_connection.export_user_data(claims.user_id).await?;
+    Ok(Json(response))
+}
+
 pub async fn invite_user(
     State(state): State<Arc<AppState>>,
     claims: Claims,
     Json(payload): Json<InviteUserRequest>,
 ) -> Result<(StatusCode, Json<InviteUserResponse>), RequestError> {
+    if !state.captcha.verify(payload.captcha_token.as_deref()).await {
+        return Err(RequestError::CaptchaRejected);
+    }
     let user_id = state
         .db_connection
-        .invite_user(claims.user_id, &payload.alias, &payload.password)
+        .invite_user(
+            claims.user_id,
+            &payload.alias,
+            &payload.password,
+            payload.role,
+        )
         .await?;
     Ok((StatusCode::CREATED, Json(InviteUserResponse { user_id })))
 }
 
-pub async fn list_chats(
+pub async fn bootstrap_status(
     State(state): State<Arc<AppState>>,
     claims: Claims,
-    Query(params): Query<ListingQuery>,
-) -> Result<Json<ListChatsResponse>, RequestError> {
-    let (page_size, page_num) = match ListingMode::from_query(params)? {
-        ListingMode::Page { limit, page } => (limit, page),
-        ListingMode::Offset { .. } => {
-            return Err(ValidationError::InvalidInput {
-                value: "offset".to_string(),
-                reason: "offset mode is not supported for chats listing".to_string(),
-            }
-            .into())
-        }
-    };
+) -> Result<Json<BootstrapStatusResponse>, RequestError> {
+    let response = state.db_connection.bootstrap_status(claims.user_id).await?;
+    Ok(Json(response))
+}
+
+pub async fn admin_stats(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+) -> Result<Json<AdminStatsResponse>, RequestError> {
+    let response = state.db_connection.admin_stats(claims.user_id).await?;
+    Ok(Json(response))
+}
+
+pub async fn admin_list_inactive_users(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Query(params): Query<ListInactiveUsersQuery>,
+) -> Result<Json<ListInactiveUsersResponse>, RequestError> {
     let response = state
         .db_connection
-        .list_chats(claims.user_id, page_size, page_num)
+        .list_inactive_users(claims.user_id, params.since)
         .await?;
     Ok(Json(response))
 }
 
-pub async fn list_messages(
+pub async fn admin_list_audit(
     State(state): State<Arc<AppState>>,
     claims: Claims,
-    Path(chat_id): Path<ChatId>,
-    Query(params): Query<ListingQuery>,
-) -> Result<Json<ListMessagesResponse>, RequestError> {
-    let response = match ListingMode::from_query(params)? {
-        ListingMode::Offset { offset, limit } => {
-            state
-                .db_connection
-                .list_messages_after(claims.user_id, chat_id, offset, limit)
-                .await?
-        }
-        ListingMode::Page { limit, page } => {
-            state
-                .db_connection
-                .list_messages(claims.user_id, chat_id, limit, page)
-                .await?
-        }
-    };
+    Query(params): Query<ListAuditQuery>,
+) -> Result<Json<ListAuditResponse>, RequestError> {
+    let limit = params.limit.unwrap_or(MAX_AUDIT_LISTING_ELEMENTS);
+    validate_limit(limit, MAX_AUDIT_LISTING_ELEMENTS)?;
+    let response = state
+        .db_connection
+        .admin_list_audit(claims.user_id, &params, limit)
+        .await?;
     Ok(Json(response))
 }
 
-pub async fn send_message(
+pub async fn admin_reset_password(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> Result<StatusCode, RequestError> {
+    state
+        .db_connection
+        .admin_reset_password(claims.user_id, &payload.alias, &payload.new_password)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn admin_import_messages(
     State(state): State<Arc<AppState>>,
     claims: Claims,
     Path(chat_id): Path<ChatId>,
-    Json(payload): Json<SendMessageRequest>,
-) -> Result<(StatusCode, Json<SendMessageResponse>), RequestError> {
-    validate_message_text(&payload.text)?;
-    let message_id = state
+    Json(payload): Json<ImportMessagesRequest>,
+) -> Result<(StatusCode, Json<ImportMessagesResponse>), RequestError> {
+    let message_ids = state
         .db_connection
-        .send_message(claims.user_id, chat_id, &payload.text)
+        .import_messages(claims.user_id, chat_id, payload.messages)
         .await?;
     Ok((
         StatusCode::CREATED,
-        Json(SendMessageResponse { message_id }),
+        Json(ImportMessagesResponse { message_ids }),
     ))
 }
 
-pub async fn mark_chat_read(
+pub async fn admin_list_invitees(
     State(state): State<Arc<AppState>>,
     claims: Claims,
-    Path(chat_id): Path<ChatId>,
-    Json(payload): Json<MarkChatReadRequest>,
+    Path(user_id): Path<UserId>,
+    Query(params): Query<ListInviteesQuery>,
+) -> Result<Json<ListInviteesResponse>, RequestError> {
+    let response = state
+        .db_connection
+        .list_invitees(claims.user_id, user_id, params.recursive.unwrap_or(false))
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn admin_user_message_rate(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Path(user_id): Path<UserId>,
+    Query(params): Query<UserMessageRateQuery>,
+) -> Result<Json<UserMessageRateResponse>, RequestError> {
+    let response = state
+        .db_connection
+        .user_message_rate(
+            claims.user_id,
+            user_id,
+            params.since,
+            params.by_chat.unwrap_or(false),
+        )
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn invite_users_bulk(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Json(payload): Json<InviteUsersBulkRequest>,
+) -> Result<(StatusCode, Json<InviteUsersBulkResponse>), RequestError> {
+    let user_ids = state
+        .db_connection
+        .invite_users_bulk(claims.user_id, payload.users)
+        .await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(InviteUsersBulkResponse { user_ids }),
+    ))
+}
+
+pub async fn list_chats(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Query(params): Query<ListingQuery>,
+    Query(filter): Query<ListChatsRequest>,
+) -> Result<Json<ListChatsResponse>, RequestError> {
+    let (page_size, page_num) =
+        ListingMode::from_query(params, MAX_CHAT_LISTING_ELEMENTS)?.into_page("chats listing")?;
+    let response = state
+        .db_connection
+        .list_chats(claims.user_id, page_size, page_num, filter.kind)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn list_private_chats(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Query(params): Query<ListingQuery>,
+) -> Result<Json<ListPrivateChatPreviewsResponse>, RequestError> {
+    let listing = ListingMode::from_query(params, MAX_CHAT_LISTING_ELEMENTS)?;
+    let response = state
+        .db_connection
+        .list_private_chats_with_preview(claims.user_id, listing)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn list_recent_chats(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Query(params): Query<RecentChatsQuery>,
+) -> Result<Json<ListChatsResponse>, RequestError> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    validate_limit(limit, MAX_CHAT_LISTING_ELEMENTS)?;
+    let response = state
+        .db_connection
+        .list_recent_chats(claims.user_id, limit)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn get_user(
+    State(state): State<Arc<AppState>>,
+    _claims: Claims,
+    Path(user_id): Path<UserId>,
+) -> Result<Json<UserProfileResponse>, RequestError> {
+    let response = state.db_connection.get_user_by_id(user_id).await?;
+    Ok(Json(response))
+}
+
+pub async fn list_blocked_users(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Query(params): Query<ListingQuery>,
+) -> Result<Json<ListBlockedUsersResponse>, RequestError> {
+    let listing = ListingMode::from_query(params, MAX_BLOCKED_USER_LISTING_ELEMENTS)?;
+    let response = state
+        .db_connection
+        .list_blocked_users(claims.user_id, listing)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn block_user(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Path(user_id): Path<UserId>,
+) -> Result<StatusCode, RequestError> {
+    state
+        .db_connection
+        .block_user(claims.user_id, user_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn unblock_user(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Path(user_id): Path<UserId>,
 ) -> Result<StatusCode, RequestError> {
     state
         .db_connection
-        .mark_chat_read(claims.user_id, chat_id, payload.up_to_message_id)
+        .unblock_user(claims.user_id, user_id)
         .await?;
     Ok(StatusCode::NO_CONTENT)
 }
+
+pub async fn search_users(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Query(params): Query<SearchUsersQuery>,
+) -> Result<Json<SearchUsersResponse>, RequestError> {
+    let limit = params.limit.unwrap_or(MAX_USER_SEARCH_ELEMENTS);
+    validate_limit(limit, MAX_USER_SEARCH_ELEMENTS)?;
+    let response = state
+        .db_connection
+        .search_users(claims.user_id, &params.query, limit)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn shared_chats(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Path(other_user_id): Path<UserId>,
+    Query(params): Query<ListingQuery>,
+) -> Result<Json<ListChatsResponse>, RequestError> {
+    let (page_size, page_num) = ListingMode::from_query(params, MAX_CHAT_LISTING_ELEMENTS)?
+        .into_page("shared chats listing")?;
+    let response = state
+        .db_connection
+        .shared_chats(claims.user_id, other_user_id, page_size, page_num)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn get_private_chat(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Path(other_user_id): Path<UserId>,
+) -> Result<Json<PrivateChatResponse>, RequestError> {
+    let response = state
+        .db_connection
+        .get_private_chat(claims.user_id, other_user_id)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn get_self_chat(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+) -> Result<Json<SelfChatResponse>, RequestError> {
+    let response = state.db_connection.get_self_chat(claims.user_id).await?;
+    Ok(Json(response))
+}
+
+pub async fn create_group_chat(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Json(payload): Json<CreateGroupChatRequest>,
+) -> Result<(StatusCode, Json<CreateChatResponse>), RequestError> {
+    let chat_id = state
+        .db_connection
+        .create_group_chat_with_members(
+            claims.user_id,
+            &payload.display_name,
+            payload.description.as_deref(),
+            &payload.members,
+        )
+        .await?;
+    Ok((StatusCode::CREATED, Json(CreateChatResponse { chat_id })))
+}
+
+pub async fn create_channel_chat(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Json(payload): Json<CreateChannelChatRequest>,
+) -> Result<(StatusCode, Json<CreateChatResponse>), RequestError> {
+    let chat_id = state
+        .db_connection
+        .create_channel_chat(
+            claims.user_id,
+            &payload.display_name,
+            payload.description.as_deref(),
+        )
+        .await?;
+    Ok((StatusCode::CREATED, Json(CreateChatResponse { chat_id })))
+}
+
+pub async fn list_memberships(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+) -> Result<Json<ListMembershipsResponse>, RequestError> {
+    let response = state.db_connection.list_memberships(claims.user_id).await?;
+    Ok(Json(response))
+}
+
+pub async fn total_unread(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+) -> Result<Json<TotalUnreadResponse>, RequestError> {
+    let response = state.db_connection.total_unread(claims.user_id).await?;
+    Ok(Json(response))
+}
+
+pub async fn unread_counts(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Json(payload): Json<UnreadCountsRequest>,
+) -> Result<Json<UnreadCountsResponse>, RequestError> {
+    let response = state
+        .db_connection
+        .unread_counts(claims.user_id, &payload.chat_ids)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn latest_message_ids(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Json(payload): Json<LatestMessageIdsRequest>,
+) -> Result<Json<LatestMessageIdsResponse>, RequestError> {
+    let response = state
+        .db_connection
+        .latest_message_ids(claims.user_id, &payload.chat_ids)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn get_my_role(member: ChatMember) -> Json<MyRoleResponse> {
+    Json(MyRoleResponse { role: member.role })
+}
+
+pub async fn list_members(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+) -> Result<Json<ListMembersResponse>, RequestError> {
+    let response = state
+        .db_connection
+        .list_members(member.user_id, member.chat_id)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn list_chat_staff(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+) -> Result<Json<ListChatStaffResponse>, RequestError> {
+    let response = state
+        .db_connection
+        .list_chat_staff(member.user_id, member.chat_id)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn list_chats_with_pending_requests(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+) -> Result<Json<ListChatsWithPendingRequestsResponse>, RequestError> {
+    let response = state
+        .db_connection
+        .list_chats_with_pending_requests(claims.user_id)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn list_join_requests(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+) -> Result<Json<ListJoinRequestsResponse>, RequestError> {
+    let response = state
+        .db_connection
+        .list_join_requests(member.user_id, member.chat_id)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn request_to_join_chat(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Path(chat_id): Path<ChatId>,
+) -> Result<StatusCode, RequestError> {
+    state
+        .db_connection
+        .request_to_join_chat(claims.user_id, chat_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn approve_join_request(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Path((_, user_id)): Path<(ChatId, UserId)>,
+) -> Result<StatusCode, RequestError> {
+    state
+        .db_connection
+        .approve_join_request(member.user_id, member.chat_id, user_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn decline_join_request(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Path((_, user_id)): Path<(ChatId, UserId)>,
+) -> Result<StatusCode, RequestError> {
+    state
+        .db_connection
+        .decline_join_request(member.user_id, member.chat_id, user_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn remove_member(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Path((_, user_id)): Path<(ChatId, UserId)>,
+) -> Result<StatusCode, RequestError> {
+    state
+        .db_connection
+        .remove_member(member.user_id, member.chat_id, user_id)
+        .await?;
+    state
+        .chat_events
+        .publish(member.chat_id, ChatEvent::MemberRemoved { user_id });
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn update_member_role(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Path((_, user_id)): Path<(ChatId, UserId)>,
+    Json(payload): Json<UpdateMemberRoleRequest>,
+) -> Result<StatusCode, RequestError> {
+    state
+        .db_connection
+        .update_member_role(member.user_id, member.chat_id, user_id, payload.role)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_messages(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Query(params): Query<ListingQuery>,
+    Query(messages_params): Query<ListMessagesQuery>,
+) -> Result<Json<ListMessagesResponse>, RequestError> {
+    let response = match ListingMode::from_query(params, MAX_MESSAGE_LISTING_ELEMENTS)? {
+        ListingMode::Offset { offset, limit } => {
+            state
+                .db_connection
+                .list_messages_after(member.user_id, member.chat_id, offset, limit)
+                .await?
+        }
+        ListingMode::Page { limit, page } if messages_params.with_total.unwrap_or(false) => {
+            state
+                .db_connection
+                .list_messages_with_total(member.user_id, member.chat_id, limit, page)
+                .await?
+        }
+        ListingMode::Page { limit, page } => {
+            state
+                .db_connection
+                .list_messages(member.user_id, member.chat_id, limit, page)
+                .await?
+        }
+        ListingMode::Window {
+            before,
+            after,
+            limit,
+        } => {
+            state
+                .db_connection
+                .list_messages_window(member.user_id, member.chat_id, before, after, limit)
+                .await?
+        }
+    };
+    Ok(Json(response))
+}
+
+pub async fn list_mentions(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Query(params): Query<ListingQuery>,
+) -> Result<Json<ListMessagesResponse>, RequestError> {
+    let listing = ListingMode::from_query(params, MAX_MESSAGE_LISTING_ELEMENTS)?;
+    let response = state
+        .db_connection
+        .list_messages_involving(member.user_id, member.chat_id, listing)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn channel_feed(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Query(params): Query<ListingQuery>,
+) -> Result<Json<ChannelFeedResponse>, RequestError> {
+    let listing = ListingMode::from_query(params, MAX_MESSAGE_LISTING_ELEMENTS)?;
+    let response = state
+        .db_connection
+        .channel_feed(claims.user_id, listing)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn list_messages_since(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Path((_, since_id)): Path<(ChatId, MessageId)>,
+    Query(params): Query<ListMessagesSinceQuery>,
+) -> Result<Json<ListMessagesResponse>, RequestError> {
+    let limit = params.limit.unwrap_or(DEFAULT_LIMIT);
+    validate_limit(limit, MAX_MESSAGE_LISTING_ELEMENTS)?;
+    validate_message_offset(since_id)?;
+    let response = state
+        .db_connection
+        .list_messages_since(member.user_id, member.chat_id, since_id, limit)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn send_message(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Json(payload): Json<SendMessageRequest>,
+) -> Result<(StatusCode, Json<SendMessageResponse>), RequestError> {
+    let message = state
+        .db_connection
+        .send_message_with_response(
+            member.user_id,
+            member.chat_id,
+            &payload.text,
+            payload.reply_to,
+        )
+        .await?;
+    state
+        .chat_events
+        .publish(member.chat_id, ChatEvent::new_message(message.clone()));
+    Ok((
+        StatusCode::CREATED,
+        Json(SendMessageResponse {
+            message_id: message.id,
+            message,
+        }),
+    ))
+}
+
+pub async fn schedule_message(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Json(payload): Json<ScheduleMessageRequest>,
+) -> Result<(StatusCode, Json<ScheduledMessageResponse>), RequestError> {
+    let response = state
+        .db_connection
+        .schedule_message(
+            member.user_id,
+            member.chat_id,
+            &payload.text,
+            payload.send_at,
+        )
+        .await?;
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub async fn cancel_scheduled_message(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Path(scheduled_message_id): Path<ScheduledMessageId>,
+) -> Result<StatusCode, RequestError> {
+    state
+        .db_connection
+        .cancel_scheduled_message(claims.user_id, scheduled_message_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn send_private_message(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Json(payload): Json<SendPrivateMessageRequest>,
+) -> Result<(StatusCode, Json<SendPrivateMessageResponse>), RequestError> {
+    let response = state
+        .db_connection
+        .send_private_message(claims.user_id, &payload.recipient_alias, &payload.text)
+        .await?;
+    state.chat_events.publish(
+        response.chat_id,
+        ChatEvent::new_message(response.message.clone()),
+    );
+    Ok((StatusCode::CREATED, Json(response)))
+}
+
+pub async fn list_thread(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Path(message_id): Path<MessageId>,
+) -> Result<Json<ListMessagesResponse>, RequestError> {
+    let response = state
+        .db_connection
+        .list_thread(claims.user_id, message_id)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn get_message_limits(
+    State(state): State<Arc<AppState>>,
+    _claims: Claims,
+) -> Json<MessageLengthLimits> {
+    Json(state.db_connection.message_length_limits())
+}
+
+pub async fn list_message_edits(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Path(message_id): Path<MessageId>,
+) -> Result<Json<ListMessageEditsResponse>, RequestError> {
+    let response = state
+        .db_connection
+        .list_message_edits(claims.user_id, message_id)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn get_message(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Path((_, message_id)): Path<(ChatId, MessageId)>,
+) -> Result<Json<MessageDetailsResponse>, RequestError> {
+    let response = state
+        .db_connection
+        .get_message_details(member.user_id, member.chat_id, message_id)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn edit_message(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Path((_, message_id)): Path<(ChatId, MessageId)>,
+    Json(payload): Json<EditMessageRequest>,
+) -> Result<Json<MessageResponse>, RequestError> {
+    let message = state
+        .db_connection
+        .edit_message(member.user_id, member.chat_id, message_id, &payload.text)
+        .await?;
+    state
+        .chat_events
+        .publish(member.chat_id, ChatEvent::EditedMessage(message.clone()));
+    Ok(Json(message))
+}
+
+pub async fn delete_message(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Path((_, message_id)): Path<(ChatId, MessageId)>,
+) -> Result<StatusCode, RequestError> {
+    state
+        .db_connection
+        .delete_message(member.user_id, member.chat_id, message_id)
+        .await?;
+    state
+        .chat_events
+        .publish(member.chat_id, ChatEvent::DeletedMessage { message_id });
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Subscribe to real-time events of the chat, membership is checked on connect and the socket is
+/// closed once the subscriber is removed from the chat or their session ends.
+pub async fn chat_events(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Query(params): Query<ChatEventsQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, RequestError> {
+    if let Some(last_event_id) = params.last_event_id {
+        validate_message_offset(last_event_id)?;
+    }
+    Ok(ws
+        .on_upgrade(move |socket| forward_chat_events(socket, state, member, params.last_event_id)))
+}
+
+/// How often an open chat events socket rechecks its session, so logout, revocation or expiry
+/// closes it even while the client stays silent.
+const SESSION_RECHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+async fn forward_chat_events(
+    mut socket: WebSocket,
+    state: Arc<AppState>,
+    member: ChatMember,
+    last_event_id: Option<MessageId>,
+) {
+    let chat_id = member.chat_id;
+    // Subscribed before the replay, so messages sent meanwhile are either replayed or broadcast
+    let mut events = state.chat_events.subscribe(chat_id);
+    let mut replayed_up_to = None;
+    if let Some(last_event_id) = last_event_id {
+        replayed_up_to = replay_missed_messages(&mut socket, &state, &member, last_event_id).await;
+        if replayed_up_to.is_none() {
+            drop(events);
+            state.chat_events.release(chat_id);
+            return;
+        }
+    }
+    let mut session_recheck = tokio::time::interval_at(
+        tokio::time::Instant::now() + SESSION_RECHECK_INTERVAL,
+        SESSION_RECHECK_INTERVAL,
+    );
+    loop {
+        tokio::select! {
+            _ = session_recheck.tick() => {
+                if !ensure_session_active(&mut socket, &state, &member).await {
+                    break;
+                }
+            }
+            event = recv_or_resync(&mut events, chat_id) => {
+                let Some(event) = event else {
+                    break;
+                };
+                if let ChatEvent::NewMessage { message, .. } = &event {
+                    if replayed_up_to.is_some_and(|up_to| message.id <= up_to) {
+                        continue;
+                    }
+                }
+                let removed = matches!(
+                    event,
+                    ChatEvent::MemberRemoved { user_id } if user_id == member.user_id
+                );
+                if !send_chat_event(&mut socket, &event.for_subscriber(member.user_id)).await {
+                    break;
+                }
+                if removed {
+                    debug!("closing chat events of removed member");
+                    let _ = socket.send(WsMessage::Close(None)).await;
+                    break;
+                }
+            }
+            // Sent messages go through the same membership check as over HTTP, so a removed member
+            // can't publish even before their socket is closed.
+            incoming = socket.recv() => match incoming {
+                Some(Ok(WsMessage::Text(frame))) => {
+                    if !ensure_session_active(&mut socket, &state, &member).await {
+                        break;
+                    }
+                    if let Err(e) = handle_client_frame(&state, &member, &frame).await {
+                        if !send_chat_event(&mut socket, &e).await {
+                            break;
+                        }
+                    }
+                }
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+    drop(events);
+    state.chat_events.release(chat_id);
+}
+
+/// Closes the socket and returns `false` once the session it was opened with is no longer active.
+async fn ensure_session_active(
+    socket: &mut WebSocket,
+    state: &AppState,
+    member: &ChatMember,
+) -> bool {
+    match state
+        .db_connection
+        .ensure_session_active(member.session_id)
+        .await
+    {
+        Ok(()) => true,
+        Err(e) => {
+            debug!("closing chat events of inactive session: {e:?}");
+            let _ = socket.send(WsMessage::Close(None)).await;
+            false
+        }
+    }
+}
+
+/// Send messages newer than `last_event_id` as `new_message` events, followed by `resync` when more
+/// were missed than a single listing carries. Returns the last replayed id, `None` once the socket
+/// is gone.
+async fn replay_missed_messages(
+    socket: &mut WebSocket,
+    state: &AppState,
+    member: &ChatMember,
+    last_event_id: MessageId,
+) -> Option<MessageId> {
+    let chat_id = member.chat_id;
+    let missed = match state
+        .db_connection
+        .list_messages_since(
+            member.user_id,
+            chat_id,
+            last_event_id,
+            MAX_MESSAGE_LISTING_ELEMENTS,
+        )
+        .await
+    {
+        Ok(response) => response.messages,
+        Err(e) => {
+            error!("failed to replay missed chat events: {e}");
+            return send_chat_event(socket, &ChatEvent::Resync { chat_id })
+                .await
+                .then_some(last_event_id);
+        }
+    };
+    let complete = missed.len() < MAX_MESSAGE_LISTING_ELEMENTS as usize;
+    let mut replayed_up_to = last_event_id;
+    for message in missed {
+        replayed_up_to = message.id;
+        if !send_chat_event(socket, &ChatEvent::new_message(message)).await {
+            return None;
+        }
+    }
+    if !complete && !send_chat_event(socket, &ChatEvent::Resync { chat_id }).await {
+        return None;
+    }
+    Some(replayed_up_to)
+}
+
+/// Returns `false` once the socket is gone.
+async fn send_chat_event(socket: &mut WebSocket, event: &ChatEvent) -> bool {
+    let payload = match serde_json::to_string(event) {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("failed to serialize chat event: {e}");
+            return true;
+        }
+    };
+    socket.send(WsMessage::Text(payload)).await.is_ok()
+}
+
+/// Rejection is returned as the event to send back to the socket the frame came from.
+async fn handle_client_frame(
+    state: &AppState,
+    member: &ChatMember,
+    frame: &str,
+) -> Result<(), ChatEvent> {
+    let ClientFrame::SendMessage {
+        text,
+        reply_to,
+        client_temp_id,
+    } = serde_json::from_str(frame).map_err(|e| ChatEvent::Rejected {
+        client_temp_id: None,
+        error: format!("invalid frame: {e}"),
+    })?;
+    let sent = async {
+        if let Some(client_temp_id) = &client_temp_id {
+            validate_client_temp_id(client_temp_id)?;
+        }
+        state
+            .db_connection
+            .send_message_with_response(member.user_id, member.chat_id, &text, reply_to)
+            .await
+    }
+    .await;
+    match sent {
+        Ok(message) => {
+            state.chat_events.publish(
+                member.chat_id,
+                ChatEvent::NewMessage {
+                    message,
+                    client_temp_id,
+                },
+            );
+            Ok(())
+        }
+        Err(e) => Err(ChatEvent::Rejected {
+            client_temp_id,
+            error: e.into_status_and_message().1,
+        }),
+    }
+}
+
+pub async fn add_reaction(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Path((_, message_id)): Path<(ChatId, MessageId)>,
+    Json(payload): Json<AddReactionRequest>,
+) -> Result<StatusCode, RequestError> {
+    state
+        .db_connection
+        .add_reaction(member.user_id, member.chat_id, message_id, &payload.emoji)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn list_reactions(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Path((message_id, emoji)): Path<(MessageId, String)>,
+    Query(params): Query<ListingQuery>,
+) -> Result<Json<ListReactorsResponse>, RequestError> {
+    let listing = ListingMode::from_query(params, MAX_REACTOR_LISTING_ELEMENTS)?;
+    let response = state
+        .db_connection
+        .list_reactions(claims.user_id, message_id, &emoji, listing)
+        .await?;
+    Ok(Json(response))
+}
+
+pub async fn replace_message_resource(
+    State(state): State<Arc<AppState>>,
+    claims: Claims,
+    Path(message_id): Path<MessageId>,
+    Json(payload): Json<ReplaceMessageResourceRequest>,
+) -> Result<StatusCode, RequestError> {
+    state
+        .db_connection
+        .replace_message_resource(claims.user_id, message_id, payload.resource_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn mark_chat_read(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+    Json(payload): Json<MarkChatReadRequest>,
+) -> Result<StatusCode, RequestError> {
+    state
+        .db_connection
+        .mark_chat_read(member.user_id, member.chat_id, payload.up_to_message_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn pin_chat(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+) -> Result<StatusCode, RequestError> {
+    state
+        .db_connection
+        .pin_chat(member.user_id, member.chat_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn unpin_chat(
+    State(state): State<Arc<AppState>>,
+    member: ChatMember,
+) -> Result<StatusCode, RequestError> {
+    state
+        .db_connection
+        .unpin_chat(member.user_id, member.chat_id)
+        .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{header, Request};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    async fn slow_handler() -> StatusCode {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        StatusCode::OK
+    }
+
+    async fn large_json_handler() -> Json<Vec<String>> {
+        Json(vec!["walrus".repeat(10); 100])
+    }
+
+    #[tokio::test]
+    async fn compression_applies_when_requested() {
+        let app = with_compression(Router::new().route("/large", get(large_json_handler)));
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/large")
+                    .header(header::ACCEPT_ENCODING, "gzip")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_ENCODING).unwrap(),
+            "gzip"
+        );
+
+        let response = app
+            .oneshot(Request::get("/large").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn serves_same_app_on_every_listener() {
+        let config = ServerConfig {
+            addresses: vec![],
+            request_timeout: Duration::from_secs(1),
+            header_read_timeout: Duration::from_secs(1),
+            compression: false,
+            access_token_cookie: None,
+            access_token_cookie_secure: true,
+            chat_events_capacity: 1,
+            trust_real_ip_header: false,
+            hide_existence: true,
+        };
+        let first = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let second = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addresses = [first.local_addr().unwrap(), second.local_addr().unwrap()];
+        let app = Router::new().route("/health", get(health));
+        tokio::spawn(async move { serve_listeners(vec![first, second], app, &config).await });
+
+        for address in addresses {
+            let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+            stream
+                .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            assert!(
+                response.starts_with("HTTP/1.1 200"),
+                "{address}: {response}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn serves_http2_with_prior_knowledge() {
+        let config = ServerConfig {
+            addresses: vec![],
+            request_timeout: Duration::from_secs(1),
+            header_read_timeout: Duration::from_secs(1),
+            compression: false,
+            access_token_cookie: None,
+            access_token_cookie_secure: true,
+            chat_events_capacity: 1,
+            trust_real_ip_header: false,
+            hide_existence: true,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let app = Router::new().route("/health", get(health));
+        tokio::spawn(async move { serve_connections(listener, app, &config).await });
+
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n")
+            .await
+            .unwrap();
+        // Empty SETTINGS frame, the server answers with its own SETTINGS frame
+        stream
+            .write_all(&[0, 0, 0, 4, 0, 0, 0, 0, 0])
+            .await
+            .unwrap();
+        let mut frame_header = [0u8; 9];
+        stream.read_exact(&mut frame_header).await.unwrap();
+        assert_eq!(frame_header[3], 4, "{frame_header:?}");
+    }
+
+    #[tokio::test]
+    async fn request_timeout_cuts_off_slow_handler() {
+        let app = with_request_timeout(
+            Router::new()
+                .route("/slow", get(slow_handler))
+                .route("/health", get(health)),
+            Duration::from_millis(50),
+        );
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/slow").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::REQUEST_TIMEOUT);
+
+        let response = app
+            .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
@@ -1,21 +1,29 @@
+use crate::auth::captcha::{CaptchaVerifier, NoCaptcha};
 use crate::config::AppConfig;
 use crate::database::connection::DbConnection;
+use crate::server::events::ChatEvents;
 use crate::server::rate_limit::RateLimiter;
 
 pub struct AppState {
     pub config: AppConfig,
     pub db_connection: DbConnection,
     pub rate_limiter: RateLimiter,
+    pub chat_events: ChatEvents,
+    pub captcha: Box<dyn CaptchaVerifier>,
 }
 
 impl AppState {
     pub async fn try_init(config: &AppConfig) -> anyhow::Result<Self> {
-        let db_connection = DbConnection::connect(&config.database).await?;
+        let db_connection = DbConnection::connect(&config.database, &config.auth)
+            .await?
+            .with_hide_existence(config.server.hide_existence);
         let rate_limiter = RateLimiter::new();
         Ok(Self {
             config: config.clone(),
             db_connection,
             rate_limiter,
+            chat_events: ChatEvents::new(config.server.chat_events_capacity),
+            captcha: Box::new(NoCaptcha),
         })
     }
 }
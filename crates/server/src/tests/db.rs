@@ -1,36 +1,177 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::{to_bytes, Body};
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE, COOKIE, SET_COOKIE};
+use axum::http::{Request, StatusCode};
+use axum::response::IntoResponse;
 use base64::prelude::BASE64_STANDARD as BASE64;
 use base64::Engine;
+use futures::{SinkExt, StreamExt};
 use once_cell::sync::Lazy;
-use tokio::sync::Mutex;
+use sqlx::{Postgres, Transaction};
+use tokio::sync::{OnceCell, RwLock, RwLockReadGuard};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+use tower::ServiceExt;
 
+use crate::auth::captcha::CaptchaVerifier;
 use crate::auth::token::TokenExchangePayload;
-use crate::auth::utils::unpack_session_id_and_token;
-use crate::config::ENV_ORIGIN_PASSWORD;
-use crate::database::commands::MAX_SESSIONS_PER_USER;
+use crate::auth::utils::{unpack_session_id_and_token, TokenKind};
+use crate::config::{AppConfig, AuthConfig, FeaturesConfig, ServerConfig};
+use crate::database::commands::{MAX_PINNED_CHATS, MAX_SESSIONS_PER_USER};
 use crate::database::connection::{DbConfig, DbConnection};
 use crate::error::{RequestError, SessionError, ValidationError};
-use crate::models::chat::{ChatId, ChatKind, ChatResponse};
+use crate::models::audit::{AuditAction, ListAuditQuery};
+use crate::models::chat::{ChatId, ChatKind, ChatResponse, ChatRole};
+use crate::models::export::EXPORT_MESSAGES_BATCH_SIZE;
+use crate::models::listing::ListingMode;
+use crate::models::message::{
+    ImportedMessage, MessageId, MessageResponse, ReactorResponse, MESSAGE_TEXT_MAX_LENGTH,
+};
 use crate::models::session::SessionId;
-use crate::models::user::{UserId, UserRole};
+use crate::models::user::{
+    BlockedUserResponse, InviteUserRequest, UserId, UserProfileResponse, UserRole,
+    USER_ALIAS_LENGTH_LIMIT,
+};
+use crate::server::constants::{
+    MAX_LATEST_MESSAGE_IDS_CHATS, MAX_LISTING_ELEMENTS, MAX_UNREAD_COUNTS_CHATS,
+};
+use crate::server::deliver_scheduled_messages_round;
+use crate::server::events::ChatEvent;
+use crate::server::router::routes;
+use crate::server::state::AppState;
 
-/// Some tests can't run in parallel, prevent them from breaking each other's state
-static SERIAL_LOCK: Lazy<Mutex<()>> = Lazy::new(Mutex::default);
+/// Tests resetting the schema take it exclusively, rollback tests share it and run in parallel
+static SERIAL_LOCK: Lazy<RwLock<()>> = Lazy::new(RwLock::default);
+/// Makes sure the shared schema exists before the first rollback test runs
+static SCHEMA_READY: OnceCell<()> = OnceCell::const_new();
 const TEST_ORIGIN_PASSWORD: &str = "test_origin_password";
 
+/// Default auth settings with the origin user bootstrapped from `TEST_ORIGIN_PASSWORD`.
+fn test_auth_config() -> AuthConfig {
+    AuthConfig {
+        origin_password: Some(TEST_ORIGIN_PASSWORD.to_string()),
+        ..AuthConfig::default()
+    }
+}
+
+pub(crate) async fn connect_db() -> DbConnection {
+    let config = DbConfig::development("walrus_db", "walrus_guest", "walruspass");
+    DbConnection::connect(&config, &test_auth_config())
+        .await
+        .unwrap()
+}
+
 async fn init_and_get_db() -> DbConnection {
     let _ = tracing_subscriber::fmt::try_init();
 
-    let config = DbConfig::development("walrus_db", "walrus_guest", "walruspass");
-    let db = DbConnection::connect(&config).await.unwrap();
-    db.drop_schema().await.unwrap();
-    std::env::set_var(ENV_ORIGIN_PASSWORD, TEST_ORIGIN_PASSWORD);
-    db.init_schema().await.unwrap();
+    let db = connect_db().await;
+    db.reset_schema().await.unwrap();
     db
 }
 
+/// Transaction over the shared schema that is rolled back on drop, nothing it writes is ever
+/// visible to other tests. Unique values (e.g. aliases) must still differ between parallel tests,
+/// otherwise inserts block on each other's uncommitted rows.
+pub(crate) struct RollbackTx {
+    tx: Transaction<'static, Postgres>,
+    _db: DbConnection,
+    _shared: RwLockReadGuard<'static, ()>,
+}
+
+impl Deref for RollbackTx {
+    type Target = Transaction<'static, Postgres>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.tx
+    }
+}
+
+impl DerefMut for RollbackTx {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.tx
+    }
+}
+
+pub(crate) async fn begin_rollback_tx() -> RollbackTx {
+    SCHEMA_READY
+        .get_or_init(|| async {
+            let _lock = SERIAL_LOCK.write().await;
+            init_and_get_db().await;
+        })
+        .await;
+    let shared = SERIAL_LOCK.read().await;
+    // Fresh pool per test, cached enum types don't survive schema resets done by serial tests
+    let db = connect_db().await;
+    let tx = db.pool().begin().await.unwrap();
+    RollbackTx {
+        tx,
+        _db: db,
+        _shared: shared,
+    }
+}
+
 async fn invite_regular(db: &DbConnection, alias: &str, pass: &str) -> UserId {
     let origin_user_id = 1;
-    db.invite_user(origin_user_id, alias, pass).await.unwrap()
+    db.invite_user(origin_user_id, alias, pass, None)
+        .await
+        .unwrap()
+}
+
+/// Moves `column` of the `table` rows where `key_column = key` back by `age`, so time windows can
+/// be covered without waiting or injecting a clock.
+async fn backdate<K>(
+    db: &DbConnection,
+    table: &str,
+    column: &str,
+    key_column: &str,
+    key: K,
+    age: chrono::Duration,
+) where
+    K: for<'q> sqlx::Encode<'q, sqlx::Postgres> + sqlx::Type<sqlx::Postgres> + Send,
+{
+    sqlx::query(&format!(
+        "UPDATE {table} SET {column} = {column} - $1 WHERE {key_column} = $2"
+    ))
+    .bind(age)
+    .bind(key)
+    .execute(db.pool())
+    .await
+    .unwrap();
+}
+
+/// App state over the test database with default server settings, for requests through `routes`.
+async fn init_app_state() -> Arc<AppState> {
+    init_app_state_with(|_| {}).await
+}
+
+/// Same as `init_app_state` with the default settings adjusted by `configure`.
+async fn init_app_state_with(configure: impl FnOnce(&mut AppConfig)) -> Arc<AppState> {
+    let mut config = AppConfig {
+        server: ServerConfig {
+            addresses: vec![],
+            request_timeout: Duration::from_secs(30),
+            header_read_timeout: Duration::from_secs(10),
+            compression: false,
+            access_token_cookie: None,
+            access_token_cookie_secure: true,
+            chat_events_capacity: 256,
+            trust_real_ip_header: false,
+            hide_existence: true,
+        },
+        database: DbConfig::development("walrus_db", "walrus_guest", "walruspass"),
+        auth: test_auth_config(),
+        features: FeaturesConfig::default(),
+    };
+    configure(&mut config);
+    Arc::new(AppState::try_init(&config).await.unwrap())
+}
+
+async fn bearer_for(db: &DbConnection, alias: &str, pass: &str) -> String {
+    let tokens = db.login(alias, pass).await.unwrap().tokens;
+    format!("Bearer {}", tokens.access_token)
 }
 
 async fn resolve_session(
@@ -38,18 +179,19 @@ async fn resolve_session(
     tokens: &TokenExchangePayload,
 ) -> Result<UserId, SessionError> {
     let packed_bytes = BASE64.decode(&tokens.access_token).unwrap();
-    let (session_id, token) = unpack_session_id_and_token(&packed_bytes).unwrap();
+    let (session_id, token) =
+        unpack_session_id_and_token(&packed_bytes, TokenKind::Access).unwrap();
     db.resolve_session(session_id, token).await
 }
 
-fn unpack_encoded_session_token(token_b64: &str) -> (SessionId, Vec<u8>) {
+fn unpack_encoded_session_token(token_b64: &str, kind: TokenKind) -> (SessionId, Vec<u8>) {
     let packed_bytes = BASE64.decode(token_b64).unwrap();
-    let (session_id, token) = unpack_session_id_and_token(&packed_bytes).unwrap();
+    let (session_id, token) = unpack_session_id_and_token(&packed_bytes, kind).unwrap();
     (session_id, token.to_vec())
 }
 
 async fn list_user_chats(db: &DbConnection, user_id: UserId) -> Vec<ChatResponse> {
-    db.list_chats(user_id, 100, 1).await.unwrap().chats
+    db.list_chats(user_id, 100, 1, None).await.unwrap().chats
 }
 
 async fn find_matching_chats(
@@ -101,9 +243,102 @@ async fn count_chats_by_kind(db: &DbConnection, user_id: UserId, kind: ChatKind)
         .count()
 }
 
+#[tokio::test]
+async fn auto_migrate_initializes_empty_database() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    db.drop_schema().await.unwrap();
+
+    let mut config = DbConfig::development("walrus_db", "walrus_guest", "walruspass");
+    config.auto_migrate = Some(false);
+    let manual = DbConnection::connect(&config, &test_auth_config())
+        .await
+        .unwrap();
+    let err = manual.prepare_schema().await.unwrap_err();
+    assert!(err.to_string().contains("pending migrations"), "{err}");
+
+    config.auto_migrate = Some(true);
+    let auto = DbConnection::connect(&config, &test_auth_config())
+        .await
+        .unwrap();
+    auto.prepare_schema().await.unwrap();
+    auto.login("origin", TEST_ORIGIN_PASSWORD).await.unwrap();
+    // Up to date schema passes the check without migrating
+    manual.prepare_schema().await.unwrap();
+}
+
+#[tokio::test]
+async fn reset_schema_leaves_only_origin_user() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let origin_user_id = 1;
+    let user_a = invite_regular(&db, "before_reset_a", "passforreseta").await;
+    let _user_b = invite_regular(&db, "before_reset_b", "passforresetb").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::WithSelf, None).await;
+    db.send_message(user_a, chat_id, "to be wiped")
+        .await
+        .unwrap();
+
+    db.reset_schema().await.unwrap();
+    let db = connect_db().await;
+    // startup path on already initialized schema should be a no-op
+    db.init_schema().await.unwrap();
+
+    let origin = db.whoami(origin_user_id).await.unwrap();
+    assert_eq!(origin.alias, "origin");
+    assert_eq!(origin.role, UserRole::Admin);
+    let origin_chats = list_user_chats(&db, origin_user_id).await;
+    assert_eq!(origin_chats.len(), 1);
+    assert_eq!(origin_chats[0].kind, ChatKind::WithSelf);
+    assert!(origin_chats[0].last_message_id.is_none());
+
+    let err = db
+        .login("before_reset_a", "passforreseta")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RequestError::BadCredentials));
+    db.login("origin", TEST_ORIGIN_PASSWORD).await.unwrap();
+
+    // identities start over, so the next invited user takes the id right after origin
+    let user_c = invite_regular(&db, "after_reset_c", "passforresetc").await;
+    assert_eq!(user_c, origin_user_id + 1);
+}
+
+#[tokio::test]
+async fn reads_survive_terminated_pool_connection() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_id = invite_regular(&db, "survivor", "passforsurvivor").await;
+
+    for test_before_acquire in [true, false] {
+        let mut config = DbConfig::development("walrus_db", "walrus_guest", "walruspass");
+        config.max_connections = Some(1);
+        config.test_before_acquire = Some(test_before_acquire);
+        let single = DbConnection::connect(&config, &test_auth_config())
+            .await
+            .unwrap();
+        let pid: i32 = sqlx::query_scalar("SELECT pg_backend_pid()")
+            .fetch_one(single.pool())
+            .await
+            .unwrap();
+
+        // Simulates Postgres restart for the only pooled connection.
+        let terminated: bool = sqlx::query_scalar("SELECT pg_terminate_backend($1)")
+            .bind(pid)
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+        assert!(terminated);
+
+        let whoami = single.whoami(user_id).await.unwrap();
+        assert_eq!(whoami.alias, "survivor");
+    }
+}
+
 #[tokio::test]
 async fn create_chat_with_self() {
-    let _lock = SERIAL_LOCK.lock().await;
+    let _lock = SERIAL_LOCK.write().await;
     let db = init_and_get_db().await;
 
     let msg_a_1 = "Hi chat with self, here I will be sending messages for myself!";
@@ -143,7 +378,7 @@ async fn create_chat_with_self() {
 
 #[tokio::test]
 async fn create_private_chat() {
-    let _lock = SERIAL_LOCK.lock().await;
+    let _lock = SERIAL_LOCK.write().await;
     let db = init_and_get_db().await;
 
     let msg_a_1 = "Oh hi there baguette, just wanted to check if you still have those bakery?";
@@ -201,9 +436,97 @@ async fn create_private_chat() {
     assert_eq!(user_b_private_chat.display_name.as_deref(), Some(alias_a));
 }
 
+#[tokio::test]
+async fn private_chat_pairs_are_unique_and_ordered() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let origin_user_id = 1;
+    let user_a = invite_regular(&db, "pair_user_a", "passforpairusera").await;
+    let user_b = invite_regular(&db, "pair_user_b", "passforpairuserb").await;
+
+    // Invites created pairs (origin, a), (origin, b) and (a, b)
+    let pairs: Vec<(ChatId, UserId, UserId)> = sqlx::query_as(
+        "SELECT chat_id, user_id_low, user_id_high FROM private_chats ORDER BY chat_id",
+    )
+    .fetch_all(db.pool())
+    .await
+    .unwrap();
+    assert_eq!(
+        pairs
+            .iter()
+            .map(|(_, low, high)| (*low, *high))
+            .collect::<Vec<_>>(),
+        [
+            (origin_user_id, user_a),
+            (origin_user_id, user_b),
+            (user_a, user_b)
+        ]
+    );
+    for (chat_id, low, high) in &pairs {
+        assert_eq!(
+            db.find_private_chat(*high, *low).await.unwrap(),
+            Some(*chat_id)
+        );
+    }
+
+    let first_chat_id = pairs[0].0;
+    let violated_constraint = |sql: &'static str| {
+        let db = &db;
+        async move {
+            let err = sqlx::query(sql)
+                .bind(first_chat_id)
+                .bind(origin_user_id)
+                .bind(user_b)
+                .execute(db.pool())
+                .await
+                .unwrap_err();
+            err.as_database_error()
+                .and_then(|db_error| db_error.constraint())
+                .map(str::to_string)
+        }
+    };
+    let duplicate = violated_constraint(
+        "UPDATE private_chats SET user_id_low = $2, user_id_high = $3 WHERE chat_id = $1",
+    )
+    .await;
+    assert_eq!(duplicate.as_deref(), Some("private_chat_pair_unique"));
+    let reversed = violated_constraint(
+        "UPDATE private_chats SET user_id_low = $3, user_id_high = $2 WHERE chat_id = $1",
+    )
+    .await;
+    assert_eq!(reversed.as_deref(), Some("private_chat_pair_order"));
+
+    // Peer display name comes from the pair, not from other memberships
+    let chat = find_chat_by_id(&db, user_a, pairs[2].0).await;
+    assert_eq!(chat.display_name.as_deref(), Some("pair_user_b"));
+}
+
+#[tokio::test]
+async fn create_private_chat_with_self_is_rejected() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let alias = "lonely_user";
+    let user_id = invite_regular(&db, alias, "passforlonely").await;
+
+    let err = db.create_private_chat(user_id, alias).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InvalidInput { value, .. }) if value == alias
+    ));
+    assert_eq!(
+        count_chats_by_kind(&db, user_id, ChatKind::Private).await,
+        1
+    );
+    assert_eq!(
+        count_chats_by_kind(&db, user_id, ChatKind::WithSelf).await,
+        1
+    );
+}
+
 #[tokio::test]
 async fn invite_user_creates_private_chats_with_all_existing_users() {
-    let _lock = SERIAL_LOCK.lock().await;
+    let _lock = SERIAL_LOCK.write().await;
     let db = init_and_get_db().await;
 
     let origin_user_id = 1;
@@ -212,7 +535,7 @@ async fn invite_user_creates_private_chats_with_all_existing_users() {
 
     let new_user_alias = "new_joiner";
     let new_user_id = db
-        .invite_user(origin_user_id, new_user_alias, "passfornewjoiner")
+        .invite_user(origin_user_id, new_user_alias, "passfornewjoiner", None)
         .await
         .unwrap();
 
@@ -253,24 +576,259 @@ async fn invite_user_creates_private_chats_with_all_existing_users() {
 
 #[tokio::test]
 async fn invite_user_requires_admin_role() {
-    let _lock = SERIAL_LOCK.lock().await;
+    let _lock = SERIAL_LOCK.write().await;
     let db = init_and_get_db().await;
 
     let inviter = invite_regular(&db, "not_admin", "passforadmin").await;
 
     let err = db
-        .invite_user(inviter, "should_fail", "passfornewuser")
+        .invite_user(inviter, "should_fail", "passfornewuser", None)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InsufficientPermissions { .. })
+    ));
+}
+
+#[tokio::test]
+async fn invite_user_with_taken_alias_returns_already_exists() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let origin_user_id = 1;
+    let alias = "twice_invited";
+    let user_id = invite_regular(&db, alias, "passfortwice").await;
+
+    let err = db
+        .invite_user(origin_user_id, alias, "otherpassfortwice", None)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::AlreadyExists)
+    ));
+    let origin_err = db
+        .invite_user(origin_user_id, "origin", "otherpassfororigin", None)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        origin_err,
+        RequestError::Validation(ValidationError::AlreadyExists)
+    ));
+
+    // failed invite must not leave partial chats behind
+    assert_eq!(list_user_chats(&db, user_id).await.len(), 2);
+    assert_eq!(list_user_chats(&db, origin_user_id).await.len(), 2);
+}
+
+#[tokio::test]
+async fn invite_leaves_nothing_behind_when_self_chat_setup_fails() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let origin_user_id = 1;
+    let count_chats = || async {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM chats")
+            .fetch_one(db.pool())
+            .await
+            .unwrap()
+    };
+    let chats_before = count_chats().await;
+
+    // Fail membership insert after the self chat row is already created
+    for statement in [
+        "
+        CREATE OR REPLACE FUNCTION fail_self_chat_membership() RETURNS trigger AS $$
+        BEGIN
+            IF (SELECT kind FROM chats WHERE id = NEW.chat_id) = 'with_self' THEN
+                RAISE EXCEPTION 'injected self chat membership failure';
+            END IF;
+            RETURN NEW;
+        END
+        $$ LANGUAGE plpgsql;
+        ",
+        "
+        CREATE TRIGGER fail_self_chat_membership BEFORE INSERT ON chats_members
+        FOR EACH ROW EXECUTE FUNCTION fail_self_chat_membership();
+        ",
+    ] {
+        sqlx::query(statement).execute(db.pool()).await.unwrap();
+    }
+    let err = db
+        .invite_user(origin_user_id, "half_invited", "passforhalfinvited", None)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RequestError::Sqlx(sqlx::Error::Database(_))));
+    for statement in [
+        "DROP TRIGGER fail_self_chat_membership ON chats_members;",
+        "DROP FUNCTION fail_self_chat_membership;",
+    ] {
+        sqlx::query(statement).execute(db.pool()).await.unwrap();
+    }
+
+    assert_eq!(count_chats().await, chats_before);
+    let result = db
+        .login("half_invited", "passforhalfinvited")
+        .await
+        .unwrap_err();
+    assert!(matches!(result, RequestError::BadCredentials));
+    // Alias is free again, nothing of the failed attempt was committed
+    let user_id = invite_regular(&db, "half_invited", "passforhalfinvited").await;
+    assert_eq!(
+        count_chats_by_kind(&db, user_id, ChatKind::WithSelf).await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn invite_users_bulk_is_all_or_nothing() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let origin_user_id = 1;
+    let existing = invite_regular(&db, "bulk_existing", "passforexisting").await;
+    let invite = |alias: &str| InviteUserRequest {
+        alias: alias.to_string(),
+        password: format!("passfor{alias}"),
+        role: None,
+        captcha_token: None,
+    };
+
+    let err = db
+        .invite_users_bulk(
+            origin_user_id,
+            vec![invite("bulk_a"), invite("bulk_existing"), invite("bulk_b")],
+        )
         .await
         .unwrap_err();
     assert!(matches!(
         err,
+        RequestError::Validation(ValidationError::InvalidInput { value, .. }) if value == "bulk_existing"
+    ));
+    let result = db.login("bulk_a", "passforbulk_a").await.unwrap_err();
+    assert!(matches!(result, RequestError::BadCredentials));
+    assert_eq!(list_user_chats(&db, existing).await.len(), 2);
+
+    let repeated_err = db
+        .invite_users_bulk(origin_user_id, vec![invite("bulk_a"), invite("bulk_a")])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        repeated_err,
+        RequestError::Validation(ValidationError::InvalidInput { value, .. }) if value == "bulk_a"
+    ));
+
+    let forbidden_err = db
+        .invite_users_bulk(existing, vec![invite("bulk_a")])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        forbidden_err,
         RequestError::Validation(ValidationError::InsufficientPermissions { .. })
     ));
+
+    let user_ids = db
+        .invite_users_bulk(origin_user_id, vec![invite("bulk_a"), invite("bulk_b")])
+        .await
+        .unwrap();
+    assert_eq!(user_ids.len(), 2);
+    assert_eq!(list_user_chats(&db, existing).await.len(), 4);
+    assert!(
+        !find_matching_chats(&db, user_ids[0], ChatKind::Private, Some("bulk_b"))
+            .await
+            .is_empty()
+    );
+}
+
+#[tokio::test]
+async fn invite_without_role_gets_configured_default() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let origin_user_id = 1;
+    assert_eq!(db.auth().default_invited_role, UserRole::Regular);
+
+    let defaulted = db
+        .invite_user(origin_user_id, "role_default", "passforroledefault", None)
+        .await
+        .unwrap();
+    let explicit = db
+        .invite_user(
+            origin_user_id,
+            "role_explicit",
+            "passforroleexplicit",
+            Some(UserRole::Admin),
+        )
+        .await
+        .unwrap();
+    let bulk = db
+        .invite_users_bulk(
+            origin_user_id,
+            vec![InviteUserRequest {
+                alias: "role_bulk".to_string(),
+                password: "passforrolebulk".to_string(),
+                role: None,
+                captcha_token: None,
+            }],
+        )
+        .await
+        .unwrap();
+
+    let role_of = |user_id| {
+        let db = &db;
+        async move { db.whoami(user_id).await.unwrap().role }
+    };
+    assert_eq!(role_of(defaulted).await, UserRole::Regular);
+    assert_eq!(role_of(bulk[0]).await, UserRole::Regular);
+    assert_eq!(role_of(explicit).await, UserRole::Admin);
+}
+
+#[tokio::test]
+async fn alias_at_column_limit_is_stored_and_longer_is_rejected() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let origin_user_id = 1;
+    let at_limit = "a".repeat(USER_ALIAS_LENGTH_LIMIT);
+    let over_limit = "a".repeat(USER_ALIAS_LENGTH_LIMIT + 1);
+
+    let user_id = db
+        .invite_user(origin_user_id, &at_limit, "passforlongalias", None)
+        .await
+        .unwrap();
+    assert_eq!(db.whoami(user_id).await.unwrap().alias, at_limit);
+    let err = db
+        .invite_user(origin_user_id, &over_limit, "passforlongalias", None)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
+    let err = db.change_alias(user_id, &over_limit).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
+}
+
+#[tokio::test]
+async fn chat_exists_reports_kind() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user = invite_regular(&db, "exists_user", "passforexists").await;
+    let with_self = find_chat_id(&db, user, ChatKind::WithSelf, None).await;
+    let group = db.create_group_chat(user, "Exists", None).await.unwrap();
+
+    assert_eq!(
+        db.chat_exists(with_self).await.unwrap(),
+        Some(ChatKind::WithSelf)
+    );
+    assert_eq!(db.chat_exists(group).await.unwrap(), Some(ChatKind::Group));
+    assert_eq!(db.chat_exists(ChatId::MAX).await.unwrap(), None);
 }
 
 #[tokio::test]
 async fn list_messages_pagination() {
-    let _lock = SERIAL_LOCK.lock().await;
+    let _lock = SERIAL_LOCK.write().await;
     let db = init_and_get_db().await;
 
     let user_a = invite_regular(&db, "pager_a", "pagerpassa").await;
@@ -320,368 +878,4478 @@ async fn list_messages_pagination() {
 }
 
 #[tokio::test]
-async fn list_chats_exposes_last_message_preview_and_unread_count() {
-    let _lock = SERIAL_LOCK.lock().await;
+async fn list_messages_page_beyond_end_is_empty_with_total_hint() {
+    let _lock = SERIAL_LOCK.write().await;
     let db = init_and_get_db().await;
 
-    let user_a = invite_regular(&db, "preview_a", "passforpreviewa").await;
-    let user_b = invite_regular(&db, "preview_b", "passforpreviewb").await;
-    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("preview_b")).await;
+    let user_a = invite_regular(&db, "overshoot_a", "overshootpassa").await;
+    let _user_b = invite_regular(&db, "overshoot_b", "overshootpassb").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("overshoot_b")).await;
 
-    let _msg_1 = db
-        .send_message(user_a, chat_id, "message_from_a")
+    for text in ["msg_1", "msg_2", "msg_3"] {
+        db.send_message(user_a, chat_id, text).await.unwrap();
+    }
+
+    let beyond = db.list_messages(user_a, chat_id, 2, 1000).await.unwrap();
+    assert!(beyond.messages.is_empty());
+    assert_eq!(beyond.total_pages, None);
+
+    let with_total = db
+        .list_messages_with_total(user_a, chat_id, 2, 1000)
         .await
         .unwrap();
-    let msg_2 = db
-        .send_message(user_b, chat_id, "message_from_b")
+    assert!(with_total.messages.is_empty());
+    assert_eq!(with_total.total_pages, Some(2));
+
+    let last = db
+        .list_messages_with_total(user_a, chat_id, 2, 2)
         .await
         .unwrap();
+    assert_eq!(last.messages.len(), 1);
+    assert_eq!(last.total_pages, Some(2));
+}
 
-    let chats_for_a = list_user_chats(&db, user_a).await;
-    assert_eq!(chats_for_a.first().map(|chat| chat.id), Some(chat_id));
-
-    let chat_for_a = find_chat_by_id(&db, user_a, chat_id).await;
-    assert_eq!(chat_for_a.last_message_id, Some(msg_2));
-    assert_eq!(
-        chat_for_a.last_message_text.as_deref(),
-        Some("message_from_b")
-    );
-    assert!(chat_for_a.last_message_at.is_some());
-    assert_eq!(chat_for_a.unread_count, 1);
-
-    let chat_for_b = find_chat_by_id(&db, user_b, chat_id).await;
-    assert_eq!(chat_for_b.last_message_id, Some(msg_2));
-    assert_eq!(
-        chat_for_b.last_message_text.as_deref(),
-        Some("message_from_b")
-    );
-    assert_eq!(chat_for_b.unread_count, 1);
+#[tokio::test]
+async fn list_messages_pages_stay_full_across_deleted_messages() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
 
-    db.mark_chat_read(user_b, chat_id, msg_2).await.unwrap();
+    let user_a = invite_regular(&db, "gaps_a", "passforgapsa").await;
+    let _user_b = invite_regular(&db, "gaps_b", "passforgapsb").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("gaps_b")).await;
 
-    let chat_for_b_after_read = find_chat_by_id(&db, user_b, chat_id).await;
-    assert_eq!(chat_for_b_after_read.unread_count, 0);
+    let mut sent = Vec::new();
+    for text in ["msg_1", "msg_2", "msg_3", "msg_4", "msg_5", "msg_6"] {
+        sent.push(db.send_message(user_a, chat_id, text).await.unwrap());
+    }
+    db.delete_message(user_a, chat_id, sent[1]).await.unwrap();
+    db.delete_message(user_a, chat_id, sent[3]).await.unwrap();
 
-    db.send_message(user_b, chat_id, "message_from_b_2")
+    // Deleted messages leave gaps in ids only, pages are backfilled with the remaining ones
+    let ids = |messages: Vec<MessageResponse>| -> Vec<MessageId> {
+        messages.into_iter().map(|message| message.id).collect()
+    };
+    let page_1 = db.list_messages(user_a, chat_id, 2, 1).await.unwrap();
+    assert_eq!(ids(page_1.messages), [sent[0], sent[2]]);
+    let page_2 = db.list_messages(user_a, chat_id, 2, 2).await.unwrap();
+    assert_eq!(ids(page_2.messages), [sent[4], sent[5]]);
+    let since = db.list_messages_since(user_a, chat_id, 0, 2).await.unwrap();
+    assert_eq!(ids(since.messages), [sent[0], sent[2]]);
+    let since = db
+        .list_messages_since(user_a, chat_id, sent[2], 2)
         .await
         .unwrap();
+    assert_eq!(ids(since.messages), [sent[4], sent[5]]);
+}
 
-    let chat_for_a_after_new = find_chat_by_id(&db, user_a, chat_id).await;
-    assert_eq!(chat_for_a_after_new.unread_count, 2);
-    let chat_for_b_after_new = find_chat_by_id(&db, user_b, chat_id).await;
-    assert_eq!(chat_for_b_after_new.unread_count, 0);
+#[tokio::test]
+async fn list_messages_orders_by_id_regardless_of_created_at() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let user_a = invite_regular(&db, "order_a", "orderpassa").await;
+    let _user_b = invite_regular(&db, "order_b", "orderpassb").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("order_b")).await;
+
+    let mut sent = Vec::new();
+    for text in ["first", "second", "third"] {
+        sent.push(db.send_message(user_a, chat_id, text).await.unwrap());
+    }
+    // Simulate clock going backwards: later messages get earlier timestamps
+    sqlx::query(
+        "UPDATE messages SET created_at = current_timestamp - (id * interval '1 hour') WHERE chat_id = $1",
+    )
+    .bind(chat_id)
+    .execute(db.pool())
+    .await
+    .unwrap();
+
+    let paged = db
+        .list_messages(user_a, chat_id, 10, 1)
+        .await
+        .unwrap()
+        .messages;
+    let incremental = db
+        .list_messages_since(user_a, chat_id, 0, 10)
+        .await
+        .unwrap()
+        .messages;
+    for messages in [paged, incremental] {
+        let ids: Vec<_> = messages.iter().map(|message| message.id).collect();
+        assert_eq!(ids, sent);
+        assert!(messages[0].created_at > messages[2].created_at);
+    }
 }
 
 #[tokio::test]
-async fn mark_chat_read_is_monotonic_and_validates_target_message_scope() {
-    let _lock = SERIAL_LOCK.lock().await;
+async fn list_messages_since_returns_only_newer_messages() {
+    let _lock = SERIAL_LOCK.write().await;
     let db = init_and_get_db().await;
 
-    let user_a = invite_regular(&db, "reader_a", "passforreadera").await;
-    let user_b = invite_regular(&db, "reader_b", "passforreaderb").await;
-    let user_c = invite_regular(&db, "reader_c", "passforreaderc").await;
-    let chat_ab_id = find_chat_id(&db, user_a, ChatKind::Private, Some("reader_b")).await;
-    let self_chat_b_id = find_chat_id(&db, user_b, ChatKind::WithSelf, None).await;
+    let user_a = invite_regular(&db, "sync_a", "syncpassa").await;
+    let user_b = invite_regular(&db, "sync_b", "syncpassb").await;
+    let user_c = invite_regular(&db, "sync_c", "syncpassc").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("sync_b")).await;
 
-    let msg_1 = db
-        .send_message(user_a, chat_ab_id, "a_msg_1")
+    db.send_message(user_a, chat_id, "before_1").await.unwrap();
+    let last_seen = db.send_message(user_b, chat_id, "before_2").await.unwrap();
+    db.send_message(user_b, chat_id, "after_1").await.unwrap();
+    db.send_message(user_a, chat_id, "after_2").await.unwrap();
+
+    let missed = db
+        .list_messages_since(user_a, chat_id, last_seen, 100)
         .await
-        .unwrap();
-    let msg_2 = db
-        .send_message(user_a, chat_ab_id, "a_msg_2")
+        .unwrap()
+        .messages;
+    assert_eq!(missed.len(), 2);
+    assert_eq!(missed[0].text.as_deref(), Some("after_1"));
+    assert_eq!(missed[1].text.as_deref(), Some("after_2"));
+    assert!(missed.iter().all(|message| message.id > last_seen));
+
+    let limited = db
+        .list_messages_since(user_a, chat_id, last_seen, 1)
         .await
-        .unwrap();
-    db.mark_chat_read(user_b, chat_ab_id, msg_2).await.unwrap();
+        .unwrap()
+        .messages;
+    assert_eq!(limited.len(), 1);
+    assert_eq!(limited[0].text.as_deref(), Some("after_1"));
 
-    // Older cursor update should not move read position backwards.
-    db.mark_chat_read(user_b, chat_ab_id, msg_1).await.unwrap();
+    let latest = missed.last().unwrap().id;
+    let nothing_new = db
+        .list_messages_since(user_b, chat_id, latest, 100)
+        .await
+        .unwrap()
+        .messages;
+    assert!(nothing_new.is_empty());
 
-    db.send_message(user_a, chat_ab_id, "a_msg_3")
+    let err = db
+        .list_messages_since(user_c, chat_id, 0, 100)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
+}
+
+#[tokio::test]
+async fn get_message_details_includes_reply_and_reactions() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let user_a = invite_regular(&db, "details_a", "passfordetailsa").await;
+    let user_b = invite_regular(&db, "details_b", "passfordetailsb").await;
+    let user_c = invite_regular(&db, "details_c", "passfordetailsc").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("details_b")).await;
+    let self_chat_b = find_chat_id(&db, user_b, ChatKind::WithSelf, None).await;
+
+    let original = db.send_message(user_a, chat_id, "original").await.unwrap();
+    let reply = db
+        .send_message_with_reply(user_b, chat_id, "reply", Some(original))
         .await
         .unwrap();
-    let chat_for_b = find_chat_by_id(&db, user_b, chat_ab_id).await;
-    assert_eq!(chat_for_b.unread_count, 1);
+    db.add_reaction(user_a, chat_id, reply, "👍").await.unwrap();
+    db.add_reaction(user_b, chat_id, reply, "👍").await.unwrap();
+    db.add_reaction(user_b, chat_id, reply, "❤️").await.unwrap();
+    // repeated reaction is idempotent
+    db.add_reaction(user_b, chat_id, reply, "❤️").await.unwrap();
 
-    let wrong_chat_message_id = db
-        .send_message(user_b, self_chat_b_id, "self_only")
+    let details = db
+        .get_message_details(user_a, chat_id, reply)
+        .await
+        .unwrap();
+    assert_eq!(details.message.id, reply);
+    assert_eq!(details.message.text.as_deref(), Some("reply"));
+    assert_eq!(details.message.user_id, Some(user_b));
+    assert_eq!(details.message.reply_to, Some(original));
+    assert_eq!(details.sender_role, Some(ChatRole::Member));
+    let replied = details.reply_to_message.unwrap();
+    assert_eq!(replied.id, original);
+    assert_eq!(replied.text.as_deref(), Some("original"));
+    assert_eq!(replied.user_id, Some(user_a));
+    assert_eq!(details.reactions.len(), 2);
+    assert_eq!(details.reactions[0].emoji, "👍");
+    assert_eq!(details.reactions[0].count, 2);
+    assert!(details.reactions[0].reacted_by_caller);
+    assert_eq!(details.reactions[1].emoji, "❤️");
+    assert_eq!(details.reactions[1].count, 1);
+    assert!(!details.reactions[1].reacted_by_caller);
+
+    let plain = db
+        .get_message_details(user_b, chat_id, original)
         .await
         .unwrap();
+    assert!(plain.reply_to_message.is_none());
+    assert!(plain.reactions.is_empty());
 
     let non_member_err = db
-        .mark_chat_read(user_c, chat_ab_id, msg_2)
+        .get_message_details(user_c, chat_id, reply)
         .await
         .unwrap_err();
     assert!(matches!(
         non_member_err,
         RequestError::Validation(ValidationError::NotFound)
     ));
-
     let wrong_chat_err = db
-        .mark_chat_read(user_b, chat_ab_id, wrong_chat_message_id)
+        .get_message_details(user_b, self_chat_b, reply)
         .await
         .unwrap_err();
     assert!(matches!(
         wrong_chat_err,
         RequestError::Validation(ValidationError::NotFound)
     ));
+
+    let cross_chat_reply_err = db
+        .send_message_with_reply(user_b, self_chat_b, "sneaky", Some(original))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        cross_chat_reply_err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
+    let bad_reaction_err = db
+        .add_reaction(user_a, chat_id, reply, "two words")
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        bad_reaction_err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
 }
 
 #[tokio::test]
-async fn login_and_resolve_session() {
-    let _lock = SERIAL_LOCK.lock().await;
+async fn send_message_with_response_returns_created_message() {
+    let _lock = SERIAL_LOCK.write().await;
     let db = init_and_get_db().await;
 
-    let (alias_a, pass_a) = ("existing_user_a", "existing_password_a");
-    let (alias_b, pass_b) = ("existing_user_b", "existing_password_b");
-    let user_id_a = invite_regular(&db, alias_a, pass_a).await;
-    let user_id_b = invite_regular(&db, alias_b, pass_b).await;
+    let user_a = invite_regular(&db, "echo_a", "passforechoa").await;
+    let _user_b = invite_regular(&db, "echo_b", "passforechob").await;
+    db.change_display_name(user_a, "Echo A").await.unwrap();
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("echo_b")).await;
 
-    // invalid variants
-    let result = db
-        .login("non_existent", "wrong_password")
+    let before = chrono::Utc::now();
+    let sent = db
+        .send_message_with_response(user_a, chat_id, "hello there", None)
         .await
-        .unwrap_err();
-    assert!(matches!(result, RequestError::BadCredentials));
-    let result = db.login("non_existent", pass_a).await.unwrap_err();
-    assert!(matches!(result, RequestError::BadCredentials));
-    let result = db.login(alias_a, "wrong_password").await.unwrap_err();
-    assert!(matches!(result, RequestError::BadCredentials));
-    let result = db.login(alias_a, pass_b).await.unwrap_err();
-    assert!(matches!(result, RequestError::BadCredentials));
-    let result = db.login(alias_b, pass_a).await.unwrap_err();
-    assert!(matches!(result, RequestError::BadCredentials));
-
-    // normal login
-    let result_a = db.login(alias_a, pass_a).await.unwrap();
-    let resolved_user_a = resolve_session(&db, &result_a).await.unwrap();
-    assert_eq!(resolved_user_a, user_id_a);
+        .unwrap();
+    assert_eq!(sent.text.as_deref(), Some("hello there"));
+    assert_eq!(sent.user_id, Some(user_a));
+    assert_eq!(sent.user_display_name.as_deref(), Some("Echo A"));
+    assert!(sent.edited_at.is_none());
+    assert!(sent.created_at >= before - chrono::Duration::seconds(5));
 
-    let result_b = db.login(alias_b, pass_b).await.unwrap();
-    let resolved_user_b = resolve_session(&db, &result_b).await.unwrap();
-    assert_eq!(resolved_user_b, user_id_b);
+    let listed = db
+        .list_messages(user_a, chat_id, 10, 1)
+        .await
+        .unwrap()
+        .messages;
+    let stored = listed.iter().find(|m| m.id == sent.id).unwrap();
+    assert_eq!(stored.created_at, sent.created_at);
+    assert_eq!(stored.text, sent.text);
 }
 
 #[tokio::test]
-async fn change_password() {
-    let _lock = SERIAL_LOCK.lock().await;
+async fn list_thread_collects_reply_tree() {
+    let _lock = SERIAL_LOCK.write().await;
     let db = init_and_get_db().await;
 
-    let (alias, pass) = ("existing_user_a", "existing_password_a");
-    let user_id = invite_regular(&db, alias, pass).await;
-    let new_password = "updated_password_a";
-
-    let current_session = db.login(alias, pass).await.unwrap();
-    let (current_session_id, _token) = unpack_encoded_session_token(&current_session.access_token);
-    let other_session = db.login(alias, pass).await.unwrap();
+    let user_a = invite_regular(&db, "thread_a", "passforthreada").await;
+    let user_b = invite_regular(&db, "thread_b", "passforthreadb").await;
+    let outsider = invite_regular(&db, "thread_c", "passforthreadc").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("thread_b")).await;
 
-    let result = db
-        .change_password(
-            user_id,
-            current_session_id,
-            "wrong_current_password",
-            new_password,
-        )
+    let root = db.send_message(user_a, chat_id, "root").await.unwrap();
+    let unrelated = db.send_message(user_b, chat_id, "unrelated").await.unwrap();
+    let first = db
+        .send_message_with_reply(user_b, chat_id, "first", Some(root))
         .await
-        .unwrap_err();
-    assert!(matches!(result, RequestError::BadCredentials));
-
-    db.change_password(user_id, current_session_id, pass, new_password)
+        .unwrap();
+    let second = db
+        .send_message_with_reply(user_a, chat_id, "second", Some(root))
+        .await
+        .unwrap();
+    let nested = db
+        .send_message_with_reply(user_a, chat_id, "nested", Some(first))
+        .await
+        .unwrap();
+    db.send_message_with_reply(user_b, chat_id, "other branch", Some(unrelated))
         .await
         .unwrap();
 
-    let old_login_result = db.login(alias, pass).await.unwrap_err();
-    assert!(matches!(old_login_result, RequestError::BadCredentials));
+    let thread = db.list_thread(user_b, root).await.unwrap().messages;
+    let ids: Vec<MessageId> = thread.iter().map(|message| message.id).collect();
+    assert_eq!(ids, vec![root, first, second, nested]);
 
-    let still_valid = resolve_session(&db, &current_session).await.unwrap();
-    assert_eq!(still_valid, user_id);
-    let revoked = resolve_session(&db, &other_session).await.unwrap_err();
-    assert!(matches!(revoked, SessionError::TokenNotFound));
+    let subthread = db.list_thread(user_a, first).await.unwrap().messages;
+    let ids: Vec<MessageId> = subthread.iter().map(|message| message.id).collect();
+    assert_eq!(ids, vec![first, nested]);
 
-    let new_login_result = db.login(alias, new_password).await.unwrap();
-    let resolved_user = resolve_session(&db, &new_login_result).await.unwrap();
-    assert_eq!(resolved_user, user_id);
+    let err = db.list_thread(outsider, root).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
 }
 
 #[tokio::test]
-async fn whoami_returns_alias_and_display_name() {
-    let _lock = SERIAL_LOCK.lock().await;
+async fn reply_keeps_snapshot_of_original_text() {
+    let _lock = SERIAL_LOCK.write().await;
     let db = init_and_get_db().await;
 
-    let initial_alias = "existing_user_a";
-    let pass = "existing_password_a";
-    let user_id = invite_regular(&db, initial_alias, pass).await;
-
-    let initial_whoami = db.whoami(user_id).await.unwrap();
-    assert_eq!(initial_whoami.user_id, user_id);
-    assert_eq!(initial_whoami.alias, initial_alias);
-    assert_eq!(initial_whoami.display_name, initial_alias);
-    assert_eq!(initial_whoami.role, UserRole::Regular);
+    let user_a = invite_regular(&db, "snapshot_a", "passforsnapshota").await;
+    let _user_b = invite_regular(&db, "snapshot_b", "passforsnapshotb").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("snapshot_b")).await;
 
-    db.change_alias(user_id, "renamed_user_a").await.unwrap();
-    db.change_display_name(user_id, "Renamed Display")
+    let original = db
+        .send_message(user_a, chat_id, "before edit")
+        .await
+        .unwrap();
+    let reply = db
+        .send_message_with_reply(user_a, chat_id, "reply", Some(original))
+        .await
+        .unwrap();
+    db.edit_message(user_a, chat_id, original, "after edit")
         .await
         .unwrap();
 
-    let updated_whoami = db.whoami(user_id).await.unwrap();
-    assert_eq!(updated_whoami.user_id, user_id);
-    assert_eq!(updated_whoami.alias, "renamed_user_a");
-    assert_eq!(updated_whoami.display_name, "Renamed Display");
-    assert_eq!(updated_whoami.role, UserRole::Regular);
-}
-
-#[tokio::test]
-async fn change_alias() {
-    let _lock = SERIAL_LOCK.lock().await;
-    let db = init_and_get_db().await;
-
-    let (old_alias, pass) = ("existing_user_a", "existing_password_a");
-    let user_id = invite_regular(&db, old_alias, pass).await;
-    let taken_alias = "existing_user_b";
-    let _other_user = invite_regular(&db, taken_alias, "existing_password_b").await;
-
-    let new_alias = "renamed_user_a";
-    db.change_alias(user_id, new_alias).await.unwrap();
-
-    let old_login_result = db.login(old_alias, pass).await.unwrap_err();
-    assert!(matches!(old_login_result, RequestError::BadCredentials));
-
-    let new_login_result = db.login(new_alias, pass).await.unwrap();
-    let resolved_user = resolve_session(&db, &new_login_result).await.unwrap();
-    assert_eq!(resolved_user, user_id);
-
-    let duplicate_err = db.change_alias(user_id, taken_alias).await.unwrap_err();
-    assert!(matches!(
-        duplicate_err,
-        RequestError::Validation(ValidationError::AlreadyExists)
-    ));
-
-    let invalid_err = db.change_alias(user_id, "bad alias").await.unwrap_err();
-    assert!(matches!(
-        invalid_err,
-        RequestError::Validation(ValidationError::InvalidInput { .. })
-    ));
+    let messages = db
+        .list_messages(user_a, chat_id, 10, 1)
+        .await
+        .unwrap()
+        .messages;
+    let edited = messages.iter().find(|m| m.id == original).unwrap();
+    assert_eq!(edited.text.as_deref(), Some("after edit"));
+    assert!(edited.reply_snapshot.is_none());
+    let reply_message = messages.iter().find(|m| m.id == reply).unwrap();
+    assert_eq!(reply_message.reply_to, Some(original));
+    assert_eq!(reply_message.reply_snapshot.as_deref(), Some("before edit"));
 }
 
 #[tokio::test]
-async fn change_display_name() {
-    let _lock = SERIAL_LOCK.lock().await;
+async fn replace_message_resource_surfaces_in_listing() {
+    let _lock = SERIAL_LOCK.write().await;
     let db = init_and_get_db().await;
 
-    let user_a = invite_regular(&db, "existing_user_a", "existing_password_a").await;
-    let user_b_alias = "existing_user_b";
-    let user_b = invite_regular(&db, user_b_alias, "existing_password_b").await;
+    let user_a = invite_regular(&db, "uploader_a", "passforuploadera").await;
+    let user_b = invite_regular(&db, "uploader_b", "passforuploaderb").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("uploader_b")).await;
+    let first_upload = db
+        .create_resource(user_a, "https://cdn.example/first.png")
+        .await
+        .unwrap();
+    let second_upload = db
+        .create_resource(user_a, "https://cdn.example/second.png")
+        .await
+        .unwrap();
+    let foreign_upload = db
+        .create_resource(user_b, "https://cdn.example/foreign.png")
+        .await
+        .unwrap();
 
-    assert!(
-        !find_matching_chats(&db, user_a, ChatKind::Private, Some(user_b_alias))
-            .await
-            .is_empty()
-    );
+    let message_id = db.send_message(user_a, chat_id, "photo").await.unwrap();
+    let before = db
+        .list_messages(user_b, chat_id, 10, 1)
+        .await
+        .unwrap()
+        .messages;
+    assert!(before[0].resource_url.is_none());
+    assert!(before[0].edited_at.is_none());
 
-    let new_display_name = "Baker Ben";
-    db.change_display_name(user_b, new_display_name)
+    db.replace_message_resource(user_a, message_id, first_upload)
         .await
         .unwrap();
-
-    assert!(
-        find_matching_chats(&db, user_a, ChatKind::Private, Some(user_b_alias))
-            .await
-            .is_empty()
-    );
-    assert!(
-        !find_matching_chats(&db, user_a, ChatKind::Private, Some(new_display_name))
-            .await
-            .is_empty()
+    db.replace_message_resource(user_a, message_id, second_upload)
+        .await
+        .unwrap();
+    let after = db
+        .list_messages(user_b, chat_id, 10, 1)
+        .await
+        .unwrap()
+        .messages;
+    assert_eq!(
+        after[0].resource_url.as_deref(),
+        Some("https://cdn.example/second.png")
     );
+    assert!(after[0].edited_at.is_some());
 
-    let user_b_login = db.login(user_b_alias, "existing_password_b").await.unwrap();
-    let resolved_user_b = resolve_session(&db, &user_b_login).await.unwrap();
-    assert_eq!(resolved_user_b, user_b);
-
-    let empty_err = db.change_display_name(user_b, "").await.unwrap_err();
+    let not_author_err = db
+        .replace_message_resource(user_b, message_id, foreign_upload)
+        .await
+        .unwrap_err();
     assert!(matches!(
-        empty_err,
-        RequestError::Validation(ValidationError::InvalidInput { .. })
+        not_author_err,
+        RequestError::Validation(ValidationError::NotFound)
     ));
-
-    let padded_err = db
-        .change_display_name(user_b, " Display Name ")
+    let foreign_resource_err = db
+        .replace_message_resource(user_a, message_id, foreign_upload)
         .await
         .unwrap_err();
     assert!(matches!(
-        padded_err,
-        RequestError::Validation(ValidationError::InvalidInput { .. })
+        foreign_resource_err,
+        RequestError::Validation(ValidationError::NotFound)
     ));
-
-    let too_long_display_name = "x".repeat(31);
-    let too_long_err = db
-        .change_display_name(user_b, &too_long_display_name)
+    let missing_resource_err = db
+        .replace_message_resource(user_a, message_id, foreign_upload + 100)
         .await
         .unwrap_err();
     assert!(matches!(
-        too_long_err,
-        RequestError::Validation(ValidationError::InvalidInput { .. })
+        missing_resource_err,
+        RequestError::Validation(ValidationError::NotFound)
     ));
 }
 
 #[tokio::test]
-async fn limit_sessions_count() {
-    let _lock = SERIAL_LOCK.lock().await;
+async fn removed_member_cannot_replace_message_resource() {
+    let _lock = SERIAL_LOCK.write().await;
     let db = init_and_get_db().await;
 
-    let (alias, pass) = ("existing_user_a", "existing_password_a");
-    let _ = invite_regular(&db, alias, pass).await;
-
-    let first_session = db.login(alias, pass).await.unwrap();
-    let _ok = resolve_session(&db, &first_session).await.unwrap();
-    let second_session = db.login(alias, pass).await.unwrap();
-    let _ok = resolve_session(&db, &second_session).await.unwrap();
-
+    let owner = invite_regular(&db, "resource_group_owner", "passforresourceowner").await;
+    let member = invite_regular(&db, "resource_group_member", "passforresourcemember").await;
+    let group = db
+        .create_group_chat(owner, "Resource group", None)
+        .await
+        .unwrap();
+    db.add_members_to_group_chat(owner, group, &[member])
+        .await
+        .unwrap();
+    let upload = db
+        .create_resource(member, "https://cdn.example/removed.png")
+        .await
+        .unwrap();
+    let message_id = db.send_message(member, group, "photo").await.unwrap();
+
+    db.remove_member(owner, group, member).await.unwrap();
+    let err = db
+        .replace_message_resource(member, message_id, upload)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
+    let messages = db
+        .list_messages(owner, group, 10, 1)
+        .await
+        .unwrap()
+        .messages;
+    assert_eq!(messages[0].id, message_id);
+    assert!(messages[0].resource_url.is_none());
+}
+
+#[tokio::test]
+async fn list_chats_exposes_last_message_preview_and_unread_count() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let user_a = invite_regular(&db, "preview_a", "passforpreviewa").await;
+    let user_b = invite_regular(&db, "preview_b", "passforpreviewb").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("preview_b")).await;
+
+    let _msg_1 = db
+        .send_message(user_a, chat_id, "message_from_a")
+        .await
+        .unwrap();
+    let msg_2 = db
+        .send_message(user_b, chat_id, "message_from_b")
+        .await
+        .unwrap();
+
+    let chats_for_a = list_user_chats(&db, user_a).await;
+    assert_eq!(chats_for_a.first().map(|chat| chat.id), Some(chat_id));
+
+    let chat_for_a = find_chat_by_id(&db, user_a, chat_id).await;
+    assert_eq!(chat_for_a.last_message_id, Some(msg_2));
+    assert_eq!(
+        chat_for_a.last_message_text.as_deref(),
+        Some("message_from_b")
+    );
+    assert!(chat_for_a.last_message_at.is_some());
+    assert_eq!(chat_for_a.unread_count, 1);
+
+    let chat_for_b = find_chat_by_id(&db, user_b, chat_id).await;
+    assert_eq!(chat_for_b.last_message_id, Some(msg_2));
+    assert_eq!(
+        chat_for_b.last_message_text.as_deref(),
+        Some("message_from_b")
+    );
+    assert_eq!(chat_for_b.unread_count, 1);
+
+    db.mark_chat_read(user_b, chat_id, msg_2).await.unwrap();
+
+    let chat_for_b_after_read = find_chat_by_id(&db, user_b, chat_id).await;
+    assert_eq!(chat_for_b_after_read.unread_count, 0);
+
+    db.send_message(user_b, chat_id, "message_from_b_2")
+        .await
+        .unwrap();
+
+    let chat_for_a_after_new = find_chat_by_id(&db, user_a, chat_id).await;
+    assert_eq!(chat_for_a_after_new.unread_count, 2);
+    let chat_for_b_after_new = find_chat_by_id(&db, user_b, chat_id).await;
+    assert_eq!(chat_for_b_after_new.unread_count, 0);
+}
+
+#[tokio::test]
+async fn list_chats_filters_by_kind() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let user_a = invite_regular(&db, "filter_a", "passforfiltera").await;
+    let _user_b = invite_regular(&db, "filter_b", "passforfilterb").await;
+    let group_id = db
+        .create_group_chat(user_a, "Filter Group", None)
+        .await
+        .unwrap();
+
+    let all_chats = list_user_chats(&db, user_a).await;
+    assert_eq!(all_chats.len(), 4);
+
+    let private_chats = db
+        .list_chats(user_a, 100, 1, Some(ChatKind::Private))
+        .await
+        .unwrap()
+        .chats;
+    assert_eq!(private_chats.len(), 2);
+    assert!(private_chats
+        .iter()
+        .all(|chat| chat.kind == ChatKind::Private));
+
+    let group_chats = db
+        .list_chats(user_a, 100, 1, Some(ChatKind::Group))
+        .await
+        .unwrap()
+        .chats;
+    assert_eq!(group_chats.len(), 1);
+    assert_eq!(group_chats[0].id, group_id);
+
+    let self_chats = db
+        .list_chats(user_a, 100, 1, Some(ChatKind::WithSelf))
+        .await
+        .unwrap()
+        .chats;
+    assert_eq!(self_chats.len(), 1);
+
+    let channel_chats = db
+        .list_chats(user_a, 100, 1, Some(ChatKind::Channel))
+        .await
+        .unwrap()
+        .chats;
+    assert!(channel_chats.is_empty());
+}
+
+#[tokio::test]
+async fn shared_chats_returns_common_chats_only() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let user_a = invite_regular(&db, "shared_a", "passforshareda").await;
+    let user_b = invite_regular(&db, "shared_b", "passforsharedb").await;
+    let user_c = invite_regular(&db, "shared_c", "passforsharedc").await;
+    let common_group = db.create_group_chat(user_a, "Common", None).await.unwrap();
+    db.add_members_to_group_chat(user_a, common_group, &[user_b])
+        .await
+        .unwrap();
+    let other_group = db.create_group_chat(user_a, "Other", None).await.unwrap();
+    db.add_members_to_group_chat(user_a, other_group, &[user_c])
+        .await
+        .unwrap();
+    let private_ab = find_chat_id(&db, user_a, ChatKind::Private, Some("shared_b")).await;
+
+    let shared = db.shared_chats(user_a, user_b, 10, 1).await.unwrap().chats;
+    let mut shared_ids: Vec<ChatId> = shared.iter().map(|chat| chat.id).collect();
+    shared_ids.sort();
+    let mut expected = vec![common_group, private_ab];
+    expected.sort();
+    assert_eq!(shared_ids, expected);
+
+    let first_page = db.shared_chats(user_a, user_b, 1, 1).await.unwrap().chats;
+    let second_page = db.shared_chats(user_a, user_b, 1, 2).await.unwrap().chats;
+    let mut paged_ids = vec![first_page[0].id, second_page[0].id];
+    paged_ids.sort();
+    assert_eq!(first_page.len(), 1);
+    assert_eq!(second_page.len(), 1);
+    assert_eq!(paged_ids, expected);
+
+    let self_err = db.shared_chats(user_a, user_a, 10, 1).await.unwrap_err();
+    assert!(matches!(
+        self_err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
+}
+
+#[tokio::test]
+async fn owned_chats_limit_is_enforced() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_id = invite_regular(&db, "chat_hoarder", "passforhoarder").await;
+
+    let mut config = DbConfig::development("walrus_db", "walrus_guest", "walruspass");
+    config.max_owned_chats = Some(2);
+    let limited = DbConnection::connect(&config, &test_auth_config())
+        .await
+        .unwrap();
+    limited
+        .create_group_chat(user_id, "First", None)
+        .await
+        .unwrap();
+    limited
+        .create_channel_chat(user_id, "Second", None)
+        .await
+        .unwrap();
+
+    let err = limited
+        .create_group_chat(user_id, "Third", None)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::LimitExceeded {
+            attempted: 3,
+            limit: 2,
+            ..
+        })
+    ));
+    // Self chat and private chats created on invite don't count towards the limit.
+    assert_eq!(
+        count_chats_by_kind(&db, user_id, ChatKind::WithSelf).await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn concurrent_chat_creations_respect_owned_chats_limit() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_id = invite_regular(&db, "chat_racer", "passforchatracer").await;
+
+    let mut config = DbConfig::development("walrus_db", "walrus_guest", "walruspass");
+    config.max_owned_chats = Some(2);
+    let limited = DbConnection::connect(&config, &test_auth_config())
+        .await
+        .unwrap();
+    limited
+        .create_group_chat(user_id, "First", None)
+        .await
+        .unwrap();
+
+    let (group, channel) = tokio::join!(
+        limited.create_group_chat(user_id, "Racing group", None),
+        limited.create_channel_chat(user_id, "Racing channel", None)
+    );
+    assert!(group.is_ok() != channel.is_ok(), "{group:?} {channel:?}");
+    assert_eq!(
+        count_chats_by_kind(&db, user_id, ChatKind::Group).await
+            + count_chats_by_kind(&db, user_id, ChatKind::Channel).await,
+        2
+    );
+}
+
+#[tokio::test]
+async fn message_length_limit_depends_on_chat_kind() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_id = invite_regular(&db, "short_poster", "passforshortposter").await;
+
+    let mut config = DbConfig::development("walrus_db", "walrus_guest", "walruspass");
+    config.max_message_length_with_self = Some(10);
+    let limited = DbConnection::connect(&config, &test_auth_config())
+        .await
+        .unwrap();
+    let self_chat = find_chat_id(&db, user_id, ChatKind::WithSelf, None).await;
+    let channel = limited
+        .create_channel_chat(user_id, "Long posts", None)
+        .await
+        .unwrap();
+    let long_text = "ж".repeat(11);
+
+    let err = limited
+        .send_message(user_id, self_chat, &long_text)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::LimitExceeded {
+            attempted: 11,
+            limit: 10,
+            ..
+        })
+    ));
+    let message_id = limited
+        .send_message(user_id, self_chat, &"ж".repeat(10))
+        .await
+        .unwrap();
+    // Edits can't sneak past the limit either
+    limited
+        .edit_message(user_id, self_chat, message_id, &long_text)
+        .await
+        .unwrap_err();
+    limited
+        .send_message(user_id, channel, &long_text)
+        .await
+        .unwrap();
+    assert_eq!(limited.message_length_limits().with_self, 10);
+    assert_eq!(
+        limited.message_length_limits().channel,
+        MESSAGE_TEXT_MAX_LENGTH
+    );
+}
+
+#[tokio::test]
+async fn list_chats_exposes_group_description() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let user_a = invite_regular(&db, "describer_a", "passfordescriber").await;
+    let described_id = db
+        .create_group_chat(user_a, "Bakery Club", Some("Daily bread talks"))
+        .await
+        .unwrap();
+    let plain_id = db
+        .create_group_chat(user_a, "Plain Club", None)
+        .await
+        .unwrap();
+
+    let described = find_chat_by_id(&db, user_a, described_id).await;
+    assert_eq!(described.description.as_deref(), Some("Daily bread talks"));
+    let plain = find_chat_by_id(&db, user_a, plain_id).await;
+    assert!(plain.description.is_none());
+    let self_chat = find_chat_id(&db, user_a, ChatKind::WithSelf, None).await;
+    assert!(find_chat_by_id(&db, user_a, self_chat)
+        .await
+        .description
+        .is_none());
+}
+
+#[tokio::test]
+async fn list_memberships_reports_role_per_chat() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let user_a = invite_regular(&db, "member_a", "passformembera").await;
+    let user_b = invite_regular(&db, "member_b", "passformemberb").await;
+    let owned_group = db
+        .create_group_chat(user_a, "Owned Group", None)
+        .await
+        .unwrap();
+    let joined_group = db
+        .create_group_chat(user_b, "Joined Group", None)
+        .await
+        .unwrap();
+    db.add_members_to_group_chat(user_b, joined_group, &[user_a])
+        .await
+        .unwrap();
+
+    let memberships = db.list_memberships(user_a).await.unwrap().memberships;
+    assert_eq!(memberships.len(), 5);
+
+    let owned = memberships
+        .iter()
+        .find(|membership| membership.chat_id == owned_group)
+        .unwrap();
+    assert_eq!(owned.role, ChatRole::Owner);
+    assert_eq!(owned.kind, ChatKind::Group);
+    assert_eq!(owned.display_name.as_deref(), Some("Owned Group"));
+
+    let joined = memberships
+        .iter()
+        .find(|membership| membership.chat_id == joined_group)
+        .unwrap();
+    assert_eq!(joined.role, ChatRole::Member);
+    assert_eq!(joined.display_name.as_deref(), Some("Joined Group"));
+
+    let private = memberships
+        .iter()
+        .find(|membership| {
+            membership.kind == ChatKind::Private
+                && membership.display_name.as_deref() == Some("member_b")
+        })
+        .unwrap();
+    assert_eq!(private.role, ChatRole::Member);
+}
+
+#[tokio::test]
+async fn get_my_role_reports_caller_role() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let owner = invite_regular(&db, "role_owner", "passforowner").await;
+    let member = invite_regular(&db, "role_member", "passformember").await;
+    let outsider = invite_regular(&db, "role_outsider", "passforoutsider").await;
+    let group = db.create_group_chat(owner, "Roles", None).await.unwrap();
+    db.add_members_to_group_chat(owner, group, &[member])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        db.get_my_role(owner, group).await.unwrap().role,
+        ChatRole::Owner
+    );
+    assert_eq!(
+        db.get_my_role(member, group).await.unwrap().role,
+        ChatRole::Member
+    );
+    let err = db.get_my_role(outsider, group).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
+}
+
+#[tokio::test]
+async fn channel_members_cannot_see_each_other() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let owner = invite_regular(&db, "channel_owner", "passforowner").await;
+    let moderator = invite_regular(&db, "channel_mod", "passformoderator").await;
+    let member = invite_regular(&db, "channel_member", "passformember").await;
+    let outsider = invite_regular(&db, "channel_outsider", "passforoutsider").await;
+    let channel = db
+        .create_channel_chat(owner, "News", Some("announcements"))
+        .await
+        .unwrap();
+    db.add_members_to_group_chat(owner, channel, &[moderator, member])
+        .await
+        .unwrap();
+    db.update_member_role(owner, channel, moderator, ChatRole::Moderator)
+        .await
+        .unwrap();
+
+    let hidden = db.list_members(member, channel).await.unwrap();
+    assert_eq!(hidden.members_count, 3);
+    assert!(hidden.members.is_none());
+
+    for privileged in [owner, moderator] {
+        let visible = db.list_members(privileged, channel).await.unwrap();
+        assert_eq!(visible.members_count, 3);
+        let members = visible.members.unwrap();
+        assert!(members
+            .iter()
+            .any(|m| m.user_id == member && m.role == ChatRole::Member));
+        assert!(members
+            .iter()
+            .any(|m| m.user_id == owner && m.role == ChatRole::Owner));
+    }
+
+    let outsider_result = db.list_members(outsider, channel).await;
+    assert!(matches!(
+        outsider_result,
+        Err(RequestError::Validation(ValidationError::NotFound))
+    ));
+
+    // Group chats keep the full member list visible to everyone in them.
+    let group = db.create_group_chat(owner, "Open", None).await.unwrap();
+    db.add_members_to_group_chat(owner, group, &[member])
+        .await
+        .unwrap();
+    let group_members = db.list_members(member, group).await.unwrap();
+    assert_eq!(group_members.members.unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn members_with_same_display_name_are_flagged() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let owner = invite_regular(&db, "namesake_owner", "passforowner").await;
+    let first = invite_regular(&db, "namesake_a", "passfornamesakea").await;
+    let second = invite_regular(&db, "namesake_b", "passfornamesakeb").await;
+    let other = invite_regular(&db, "namesake_other", "passforother").await;
+    db.change_display_name(first, "Alex").await.unwrap();
+    db.change_display_name(second, "alex").await.unwrap();
+    db.change_display_name(other, "Sam").await.unwrap();
+    let group = db
+        .create_group_chat(owner, "Namesakes", None)
+        .await
+        .unwrap();
+    db.add_members_to_group_chat(owner, group, &[first, second, other])
+        .await
+        .unwrap();
+
+    let members = db
+        .list_members(owner, group)
+        .await
+        .unwrap()
+        .members
+        .unwrap();
+    let flagged: Vec<_> = members
+        .iter()
+        .filter(|m| m.duplicate_display_name)
+        .map(|m| (m.user_id, m.alias.as_str()))
+        .collect();
+    assert_eq!(flagged, vec![(first, "namesake_a"), (second, "namesake_b")]);
+}
+
+#[tokio::test]
+async fn chat_staff_lists_only_owners_and_moderators() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let owner = invite_regular(&db, "staff_owner", "passforowner").await;
+    let moderator = invite_regular(&db, "staff_mod", "passformoderator").await;
+    let member_a = invite_regular(&db, "staff_member_a", "passformembera").await;
+    let member_b = invite_regular(&db, "staff_member_b", "passformemberb").await;
+    let outsider = invite_regular(&db, "staff_outsider", "passforoutsider").await;
+    let channel = db
+        .create_channel_chat(owner, "Staffed", None)
+        .await
+        .unwrap();
+    db.add_members_to_group_chat(owner, channel, &[moderator, member_a, member_b])
+        .await
+        .unwrap();
+    db.update_member_role(owner, channel, moderator, ChatRole::Moderator)
+        .await
+        .unwrap();
+
+    // Plain channel member can't see the audience, but can see its staff
+    let staff = db.list_chat_staff(member_a, channel).await.unwrap().staff;
+    let staff: Vec<_> = staff.iter().map(|m| (m.user_id, m.role)).collect();
+    assert_eq!(
+        staff,
+        vec![(owner, ChatRole::Owner), (moderator, ChatRole::Moderator)]
+    );
+
+    let err = db.list_chat_staff(outsider, channel).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
+}
+
+#[tokio::test]
+async fn posting_rights_follow_chat_kind_and_role() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let owner = invite_regular(&db, "posting_owner", "passforowner").await;
+    let moderator = invite_regular(&db, "posting_mod", "passformoderator").await;
+    let member = invite_regular(&db, "posting_member", "passformember").await;
+    let channel = db
+        .create_channel_chat(owner, "Bulletin", None)
+        .await
+        .unwrap();
+    let group = db.create_group_chat(owner, "Lounge", None).await.unwrap();
+    for chat_id in [channel, group] {
+        db.add_members_to_group_chat(owner, chat_id, &[moderator, member])
+            .await
+            .unwrap();
+    }
+    db.update_member_role(owner, channel, moderator, ChatRole::Moderator)
+        .await
+        .unwrap();
+
+    let blocked = db.send_message(member, channel, "member post").await;
+    assert!(matches!(
+        blocked,
+        Err(RequestError::Validation(ValidationError::Forbidden))
+    ));
+    db.send_message(owner, channel, "owner post").await.unwrap();
+    db.send_message(moderator, channel, "moderator post")
+        .await
+        .unwrap();
+
+    db.send_message(member, group, "group post").await.unwrap();
+    let self_chat = find_chat_id(&db, member, ChatKind::WithSelf, None).await;
+    db.send_message(member, self_chat, "note to self")
+        .await
+        .unwrap();
+    let private_chat = find_chat_id(&db, member, ChatKind::Private, Some("posting_owner")).await;
+    db.send_message(member, private_chat, "direct post")
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn chat_access_failure_follows_hide_existence_policy() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let owner = invite_regular(&db, "policy_owner", "passforowner").await;
+    let outsider = invite_regular(&db, "policy_outsider", "passforoutsider").await;
+    let group = db.create_group_chat(owner, "Private", None).await.unwrap();
+    let missing_chat: ChatId = group + 1000;
+
+    // Default policy doesn't reveal whether the chat exists.
+    let hidden = db.list_messages(outsider, group, 10, 1).await.unwrap_err();
+    assert_eq!(hidden.into_response().status(), StatusCode::NOT_FOUND);
+
+    let state = init_app_state_with(|config| config.server.hide_existence = false).await;
+    let explicit_db = &state.db_connection;
+    let forbidden = explicit_db
+        .list_messages(outsider, group, 10, 1)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        forbidden,
+        RequestError::Validation(ValidationError::Forbidden)
+    ));
+    assert_eq!(forbidden.into_response().status(), StatusCode::FORBIDDEN);
+    let send_forbidden = explicit_db
+        .send_message(outsider, group, "hello")
+        .await
+        .unwrap_err();
+    assert_eq!(
+        send_forbidden.into_response().status(),
+        StatusCode::FORBIDDEN
+    );
+
+    let missing = explicit_db
+        .list_messages(outsider, missing_chat, 10, 1)
+        .await
+        .unwrap_err();
+    assert_eq!(missing.into_response().status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn total_unread_sums_across_chats() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let user_a = invite_regular(&db, "badge_a", "passforbadgea").await;
+    let user_b = invite_regular(&db, "badge_b", "passforbadgeb").await;
+    let chat_ab_id = find_chat_id(&db, user_a, ChatKind::Private, Some("badge_b")).await;
+    let group = db.create_group_chat(user_a, "Badge", None).await.unwrap();
+    db.add_members_to_group_chat(user_a, group, &[user_b])
+        .await
+        .unwrap();
+
+    let private_1 = db.send_message(user_a, chat_ab_id, "p1").await.unwrap();
+    db.send_message(user_a, chat_ab_id, "p2").await.unwrap();
+    db.send_message(user_a, group, "g1").await.unwrap();
+    // Own messages never count as unread.
+    db.send_message(user_b, group, "own").await.unwrap();
+
+    assert_eq!(db.total_unread(user_b).await.unwrap().total_unread, 3);
+    assert_eq!(db.total_unread(user_a).await.unwrap().total_unread, 1);
+
+    db.mark_chat_read(user_b, chat_ab_id, private_1)
+        .await
+        .unwrap();
+    assert_eq!(db.total_unread(user_b).await.unwrap().total_unread, 2);
+}
+
+#[tokio::test]
+async fn adding_existing_member_reports_already_exists() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let owner = invite_regular(&db, "readd_owner", "passforreaddowner").await;
+    let member = invite_regular(&db, "readd_member", "passforreaddmember").await;
+    let newcomer = invite_regular(&db, "readd_new", "passforreaddnew").await;
+    let group = db.create_group_chat(owner, "Readd", None).await.unwrap();
+    db.add_members_to_group_chat(owner, group, &[member])
+        .await
+        .unwrap();
+
+    let err = db
+        .add_members_to_group_chat(owner, group, &[newcomer, member])
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::AlreadyExists)
+    ));
+    assert_eq!(err.into_response().status(), StatusCode::CONFLICT);
+    // Batch is rolled back as a whole
+    let members = db.list_members(owner, group).await.unwrap();
+    assert_eq!(members.members_count, 2);
+}
+
+#[tokio::test]
+async fn moderators_cannot_remove_or_demote_owners() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let owner = invite_regular(&db, "hierarchy_owner", "passforhierowner").await;
+    let moderator = invite_regular(&db, "hierarchy_mod", "passforhiermod").await;
+    let member = invite_regular(&db, "hierarchy_member", "passforhiermember").await;
+    let group = db
+        .create_group_chat(owner, "Hierarchy", None)
+        .await
+        .unwrap();
+    db.add_members_to_group_chat(owner, group, &[moderator, member])
+        .await
+        .unwrap();
+    db.update_member_role(owner, group, moderator, ChatRole::Moderator)
+        .await
+        .unwrap();
+
+    let forbidden = |result: Result<(), RequestError>| {
+        matches!(
+            result,
+            Err(RequestError::Validation(ValidationError::Forbidden))
+        )
+    };
+    assert!(forbidden(db.remove_member(moderator, group, owner).await));
+    assert!(forbidden(
+        db.update_member_role(moderator, group, owner, ChatRole::Member)
+            .await
+    ));
+    // Appointing another owner would be a way around the rule
+    assert!(forbidden(
+        db.update_member_role(moderator, group, member, ChatRole::Owner)
+            .await
+    ));
+    assert!(forbidden(db.remove_member(member, group, moderator).await));
+
+    db.remove_member(owner, group, moderator).await.unwrap();
+    let members = db.list_members(owner, group).await.unwrap();
+    assert_eq!(members.members_count, 2);
+    let roles: Vec<_> = members
+        .members
+        .unwrap()
+        .into_iter()
+        .map(|member| (member.user_id, member.role))
+        .collect();
+    assert!(roles.contains(&(owner, ChatRole::Owner)));
+    assert!(roles.contains(&(member, ChatRole::Member)));
+}
+
+#[tokio::test]
+async fn unread_counts_cover_requested_chats_in_one_call() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let user_a = invite_regular(&db, "counts_a", "passforcountsa").await;
+    let user_b = invite_regular(&db, "counts_b", "passforcountsb").await;
+    let user_c = invite_regular(&db, "counts_c", "passforcountsc").await;
+    let chat_ab = find_chat_id(&db, user_a, ChatKind::Private, Some("counts_b")).await;
+    let chat_bc = find_chat_id(&db, user_b, ChatKind::Private, Some("counts_c")).await;
+    let chat_ac = find_chat_id(&db, user_a, ChatKind::Private, Some("counts_c")).await;
+    let group = db.create_group_chat(user_a, "Counts", None).await.unwrap();
+    db.add_members_to_group_chat(user_a, group, &[user_b])
+        .await
+        .unwrap();
+
+    db.send_message(user_a, chat_ab, "ab1").await.unwrap();
+    db.send_message(user_a, chat_ab, "ab2").await.unwrap();
+    db.send_message(user_c, chat_bc, "bc1").await.unwrap();
+    db.send_message(user_b, group, "own").await.unwrap();
+    db.send_message(user_c, chat_ac, "ac1").await.unwrap();
+
+    // Chat of other users is left out instead of revealing its counter
+    let counts = db
+        .unread_counts(user_b, &[chat_ab, chat_bc, group, chat_ac])
+        .await
+        .unwrap()
+        .unread_counts;
+    assert_eq!(counts.len(), 3);
+    assert_eq!(counts[&chat_ab], 2);
+    assert_eq!(counts[&chat_bc], 1);
+    assert_eq!(counts[&group], 0);
+
+    assert!(db
+        .unread_counts(user_b, &[])
+        .await
+        .unwrap()
+        .unread_counts
+        .is_empty());
+    let too_many: Vec<ChatId> = (1..=MAX_UNREAD_COUNTS_CHATS as ChatId + 1).collect();
+    let err = db.unread_counts(user_b, &too_many).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::LimitExceeded { .. })
+    ));
+}
+
+#[tokio::test]
+async fn latest_message_ids_match_last_message_per_chat() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let user_a = invite_regular(&db, "latest_a", "passforlatesta").await;
+    let user_b = invite_regular(&db, "latest_b", "passforlatestb").await;
+    let _user_c = invite_regular(&db, "latest_c", "passforlatestc").await;
+    let chat_ab = find_chat_id(&db, user_a, ChatKind::Private, Some("latest_b")).await;
+    let chat_bc = find_chat_id(&db, user_b, ChatKind::Private, Some("latest_c")).await;
+    let chat_ac = find_chat_id(&db, user_a, ChatKind::Private, Some("latest_c")).await;
+    let group = db.create_group_chat(user_a, "Latest", None).await.unwrap();
+    db.add_members_to_group_chat(user_a, group, &[user_b])
+        .await
+        .unwrap();
+
+    db.send_message(user_a, chat_ab, "ab1").await.unwrap();
+    let last_ab = db.send_message(user_b, chat_ab, "ab2").await.unwrap();
+    let last_bc = db.send_message(user_b, chat_bc, "bc1").await.unwrap();
+    db.send_message(user_a, chat_ac, "ac1").await.unwrap();
+
+    // Chat of other users is left out, chat without messages has no id yet
+    let latest = db
+        .latest_message_ids(user_b, &[chat_ab, chat_bc, group, chat_ac])
+        .await
+        .unwrap()
+        .latest_message_ids;
+    assert_eq!(latest.len(), 3);
+    assert_eq!(latest[&chat_ab], Some(last_ab));
+    assert_eq!(latest[&chat_bc], Some(last_bc));
+    assert_eq!(latest[&group], None);
+
+    let too_many: Vec<ChatId> = (1..=MAX_LATEST_MESSAGE_IDS_CHATS as ChatId + 1).collect();
+    let err = db.latest_message_ids(user_b, &too_many).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::LimitExceeded { .. })
+    ));
+}
+
+#[tokio::test]
+async fn mark_chat_read_is_monotonic_and_validates_target_message_scope() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let user_a = invite_regular(&db, "reader_a", "passforreadera").await;
+    let user_b = invite_regular(&db, "reader_b", "passforreaderb").await;
+    let user_c = invite_regular(&db, "reader_c", "passforreaderc").await;
+    let chat_ab_id = find_chat_id(&db, user_a, ChatKind::Private, Some("reader_b")).await;
+    let self_chat_b_id = find_chat_id(&db, user_b, ChatKind::WithSelf, None).await;
+
+    let msg_1 = db
+        .send_message(user_a, chat_ab_id, "a_msg_1")
+        .await
+        .unwrap();
+    let msg_2 = db
+        .send_message(user_a, chat_ab_id, "a_msg_2")
+        .await
+        .unwrap();
+    db.mark_chat_read(user_b, chat_ab_id, msg_2).await.unwrap();
+
+    // Older cursor update should not move read position backwards.
+    db.mark_chat_read(user_b, chat_ab_id, msg_1).await.unwrap();
+
+    db.send_message(user_a, chat_ab_id, "a_msg_3")
+        .await
+        .unwrap();
+    let chat_for_b = find_chat_by_id(&db, user_b, chat_ab_id).await;
+    assert_eq!(chat_for_b.unread_count, 1);
+
+    let wrong_chat_message_id = db
+        .send_message(user_b, self_chat_b_id, "self_only")
+        .await
+        .unwrap();
+
+    let non_member_err = db
+        .mark_chat_read(user_c, chat_ab_id, msg_2)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        non_member_err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
+
+    let wrong_chat_err = db
+        .mark_chat_read(user_b, chat_ab_id, wrong_chat_message_id)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        wrong_chat_err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
+}
+
+#[tokio::test]
+async fn mark_chat_read_only_moves_forward_within_the_chat() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_a = invite_regular(&db, "cursor_a", "passforcursora").await;
+    let user_b = invite_regular(&db, "cursor_b", "passforcursorb").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("cursor_b")).await;
+    let mut sent = Vec::new();
+    for text in ["one", "two", "three", "four"] {
+        sent.push(db.send_message(user_a, chat_id, text).await.unwrap());
+    }
+
+    db.mark_chat_read(user_b, chat_id, sent[2]).await.unwrap();
+    assert_eq!(find_chat_by_id(&db, user_b, chat_id).await.unread_count, 1);
+    for older in [sent[0], sent[1], sent[2]] {
+        db.mark_chat_read(user_b, chat_id, older).await.unwrap();
+        assert_eq!(find_chat_by_id(&db, user_b, chat_id).await.unread_count, 1);
+    }
+    db.mark_chat_read(user_b, chat_id, sent[3]).await.unwrap();
+    assert_eq!(find_chat_by_id(&db, user_b, chat_id).await.unread_count, 0);
+
+    let other_chat = find_chat_id(&db, user_a, ChatKind::WithSelf, None).await;
+    let other_message = db
+        .send_message(user_a, other_chat, "elsewhere")
+        .await
+        .unwrap();
+    let missing_message = other_message + 1000;
+    for message_id in [other_message, missing_message] {
+        let err = db
+            .mark_chat_read(user_b, chat_id, message_id)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            RequestError::Validation(ValidationError::InvalidInput { .. })
+        ));
+    }
+    assert_eq!(find_chat_by_id(&db, user_b, chat_id).await.unread_count, 0);
+}
+
+#[tokio::test]
+async fn login_and_resolve_session() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let (alias_a, pass_a) = ("existing_user_a", "existing_password_a");
+    let (alias_b, pass_b) = ("existing_user_b", "existing_password_b");
+    let user_id_a = invite_regular(&db, alias_a, pass_a).await;
+    let user_id_b = invite_regular(&db, alias_b, pass_b).await;
+
+    // invalid variants
+    let result = db
+        .login("non_existent", "wrong_password")
+        .await
+        .unwrap_err();
+    assert!(matches!(result, RequestError::BadCredentials));
+    let result = db.login("non_existent", pass_a).await.unwrap_err();
+    assert!(matches!(result, RequestError::BadCredentials));
+    let result = db.login(alias_a, "wrong_password").await.unwrap_err();
+    assert!(matches!(result, RequestError::BadCredentials));
+    let result = db.login(alias_a, pass_b).await.unwrap_err();
+    assert!(matches!(result, RequestError::BadCredentials));
+    let result = db.login(alias_b, pass_a).await.unwrap_err();
+    assert!(matches!(result, RequestError::BadCredentials));
+
+    // normal login
+    let result_a = db.login(alias_a, pass_a).await.unwrap().tokens;
+    let resolved_user_a = resolve_session(&db, &result_a).await.unwrap();
+    assert_eq!(resolved_user_a, user_id_a);
+
+    let result_b = db.login(alias_b, pass_b).await.unwrap().tokens;
+    let resolved_user_b = resolve_session(&db, &result_b).await.unwrap();
+    assert_eq!(resolved_user_b, user_id_b);
+}
+
+#[tokio::test]
+async fn login_returns_user_profile() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let (alias, pass) = ("profile_user", "profile_password");
+    let user_id = invite_regular(&db, alias, pass).await;
+    db.change_display_name(user_id, "Profile Name")
+        .await
+        .unwrap();
+
+    let result = db.login(alias, pass).await.unwrap();
+    assert_eq!(result.profile.user_id, user_id);
+    assert_eq!(result.profile.alias, alias);
+    assert_eq!(result.profile.display_name, "Profile Name");
+    assert_eq!(result.profile.role, UserRole::Regular);
+    let resolved_user = resolve_session(&db, &result.tokens).await.unwrap();
+    assert_eq!(resolved_user, user_id);
+
+    let origin = db.login("origin", TEST_ORIGIN_PASSWORD).await.unwrap();
+    assert_eq!(origin.profile.role, UserRole::Admin);
+}
+
+#[tokio::test]
+async fn change_password() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let (alias, pass) = ("existing_user_a", "existing_password_a");
+    let user_id = invite_regular(&db, alias, pass).await;
+    let new_password = "updated_password_a";
+
+    let current_session = db.login(alias, pass).await.unwrap().tokens;
+    let (current_session_id, _token) =
+        unpack_encoded_session_token(&current_session.access_token, TokenKind::Access);
+    let other_session = db.login(alias, pass).await.unwrap().tokens;
+
+    let result = db
+        .change_password(
+            user_id,
+            current_session_id,
+            "wrong_current_password",
+            new_password,
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(result, RequestError::BadCredentials));
+
+    db.change_password(user_id, current_session_id, pass, new_password)
+        .await
+        .unwrap();
+
+    let old_login_result = db.login(alias, pass).await.unwrap_err();
+    assert!(matches!(old_login_result, RequestError::BadCredentials));
+
+    let still_valid = resolve_session(&db, &current_session).await.unwrap();
+    assert_eq!(still_valid, user_id);
+    let revoked = resolve_session(&db, &other_session).await.unwrap_err();
+    assert!(matches!(revoked, SessionError::TokenNotFound));
+
+    let new_login_result = db.login(alias, new_password).await.unwrap().tokens;
+    let resolved_user = resolve_session(&db, &new_login_result).await.unwrap();
+    assert_eq!(resolved_user, user_id);
+}
+
+#[tokio::test]
+async fn whoami_returns_alias_and_display_name() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let initial_alias = "existing_user_a";
+    let pass = "existing_password_a";
+    let user_id = invite_regular(&db, initial_alias, pass).await;
+
+    let initial_whoami = db.whoami(user_id).await.unwrap();
+    assert_eq!(initial_whoami.user_id, user_id);
+    assert_eq!(initial_whoami.alias, initial_alias);
+    assert_eq!(initial_whoami.display_name, initial_alias);
+    assert_eq!(initial_whoami.role, UserRole::Regular);
+
+    db.change_alias(user_id, "renamed_user_a").await.unwrap();
+    db.change_display_name(user_id, "Renamed Display")
+        .await
+        .unwrap();
+
+    let updated_whoami = db.whoami(user_id).await.unwrap();
+    assert_eq!(updated_whoami.user_id, user_id);
+    assert_eq!(updated_whoami.alias, "renamed_user_a");
+    assert_eq!(updated_whoami.display_name, "Renamed Display");
+    assert_eq!(updated_whoami.role, UserRole::Regular);
+}
+
+#[tokio::test]
+async fn change_alias() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let (old_alias, pass) = ("existing_user_a", "existing_password_a");
+    let user_id = invite_regular(&db, old_alias, pass).await;
+    let taken_alias = "existing_user_b";
+    let _other_user = invite_regular(&db, taken_alias, "existing_password_b").await;
+
+    let new_alias = "renamed_user_a";
+    db.change_alias(user_id, new_alias).await.unwrap();
+
+    let old_login_result = db.login(old_alias, pass).await.unwrap_err();
+    assert!(matches!(old_login_result, RequestError::BadCredentials));
+
+    let new_login_result = db.login(new_alias, pass).await.unwrap().tokens;
+    let resolved_user = resolve_session(&db, &new_login_result).await.unwrap();
+    assert_eq!(resolved_user, user_id);
+
+    let duplicate_err = db.change_alias(user_id, taken_alias).await.unwrap_err();
+    assert!(matches!(
+        duplicate_err,
+        RequestError::Validation(ValidationError::AlreadyExists)
+    ));
+
+    let invalid_err = db.change_alias(user_id, "bad alias").await.unwrap_err();
+    assert!(matches!(
+        invalid_err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
+}
+
+#[tokio::test]
+async fn change_display_name() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let user_a = invite_regular(&db, "existing_user_a", "existing_password_a").await;
+    let user_b_alias = "existing_user_b";
+    let user_b = invite_regular(&db, user_b_alias, "existing_password_b").await;
+
+    assert!(
+        !find_matching_chats(&db, user_a, ChatKind::Private, Some(user_b_alias))
+            .await
+            .is_empty()
+    );
+
+    let new_display_name = "Baker Ben";
+    db.change_display_name(user_b, new_display_name)
+        .await
+        .unwrap();
+
+    assert!(
+        find_matching_chats(&db, user_a, ChatKind::Private, Some(user_b_alias))
+            .await
+            .is_empty()
+    );
+    assert!(
+        !find_matching_chats(&db, user_a, ChatKind::Private, Some(new_display_name))
+            .await
+            .is_empty()
+    );
+
+    let user_b_login = db
+        .login(user_b_alias, "existing_password_b")
+        .await
+        .unwrap()
+        .tokens;
+    let resolved_user_b = resolve_session(&db, &user_b_login).await.unwrap();
+    assert_eq!(resolved_user_b, user_b);
+
+    let empty_err = db.change_display_name(user_b, "").await.unwrap_err();
+    assert!(matches!(
+        empty_err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
+
+    let padded_err = db
+        .change_display_name(user_b, " Display Name ")
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        padded_err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
+
+    let too_long_display_name = "x".repeat(31);
+    let too_long_err = db
+        .change_display_name(user_b, &too_long_display_name)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        too_long_err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
+}
+
+#[tokio::test]
+async fn login_with_known_session_reuses_it() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_a = invite_regular(&db, "relogin_a", "passforrelogina").await;
+    invite_regular(&db, "relogin_b", "passforreloginb").await;
+
+    let first = db
+        .login("relogin_a", "passforrelogina")
+        .await
+        .unwrap()
+        .tokens;
+    let (session_id, _) = unpack_encoded_session_token(&first.access_token, TokenKind::Access);
+    let second = db
+        .login_with_session("relogin_a", "passforrelogina", Some(session_id))
+        .await
+        .unwrap()
+        .tokens;
+    assert_eq!(
+        unpack_encoded_session_token(&second.access_token, TokenKind::Access).0,
+        session_id
+    );
+    let sessions: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE user_id = $1")
+        .bind(user_a)
+        .fetch_one(db.pool())
+        .await
+        .unwrap();
+    assert_eq!(sessions, 1);
+    // Tokens are rotated, the ones from the first login stop working
+    assert!(resolve_session(&db, &first).await.is_err());
+    assert_eq!(resolve_session(&db, &second).await.unwrap(), user_a);
+
+    let err = db
+        .login_with_session("relogin_b", "passforreloginb", Some(session_id))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
+}
+
+#[tokio::test]
+async fn limit_sessions_count() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let (alias, pass) = ("existing_user_a", "existing_password_a");
+    let _ = invite_regular(&db, alias, pass).await;
+
+    let first_session = db.login(alias, pass).await.unwrap().tokens;
+    let _ok = resolve_session(&db, &first_session).await.unwrap();
+    let second_session = db.login(alias, pass).await.unwrap().tokens;
+    let _ok = resolve_session(&db, &second_session).await.unwrap();
+
     for _i in 0..MAX_SESSIONS_PER_USER - 2 {
-        let session = db.login(alias, pass).await.unwrap();
+        let session = db.login(alias, pass).await.unwrap().tokens;
         let _ok = resolve_session(&db, &session).await.unwrap();
     }
 
-    // creating session number MAX + 1, this should invalidate one (first) session
-    let latest_session = db.login(alias, pass).await.unwrap();
-    let _ok = resolve_session(&db, &latest_session).await.unwrap();
-    let _ok = resolve_session(&db, &second_session).await.unwrap();
-    let _ok = resolve_session(&db, &first_session).await.unwrap_err();
+    // creating session number MAX + 1, this should invalidate one (first) session
+    let latest_session = db.login(alias, pass).await.unwrap().tokens;
+    let _ok = resolve_session(&db, &latest_session).await.unwrap();
+    let _ok = resolve_session(&db, &second_session).await.unwrap();
+    let _ok = resolve_session(&db, &first_session).await.unwrap_err();
+}
+
+#[tokio::test]
+async fn latest_login_survives_session_trimming() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let (alias, pass) = ("trimmed_user", "passfortrimmeduser");
+    let user_id = invite_regular(&db, alias, pass).await;
+    let oldest = db.login(alias, pass).await.unwrap().tokens;
+    // Fill up the limit with sessions expiring later than any new login, all at the same time
+    let (template_id, _token) =
+        unpack_encoded_session_token(&oldest.access_token, TokenKind::Access);
+    sqlx::query(
+        "
+        INSERT INTO sessions (id, user_id, ip, first_seen_at, last_seen_at, refresh_token_hash,
+            refresh_token_expires_at, access_token_hash, access_token_expires_at, refresh_counter)
+        SELECT gen_random_uuid(), user_id, ip, first_seen_at, last_seen_at, refresh_token_hash,
+            refresh_token_expires_at, access_token_hash,
+            current_timestamp + interval '1 year', refresh_counter
+        FROM sessions, generate_series(1, $2)
+        WHERE id = $1;
+        ",
+    )
+    .bind(template_id)
+    .bind(MAX_SESSIONS_PER_USER - 1)
+    .execute(db.pool())
+    .await
+    .unwrap();
+
+    for _ in 0..5 {
+        let latest = db.login(alias, pass).await.unwrap().tokens;
+        assert_eq!(resolve_session(&db, &latest).await.unwrap(), user_id);
+        let sessions_count: i64 =
+            sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE user_id = $1")
+                .bind(user_id)
+                .fetch_one(db.pool())
+                .await
+                .unwrap();
+        assert_eq!(sessions_count, i64::from(MAX_SESSIONS_PER_USER));
+    }
+    resolve_session(&db, &oldest).await.unwrap_err();
+}
+
+#[tokio::test]
+async fn logout() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let (alias, pass) = ("existing_user_a", "existing_pass_a");
+    let _ = invite_regular(&db, alias, pass).await;
+
+    let session = db.login(alias, pass).await.unwrap().tokens;
+    let _ok = resolve_session(&db, &session).await.unwrap();
+
+    let (session_id, _token) =
+        unpack_encoded_session_token(&session.access_token, TokenKind::Access);
+    db.logout(session_id).await.unwrap();
+
+    let err = resolve_session(&db, &session).await.unwrap_err();
+    assert!(matches!(err, SessionError::TokenNotFound));
+}
+
+async fn connect_db_with_logout_grace(grace_secs: u64) -> DbConnection {
+    let mut config = DbConfig::development("walrus_db", "walrus_guest", "walruspass");
+    config.logout_grace_secs = Some(grace_secs);
+    DbConnection::connect(&config, &test_auth_config())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn undo_logout_within_grace_window() {
+    let _lock = SERIAL_LOCK.write().await;
+    let _ = init_and_get_db().await;
+    let db = connect_db_with_logout_grace(60).await;
+
+    let (alias, pass) = ("undo_user", "passforundouser");
+    let _ = invite_regular(&db, alias, pass).await;
+    let session = db.login(alias, pass).await.unwrap().tokens;
+    let (session_id, refresh_token) =
+        unpack_encoded_session_token(&session.refresh_token, TokenKind::Refresh);
+
+    db.logout(session_id).await.unwrap();
+    let err = resolve_session(&db, &session).await.unwrap_err();
+    assert!(matches!(err, SessionError::TokenNotFound));
+    let err = db
+        .refresh_session(session_id, &refresh_token)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RequestError::BadCredentials));
+
+    let err = db.undo_logout(session_id, b"wrong").await.unwrap_err();
+    assert!(matches!(err, RequestError::BadCredentials));
+    db.undo_logout(session_id, &refresh_token).await.unwrap();
+    resolve_session(&db, &session).await.unwrap();
+
+    // Within window nothing gets purged.
+    db.logout(session_id).await.unwrap();
+    assert_eq!(db.purge_logged_out_sessions().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn undo_logout_after_grace_window_fails() {
+    let _lock = SERIAL_LOCK.write().await;
+    let _ = init_and_get_db().await;
+    let db = connect_db_with_logout_grace(60).await;
+
+    let (alias, pass) = ("late_undo_user", "passforlateundo");
+    let _ = invite_regular(&db, alias, pass).await;
+    let session = db.login(alias, pass).await.unwrap().tokens;
+    let (session_id, refresh_token) =
+        unpack_encoded_session_token(&session.refresh_token, TokenKind::Refresh);
+
+    db.logout(session_id).await.unwrap();
+    backdate(
+        &db,
+        "sessions",
+        "logged_out_at",
+        "id",
+        session_id,
+        chrono::Duration::minutes(2),
+    )
+    .await;
+
+    let err = db
+        .undo_logout(session_id, &refresh_token)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RequestError::Expired));
+
+    assert_eq!(db.purge_logged_out_sessions().await.unwrap(), 1);
+    let err = db
+        .undo_logout(session_id, &refresh_token)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RequestError::BadCredentials));
+}
+
+#[tokio::test]
+async fn logged_out_sessions_are_trimmed_before_live_ones() {
+    let _lock = SERIAL_LOCK.write().await;
+    let _ = init_and_get_db().await;
+    let db = connect_db_with_logout_grace(60).await;
+
+    let (alias, pass) = ("trim_logged_out_user", "passfortrimloggedout");
+    let _ = invite_regular(&db, alias, pass).await;
+    let oldest = db.login(alias, pass).await.unwrap().tokens;
+    for _ in 0..MAX_SESSIONS_PER_USER - 2 {
+        let _ = db.login(alias, pass).await.unwrap();
+    }
+    // Logged out session expires later than every other one, yet it is the first to go
+    let logged_out = db.login(alias, pass).await.unwrap().tokens;
+    let (logged_out_id, refresh_token) =
+        unpack_encoded_session_token(&logged_out.refresh_token, TokenKind::Refresh);
+    db.logout(logged_out_id).await.unwrap();
+
+    let latest = db.login(alias, pass).await.unwrap().tokens;
+    resolve_session(&db, &latest).await.unwrap();
+    resolve_session(&db, &oldest).await.unwrap();
+    let err = db
+        .undo_logout(logged_out_id, &refresh_token)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RequestError::BadCredentials));
+}
+
+#[tokio::test]
+async fn refresh_token() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let (alias, pass) = ("existing_user_a", "existing_pass_a");
+    let _ = invite_regular(&db, alias, pass).await;
+
+    let first_session = db.login(alias, pass).await.unwrap().tokens;
+    let _ok = resolve_session(&db, &first_session).await.unwrap();
+
+    let (session_id, token) =
+        unpack_encoded_session_token(&first_session.refresh_token, TokenKind::Refresh);
+    let second_session = db.refresh_session(session_id, &token).await.unwrap();
+    assert_ne!(second_session.refresh_token, first_session.refresh_token);
+    assert_ne!(second_session.access_token, first_session.access_token);
+
+    let _ok = resolve_session(&db, &second_session).await.unwrap();
+    resolve_session(&db, &first_session).await.unwrap_err();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_refreshes_with_same_token_succeed_once() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let (alias, pass) = ("refresh_race", "passforrefreshrace");
+    invite_regular(&db, alias, pass).await;
+
+    for _ in 0..5 {
+        let session = db.login(alias, pass).await.unwrap().tokens;
+        let (session_id, token) =
+            unpack_encoded_session_token(&session.refresh_token, TokenKind::Refresh);
+        let results =
+            futures::future::join_all((0..10).map(|_| db.refresh_session(session_id, &token)))
+                .await;
+        let succeeded: Vec<_> = results.iter().filter_map(|r| r.as_ref().ok()).collect();
+        assert_eq!(succeeded.len(), 1);
+        assert!(results
+            .iter()
+            .filter_map(|r| r.as_ref().err())
+            .all(|e| matches!(e, RequestError::BadCredentials)));
+
+        let (_, new_token) =
+            unpack_encoded_session_token(&succeeded[0].refresh_token, TokenKind::Refresh);
+        db.refresh_session(session_id, &new_token).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn access_token_cookie_authenticates_requests() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let (alias, pass) = ("cookie_user", "passforcookieuser");
+    let user_id = invite_regular(&db, alias, pass).await;
+
+    let app = routes(
+        init_app_state_with(|config| {
+            config.server.access_token_cookie = Some("walrus_access".to_string());
+        })
+        .await,
+    );
+
+    let login = Request::post("/auth/login")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({ "alias": alias, "password": pass }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(login).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let set_cookie = response.headers()[SET_COOKIE].to_str().unwrap().to_string();
+    for attribute in ["HttpOnly", "Secure", "SameSite=Strict"] {
+        assert!(set_cookie.contains(attribute), "{set_cookie}");
+    }
+    let cookie = set_cookie.split(';').next().unwrap().to_string();
+    assert!(cookie.starts_with("walrus_access="));
+
+    let whoami = Request::get("/auth/whoami")
+        .header(COOKIE, &cookie)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(whoami).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let profile: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(profile["user_id"], user_id);
+
+    let anonymous = Request::get("/auth/whoami").body(Body::empty()).unwrap();
+    let response = app.oneshot(anonymous).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn capabilities_report_server_limits() {
+    let _lock = SERIAL_LOCK.write().await;
+    let _db = init_and_get_db().await;
+    let app = routes(init_app_state().await);
+
+    // Available before login, so clients can configure themselves up front
+    let request = Request::get("/capabilities").body(Body::empty()).unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let capabilities: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(capabilities["max_listing_elements"], MAX_LISTING_ELEMENTS);
+    assert_eq!(
+        capabilities["message_length_limits"]["channel"],
+        MESSAGE_TEXT_MAX_LENGTH
+    );
+    assert_eq!(capabilities["max_sessions_per_user"], MAX_SESSIONS_PER_USER);
+    assert_eq!(capabilities["features"]["websockets"], true);
+}
+
+#[tokio::test]
+async fn disabled_features_are_not_routed() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let (alias, pass) = ("features_user", "passforfeaturesuser");
+    let user_id = invite_regular(&db, alias, pass).await;
+    let chat_id = find_chat_id(&db, user_id, ChatKind::WithSelf, None).await;
+    let bearer = bearer_for(&db, alias, pass).await;
+
+    let app = routes(
+        init_app_state_with(|config| {
+            config.features = FeaturesConfig {
+                websockets: false,
+                channels: false,
+            };
+        })
+        .await,
+    );
+
+    let request = Request::get(format!("/chats/{chat_id}/events"))
+        .header(AUTHORIZATION, &bearer)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let request = Request::post("/chats/channel")
+        .header(AUTHORIZATION, &bearer)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({ "display_name": "Disabled" }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    // Existing channels keep working, their feed stays routed
+    let request = Request::get("/chats/channel/feed")
+        .header(AUTHORIZATION, &bearer)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let request = Request::get("/capabilities").body(Body::empty()).unwrap();
+    let response = app.oneshot(request).await.unwrap();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let capabilities: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(capabilities["features"]["websockets"], false);
+    assert_eq!(capabilities["features"]["channels"], false);
+}
+
+#[tokio::test]
+async fn pending_join_requests_dashboard_lists_only_chats_with_requests() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let owner = invite_regular(&db, "dashboard_owner", "passfordashowner").await;
+    let requester_a = invite_regular(&db, "join_requester_a", "passforrequestera").await;
+    let requester_b = invite_regular(&db, "join_requester_b", "passforrequesterb").await;
+    let requested = db
+        .create_group_chat(owner, "Requested", None)
+        .await
+        .unwrap();
+    let quiet = db.create_channel_chat(owner, "Quiet", None).await.unwrap();
+
+    db.request_to_join_chat(requester_a, requested)
+        .await
+        .unwrap();
+    db.request_to_join_chat(requester_b, requested)
+        .await
+        .unwrap();
+    // Repeated request doesn't count twice
+    db.request_to_join_chat(requester_a, requested)
+        .await
+        .unwrap();
+    let pending = db
+        .list_chats_with_pending_requests(owner)
+        .await
+        .unwrap()
+        .chats;
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].chat_id, requested);
+    assert_eq!(pending[0].pending_requests, 2);
+    assert!(pending.iter().all(|chat| chat.chat_id != quiet));
+    assert!(db
+        .list_chats_with_pending_requests(requester_a)
+        .await
+        .unwrap()
+        .chats
+        .is_empty());
+    let requests = db.list_join_requests(owner, requested).await.unwrap();
+    assert_eq!(requests.requests[0].alias, "join_requester_a");
+
+    db.approve_join_request(owner, requested, requester_a)
+        .await
+        .unwrap();
+    assert_eq!(
+        db.get_my_role(requester_a, requested).await.unwrap().role,
+        ChatRole::Member
+    );
+    // Plain members can't handle requests
+    let err = db
+        .decline_join_request(requester_a, requested, requester_b)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::Forbidden)
+    ));
+    let err = db
+        .request_to_join_chat(requester_a, requested)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::AlreadyExists)
+    ));
+    db.decline_join_request(owner, requested, requester_b)
+        .await
+        .unwrap();
+    db.get_my_role(requester_b, requested).await.unwrap_err();
+    assert!(db
+        .list_chats_with_pending_requests(owner)
+        .await
+        .unwrap()
+        .chats
+        .is_empty());
+
+    let private_chat = find_chat_id(&db, owner, ChatKind::Private, Some("join_requester_b")).await;
+    db.request_to_join_chat(requester_a, private_chat)
+        .await
+        .unwrap_err();
+}
+
+#[tokio::test]
+async fn blocked_users_are_listed_page_by_page() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let caller = invite_regular(&db, "block_manager", "passforblockmanager").await;
+    let mut blocked = Vec::new();
+    for alias in ["blocked_a", "blocked_b", "blocked_c"] {
+        let user_id = invite_regular(&db, alias, "passforblockeduser").await;
+        db.block_user(caller, user_id).await.unwrap();
+        blocked.push(user_id);
+    }
+    // Repeated block keeps single entry
+    db.block_user(caller, blocked[0]).await.unwrap();
+
+    let blocked_ids = |users: Vec<BlockedUserResponse>| -> Vec<UserId> {
+        users.iter().map(|user| user.user_id).collect()
+    };
+    let page = |limit, page| ListingMode::Page { limit, page };
+    let first = db
+        .list_blocked_users(caller, page(2, 1))
+        .await
+        .unwrap()
+        .blocked_users;
+    assert_eq!(first[0].alias, "blocked_a");
+    assert_eq!(blocked_ids(first), blocked[..2]);
+    let second = db
+        .list_blocked_users(caller, page(2, 2))
+        .await
+        .unwrap()
+        .blocked_users;
+    assert_eq!(blocked_ids(second), blocked[2..]);
+    let after = db
+        .list_blocked_users(
+            caller,
+            ListingMode::Offset {
+                offset: blocked[0].into(),
+                limit: 10,
+            },
+        )
+        .await
+        .unwrap()
+        .blocked_users;
+    assert_eq!(blocked_ids(after), blocked[1..]);
+    // Block list is private to the blocking user
+    let others = db
+        .list_blocked_users(blocked[0], page(10, 1))
+        .await
+        .unwrap()
+        .blocked_users;
+    assert!(others.is_empty());
+
+    db.unblock_user(caller, blocked[1]).await.unwrap();
+    let err = db.unblock_user(caller, blocked[1]).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
+    let remaining = db
+        .list_blocked_users(caller, page(10, 1))
+        .await
+        .unwrap()
+        .blocked_users;
+    assert_eq!(blocked_ids(remaining), [blocked[0], blocked[2]]);
+    db.block_user(caller, caller).await.unwrap_err();
+}
+
+#[tokio::test]
+async fn blocks_apply_to_private_chats_and_search() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let blocker = invite_regular(&db, "blocking_user", "passforblockinguser").await;
+    let blocked = invite_regular(&db, "blocked_user", "passforblockeduser").await;
+    let stranger = invite_regular(&db, "blocked_stranger", "passforblockedstranger").await;
+    let chat_id = find_chat_id(&db, blocker, ChatKind::Private, Some("blocked_user")).await;
+    db.block_user(blocker, blocked).await.unwrap();
+
+    let is_forbidden =
+        |error: RequestError| matches!(error, RequestError::Validation(ValidationError::Forbidden));
+    // Block applies both ways, to existing private chat and to sending by alias
+    for (sender, other) in [(blocker, blocked), (blocked, blocker)] {
+        let error = db.send_message(sender, chat_id, "hello").await.unwrap_err();
+        assert!(is_forbidden(error));
+        let alias = if other == blocker {
+            "blocking_user"
+        } else {
+            "blocked_user"
+        };
+        let error = db
+            .send_private_message(sender, alias, "hello")
+            .await
+            .unwrap_err();
+        assert!(is_forbidden(error));
+    }
+    db.block_user(stranger, blocked).await.unwrap();
+    let error = db
+        .create_private_chat(blocked, "blocked_stranger")
+        .await
+        .unwrap_err();
+    assert!(is_forbidden(error));
+    let aliases = |users: Vec<UserProfileResponse>| -> Vec<String> {
+        users.into_iter().map(|user| user.alias).collect()
+    };
+    let found = db.search_users(blocked, "block", 10).await.unwrap().users;
+    assert!(found.is_empty(), "{:?}", aliases(found));
+    let found = db.search_users(blocker, "block", 10).await.unwrap().users;
+    assert_eq!(aliases(found), ["blocked_stranger"]);
+
+    db.unblock_user(blocker, blocked).await.unwrap();
+    db.send_message(blocked, chat_id, "hello again")
+        .await
+        .unwrap();
+    let found = db.search_users(blocked, "block", 10).await.unwrap().users;
+    assert_eq!(aliases(found), ["blocking_user"]);
+}
+
+#[tokio::test]
+async fn self_chat_lookup_returns_chat_created_at_invite() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+
+    let user_id = invite_regular(&db, "self_chat_user", "passforselfchat").await;
+    let expected = find_chat_id(&db, user_id, ChatKind::WithSelf, None).await;
+    let found = db.get_self_chat(user_id).await.unwrap();
+    assert_eq!(found.chat_id, expected);
+
+    let origin_chat = find_chat_id(&db, 1, ChatKind::WithSelf, None).await;
+    assert_ne!(origin_chat, expected);
+    assert_eq!(db.get_self_chat(1).await.unwrap().chat_id, origin_chat);
+}
+
+#[tokio::test]
+async fn private_chat_lookup_sees_chat_created_after_miss() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let origin_user_id = 1;
+
+    let (alias_a, alias_b) = ("dm_user_a", "dm_user_b");
+    // Id the next invited user gets, looked up before the user and its chats exist
+    let user_a = origin_user_id + 1;
+    let err = db
+        .get_private_chat(origin_user_id, user_a)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
+    assert_eq!(invite_regular(&db, alias_a, "passfordmusera").await, user_a);
+    let chat_with_origin =
+        find_chat_id(&db, origin_user_id, ChatKind::Private, Some(alias_a)).await;
+    let found = db.get_private_chat(origin_user_id, user_a).await.unwrap();
+    assert_eq!(found.chat_id, chat_with_origin);
+
+    // Drop chat created by invite, so the pair can be recreated explicitly
+    let user_b = invite_regular(&db, alias_b, "passfordmuserb").await;
+    let invite_chat = find_chat_id(&db, user_a, ChatKind::Private, Some(alias_b)).await;
+    sqlx::query("DELETE FROM chats WHERE id = $1")
+        .bind(invite_chat)
+        .execute(db.pool())
+        .await
+        .unwrap();
+    db.get_private_chat(user_a, user_b).await.unwrap_err();
+    let created = db.create_private_chat(user_b, alias_a).await.unwrap();
+    assert_eq!(
+        db.get_private_chat(user_a, user_b).await.unwrap().chat_id,
+        created
+    );
+    assert_eq!(
+        db.get_private_chat(user_b, user_a).await.unwrap().chat_id,
+        created
+    );
+}
+
+#[tokio::test]
+async fn find_private_chat_returns_dm_id_only_for_its_pair() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_a = invite_regular(&db, "find_dm_a", "passforfinddma").await;
+    let user_b = invite_regular(&db, "find_dm_b", "passforfinddmb").await;
+    let user_c = invite_regular(&db, "find_dm_c", "passforfinddmc").await;
+    // Invites pair everyone up, drop one chat so that pair is unrelated
+    let unrelated = find_chat_id(&db, user_a, ChatKind::Private, Some("find_dm_c")).await;
+    sqlx::query("DELETE FROM chats WHERE id = $1")
+        .bind(unrelated)
+        .execute(db.pool())
+        .await
+        .unwrap();
+
+    let dm = find_chat_id(&db, user_a, ChatKind::Private, Some("find_dm_b")).await;
+    assert_eq!(
+        db.find_private_chat(user_a, user_b).await.unwrap(),
+        Some(dm)
+    );
+    assert_eq!(
+        db.find_private_chat(user_b, user_a).await.unwrap(),
+        Some(dm)
+    );
+    assert_eq!(db.find_private_chat(user_a, user_c).await.unwrap(), None);
+    assert_eq!(db.find_private_chat(user_a, user_a).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn chat_member_extractor_admits_only_members() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_a = invite_regular(&db, "extractor_a", "passforextractora").await;
+    let _user_b = invite_regular(&db, "extractor_b", "passforextractorb").await;
+    let _user_c = invite_regular(&db, "extractor_c", "passforextractorc").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("extractor_b")).await;
+    let message_id = db.send_message(user_a, chat_id, "hello").await.unwrap();
+
+    let app = routes(init_app_state().await);
+    let member = bearer_for(&db, "extractor_a", "passforextractora").await;
+    let outsider = bearer_for(&db, "extractor_c", "passforextractorc").await;
+
+    let my_role = |token: &str, chat: &str| {
+        Request::get(format!("/chats/{chat}/my-role"))
+            .header(AUTHORIZATION, token)
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app
+        .clone()
+        .oneshot(my_role(&member, &chat_id.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let role: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(role["role"], "member");
+
+    let response = app
+        .clone()
+        .oneshot(my_role(&outsider, &chat_id.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = app
+        .clone()
+        .oneshot(my_role(&member, "not_a_chat"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let mark_read = |token: &str| {
+        Request::post(format!("/chats/{chat_id}/read"))
+            .header(AUTHORIZATION, token)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::json!({ "up_to_message_id": message_id }).to_string(),
+            ))
+            .unwrap()
+    };
+    let response = app.clone().oneshot(mark_read(&outsider)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = app.oneshot(mark_read(&member)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn edit_and_delete_message_are_author_only() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_a = invite_regular(&db, "editor_a", "passforeditora").await;
+    let user_b = invite_regular(&db, "editor_b", "passforeditorb").await;
+    let user_c = invite_regular(&db, "editor_c", "passforeditorc").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("editor_b")).await;
+    let first = db.send_message(user_a, chat_id, "first").await.unwrap();
+    let second = db.send_message(user_a, chat_id, "second").await.unwrap();
+
+    let edited = db
+        .edit_message(user_a, chat_id, first, "first, edited")
+        .await
+        .unwrap();
+    assert_eq!(edited.text.as_deref(), Some("first, edited"));
+    assert!(edited.edited_at.is_some());
+    for caller in [user_b, user_c] {
+        let err = db
+            .edit_message(caller, chat_id, first, "hijacked")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            RequestError::Validation(ValidationError::NotFound)
+        ));
+        db.delete_message(caller, chat_id, second)
+            .await
+            .unwrap_err();
+    }
+    let err = db
+        .edit_message(user_a, chat_id, first, "  ")
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
+
+    db.delete_message(user_a, chat_id, second).await.unwrap();
+    let messages = db
+        .list_messages(user_b, chat_id, 10, 1)
+        .await
+        .unwrap()
+        .messages;
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0].text.as_deref(), Some("first, edited"));
+    let chat = find_chat_by_id(&db, user_b, chat_id).await;
+    assert_eq!(chat.last_message_id, Some(first));
+}
+
+#[tokio::test]
+async fn message_edit_history_keeps_prior_texts() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_a = invite_regular(&db, "history_a", "passforhistorya").await;
+    let user_b = invite_regular(&db, "history_b", "passforhistoryb").await;
+    let user_c = invite_regular(&db, "history_c", "passforhistoryc").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("history_b")).await;
+    let message = db.send_message(user_a, chat_id, "original").await.unwrap();
+    assert!(db
+        .list_message_edits(user_b, message)
+        .await
+        .unwrap()
+        .edits
+        .is_empty());
+
+    db.edit_message(user_a, chat_id, message, "second")
+        .await
+        .unwrap();
+    db.edit_message(user_a, chat_id, message, "third")
+        .await
+        .unwrap();
+    // Rejected edit leaves no trace
+    db.edit_message(user_b, chat_id, message, "hijacked")
+        .await
+        .unwrap_err();
+
+    let edits = db.list_message_edits(user_b, message).await.unwrap().edits;
+    let texts: Vec<_> = edits.iter().map(|edit| edit.old_text.as_deref()).collect();
+    assert_eq!(texts, vec![Some("original"), Some("second")]);
+    assert!(edits[0].edited_at <= edits[1].edited_at);
+
+    let err = db.list_message_edits(user_c, message).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
+}
+
+#[tokio::test]
+async fn message_edits_and_deletes_are_pushed_to_subscribers() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_a = invite_regular(&db, "pusher_a", "passforpushera").await;
+    let _user_b = invite_regular(&db, "pusher_b", "passforpusherb").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("pusher_b")).await;
+    let message_id = db.send_message(user_a, chat_id, "typo").await.unwrap();
+
+    let state = init_app_state().await;
+    let app = routes(state.clone());
+    let token = bearer_for(&db, "pusher_a", "passforpushera").await;
+    let mut events = state.chat_events.subscribe(chat_id);
+    let uri = format!("/chats/{chat_id}/messages/{message_id}");
+
+    let edit = Request::patch(&uri)
+        .header(AUTHORIZATION, &token)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({ "text": "fixed" }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(edit).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let event = serde_json::to_value(events.recv().await.unwrap()).unwrap();
+    assert_eq!(event["type"], "edited_message");
+    assert_eq!(event["id"], message_id);
+    assert_eq!(event["text"], "fixed");
+
+    let delete = Request::delete(&uri)
+        .header(AUTHORIZATION, &token)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(delete).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let event = serde_json::to_value(events.recv().await.unwrap()).unwrap();
+    assert_eq!(
+        event,
+        serde_json::json!({ "type": "deleted_message", "message_id": message_id })
+    );
+}
+
+#[tokio::test]
+async fn due_scheduled_messages_are_delivered_by_worker() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_a = invite_regular(&db, "scheduler_a", "passforschedulera").await;
+    let _user_b = invite_regular(&db, "scheduler_b", "passforschedulerb").await;
+    let outsider = invite_regular(&db, "scheduler_c", "passforschedulerc").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("scheduler_b")).await;
+    let now = chrono::Utc::now();
+
+    let due = db
+        .schedule_message(
+            user_a,
+            chat_id,
+            "from the past",
+            now - chrono::Duration::minutes(1),
+        )
+        .await
+        .unwrap();
+    assert_eq!(due.chat_id, chat_id);
+    let later = db
+        .schedule_message(user_a, chat_id, "not yet", now + chrono::Duration::hours(1))
+        .await
+        .unwrap();
+    let cancelled = db
+        .schedule_message(
+            user_a,
+            chat_id,
+            "never mind",
+            now - chrono::Duration::minutes(1),
+        )
+        .await
+        .unwrap();
+    db.cancel_scheduled_message(user_a, cancelled.id)
+        .await
+        .unwrap();
+    db.schedule_message(outsider, chat_id, "intrusion", now)
+        .await
+        .unwrap_err();
+    // Only the author can cancel
+    db.cancel_scheduled_message(outsider, later.id)
+        .await
+        .unwrap_err();
+
+    let state = init_app_state().await;
+    let mut events = state.chat_events.subscribe(chat_id);
+    assert_eq!(deliver_scheduled_messages_round(&state).await, 1);
+    let event = serde_json::to_value(events.recv().await.unwrap()).unwrap();
+    assert_eq!(event["type"], "new_message");
+    assert_eq!(event["text"], "from the past");
+    let messages = db.list_messages(user_a, chat_id, 100, 1).await.unwrap();
+    assert_eq!(messages.messages.len(), 1);
+    assert_eq!(messages.messages[0].user_id, Some(user_a));
+
+    // Delivered message is gone from the schedule, the future one stays there
+    assert_eq!(deliver_scheduled_messages_round(&state).await, 0);
+    db.cancel_scheduled_message(user_a, due.id)
+        .await
+        .unwrap_err();
+    db.cancel_scheduled_message(user_a, later.id).await.unwrap();
+}
+
+#[tokio::test]
+async fn failing_scheduled_message_is_dropped_without_holding_back_the_batch() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_a = invite_regular(&db, "poison_a", "passforpoisona").await;
+    let _user_b = invite_regular(&db, "poison_b", "passforpoisonb").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("poison_b")).await;
+    let due_at = chrono::Utc::now() - chrono::Duration::minutes(1);
+    for text in ["before poison", "poison", "after poison"] {
+        db.schedule_message(user_a, chat_id, text, due_at)
+            .await
+            .unwrap();
+    }
+    // Make exactly one of the due messages fail to send
+    sqlx::query(
+        "ALTER TABLE messages ADD CONSTRAINT test_no_poison CHECK (text <> 'poison') NOT VALID",
+    )
+    .execute(db.pool())
+    .await
+    .unwrap();
+
+    let state = init_app_state().await;
+    let delivered = deliver_scheduled_messages_round(&state).await;
+    sqlx::query("ALTER TABLE messages DROP CONSTRAINT test_no_poison")
+        .execute(db.pool())
+        .await
+        .unwrap();
+    assert_eq!(delivered, 2);
+    let texts: Vec<_> = db
+        .list_messages(user_a, chat_id, 100, 1)
+        .await
+        .unwrap()
+        .messages
+        .into_iter()
+        .filter_map(|message| message.text)
+        .collect();
+    assert_eq!(texts, ["before poison", "after poison"]);
+
+    // Failed message is dropped rather than retried every round
+    assert_eq!(deliver_scheduled_messages_round(&state).await, 0);
+}
+
+#[tokio::test]
+async fn mentions_listing_returns_only_messages_involving_caller() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let caller = invite_regular(&db, "mention_a", "passformentiona").await;
+    let user_b = invite_regular(&db, "mention_b", "passformentionb").await;
+    let user_c = invite_regular(&db, "mention_c", "passformentionc").await;
+    let outsider = invite_regular(&db, "mention_d", "passformentiond").await;
+    let chat_id = db
+        .create_group_chat(caller, "Mentions", None)
+        .await
+        .unwrap();
+    db.add_members_to_group_chat(caller, chat_id, &[user_b, user_c])
+        .await
+        .unwrap();
+
+    let send = |user_id, text: &'static str, reply_to| {
+        let db = &db;
+        async move {
+            db.send_message_with_reply(user_id, chat_id, text, reply_to)
+                .await
+                .unwrap()
+        }
+    };
+    send(user_b, "hello everyone", None).await;
+    let pinged = send(user_b, "ping @mention_a", None).await;
+    let shouted = send(user_c, "@MENTION_A, check this", None).await;
+    send(user_c, "@mention_ab and x@mention_a are someone else", None).await;
+    send(caller, "note to @mention_a myself", None).await;
+    let question = send(caller, "question?", None).await;
+    let answer = send(user_b, "answer", Some(question)).await;
+    send(user_c, "follow-up", Some(answer)).await;
+
+    let ids = |messages: Vec<MessageResponse>| -> Vec<MessageId> {
+        messages.iter().map(|message| message.id).collect()
+    };
+    let page = |limit, page| ListingMode::Page { limit, page };
+    let all = db
+        .list_messages_involving(caller, chat_id, page(10, 1))
+        .await
+        .unwrap()
+        .messages;
+    assert_eq!(ids(all), [pinged, shouted, answer]);
+    let second = db
+        .list_messages_involving(caller, chat_id, page(2, 2))
+        .await
+        .unwrap()
+        .messages;
+    assert_eq!(ids(second), [answer]);
+    let after = db
+        .list_messages_involving(
+            caller,
+            chat_id,
+            ListingMode::Offset {
+                offset: pinged,
+                limit: 10,
+            },
+        )
+        .await
+        .unwrap()
+        .messages;
+    assert_eq!(ids(after), [shouted, answer]);
+    // Replies of others to each other don't involve caller
+    let for_c = db
+        .list_messages_involving(user_c, chat_id, page(10, 1))
+        .await
+        .unwrap()
+        .messages;
+    assert!(for_c.is_empty());
+
+    let err = db
+        .list_messages_involving(outsider, chat_id, page(10, 1))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
+}
+
+#[tokio::test]
+async fn list_reactions_pages_through_reactors() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let mut users = Vec::new();
+    for alias in ["reactor_a", "reactor_b", "reactor_c", "reactor_d"] {
+        users.push(invite_regular(&db, alias, "passforreactor").await);
+    }
+    let outsider = invite_regular(&db, "reactor_outsider", "passforreactor").await;
+    let chat_id = db.create_group_chat(users[0], "Viral", None).await.unwrap();
+    db.add_members_to_group_chat(users[0], chat_id, &users)
+        .await
+        .unwrap();
+    let message_id = db.send_message(users[0], chat_id, "viral").await.unwrap();
+    for user_id in &users {
+        db.add_reaction(*user_id, chat_id, message_id, "🔥")
+            .await
+            .unwrap();
+    }
+    db.add_reaction(users[0], chat_id, message_id, "👍")
+        .await
+        .unwrap();
+
+    let reactor_ids = |reactors: Vec<ReactorResponse>| -> Vec<UserId> {
+        reactors.iter().map(|reactor| reactor.user_id).collect()
+    };
+    let page = |limit, page| ListingMode::Page { limit, page };
+    let first = db
+        .list_reactions(users[1], message_id, "🔥", page(3, 1))
+        .await
+        .unwrap()
+        .reactors;
+    assert_eq!(reactor_ids(first), users[..3]);
+    let second = db
+        .list_reactions(users[1], message_id, "🔥", page(3, 2))
+        .await
+        .unwrap()
+        .reactors;
+    assert_eq!(reactor_ids(second), users[3..]);
+    let after = db
+        .list_reactions(
+            users[1],
+            message_id,
+            "🔥",
+            ListingMode::Offset {
+                offset: users[1].into(),
+                limit: 10,
+            },
+        )
+        .await
+        .unwrap()
+        .reactors;
+    assert_eq!(reactor_ids(after), users[2..]);
+    let thumbs_up = db
+        .list_reactions(users[1], message_id, "👍", page(10, 1))
+        .await
+        .unwrap()
+        .reactors;
+    assert_eq!(thumbs_up.len(), 1);
+    assert_eq!(thumbs_up[0].display_name, "reactor_a");
+
+    let err = db
+        .list_reactions(outsider, message_id, "🔥", page(10, 1))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
+}
+
+#[tokio::test]
+async fn search_users_matches_alias_and_display_name_excluding_caller() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let walrus_a = invite_regular(&db, "walrus_a", "passforsearch").await;
+    let walrus_b = invite_regular(&db, "walrus_b", "passforsearch").await;
+    let seal = invite_regular(&db, "seal", "passforsearch").await;
+    let _otter = invite_regular(&db, "otter", "passforsearch").await;
+    db.change_display_name(seal, "Big Walrus Fan")
+        .await
+        .unwrap();
+
+    let found = |users: Vec<UserProfileResponse>| -> Vec<UserId> {
+        users.iter().map(|user| user.user_id).collect()
+    };
+    let users = db.search_users(walrus_a, "WALR", 10).await.unwrap().users;
+    // Prefix matches on alias come before substring match on display name
+    assert_eq!(found(users), vec![walrus_b, seal]);
+    let users = db.search_users(walrus_b, "rus_", 10).await.unwrap().users;
+    assert_eq!(found(users), vec![walrus_a]);
+    let users = db.search_users(walrus_a, "walrus", 1).await.unwrap().users;
+    assert_eq!(found(users), vec![walrus_b]);
+    // Wildcards are matched literally
+    let users = db.search_users(walrus_a, "%", 10).await.unwrap().users;
+    assert!(users.is_empty());
+
+    let err = db.search_users(walrus_a, "  ", 10).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
+}
+
+#[tokio::test]
+async fn message_without_content_is_rejected() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_a = invite_regular(&db, "ghost_a", "passforghosta").await;
+    let _user_b = invite_regular(&db, "ghost_b", "passforghostb").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("ghost_b")).await;
+
+    for text in ["", " \n\t"] {
+        let err = db.send_message(user_a, chat_id, text).await.unwrap_err();
+        assert!(matches!(
+            err,
+            RequestError::Validation(ValidationError::InvalidInput { .. })
+        ));
+    }
+    let messages = db
+        .list_messages(user_a, chat_id, 10, 1)
+        .await
+        .unwrap()
+        .messages;
+    assert!(messages.is_empty());
+
+    // Schema rejects ghost messages even when application checks are bypassed
+    let err = sqlx::query(
+        "INSERT INTO messages (chat_id, user_id, created_at) VALUES ($1, $2, current_timestamp)",
+    )
+    .bind(chat_id)
+    .bind(user_a)
+    .execute(db.pool())
+    .await
+    .unwrap_err();
+    let sqlx::Error::Database(db_error) = err else {
+        panic!("expected database error, got {err}");
+    };
+    assert_eq!(db_error.constraint(), Some("message_has_content"));
+}
+
+#[tokio::test]
+async fn origin_password_is_default_until_changed() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let origin_user_id = 1;
+    let regular = invite_regular(&db, "bootstrap_regular", "passforbootstrap").await;
+
+    assert!(db.origin_password_is_default().await.unwrap());
+    let status = db.bootstrap_status(origin_user_id).await.unwrap();
+    assert!(status.origin_password_is_default);
+    let err = db.bootstrap_status(regular).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InsufficientPermissions { .. })
+    ));
+
+    let session = db
+        .login("origin", TEST_ORIGIN_PASSWORD)
+        .await
+        .unwrap()
+        .tokens;
+    let (session_id, _token) =
+        unpack_encoded_session_token(&session.access_token, TokenKind::Access);
+    db.change_password(
+        origin_user_id,
+        session_id,
+        TEST_ORIGIN_PASSWORD,
+        "rotated_origin_password",
+    )
+    .await
+    .unwrap();
+    assert!(!db.origin_password_is_default().await.unwrap());
+}
+
+#[tokio::test]
+async fn list_messages_window_around_middle_message() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_a = invite_regular(&db, "window_a", "passforwindowa").await;
+    let _user_b = invite_regular(&db, "window_b", "passforwindowb").await;
+    let outsider = invite_regular(&db, "window_c", "passforwindowc").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("window_b")).await;
+    let mut sent = Vec::new();
+    for i in 0..9 {
+        sent.push(
+            db.send_message(user_a, chat_id, &format!("msg_{i}"))
+                .await
+                .unwrap(),
+        );
+    }
+    let window_ids = |messages: Vec<MessageResponse>| -> Vec<MessageId> {
+        messages.iter().map(|message| message.id).collect()
+    };
+
+    let anchor = Some(sent[4]);
+    let around = db
+        .list_messages_window(user_a, chat_id, anchor, anchor, 4)
+        .await
+        .unwrap()
+        .messages;
+    assert_eq!(window_ids(around), [&sent[2..4], &sent[5..7]].concat());
+
+    // Short side near the start of history leaves the rest of the limit to the other side
+    let near_start = db
+        .list_messages_window(user_a, chat_id, Some(sent[1]), Some(sent[1]), 4)
+        .await
+        .unwrap()
+        .messages;
+    assert_eq!(window_ids(near_start), [&sent[..1], &sent[2..5]].concat());
+
+    let older_only = db
+        .list_messages_window(user_a, chat_id, Some(sent[4]), None, 3)
+        .await
+        .unwrap()
+        .messages;
+    assert_eq!(window_ids(older_only), sent[1..4]);
+
+    let err = db
+        .list_messages_window(outsider, chat_id, anchor, anchor, 4)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
+}
+
+#[tokio::test]
+async fn create_group_chat_route_adds_members_by_alias() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let owner = invite_regular(&db, "group_owner", "passforgroupowner").await;
+    let member_a = invite_regular(&db, "group_member_a", "passforgroupa").await;
+    let member_b = invite_regular(&db, "group_member_b", "passforgroupb").await;
+
+    let app = routes(init_app_state().await);
+    let token = bearer_for(&db, "group_owner", "passforgroupowner").await;
+    let create_group = |members: serde_json::Value| {
+        Request::post("/chats/group")
+            .header(AUTHORIZATION, &token)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::json!({
+                    "display_name": "Walrus pod",
+                    "description": "ice floe gossip",
+                    "members": members,
+                })
+                .to_string(),
+            ))
+            .unwrap()
+    };
+
+    let response = app
+        .clone()
+        .oneshot(create_group(serde_json::json!([
+            "group_member_a",
+            "group_ghost"
+        ])))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert!(
+        error["error"].as_str().unwrap().contains("group_ghost"),
+        "{error}"
+    );
+    let owner_groups = db
+        .list_chats(owner, 100, 1, Some(ChatKind::Group))
+        .await
+        .unwrap();
+    assert!(owner_groups.chats.is_empty());
+
+    let response = app
+        .oneshot(create_group(serde_json::json!([
+            "group_member_a",
+            "group_member_b",
+            "group_owner"
+        ])))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let chat_id = created["chat_id"].as_i64().unwrap();
+
+    for (user_id, expected_role) in [
+        (owner, ChatRole::Owner),
+        (member_a, ChatRole::Member),
+        (member_b, ChatRole::Member),
+    ] {
+        let groups = db
+            .list_chats(user_id, 100, 1, Some(ChatKind::Group))
+            .await
+            .unwrap();
+        assert_eq!(groups.chats.len(), 1);
+        assert_eq!(groups.chats[0].id, chat_id);
+        assert_eq!(groups.chats[0].display_name.as_deref(), Some("Walrus pod"));
+        assert_eq!(
+            db.get_my_role(user_id, chat_id).await.unwrap().role,
+            expected_role
+        );
+    }
+    let members = db.list_members(owner, chat_id).await.unwrap();
+    assert_eq!(members.members_count, 3);
+}
+
+#[tokio::test]
+async fn create_channel_route_makes_caller_sole_poster() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let owner = invite_regular(&db, "channel_creator", "passforcreator").await;
+    let member = invite_regular(&db, "channel_reader", "passforreader").await;
+
+    let app = routes(init_app_state().await);
+    let token = bearer_for(&db, "channel_creator", "passforcreator").await;
+    let response = app
+        .oneshot(
+            Request::post("/chats/channel")
+                .header(AUTHORIZATION, &token)
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    serde_json::json!({ "display_name": "Walrus news" }).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let created: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let channel = created["chat_id"].as_i64().unwrap();
+
+    assert_eq!(
+        db.get_my_role(owner, channel).await.unwrap().role,
+        ChatRole::Owner
+    );
+    let members = db.list_members(owner, channel).await.unwrap();
+    assert_eq!(members.members_count, 1);
+
+    db.add_members_to_group_chat(owner, channel, &[member])
+        .await
+        .unwrap();
+    let member_post = db.send_message(member, channel, "can I post?").await;
+    assert!(matches!(
+        member_post,
+        Err(RequestError::Validation(ValidationError::Forbidden))
+    ));
+    db.send_message(owner, channel, "only announcements here")
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn admin_password_reset_replaces_password_and_drops_sessions() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let origin_user_id = 1;
+    let user_a = invite_regular(&db, "reset_a", "passforreseta").await;
+    let user_b = invite_regular(&db, "reset_b", "passforresetb").await;
+    let session = db.login("reset_a", "passforreseta").await.unwrap().tokens;
+
+    let err = db
+        .admin_reset_password(user_b, "reset_a", "takenoverpass")
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InsufficientPermissions { .. })
+    ));
+    let err = db
+        .admin_reset_password(origin_user_id, "reset_missing", "newpassforreset")
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::UserNotFound { .. })
+    ));
+
+    db.admin_reset_password(origin_user_id, "reset_a", "newpassforreseta")
+        .await
+        .unwrap();
+    assert!(matches!(
+        db.login("reset_a", "passforreseta").await.unwrap_err(),
+        RequestError::BadCredentials
+    ));
+    db.login("reset_a", "newpassforreseta").await.unwrap();
+    assert!(resolve_session(&db, &session).await.is_err());
+
+    let resets = db
+        .admin_list_audit(
+            origin_user_id,
+            &ListAuditQuery {
+                action: Some(AuditAction::PasswordReset),
+                ..Default::default()
+            },
+            100,
+        )
+        .await
+        .unwrap();
+    assert_eq!(resets.entries.len(), 1);
+    assert_eq!(resets.entries[0].actor_user_id, Some(origin_user_id));
+    assert_eq!(resets.entries[0].target_user_id, Some(user_a));
+}
+
+#[tokio::test]
+async fn admin_password_reset_rejects_admin_targets() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let origin_user_id = 1;
+    let admin = db
+        .invite_user(
+            origin_user_id,
+            "reset_admin",
+            "passforresetadmin",
+            Some(UserRole::Admin),
+        )
+        .await
+        .unwrap();
+    invite_regular(&db, "reset_regular", "passforresetregular").await;
+
+    for (caller, target) in [
+        (admin, "origin"),
+        (origin_user_id, "reset_admin"),
+        (admin, "reset_admin"),
+    ] {
+        let err = db
+            .admin_reset_password(caller, target, "takenoverpass")
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            RequestError::Validation(ValidationError::Forbidden)
+        ));
+    }
+    db.login("origin", TEST_ORIGIN_PASSWORD).await.unwrap();
+    db.login("reset_admin", "passforresetadmin").await.unwrap();
+
+    db.admin_reset_password(admin, "reset_regular", "newpassforregular")
+        .await
+        .unwrap();
+    db.login("reset_regular", "newpassforregular")
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn user_message_rate_counts_messages_in_window() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let origin_user_id = 1;
+    let spammer = invite_regular(&db, "rate_spammer", "passforratespammer").await;
+    let other = invite_regular(&db, "rate_other", "passforrateother").await;
+    let private = find_chat_id(&db, spammer, ChatKind::Private, Some("rate_other")).await;
+    let group = db.create_group_chat(spammer, "Rate", None).await.unwrap();
+
+    let old = db.send_message(spammer, private, "long ago").await.unwrap();
+    backdate(
+        &db,
+        "messages",
+        "created_at",
+        "id",
+        old,
+        chrono::Duration::days(1),
+    )
+    .await;
+    let window_start = chrono::Utc::now() - chrono::Duration::hours(1);
+    for text in ["spam 1", "spam 2", "spam 3"] {
+        db.send_message(spammer, group, text).await.unwrap();
+    }
+    db.send_message(spammer, private, "spam 4").await.unwrap();
+    db.send_message(other, private, "not spam").await.unwrap();
+
+    let err = db
+        .user_message_rate(other, spammer, window_start, false)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InsufficientPermissions { .. })
+    ));
+
+    let rate = db
+        .user_message_rate(origin_user_id, spammer, window_start, false)
+        .await
+        .unwrap();
+    assert_eq!(rate.total, 4);
+    assert!(rate.by_chat.is_none());
+    let rate = db
+        .user_message_rate(
+            origin_user_id,
+            spammer,
+            window_start - chrono::Duration::days(2),
+            true,
+        )
+        .await
+        .unwrap();
+    assert_eq!(rate.total, 5);
+    let by_chat: Vec<_> = rate
+        .by_chat
+        .unwrap()
+        .iter()
+        .map(|chat| (chat.chat_id, chat.count))
+        .collect();
+    assert_eq!(by_chat, [(group, 3), (private, 2)]);
+
+    let lookups = db
+        .admin_list_audit(
+            origin_user_id,
+            &ListAuditQuery {
+                action: Some(AuditAction::MessageRateViewed),
+                ..Default::default()
+            },
+            100,
+        )
+        .await
+        .unwrap();
+    assert_eq!(lookups.entries.len(), 2);
+    assert_eq!(lookups.entries[0].target_user_id, Some(spammer));
+}
+
+#[tokio::test]
+async fn admin_stats_count_seeded_data() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let origin_user_id = 1;
+    let user_a = invite_regular(&db, "stats_a", "passforstatsa").await;
+    let user_b = invite_regular(&db, "stats_b", "passforstatsb").await;
+    let group = db.create_group_chat(user_a, "Stats", None).await.unwrap();
+    db.create_channel_chat(user_b, "Stats news", None)
+        .await
+        .unwrap();
+    db.send_message(user_a, group, "one").await.unwrap();
+    db.send_message(user_a, group, "two").await.unwrap();
+    db.login("stats_a", "passforstatsa").await.unwrap();
+    let session = db.login("stats_b", "passforstatsb").await.unwrap().tokens;
+    let (session_id, _token) =
+        unpack_encoded_session_token(&session.access_token, TokenKind::Access);
+    db.logout(session_id).await.unwrap();
+
+    let err = db.admin_stats(user_a).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InsufficientPermissions { .. })
+    ));
+
+    let stats = db.admin_stats(origin_user_id).await.unwrap();
+    assert_eq!(stats.users, 3);
+    assert_eq!(stats.chats.with_self, 3);
+    // Every pair of users gets a private chat
+    assert_eq!(stats.chats.private, 3);
+    assert_eq!(stats.chats.group, 1);
+    assert_eq!(stats.chats.channel, 1);
+    assert_eq!(stats.messages, 2);
+    assert_eq!(stats.active_sessions, 1);
+}
+
+#[tokio::test]
+async fn inactive_users_report_lists_only_dormant_accounts() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let origin_user_id = 1;
+    let active = invite_regular(&db, "inactive_active", "passforactive").await;
+    let dormant = invite_regular(&db, "inactive_dormant", "passfordormant").await;
+    let never = invite_regular(&db, "inactive_never", "passfornever").await;
+    db.login("inactive_active", "passforactive").await.unwrap();
+    db.login("inactive_dormant", "passfordormant")
+        .await
+        .unwrap();
+    backdate(
+        &db,
+        "sessions",
+        "last_seen_at",
+        "user_id",
+        dormant,
+        chrono::Duration::days(60),
+    )
+    .await;
+
+    let since = chrono::Utc::now() - chrono::Duration::days(30);
+    let err = db.list_inactive_users(active, since).await.unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InsufficientPermissions { .. })
+    ));
+
+    let users = db
+        .list_inactive_users(origin_user_id, since)
+        .await
+        .unwrap()
+        .users;
+    let ids: Vec<_> = users.iter().map(|user| user.user_id).collect();
+    // Never logged in first, then longest inactive
+    assert_eq!(ids, [origin_user_id, never, dormant]);
+    assert!(users[2].last_seen_at.unwrap() < since);
+}
+
+#[tokio::test]
+async fn admin_audit_listing_filters_by_action_and_actor() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let origin_user_id = 1;
+    let user_a = invite_regular(&db, "audit_a", "passforaudita").await;
+    let user_b = invite_regular(&db, "audit_b", "passforauditb").await;
+    db.change_alias(user_a, "audit_a_renamed").await.unwrap();
+    let session = db.login("audit_b", "passforauditb").await.unwrap().tokens;
+    let (session_id, _token) =
+        unpack_encoded_session_token(&session.access_token, TokenKind::Access);
+    db.change_password(user_b, session_id, "passforauditb", "newpassforauditb")
+        .await
+        .unwrap();
+
+    let all = db
+        .admin_list_audit(origin_user_id, &ListAuditQuery::default(), 100)
+        .await
+        .unwrap();
+    assert_eq!(all.entries.len(), 4);
+    assert!(all.entries.windows(2).all(|pair| pair[0].id > pair[1].id));
+
+    let invites = db
+        .admin_list_audit(
+            origin_user_id,
+            &ListAuditQuery {
+                action: Some(AuditAction::UserInvited),
+                ..Default::default()
+            },
+            100,
+        )
+        .await
+        .unwrap();
+    let invited: Vec<_> = invites
+        .entries
+        .iter()
+        .map(|entry| (entry.actor_user_id, entry.target_user_id))
+        .collect();
+    assert_eq!(
+        invited,
+        vec![
+            (Some(origin_user_id), Some(user_b)),
+            (Some(origin_user_id), Some(user_a))
+        ]
+    );
+
+    let by_b = db
+        .admin_list_audit(
+            origin_user_id,
+            &ListAuditQuery {
+                actor_user_id: Some(user_b),
+                ..Default::default()
+            },
+            100,
+        )
+        .await
+        .unwrap();
+    assert_eq!(by_b.entries.len(), 1);
+    assert_eq!(by_b.entries[0].action, AuditAction::PasswordChanged);
+
+    let first_page = db
+        .admin_list_audit(origin_user_id, &ListAuditQuery::default(), 3)
+        .await
+        .unwrap();
+    let next_page = db
+        .admin_list_audit(
+            origin_user_id,
+            &ListAuditQuery {
+                before: first_page.entries.last().map(|entry| entry.id),
+                ..Default::default()
+            },
+            3,
+        )
+        .await
+        .unwrap();
+    assert_eq!(next_page.entries.len(), 1);
+    assert_eq!(next_page.entries[0].id, all.entries[3].id);
+
+    let future = db
+        .admin_list_audit(
+            origin_user_id,
+            &ListAuditQuery {
+                created_from: Some(chrono::Utc::now() + chrono::Duration::hours(1)),
+                ..Default::default()
+            },
+            100,
+        )
+        .await
+        .unwrap();
+    assert!(future.entries.is_empty());
+
+    let err = db
+        .admin_list_audit(user_a, &ListAuditQuery::default(), 100)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InsufficientPermissions { .. })
+    ));
+}
+
+#[tokio::test]
+async fn recent_chats_follow_latest_message_and_skip_empty_chats() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user = invite_regular(&db, "recent_user", "passforrecent").await;
+    let _peer_a = invite_regular(&db, "recent_a", "passforrecenta").await;
+    let _peer_b = invite_regular(&db, "recent_b", "passforrecentb").await;
+    let _peer_c = invite_regular(&db, "recent_c", "passforrecentc").await;
+    let _silent = invite_regular(&db, "recent_silent", "passforsilent").await;
+    let chat_a = find_chat_id(&db, user, ChatKind::Private, Some("recent_a")).await;
+    let chat_b = find_chat_id(&db, user, ChatKind::Private, Some("recent_b")).await;
+    let chat_c = find_chat_id(&db, user, ChatKind::Private, Some("recent_c")).await;
+
+    db.send_message(user, chat_a, "first").await.unwrap();
+    db.send_message(user, chat_b, "second").await.unwrap();
+    db.send_message(user, chat_c, "third").await.unwrap();
+    db.send_message(user, chat_a, "first again").await.unwrap();
+
+    let recent = db.list_recent_chats(user, 10).await.unwrap();
+    let ids: Vec<ChatId> = recent.chats.iter().map(|chat| chat.id).collect();
+    assert_eq!(ids, vec![chat_a, chat_c, chat_b]);
+    assert_eq!(
+        recent.chats[0].last_message_text.as_deref(),
+        Some("first again")
+    );
+
+    let limited = db.list_recent_chats(user, 2).await.unwrap();
+    let ids: Vec<ChatId> = limited.chats.iter().map(|chat| chat.id).collect();
+    assert_eq!(ids, vec![chat_a, chat_c]);
+}
+
+#[tokio::test]
+async fn send_private_message_creates_chat_on_first_contact() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let sender = invite_regular(&db, "dm_sender", "passfordmsender").await;
+    let recipient = invite_regular(&db, "dm_recipient", "passfordmrecipient").await;
+    // Invites pre-create private chats with everyone, drop it to get a first contact
+    let existing = find_chat_id(&db, sender, ChatKind::Private, Some("dm_recipient")).await;
+    sqlx::query("DELETE FROM chats WHERE id = $1")
+        .bind(existing)
+        .execute(db.pool())
+        .await
+        .unwrap();
+    assert!(db.get_private_chat(sender, recipient).await.is_err());
+
+    let sent = db
+        .send_private_message(sender, "dm_recipient", "hi there")
+        .await
+        .unwrap();
+    assert_eq!(sent.message.id, sent.message_id);
+    assert_eq!(
+        db.get_private_chat(recipient, sender)
+            .await
+            .unwrap()
+            .chat_id,
+        sent.chat_id
+    );
+    let received = db
+        .list_messages(recipient, sent.chat_id, 10, 1)
+        .await
+        .unwrap();
+    assert_eq!(received.messages.len(), 1);
+    assert_eq!(received.messages[0].id, sent.message_id);
+    assert_eq!(received.messages[0].text.as_deref(), Some("hi there"));
+
+    let again = db
+        .send_private_message(sender, "dm_recipient", "still there?")
+        .await
+        .unwrap();
+    assert_eq!(again.chat_id, sent.chat_id);
+
+    let unknown = db
+        .send_private_message(sender, "dm_nobody", "hello?")
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        unknown,
+        RequestError::Validation(ValidationError::UserNotFound { .. })
+    ));
+}
+
+#[tokio::test]
+async fn alias_change_cooldown_rejects_immediate_second_change() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user = invite_regular(&db, "cooldown_user", "passforcooldown").await;
+    // Without cooldown alias can change back to back
+    db.change_alias(user, "cooldown_free").await.unwrap();
+    db.change_alias(user, "cooldown_user").await.unwrap();
+
+    let mut config = DbConfig::development("walrus_db", "walrus_guest", "walruspass");
+    config.alias_change_cooldown_secs = Some(30 * 24 * 60 * 60);
+    let limited = DbConnection::connect(&config, &test_auth_config())
+        .await
+        .unwrap();
+    let err = limited
+        .change_alias(user, "cooldown_second")
+        .await
+        .unwrap_err();
+    let RequestError::Validation(ValidationError::InvalidInput { reason, .. }) = err else {
+        panic!("expected invalid input, got {err:?}");
+    };
+    assert!(reason.contains("can be changed again in"), "{reason}");
+    assert_eq!(db.whoami(user).await.unwrap().alias, "cooldown_user");
+
+    // Users who never changed alias aren't limited until their first change
+    let fresh = invite_regular(&db, "cooldown_fresh", "passforfresh").await;
+    limited
+        .change_alias(fresh, "cooldown_renamed")
+        .await
+        .unwrap();
+    assert!(limited.change_alias(fresh, "cooldown_again").await.is_err());
+}
+
+#[tokio::test]
+async fn list_invitees_follows_invite_tree() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let origin_user_id = 1;
+    let sub_admin = db
+        .invite_user(
+            origin_user_id,
+            "tree_admin",
+            "passfortreeadmin",
+            Some(UserRole::Admin),
+        )
+        .await
+        .unwrap();
+    let user_a = invite_regular(&db, "tree_a", "passfortreea").await;
+    let user_b = invite_regular(&db, "tree_b", "passfortreeb").await;
+    let nested_a = db
+        .invite_user(sub_admin, "tree_nested_a", "passfornesteda", None)
+        .await
+        .unwrap();
+    let nested_b = db
+        .invite_user(sub_admin, "tree_nested_b", "passfornestedb", None)
+        .await
+        .unwrap();
+
+    let direct = db
+        .list_invitees(origin_user_id, origin_user_id, false)
+        .await
+        .unwrap();
+    let direct_ids: Vec<_> = direct.invitees.iter().map(|u| u.user_id).collect();
+    assert_eq!(direct_ids, vec![sub_admin, user_a, user_b]);
+    assert!(direct
+        .invitees
+        .iter()
+        .all(|u| u.depth == 1 && u.invited_by == origin_user_id));
+
+    let subtree = db
+        .list_invitees(origin_user_id, origin_user_id, true)
+        .await
+        .unwrap();
+    let subtree: Vec<_> = subtree
+        .invitees
+        .iter()
+        .map(|u| (u.user_id, u.invited_by, u.depth))
+        .collect();
+    assert_eq!(
+        subtree,
+        vec![
+            (sub_admin, origin_user_id, 1),
+            (user_a, origin_user_id, 1),
+            (user_b, origin_user_id, 1),
+            (nested_a, sub_admin, 2),
+            (nested_b, sub_admin, 2),
+        ]
+    );
+
+    assert!(db
+        .list_invitees(origin_user_id, user_a, true)
+        .await
+        .unwrap()
+        .invitees
+        .is_empty());
+    let err = db
+        .list_invitees(user_a, origin_user_id, false)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InsufficientPermissions { .. })
+    ));
+    let err = db
+        .list_invitees(origin_user_id, 999_999, false)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::NotFound)
+    ));
+}
+
+#[tokio::test]
+async fn idle_timeout_rejects_stale_session() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user = invite_regular(&db, "idle_user", "passforidle").await;
+    let tokens = db.login("idle_user", "passforidle").await.unwrap().tokens;
+    let (session_id, _token) =
+        unpack_encoded_session_token(&tokens.access_token, TokenKind::Access);
+    // Each call follows login or a request counted as activity, so it backdates from now
+    let set_last_seen = |minutes_ago: i64| {
+        backdate(
+            &db,
+            "sessions",
+            "last_seen_at",
+            "id",
+            session_id,
+            chrono::Duration::minutes(minutes_ago),
+        )
+    };
+    set_last_seen(60).await;
+    // Disabled by default, access token expiration is the only limit
+    assert_eq!(resolve_session(&db, &tokens).await.unwrap(), user);
+
+    let mut config = DbConfig::development("walrus_db", "walrus_guest", "walruspass");
+    config.idle_timeout_secs = Some(30 * 60);
+    let limited = DbConnection::connect(&config, &test_auth_config())
+        .await
+        .unwrap();
+    // Request above counted as activity
+    assert_eq!(resolve_session(&limited, &tokens).await.unwrap(), user);
+
+    set_last_seen(5).await;
+    assert_eq!(resolve_session(&limited, &tokens).await.unwrap(), user);
+    let last_seen_at: chrono::DateTime<chrono::Utc> =
+        sqlx::query_scalar("SELECT last_seen_at FROM sessions WHERE id = $1")
+            .bind(session_id)
+            .fetch_one(db.pool())
+            .await
+            .unwrap();
+    assert!(chrono::Utc::now() - last_seen_at < chrono::Duration::minutes(1));
+
+    set_last_seen(31).await;
+    let err = resolve_session(&limited, &tokens).await.unwrap_err();
+    assert!(matches!(err, SessionError::TokenExpired));
+}
+
+#[tokio::test]
+async fn logout_clears_access_token_cookie() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let (alias, pass) = ("cookie_logout", "passforcookielogout");
+    invite_regular(&db, alias, pass).await;
+
+    let app = routes(
+        init_app_state_with(|config| {
+            config.server.access_token_cookie = Some("walrus_access".to_string());
+        })
+        .await,
+    );
+    let login = Request::post("/auth/login")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({ "alias": alias, "password": pass }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(login).await.unwrap();
+    let set_cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+    let cookie = set_cookie.split(';').next().unwrap().to_string();
+
+    let logout = Request::post("/auth/logout")
+        .header(COOKIE, &cookie)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(logout).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let cleared = response.headers()[SET_COOKIE].to_str().unwrap();
+    assert!(cleared.starts_with("walrus_access=;"), "{cleared}");
+    assert!(cleared.contains("Max-Age=0"), "{cleared}");
+    assert!(cleared.contains("Path=/"), "{cleared}");
+
+    // Stale cookie sent anyway is rejected since its session is gone
+    let whoami = Request::get("/auth/whoami")
+        .header(COOKIE, &cookie)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(whoami).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    // Bearer-only setup has no cookie to clear
+    let app = routes(init_app_state().await);
+    let logout = Request::post("/auth/logout")
+        .header(AUTHORIZATION, bearer_for(&db, alias, pass).await)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(logout).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(!response.headers().contains_key(SET_COOKIE));
+}
+
+#[tokio::test]
+async fn session_ping_advances_last_seen() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let (alias, pass) = ("ping_user", "passforpinguser");
+    invite_regular(&db, alias, pass).await;
+    let tokens = db.login(alias, pass).await.unwrap().tokens;
+    let (session_id, _token) =
+        unpack_encoded_session_token(&tokens.access_token, TokenKind::Access);
+    // Recent enough that resolving the token alone doesn't refresh it
+    backdate(
+        &db,
+        "sessions",
+        "last_seen_at",
+        "id",
+        session_id,
+        chrono::Duration::seconds(10),
+    )
+    .await;
+    let last_seen_at = || {
+        sqlx::query_scalar::<_, chrono::DateTime<chrono::Utc>>(
+            "SELECT last_seen_at FROM sessions WHERE id = $1",
+        )
+        .bind(session_id)
+        .fetch_one(db.pool())
+    };
+    let before = last_seen_at().await.unwrap();
+
+    let app = routes(init_app_state().await);
+    let ping = Request::post("/sessions/ping")
+        .header(AUTHORIZATION, format!("Bearer {}", tokens.access_token))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(ping).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(last_seen_at().await.unwrap() > before);
+
+    let anonymous = Request::post("/sessions/ping").body(Body::empty()).unwrap();
+    let response = app.oneshot(anonymous).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+/// Passes only clients that solved the challenge, with `human` as the solution.
+struct StubCaptcha;
+
+#[axum::async_trait]
+impl CaptchaVerifier for StubCaptcha {
+    async fn verify(&self, token: Option<&str>) -> bool {
+        token == Some("human")
+    }
+}
+
+#[tokio::test]
+async fn rejected_captcha_blocks_login_before_credentials_check() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let (alias, pass) = ("captcha_user", "passforcaptcha");
+    let user_id = invite_regular(&db, alias, pass).await;
+
+    let mut state = Arc::into_inner(init_app_state().await).unwrap();
+    state.captcha = Box::new(StubCaptcha);
+    let app = routes(Arc::new(state));
+    let login = |captcha_token: Option<&str>| {
+        Request::post("/auth/login")
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::json!({
+                    "alias": alias,
+                    "password": pass,
+                    "captcha_token": captcha_token,
+                })
+                .to_string(),
+            ))
+            .unwrap()
+    };
+    let count_sessions = || {
+        sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM sessions WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(db.pool())
+    };
+
+    for token in [None, Some("bot")] {
+        let response = app.clone().oneshot(login(token)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+    assert_eq!(count_sessions().await.unwrap(), 0);
+
+    let response = app.oneshot(login(Some("human"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(count_sessions().await.unwrap(), 1);
 }
 
 #[tokio::test]
-async fn logout() {
-    let _lock = SERIAL_LOCK.lock().await;
+async fn rejected_captcha_blocks_invite() {
+    let _lock = SERIAL_LOCK.write().await;
     let db = init_and_get_db().await;
+    let admin_bearer = bearer_for(&db, "origin", TEST_ORIGIN_PASSWORD).await;
 
-    let (alias, pass) = ("existing_user_a", "existing_pass_a");
-    let _ = invite_regular(&db, alias, pass).await;
+    let mut state = Arc::into_inner(init_app_state().await).unwrap();
+    state.captcha = Box::new(StubCaptcha);
+    let app = routes(Arc::new(state));
+    let invite = |captcha_token: Option<&str>| {
+        Request::post("/users/invite")
+            .header(AUTHORIZATION, &admin_bearer)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::json!({
+                    "alias": "captcha_invitee",
+                    "password": "passforcaptchainvitee",
+                    "captcha_token": captcha_token,
+                })
+                .to_string(),
+            ))
+            .unwrap()
+    };
 
-    let session = db.login(alias, pass).await.unwrap();
-    let _ok = resolve_session(&db, &session).await.unwrap();
+    for token in [None, Some("bot")] {
+        let response = app.clone().oneshot(invite(token)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+    let err = db
+        .login("captcha_invitee", "passforcaptchainvitee")
+        .await
+        .unwrap_err();
+    assert!(matches!(err, RequestError::BadCredentials), "{err:?}");
 
-    let (session_id, _token) = unpack_encoded_session_token(&session.access_token);
-    db.logout(session_id).await.unwrap();
+    let response = app.oneshot(invite(Some("human"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+}
 
-    let err = resolve_session(&db, &session).await.unwrap_err();
-    assert!(matches!(err, SessionError::TokenNotFound));
+#[tokio::test]
+async fn user_export_contains_only_own_data() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user_a = invite_regular(&db, "export_a", "passforexporta").await;
+    let user_b = invite_regular(&db, "export_b", "passforexportb").await;
+    let chat_id = find_chat_id(&db, user_a, ChatKind::Private, Some("export_b")).await;
+    let own = db.send_message(user_a, chat_id, "mine").await.unwrap();
+    let foreign = db.send_message(user_b, chat_id, "theirs").await.unwrap();
+    // Enough history to span several internal batches
+    sqlx::query(
+        "INSERT INTO messages (chat_id, user_id, text, created_at)
+        SELECT $1, $2, 'bulk ' || n, current_timestamp FROM generate_series(1, $3) n",
+    )
+    .bind(chat_id)
+    .bind(user_a)
+    .bind(EXPORT_MESSAGES_BATCH_SIZE as i32 + 5)
+    .execute(db.pool())
+    .await
+    .unwrap();
+    db.login("export_a", "passforexporta").await.unwrap();
+
+    let export = db.export_user_data(user_a).await.unwrap();
+    assert_eq!(export.profile.user_id, user_a);
+    assert!(export.memberships.iter().any(|m| m.chat_id == chat_id));
+    assert_eq!(
+        export.messages.len(),
+        EXPORT_MESSAGES_BATCH_SIZE as usize + 6
+    );
+    assert_eq!(export.messages[0].id, own);
+    assert!(export.messages.iter().all(|m| m.id != foreign));
+    assert!(export
+        .messages
+        .windows(2)
+        .all(|pair| pair[0].id < pair[1].id));
+    assert_eq!(export.sessions.len(), 1);
+    let serialized = serde_json::to_string(&export).unwrap();
+    assert!(!serialized.contains("token"));
+
+    let other = db.export_user_data(user_b).await.unwrap();
+    let ids: Vec<_> = other.messages.iter().map(|m| m.id).collect();
+    assert_eq!(ids, vec![foreign]);
+    assert!(other.sessions.is_empty());
 }
 
 #[tokio::test]
-async fn refresh_token() {
-    let _lock = SERIAL_LOCK.lock().await;
+async fn multibyte_values_at_column_limit_are_stored() {
+    let _lock = SERIAL_LOCK.write().await;
     let db = init_and_get_db().await;
+    let user = invite_regular(&db, "multibyte_user", "passformultibyte").await;
 
-    let (alias, pass) = ("existing_user_a", "existing_pass_a");
-    let _ = invite_regular(&db, alias, pass).await;
+    let display_name = "ж".repeat(30);
+    db.change_display_name(user, &display_name).await.unwrap();
+    let alias = "ё".repeat(30);
+    db.change_alias(user, &alias).await.unwrap();
+    let profile = db.whoami(user).await.unwrap();
+    assert_eq!(profile.display_name, display_name);
+    assert_eq!(profile.alias, alias);
 
-    let first_session = db.login(alias, pass).await.unwrap();
-    let _ok = resolve_session(&db, &first_session).await.unwrap();
+    // Rejected by validation rather than by the database column
+    let err = db
+        .change_display_name(user, &"ж".repeat(31))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
 
-    let (session_id, token) = unpack_encoded_session_token(&first_session.refresh_token);
-    let second_session = db.refresh_session(session_id, &token).await.unwrap();
-    assert_ne!(second_session.refresh_token, first_session.refresh_token);
-    assert_ne!(second_session.access_token, first_session.access_token);
+    let chat_id = find_chat_id(&db, user, ChatKind::WithSelf, None).await;
+    let message_id = db
+        .send_message(user, chat_id, &"ж".repeat(4096))
+        .await
+        .unwrap();
+    db.add_reaction(user, chat_id, message_id, &"👍".repeat(32))
+        .await
+        .unwrap();
+    let err = db
+        .send_message(user, chat_id, &"ж".repeat(4097))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::LimitExceeded { .. })
+    ));
+}
 
-    let _ok = resolve_session(&db, &second_session).await.unwrap();
-    resolve_session(&db, &first_session).await.unwrap_err();
+#[tokio::test]
+async fn pinned_chat_sorts_above_more_recent_chats() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user = invite_regular(&db, "pin_user", "passforpinuser").await;
+    let _old_peer = invite_regular(&db, "pin_old", "passforpinold").await;
+    let _new_peer = invite_regular(&db, "pin_new", "passforpinnew").await;
+    let old_chat = find_chat_id(&db, user, ChatKind::Private, Some("pin_old")).await;
+    let new_chat = find_chat_id(&db, user, ChatKind::Private, Some("pin_new")).await;
+
+    db.send_message(user, old_chat, "older").await.unwrap();
+    db.send_message(user, new_chat, "newer").await.unwrap();
+    let ids: Vec<ChatId> = list_user_chats(&db, user)
+        .await
+        .iter()
+        .map(|c| c.id)
+        .collect();
+    assert!(ids.iter().position(|id| *id == new_chat) < ids.iter().position(|id| *id == old_chat));
+
+    db.pin_chat(user, old_chat).await.unwrap();
+    let chats = list_user_chats(&db, user).await;
+    assert_eq!(chats[0].id, old_chat);
+    assert!(chats[0].pinned_at.is_some());
+    assert!(chats.iter().skip(1).all(|chat| chat.pinned_at.is_none()));
+
+    // Recents stay ordered by activity only
+    let recent = db.list_recent_chats(user, 10).await.unwrap();
+    assert_eq!(recent.chats[0].id, new_chat);
+
+    db.unpin_chat(user, old_chat).await.unwrap();
+    let chats = list_user_chats(&db, user).await;
+    assert_eq!(chats[0].id, new_chat);
+}
+
+#[tokio::test]
+async fn pinned_chats_are_capped() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user = invite_regular(&db, "pin_cap", "passforpincap").await;
+    let mut chats = Vec::new();
+    for i in 0..=MAX_PINNED_CHATS {
+        chats.push(
+            db.create_group_chat(user, &format!("pin group {i}"), None)
+                .await
+                .unwrap(),
+        );
+    }
+    for chat_id in &chats[..MAX_PINNED_CHATS] {
+        db.pin_chat(user, *chat_id).await.unwrap();
+    }
+    // Re-pinning doesn't count against the limit
+    db.pin_chat(user, chats[0]).await.unwrap();
+
+    let result = db.pin_chat(user, chats[MAX_PINNED_CHATS]).await;
+    assert!(matches!(
+        result,
+        Err(RequestError::Validation(
+            ValidationError::LimitExceeded { .. }
+        ))
+    ));
+
+    let stranger = invite_regular(&db, "pin_stranger", "passforpinstranger").await;
+    let result = db.pin_chat(stranger, chats[0]).await;
+    assert!(matches!(
+        result,
+        Err(RequestError::Validation(ValidationError::NotFound))
+    ));
+}
+
+#[tokio::test]
+async fn concurrent_pins_respect_cap() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user = invite_regular(&db, "pin_race", "passforpinrace").await;
+    let mut chats = Vec::new();
+    for i in 0..=MAX_PINNED_CHATS {
+        chats.push(
+            db.create_group_chat(user, &format!("pin race group {i}"), None)
+                .await
+                .unwrap(),
+        );
+    }
+    for chat_id in &chats[..MAX_PINNED_CHATS - 1] {
+        db.pin_chat(user, *chat_id).await.unwrap();
+    }
+
+    let (first, second) = tokio::join!(
+        db.pin_chat(user, chats[MAX_PINNED_CHATS - 1]),
+        db.pin_chat(user, chats[MAX_PINNED_CHATS])
+    );
+    assert!(first.is_ok() != second.is_ok(), "{first:?} {second:?}");
+    let pinned = db
+        .list_chats(user, 20, 1, None)
+        .await
+        .unwrap()
+        .chats
+        .into_iter()
+        .filter(|chat| chat.pinned_at.is_some())
+        .count();
+    assert_eq!(pinned, MAX_PINNED_CHATS);
+}
+
+#[tokio::test]
+async fn channel_feed_interleaves_followed_channels_by_recency() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let owner = invite_regular(&db, "feed_owner", "passforfeedowner").await;
+    let follower = invite_regular(&db, "feed_follower", "passforfeedfollower").await;
+    let news = db.create_channel_chat(owner, "News", None).await.unwrap();
+    let sports = db.create_channel_chat(owner, "Sports", None).await.unwrap();
+    let unfollowed = db
+        .create_channel_chat(owner, "Unfollowed", None)
+        .await
+        .unwrap();
+    let group = db
+        .create_group_chat(owner, "Feed group", None)
+        .await
+        .unwrap();
+    db.add_members_to_group_chat(owner, news, &[follower])
+        .await
+        .unwrap();
+    db.add_members_to_group_chat(owner, sports, &[follower])
+        .await
+        .unwrap();
+    db.add_members_to_group_chat(owner, group, &[follower])
+        .await
+        .unwrap();
+
+    let first = db.send_message(owner, news, "news 1").await.unwrap();
+    let second = db.send_message(owner, sports, "sports 1").await.unwrap();
+    db.send_message(owner, unfollowed, "hidden").await.unwrap();
+    db.send_message(owner, group, "not a channel")
+        .await
+        .unwrap();
+    let third = db.send_message(owner, news, "news 2").await.unwrap();
+
+    let feed = db
+        .channel_feed(follower, ListingMode::Page { limit: 10, page: 1 })
+        .await
+        .unwrap();
+    let entries: Vec<(ChatId, MessageId)> = feed
+        .messages
+        .iter()
+        .map(|entry| (entry.chat_id, entry.message.id))
+        .collect();
+    assert_eq!(
+        entries,
+        vec![(news, third), (sports, second), (news, first)]
+    );
+    assert_eq!(
+        feed.messages[1].chat_display_name.as_deref(),
+        Some("Sports")
+    );
+
+    let older = db
+        .channel_feed(
+            follower,
+            ListingMode::Offset {
+                offset: second,
+                limit: 10,
+            },
+        )
+        .await
+        .unwrap();
+    let ids: Vec<MessageId> = older
+        .messages
+        .iter()
+        .map(|entry| entry.message.id)
+        .collect();
+    assert_eq!(ids, vec![first]);
+}
+
+#[tokio::test]
+async fn cross_submitted_token_kinds_are_rejected_distinctly() {
+    let _lock = SERIAL_LOCK.write().await;
+    let _db = init_and_get_db().await;
+    let state = init_app_state().await;
+    invite_regular(&state.db_connection, "token_kind", "passfortokenkind").await;
+    let tokens = state
+        .db_connection
+        .login("token_kind", "passfortokenkind")
+        .await
+        .unwrap()
+        .tokens;
+    let app = routes(state);
+
+    let refresh_with_access = Request::post("/auth/refresh")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(
+            serde_json::json!({ "refresh_token": tokens.access_token }).to_string(),
+        ))
+        .unwrap();
+    let response = app.clone().oneshot(refresh_with_access).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(
+        error["error"],
+        RequestError::WrongTokenKind {
+            expected: TokenKind::Refresh
+        }
+        .to_string()
+    );
+
+    let authorize_with_refresh = Request::get("/auth/whoami")
+        .header(AUTHORIZATION, format!("Bearer {}", tokens.refresh_token))
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(authorize_with_refresh).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let wrong_kind: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    let malformed = Request::get("/auth/whoami")
+        .header(AUTHORIZATION, "Bearer AAAA")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.oneshot(malformed).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let bad_token: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_ne!(wrong_kind["error"], bad_token["error"]);
+}
+
+#[tokio::test]
+async fn imported_messages_keep_timestamps_and_list_in_order() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let origin_user_id = 1;
+    let author_a = invite_regular(&db, "import_a", "passforimporta").await;
+    let author_b = invite_regular(&db, "import_b", "passforimportb").await;
+    let outsider = invite_regular(&db, "import_out", "passforimportout").await;
+    let group = db
+        .create_group_chat(author_a, "Imported", None)
+        .await
+        .unwrap();
+    db.add_members_to_group_chat(author_a, group, &[author_b])
+        .await
+        .unwrap();
+
+    let start = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    // Submitted newest first, the import orders them by original time
+    let messages: Vec<ImportedMessage> = (0..100)
+        .rev()
+        .map(|i| ImportedMessage {
+            user_id: if i % 2 == 0 { author_a } else { author_b },
+            text: format!("imported {i}"),
+            created_at: start + chrono::Duration::minutes(i),
+        })
+        .collect();
+
+    let err = db
+        .import_messages(author_a, group, messages.clone())
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InsufficientPermissions { .. })
+    ));
+    let mut with_outsider = messages.clone();
+    with_outsider[0].user_id = outsider;
+    let err = db
+        .import_messages(origin_user_id, group, with_outsider)
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
+
+    let ids = db
+        .import_messages(origin_user_id, group, messages)
+        .await
+        .unwrap();
+    assert_eq!(ids.len(), 100);
+
+    let listed = db
+        .list_messages(author_a, group, 100, 1)
+        .await
+        .unwrap()
+        .messages;
+    assert_eq!(listed.len(), 100);
+    for (i, message) in listed.iter().enumerate() {
+        let i = i as i64;
+        assert_eq!(message.text, Some(format!("imported {i}")));
+        assert_eq!(message.created_at, start + chrono::Duration::minutes(i));
+        let author = if i % 2 == 0 { author_a } else { author_b };
+        assert_eq!(message.user_id, Some(author));
+    }
+    let chat = list_user_chats(&db, author_a)
+        .await
+        .into_iter()
+        .find(|chat| chat.id == group)
+        .unwrap();
+    assert_eq!(chat.last_message_id, ids.last().copied());
+
+    // Imported history would be listed after messages the chat already has
+    let err = db
+        .import_messages(
+            origin_user_id,
+            group,
+            vec![ImportedMessage {
+                user_id: author_a,
+                text: "late import".to_string(),
+                created_at: start - chrono::Duration::minutes(1),
+            }],
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        RequestError::Validation(ValidationError::InvalidInput { .. })
+    ));
+}
+
+#[tokio::test]
+async fn private_chat_previews_resolve_peer_and_follow_recency() {
+    let _lock = SERIAL_LOCK.write().await;
+    let db = init_and_get_db().await;
+    let user = invite_regular(&db, "inbox_user", "passforinboxuser").await;
+    let peer_a = invite_regular(&db, "inbox_a", "passforinboxa").await;
+    let peer_b = invite_regular(&db, "inbox_b", "passforinboxb").await;
+    let chat_a = find_chat_id(&db, user, ChatKind::Private, Some("inbox_a")).await;
+    let chat_b = find_chat_id(&db, user, ChatKind::Private, Some("inbox_b")).await;
+    let group = db
+        .create_group_chat(user, "Inbox group", None)
+        .await
+        .unwrap();
+
+    db.send_message(peer_a, chat_a, "from a").await.unwrap();
+    let latest_b = db.send_message(user, chat_b, "to b").await.unwrap();
+    db.send_message(user, group, "group chatter").await.unwrap();
+
+    let inbox = db
+        .list_private_chats_with_preview(user, ListingMode::Page { limit: 10, page: 1 })
+        .await
+        .unwrap()
+        .chats;
+    assert!(inbox.iter().all(|chat| chat.chat_id != group));
+    assert_eq!(inbox[0].chat_id, chat_b);
+    assert_eq!(inbox[0].peer_user_id, peer_b);
+    assert_eq!(inbox[0].peer_alias, "inbox_b");
+    assert_eq!(inbox[0].last_message_id, Some(latest_b));
+    assert_eq!(inbox[0].last_message_user_id, Some(user));
+    assert_eq!(inbox[1].chat_id, chat_a);
+    assert_eq!(inbox[1].peer_user_id, peer_a);
+    assert_eq!(inbox[1].last_message_text.as_deref(), Some("from a"));
+
+    // The same chat seen from the other side resolves to the caller
+    let peer_inbox = db
+        .list_private_chats_with_preview(peer_a, ListingMode::Page { limit: 10, page: 1 })
+        .await
+        .unwrap()
+        .chats;
+    assert_eq!(peer_inbox[0].chat_id, chat_a);
+    assert_eq!(peer_inbox[0].peer_user_id, user);
+
+    let second_page = db
+        .list_private_chats_with_preview(user, ListingMode::Page { limit: 1, page: 2 })
+        .await
+        .unwrap()
+        .chats;
+    assert_eq!(second_page.len(), 1);
+    assert_eq!(second_page[0].chat_id, chat_a);
+}
+
+#[tokio::test]
+async fn removed_member_socket_is_closed_and_never_publishes() {
+    let _lock = SERIAL_LOCK.write().await;
+    let _db = init_and_get_db().await;
+    let state = init_app_state().await;
+    let db = &state.db_connection;
+    let owner = invite_regular(db, "live_owner", "passforliveowner").await;
+    let member = invite_regular(db, "live_member", "passforlivemember").await;
+    let chat_id = db.create_group_chat(owner, "Live", None).await.unwrap();
+    db.add_members_to_group_chat(owner, chat_id, &[member])
+        .await
+        .unwrap();
+    let owner_bearer = bearer_for(db, "live_owner", "passforliveowner").await;
+    let member_bearer = bearer_for(db, "live_member", "passforlivemember").await;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let app = routes(state.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    let connect = |bearer: String| async move {
+        let mut request = format!("ws://{address}/chats/{chat_id}/events")
+            .into_client_request()
+            .unwrap();
+        request
+            .headers_mut()
+            .insert(AUTHORIZATION, bearer.parse().unwrap());
+        tokio_tungstenite::connect_async(request).await.unwrap().0
+    };
+    let mut owner_socket = connect(owner_bearer.clone()).await;
+    let mut member_socket = connect(member_bearer.clone()).await;
+    while state.chat_events.subscriber_count(chat_id) < 2 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    // Invalid frames are rejected back to the sender only
+    member_socket
+        .send(WsMessage::Text("over the socket".to_string()))
+        .await
+        .unwrap();
+    let Some(Ok(WsMessage::Text(payload))) = member_socket.next().await else {
+        panic!("expected rejection");
+    };
+    let event: serde_json::Value = serde_json::from_str(&payload).unwrap();
+    assert_eq!(event["type"], "rejected");
+
+    let app = routes(state.clone());
+    let request = Request::delete(format!("/chats/{chat_id}/members/{member}"))
+        .header(AUTHORIZATION, &owner_bearer)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let Some(Ok(WsMessage::Text(payload))) = member_socket.next().await else {
+        panic!("expected removal event");
+    };
+    let event: serde_json::Value = serde_json::from_str(&payload).unwrap();
+    assert_eq!(event["type"], "member_removed");
+    assert_eq!(event["user_id"], member);
+    assert!(matches!(
+        member_socket.next().await,
+        Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None
+    ));
+    assert!(member_socket
+        .send(WsMessage::Text("after removal".to_string()))
+        .await
+        .is_err());
+
+    let send_message = |bearer: &str, text: &str| {
+        Request::post(format!("/chats/{chat_id}/messages"))
+            .header(AUTHORIZATION, bearer)
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::json!({ "text": text }).to_string()))
+            .unwrap()
+    };
+    let response = app
+        .clone()
+        .oneshot(send_message(&member_bearer, "still here?"))
+        .await
+        .unwrap();
+    assert!(!response.status().is_success());
+    let response = app
+        .oneshot(send_message(&owner_bearer, "goodbye"))
+        .await
+        .unwrap();
+    assert!(response.status().is_success());
+
+    // Owner sees the removal followed directly by their own message, nothing from the member
+    let mut owner_events = Vec::new();
+    for _ in 0..2 {
+        let Some(Ok(WsMessage::Text(payload))) = owner_socket.next().await else {
+            panic!("expected event");
+        };
+        owner_events.push(serde_json::from_str::<serde_json::Value>(&payload).unwrap());
+    }
+    assert_eq!(owner_events[0]["type"], "member_removed");
+    assert_eq!(owner_events[1]["type"], "new_message");
+    assert_eq!(owner_events[1]["text"], "goodbye");
+}
+
+#[tokio::test]
+async fn socket_sent_message_echoes_temp_id_to_sender_only() {
+    let _lock = SERIAL_LOCK.write().await;
+    let _db = init_and_get_db().await;
+    let state = init_app_state().await;
+    let db = &state.db_connection;
+    let sender = invite_regular(db, "echo_sender", "passforechosender").await;
+    let _peer = invite_regular(db, "echo_peer", "passforechopeer").await;
+    let chat_id = find_chat_id(db, sender, ChatKind::Private, Some("echo_peer")).await;
+    let sender_bearer = bearer_for(db, "echo_sender", "passforechosender").await;
+    let peer_bearer = bearer_for(db, "echo_peer", "passforechopeer").await;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let app = routes(state.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    let connect = |bearer: String| async move {
+        let mut request = format!("ws://{address}/chats/{chat_id}/events")
+            .into_client_request()
+            .unwrap();
+        request
+            .headers_mut()
+            .insert(AUTHORIZATION, bearer.parse().unwrap());
+        tokio_tungstenite::connect_async(request).await.unwrap().0
+    };
+    let mut sender_socket = connect(sender_bearer).await;
+    let mut peer_socket = connect(peer_bearer).await;
+    while state.chat_events.subscriber_count(chat_id) < 2 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    async fn next_event<S>(socket: &mut S) -> serde_json::Value
+    where
+        S: StreamExt<Item = Result<WsMessage, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    {
+        let Some(Ok(WsMessage::Text(payload))) = socket.next().await else {
+            panic!("expected event");
+        };
+        serde_json::from_str(&payload).unwrap()
+    }
+
+    let frame = serde_json::json!({
+        "type": "send_message",
+        "text": "optimistic",
+        "client_temp_id": "tmp-42",
+    });
+    sender_socket
+        .send(WsMessage::Text(frame.to_string()))
+        .await
+        .unwrap();
+    let echoed = next_event(&mut sender_socket).await;
+    assert_eq!(echoed["type"], "new_message");
+    assert_eq!(echoed["client_temp_id"], "tmp-42");
+    assert_eq!(echoed["text"], "optimistic");
+    let messages = db
+        .list_messages(sender, chat_id, 10, 1)
+        .await
+        .unwrap()
+        .messages;
+    assert_eq!(echoed["id"], messages[0].id);
+    let delivered = next_event(&mut peer_socket).await;
+    assert_eq!(delivered["type"], "new_message");
+    assert_eq!(delivered["id"], messages[0].id);
+    assert!(delivered.get("client_temp_id").is_none(), "{delivered}");
+
+    // Failed sends are reported back with the temp id instead of being broadcast
+    let frame = serde_json::json!({
+        "type": "send_message",
+        "text": " ",
+        "client_temp_id": "tmp-43",
+    });
+    sender_socket
+        .send(WsMessage::Text(frame.to_string()))
+        .await
+        .unwrap();
+    let rejected = next_event(&mut sender_socket).await;
+    assert_eq!(rejected["type"], "rejected");
+    assert_eq!(rejected["client_temp_id"], "tmp-43");
+}
+
+#[tokio::test]
+async fn socket_of_logged_out_session_is_closed_and_never_publishes() {
+    let _lock = SERIAL_LOCK.write().await;
+    let _db = init_and_get_db().await;
+    let state = init_app_state().await;
+    let db = &state.db_connection;
+    let sender = invite_regular(db, "logout_sender", "passforlogoutsender").await;
+    let _peer = invite_regular(db, "logout_peer", "passforlogoutpeer").await;
+    let chat_id = find_chat_id(db, sender, ChatKind::Private, Some("logout_peer")).await;
+    let sender_bearer = bearer_for(db, "logout_sender", "passforlogoutsender").await;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let app = routes(state.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    let mut request = format!("ws://{address}/chats/{chat_id}/events")
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert(AUTHORIZATION, sender_bearer.parse().unwrap());
+    let mut socket = tokio_tungstenite::connect_async(request).await.unwrap().0;
+    while state.chat_events.subscriber_count(chat_id) < 1 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let request = Request::post("/auth/logout")
+        .header(AUTHORIZATION, &sender_bearer)
+        .body(Body::empty())
+        .unwrap();
+    let response = routes(state.clone()).oneshot(request).await.unwrap();
+    assert!(response.status().is_success());
+
+    let frame = serde_json::json!({ "type": "send_message", "text": "after logout" });
+    socket
+        .send(WsMessage::Text(frame.to_string()))
+        .await
+        .unwrap();
+    assert!(matches!(
+        socket.next().await,
+        Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None
+    ));
+    let messages = db
+        .list_messages(sender, chat_id, 10, 1)
+        .await
+        .unwrap()
+        .messages;
+    assert!(messages.is_empty(), "{messages:?}");
+}
+
+#[tokio::test]
+async fn messages_sent_during_disconnect_are_replayed_on_reconnect() {
+    let _lock = SERIAL_LOCK.write().await;
+    let _db = init_and_get_db().await;
+    let state = init_app_state().await;
+    let db = &state.db_connection;
+    let reader = invite_regular(db, "resume_reader", "passforresumereader").await;
+    let writer = invite_regular(db, "resume_writer", "passforresumewriter").await;
+    let chat_id = find_chat_id(db, reader, ChatKind::Private, Some("resume_writer")).await;
+    let reader_bearer = bearer_for(db, "resume_reader", "passforresumereader").await;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let app = routes(state.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    let connect = |query: String| {
+        let bearer = reader_bearer.clone();
+        let state = state.clone();
+        async move {
+            let mut request = format!("ws://{address}/chats/{chat_id}/events{query}")
+                .into_client_request()
+                .unwrap();
+            request
+                .headers_mut()
+                .insert(AUTHORIZATION, bearer.parse().unwrap());
+            let socket = tokio_tungstenite::connect_async(request).await.unwrap().0;
+            while state.chat_events.subscriber_count(chat_id) < 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            socket
+        }
+    };
+    async fn next_message_id<S>(socket: &mut S) -> MessageId
+    where
+        S: StreamExt<Item = Result<WsMessage, tokio_tungstenite::tungstenite::Error>> + Unpin,
+    {
+        let Some(Ok(WsMessage::Text(payload))) = socket.next().await else {
+            panic!("expected event");
+        };
+        let event: serde_json::Value = serde_json::from_str(&payload).unwrap();
+        assert_eq!(event["type"], "new_message");
+        event["id"].as_i64().unwrap()
+    }
+    let publish = |text: &str| {
+        let state = state.clone();
+        let text = text.to_string();
+        async move {
+            let message = state
+                .db_connection
+                .send_message_with_response(writer, chat_id, &text, None)
+                .await
+                .unwrap();
+            let message_id = message.id;
+            state
+                .chat_events
+                .publish(chat_id, ChatEvent::new_message(message));
+            message_id
+        }
+    };
+
+    let mut socket = connect(String::new()).await;
+    let seen = publish("before").await;
+    assert_eq!(next_message_id(&mut socket).await, seen);
+    socket.close(None).await.unwrap();
+    drop(socket);
+    while state.chat_events.subscriber_count(chat_id) > 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let missed_a = publish("during a").await;
+    let missed_b = publish("during b").await;
+    let mut socket = connect(format!("?last_event_id={seen}")).await;
+    assert_eq!(next_message_id(&mut socket).await, missed_a);
+    assert_eq!(next_message_id(&mut socket).await, missed_b);
+    // Live events follow the replay without duplicates
+    let live = publish("after").await;
+    assert_eq!(next_message_id(&mut socket).await, live);
+
+    let mut request = format!("ws://{address}/chats/{chat_id}/events?last_event_id=-1")
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert(AUTHORIZATION, reader_bearer.parse().unwrap());
+    assert!(tokio_tungstenite::connect_async(request).await.is_err());
 }
@@ -1 +1 @@
-mod db;
+pub(crate) mod db;